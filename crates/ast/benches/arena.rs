@@ -0,0 +1,236 @@
+//! Compares the borrowed, boxed AST from `ria_parser` with the arena AST on a
+//! large generated module.
+//!
+//! Run with `cargo bench -p ria_ast`. Pass a number of defs to change the
+//! size of the input.
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    hint::black_box,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::{Duration, Instant},
+};
+
+use ria_ast::{Ast, Expr as ArenaExpr, ExprId};
+use ria_parser::{expr::Expr, parse_module};
+
+/// Counts live bytes and allocations so memory use can be compared.
+struct Counting;
+
+static BYTES: AtomicUsize = AtomicUsize::new(0);
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        BYTES.fetch_add(layout.size(), Ordering::Relaxed);
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        BYTES.fetch_sub(layout.size(), Ordering::Relaxed);
+        ALLOCATIONS.fetch_sub(1, Ordering::Relaxed);
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        BYTES.fetch_add(new_size, Ordering::Relaxed);
+        BYTES.fetch_sub(layout.size(), Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: Counting = Counting;
+
+/// Live bytes and allocations at a point in time.
+#[derive(Clone, Copy)]
+struct Usage {
+    bytes: usize,
+    allocations: usize,
+}
+
+impl Usage {
+    fn now() -> Self {
+        Self {
+            bytes: BYTES.load(Ordering::Relaxed),
+            allocations: ALLOCATIONS.load(Ordering::Relaxed),
+        }
+    }
+
+    fn since(self, before: Usage) -> Usage {
+        Usage {
+            bytes: self.bytes - before.bytes,
+            allocations: self.allocations - before.allocations,
+        }
+    }
+}
+
+/// Generates a module of `defs` definitions mixing lambdas, calls and blocks.
+fn generate(defs: usize) -> String {
+    let mut source = String::new();
+    for i in 0..defs {
+        let prev = if i == 0 {
+            "f0".to_string()
+        } else {
+            format!("f{}", i - 1)
+        };
+        source.push_str(&format!(
+            "f{i} = \\x -> \\y -> (z = {prev} x y\n  w = \\a -> a z x\n  w y {prev} x)\n"
+        ));
+    }
+    source
+}
+
+/// Runs `f` `iterations` times, returning the average time per run.
+fn time<T>(iterations: u32, mut f: impl FnMut() -> T) -> Duration {
+    let start = Instant::now();
+    for _ in 0..iterations {
+        black_box(f());
+    }
+    start.elapsed() / iterations
+}
+
+fn count_boxed(expr: &Expr) -> usize {
+    1 + match expr {
+        Expr::Variable(_) | Expr::Qualified(_) | Expr::Int(_) => 0,
+        Expr::Lambda(lambda) => count_boxed(&lambda.body),
+        Expr::Block(block) => {
+            block
+                .defs
+                .defs
+                .iter()
+                .map(|def| count_boxed(&def.expr))
+                .sum::<usize>()
+                + block.expr.as_deref().map_or(0, count_boxed)
+        }
+        Expr::Call(call) => count_boxed(&call.func) + count_boxed(&call.arg),
+        Expr::Match(matching) => {
+            count_boxed(&matching.scrutinee)
+                + matching
+                    .arms
+                    .iter()
+                    .map(|arm| count_boxed(&arm.body))
+                    .sum::<usize>()
+        }
+        Expr::Tuple(tuple) => tuple.items.iter().map(count_boxed).sum(),
+        Expr::Record(record) => record
+            .fields
+            .iter()
+            .map(|field| count_boxed(&field.value))
+            .sum(),
+        Expr::Access(access) => count_boxed(&access.expr),
+    }
+}
+
+fn count_arena(ast: &Ast, id: ExprId) -> usize {
+    1 + match ast.expr(id) {
+        ArenaExpr::Variable(_) | ArenaExpr::Qualified(_) | ArenaExpr::Int(_) => 0,
+        ArenaExpr::Lambda(lambda) => count_arena(ast, lambda.body),
+        ArenaExpr::Block(block) => {
+            ast.defs(block.defs)
+                .iter()
+                .map(|def| count_arena(ast, def.expr))
+                .sum::<usize>()
+                + block.expr.map_or(0, |expr| count_arena(ast, expr))
+        }
+        ArenaExpr::Call(call) => count_arena(ast, call.func) + count_arena(ast, call.arg),
+        ArenaExpr::Match(matching) => {
+            count_arena(ast, matching.scrutinee)
+                + ast
+                    .arms(matching.arms)
+                    .iter()
+                    .map(|arm| count_arena(ast, arm.body))
+                    .sum::<usize>()
+        }
+        ArenaExpr::Tuple(tuple) => ast
+            .items(tuple.items)
+            .iter()
+            .map(|&item| count_arena(ast, item))
+            .sum(),
+        ArenaExpr::Record(record) => ast
+            .fields(record.fields)
+            .iter()
+            .map(|field| count_arena(ast, field.value))
+            .sum(),
+        ArenaExpr::Access(access) => count_arena(ast, access.expr),
+    }
+}
+
+fn main() {
+    let defs = std::env::args()
+        .skip(1)
+        .find_map(|arg| arg.parse().ok())
+        .unwrap_or(5_000);
+    let source = generate(defs);
+    let iterations = 10;
+    println!("{defs} defs, {} bytes of source\n", source.len());
+
+    // memory retained by each representation
+    let before = Usage::now();
+    let boxed = parse_module(&source).unwrap();
+    let boxed_usage = Usage::now().since(before);
+
+    let before = Usage::now();
+    let mut ast = Ast::new();
+    let module = ast.lower_module(&boxed);
+    let arena_usage = Usage::now().since(before);
+
+    println!("retained memory");
+    println!(
+        "  boxed: {:>10} bytes in {:>7} allocations (plus the source)",
+        boxed_usage.bytes, boxed_usage.allocations
+    );
+    println!(
+        "  arena: {:>10} bytes in {:>7} allocations",
+        arena_usage.bytes, arena_usage.allocations
+    );
+
+    println!("\nparse");
+    let parse = time(iterations, || parse_module(&source).unwrap());
+    let lower = time(iterations, || {
+        let mut ast = Ast::new();
+        ast.lower_module(&boxed);
+        ast
+    });
+    println!("  boxed:                  {parse:?}");
+    println!("  arena (parse + lower):  {:?}", parse + lower);
+    println!("  lowering alone:         {lower:?}");
+
+    println!("\ntraverse");
+    let boxed_traverse = time(iterations, || {
+        boxed
+            .defs()
+            .defs
+            .iter()
+            .map(|def| count_boxed(&def.expr))
+            .sum::<usize>()
+    });
+    let arena_traverse = time(iterations, || {
+        ast.defs(module.defs)
+            .iter()
+            .map(|def| count_arena(&ast, def.expr))
+            .sum::<usize>()
+    });
+    println!("  boxed: {boxed_traverse:?}");
+    println!("  arena: {arena_traverse:?}");
+
+    println!("\ndrop");
+    let mut boxed_drop = Duration::ZERO;
+    let mut arena_drop = Duration::ZERO;
+    for _ in 0..iterations {
+        let boxed = parse_module(&source).unwrap();
+        let mut ast = Ast::new();
+        ast.lower_module(&boxed);
+
+        let start = Instant::now();
+        drop(boxed);
+        boxed_drop += start.elapsed();
+
+        let start = Instant::now();
+        drop(ast);
+        arena_drop += start.elapsed();
+    }
+    println!("  boxed: {:?}", boxed_drop / iterations);
+    println!("  arena: {:?}", arena_drop / iterations);
+}
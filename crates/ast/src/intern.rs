@@ -0,0 +1,83 @@
+use std::collections::HashMap;
+
+/// An interned string. Compare `Symbol`s instead of the strings themselves,
+/// and resolve them with the [`Interner`] that created them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Symbol(u32);
+
+impl Symbol {
+    /// Returns the index of the symbol in its interner.
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+/// Deduplicates strings, handing out a [`Symbol`] for each distinct one.
+#[derive(Debug, Default)]
+pub struct Interner {
+    symbols: HashMap<Box<str>, Symbol>,
+    strings: Vec<Box<str>>,
+}
+
+impl Interner {
+    /// Creates a new, empty `Interner`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the symbol for `string`, interning it if it hasn't been seen
+    /// before.
+    pub fn intern(&mut self, string: &str) -> Symbol {
+        if let Some(&symbol) = self.symbols.get(string) {
+            return symbol;
+        }
+
+        let symbol = Symbol(
+            self.strings
+                .len()
+                .try_into()
+                .expect("interned more than u32::MAX strings"),
+        );
+        self.strings.push(string.into());
+        self.symbols.insert(string.into(), symbol);
+        symbol
+    }
+
+    /// Returns the symbol for `string` if it has been interned.
+    pub fn get(&self, string: &str) -> Option<Symbol> {
+        self.symbols.get(string).copied()
+    }
+
+    /// Returns the string that `symbol` was interned from.
+    pub fn resolve(&self, symbol: Symbol) -> &str {
+        &self.strings[symbol.index()]
+    }
+
+    /// Returns the number of distinct strings interned.
+    pub fn len(&self) -> usize {
+        self.strings.len()
+    }
+
+    /// Returns whether no strings have been interned.
+    pub fn is_empty(&self) -> bool {
+        self.strings.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Interner;
+
+    #[test]
+    fn intern_dedups() {
+        let mut interner = Interner::new();
+        let x = interner.intern("x");
+        let y = interner.intern("y");
+
+        assert_ne!(x, y);
+        assert_eq!(interner.intern("x"), x);
+        assert_eq!(interner.resolve(y), "y");
+        assert_eq!(interner.get("z"), None);
+        assert_eq!(interner.len(), 2);
+    }
+}
@@ -0,0 +1,669 @@
+//! An owned AST that doesn't borrow from the source.
+//!
+//! Identifiers are interned as [`Symbol`]s and nodes live in an [`Ast`]
+//! arena, referring to each other by index, so a whole module is a handful of
+//! allocations and can be kept around after the source is gone.
+
+use std::{fmt, hash::Hash, marker::PhantomData, ops::Range};
+
+use ria_lexer::Spanned;
+use ria_parser::{
+    data as borrowed_data, def as borrowed_def, expr as borrowed, module as borrowed_module,
+    pattern as borrowed_pattern,
+};
+
+pub use self::intern::{Interner, Symbol};
+
+mod intern;
+
+/// A compact span of source text.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Span {
+    pub start: u32,
+    pub end: u32,
+}
+
+impl From<Range<usize>> for Span {
+    fn from(range: Range<usize>) -> Self {
+        Self {
+            start: range.start as u32,
+            end: range.end as u32,
+        }
+    }
+}
+
+impl From<Span> for Range<usize> {
+    fn from(span: Span) -> Self {
+        span.start as usize..span.end as usize
+    }
+}
+
+/// An interned identifier and where it appears.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Ident {
+    pub symbol: Symbol,
+    pub span: Span,
+}
+
+/// The index of an [`Expr`] in an [`Ast`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ExprId(u32);
+
+/// A run of consecutive nodes of type `T` in an [`Ast`].
+pub struct NodeRange<T> {
+    start: u32,
+    len: u32,
+    node: PhantomData<fn() -> T>,
+}
+
+/// A run of consecutive [`Def`]s in an [`Ast`].
+pub type DefRange = NodeRange<Def>;
+
+impl<T> NodeRange<T> {
+    fn new(start: usize, len: usize) -> Self {
+        Self {
+            start: start as u32,
+            len: len as u32,
+            node: PhantomData,
+        }
+    }
+
+    /// Returns the number of nodes in the range.
+    pub fn len(self) -> usize {
+        self.len as usize
+    }
+
+    /// Returns whether the range holds no nodes.
+    pub fn is_empty(self) -> bool {
+        self.len == 0
+    }
+
+    fn indices(self) -> Range<usize> {
+        self.start as usize..(self.start + self.len) as usize
+    }
+}
+
+// derived impls would require `T` to implement each trait too
+
+impl<T> Clone for NodeRange<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for NodeRange<T> {}
+
+impl<T> fmt::Debug for NodeRange<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NodeRange")
+            .field("start", &self.start)
+            .field("len", &self.len)
+            .finish()
+    }
+}
+
+impl<T> PartialEq for NodeRange<T> {
+    fn eq(&self, other: &Self) -> bool {
+        (self.start, self.len) == (other.start, other.len)
+    }
+}
+
+impl<T> Eq for NodeRange<T> {}
+
+impl<T> Hash for NodeRange<T> {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        (self.start, self.len).hash(state);
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Expr {
+    Variable(Ident),
+    Qualified(Qualified),
+    Int(Int),
+    Lambda(Lambda),
+    Block(Block),
+    Call(Call),
+    Match(Match),
+    Tuple(Tuple),
+    Record(Record),
+    Access(Access),
+}
+
+/// A reference to a def of an imported module, like `other.a`, or to a field
+/// of a variable that isn't a module.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Qualified {
+    pub module: Ident,
+    pub name: Ident,
+}
+
+/// An integer literal.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Int {
+    pub value: i64,
+    pub span: Span,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Lambda {
+    pub param: Ident,
+    pub body: ExprId,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Block {
+    pub types: NodeRange<TypeDef>,
+    pub defs: DefRange,
+    pub expr: Option<ExprId>,
+    /// The span of the whole block, including the parentheses.
+    pub span: Span,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Call {
+    pub func: ExprId,
+    pub arg: ExprId,
+    /// The span of the whole call, including any parentheses around the
+    /// function or the argument.
+    pub span: Span,
+}
+
+/// A `match` expression.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Match {
+    pub scrutinee: ExprId,
+    pub arms: NodeRange<Arm>,
+    /// The span of the whole expression, from `match` to the end of the last
+    /// arm.
+    pub span: Span,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Arm {
+    pub pattern: Pattern,
+    pub body: ExprId,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Pattern {
+    Wildcard(Span),
+    Variable(Ident),
+    Int(Int),
+    Constructor {
+        name: Ident,
+        args: NodeRange<Pattern>,
+        span: Span,
+    },
+}
+
+/// A tuple, like `(a, b)`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Tuple {
+    pub items: NodeRange<ExprId>,
+    /// The span of the whole tuple, including the parentheses.
+    pub span: Span,
+}
+
+/// A record literal, like `{ a = 1 }`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Record {
+    pub fields: NodeRange<RecordField>,
+    /// The span of the whole record, including the braces.
+    pub span: Span,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RecordField {
+    pub name: Ident,
+    pub value: ExprId,
+}
+
+/// A field access, like `r.a` or `t.0`. Accesses of a variable with a name
+/// are [`Qualified`] instead.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Access {
+    pub expr: ExprId,
+    pub field: Field,
+    /// The span of the field, after the dot.
+    pub field_span: Span,
+    /// The span of the whole access, including any parentheses around the
+    /// expression.
+    pub span: Span,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Field {
+    Name(Symbol),
+    Index(u32),
+}
+
+/// A `type` declaration.
+///
+/// The types of the constructors' fields aren't kept, since nothing checks
+/// them yet.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TypeDef {
+    pub name: Ident,
+    pub constructors: NodeRange<Constructor>,
+    pub span: Span,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Constructor {
+    pub name: Ident,
+    /// The number of fields.
+    pub arity: u32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Def {
+    /// The identifier that is being assigned to.
+    pub ident: Ident,
+    /// The value that is assigned to `ident`.
+    pub expr: ExprId,
+}
+
+/// A module - a file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Module {
+    /// The top-level type declarations in the file.
+    pub types: NodeRange<TypeDef>,
+    /// The top-level definitions in the file.
+    pub defs: DefRange,
+}
+
+/// The arena that owns every node and identifier of one or more modules.
+#[derive(Debug, Default)]
+pub struct Ast {
+    pub interner: Interner,
+    exprs: Vec<Expr>,
+    defs: Vec<Def>,
+    arms: Vec<Arm>,
+    patterns: Vec<Pattern>,
+    types: Vec<TypeDef>,
+    constructors: Vec<Constructor>,
+    items: Vec<ExprId>,
+    fields: Vec<RecordField>,
+}
+
+impl Ast {
+    /// Creates a new, empty `Ast`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the expression at `id`.
+    pub fn expr(&self, id: ExprId) -> &Expr {
+        &self.exprs[id.0 as usize]
+    }
+
+    /// Returns the defs in `range`.
+    pub fn defs(&self, range: DefRange) -> &[Def] {
+        &self.defs[range.indices()]
+    }
+
+    /// Returns the arms in `range`.
+    pub fn arms(&self, range: NodeRange<Arm>) -> &[Arm] {
+        &self.arms[range.indices()]
+    }
+
+    /// Returns the patterns in `range`.
+    pub fn patterns(&self, range: NodeRange<Pattern>) -> &[Pattern] {
+        &self.patterns[range.indices()]
+    }
+
+    /// Returns the type declarations in `range`.
+    pub fn types(&self, range: NodeRange<TypeDef>) -> &[TypeDef] {
+        &self.types[range.indices()]
+    }
+
+    /// Returns the constructors in `range`.
+    pub fn constructors(&self, range: NodeRange<Constructor>) -> &[Constructor] {
+        &self.constructors[range.indices()]
+    }
+
+    /// Returns the tuple items in `range`.
+    pub fn items(&self, range: NodeRange<ExprId>) -> &[ExprId] {
+        &self.items[range.indices()]
+    }
+
+    /// Returns the record fields in `range`.
+    pub fn fields(&self, range: NodeRange<RecordField>) -> &[RecordField] {
+        &self.fields[range.indices()]
+    }
+
+    /// Returns the string an identifier was interned from.
+    pub fn name(&self, ident: Ident) -> &str {
+        self.interner.resolve(ident.symbol)
+    }
+
+    /// Returns the number of expressions in the arena.
+    pub fn expr_count(&self) -> usize {
+        self.exprs.len()
+    }
+
+    /// Adds `expr` to the arena.
+    pub fn alloc(&mut self, expr: Expr) -> ExprId {
+        let id = ExprId(
+            self.exprs
+                .len()
+                .try_into()
+                .expect("allocated more than u32::MAX expressions"),
+        );
+        self.exprs.push(expr);
+        id
+    }
+
+    /// Returns the span of source text covered by the expression at `id`.
+    pub fn span(&self, id: ExprId) -> Span {
+        match self.expr(id) {
+            Expr::Variable(ident) => ident.span,
+            Expr::Qualified(qualified) => Span {
+                start: qualified.module.span.start,
+                end: qualified.name.span.end,
+            },
+            Expr::Int(int) => int.span,
+            Expr::Lambda(lambda) => Span {
+                start: lambda.param.span.start,
+                end: self.span(lambda.body).end,
+            },
+            Expr::Block(block) => block.span,
+            Expr::Call(call) => call.span,
+            Expr::Match(matching) => matching.span,
+            Expr::Tuple(tuple) => tuple.span,
+            Expr::Record(record) => record.span,
+            Expr::Access(access) => access.span,
+        }
+    }
+
+    fn ident(&mut self, ident: &Spanned<&str>) -> Ident {
+        Ident {
+            symbol: self.interner.intern(ident.inner()),
+            span: ident.1.clone().into(),
+        }
+    }
+
+    /// Copies a parsed module into the arena.
+    pub fn lower_module(&mut self, module: &borrowed_module::Module) -> Module {
+        Module {
+            types: self.lower_types(&module.defs().types),
+            defs: self.lower_defs(module.defs()),
+        }
+    }
+
+    /// Copies parsed type declarations into the arena.
+    pub fn lower_types(&mut self, types: &[borrowed_data::TypeDef]) -> NodeRange<TypeDef> {
+        let lowered: Vec<_> = types
+            .iter()
+            .map(|type_def| {
+                let constructors: Vec<_> = type_def
+                    .constructors
+                    .iter()
+                    .map(|constructor| Constructor {
+                        name: self.ident(&constructor.name),
+                        arity: constructor.arity() as u32,
+                    })
+                    .collect();
+                let range = NodeRange::new(self.constructors.len(), constructors.len());
+                self.constructors.extend(constructors);
+                TypeDef {
+                    name: self.ident(&type_def.name),
+                    constructors: range,
+                    span: type_def.span.clone().into(),
+                }
+            })
+            .collect();
+
+        let range = NodeRange::new(self.types.len(), lowered.len());
+        self.types.extend(lowered);
+        range
+    }
+
+    /// Copies a parsed pattern into the arena, after the arguments of any
+    /// constructor in it.
+    pub fn lower_pattern(&mut self, pattern: &borrowed_pattern::Pattern) -> Pattern {
+        match pattern {
+            borrowed_pattern::Pattern::Wildcard(span) => Pattern::Wildcard(span.clone().into()),
+            borrowed_pattern::Pattern::Variable(name) => Pattern::Variable(self.ident(name)),
+            borrowed_pattern::Pattern::Int(Spanned(value, span)) => Pattern::Int(Int {
+                value: *value,
+                span: span.clone().into(),
+            }),
+            borrowed_pattern::Pattern::Constructor(constructor) => {
+                let args: Vec<_> = constructor
+                    .args
+                    .iter()
+                    .map(|arg| self.lower_pattern(arg))
+                    .collect();
+                let range = NodeRange::new(self.patterns.len(), args.len());
+                self.patterns.extend(args);
+                Pattern::Constructor {
+                    name: self.ident(&constructor.name),
+                    args: range,
+                    span: constructor.span.clone().into(),
+                }
+            }
+        }
+    }
+
+    /// Copies a parsed def list into the arena, keeping the defs consecutive.
+    pub fn lower_defs(&mut self, defs: &borrowed_def::DefList) -> DefRange {
+        // lowering the expressions may add the defs of nested blocks, so
+        // only add these once they're all lowered
+        let lowered: Vec<_> = defs
+            .defs
+            .iter()
+            .map(|def| Def {
+                ident: self.ident(&def.ident),
+                expr: self.lower_expr(&def.expr),
+            })
+            .collect();
+
+        let range = DefRange::new(self.defs.len(), lowered.len());
+        self.defs.extend(lowered);
+        range
+    }
+
+    /// Copies a parsed expression into the arena.
+    pub fn lower_expr(&mut self, expr: &borrowed::Expr) -> ExprId {
+        let expr = match expr {
+            borrowed::Expr::Variable(ident) => Expr::Variable(self.ident(ident)),
+            borrowed::Expr::Qualified(qualified) => Expr::Qualified(Qualified {
+                module: self.ident(&qualified.module),
+                name: self.ident(&qualified.name),
+            }),
+            borrowed::Expr::Int(Spanned(value, span)) => Expr::Int(Int {
+                value: *value,
+                span: span.clone().into(),
+            }),
+            borrowed::Expr::Lambda(lambda) => Expr::Lambda(Lambda {
+                param: self.ident(&lambda.param),
+                body: self.lower_expr(&lambda.body),
+            }),
+            borrowed::Expr::Block(block) => Expr::Block(Block {
+                types: self.lower_types(&block.defs.types),
+                defs: self.lower_defs(&block.defs),
+                expr: block.expr.as_ref().map(|expr| self.lower_expr(expr)),
+                span: block.span.clone().into(),
+            }),
+            borrowed::Expr::Call(call) => Expr::Call(Call {
+                func: self.lower_expr(&call.func),
+                arg: self.lower_expr(&call.arg),
+                span: call.span.clone().into(),
+            }),
+            borrowed::Expr::Match(matching) => {
+                let scrutinee = self.lower_expr(&matching.scrutinee);
+                let arms: Vec<_> = matching
+                    .arms
+                    .iter()
+                    .map(|arm| Arm {
+                        pattern: self.lower_pattern(&arm.pattern),
+                        body: self.lower_expr(&arm.body),
+                    })
+                    .collect();
+                let range = NodeRange::new(self.arms.len(), arms.len());
+                self.arms.extend(arms);
+                Expr::Match(Match {
+                    scrutinee,
+                    arms: range,
+                    span: matching.span.clone().into(),
+                })
+            }
+            borrowed::Expr::Tuple(tuple) => {
+                let items: Vec<_> = tuple
+                    .items
+                    .iter()
+                    .map(|item| self.lower_expr(item))
+                    .collect();
+                let range = NodeRange::new(self.items.len(), items.len());
+                self.items.extend(items);
+                Expr::Tuple(Tuple {
+                    items: range,
+                    span: tuple.span.clone().into(),
+                })
+            }
+            borrowed::Expr::Record(record) => {
+                let fields: Vec<_> = record
+                    .fields
+                    .iter()
+                    .map(|field| RecordField {
+                        name: self.ident(&field.name),
+                        value: self.lower_expr(&field.value),
+                    })
+                    .collect();
+                let range = NodeRange::new(self.fields.len(), fields.len());
+                self.fields.extend(fields);
+                Expr::Record(Record {
+                    fields: range,
+                    span: record.span.clone().into(),
+                })
+            }
+            borrowed::Expr::Access(access) => Expr::Access(Access {
+                expr: self.lower_expr(&access.expr),
+                field: match access.field.0 {
+                    borrowed::Field::Name(name) => Field::Name(self.interner.intern(name)),
+                    borrowed::Field::Index(index) => Field::Index(index as u32),
+                },
+                field_span: access.field.1.clone().into(),
+                span: access.span.clone().into(),
+            }),
+        };
+        self.alloc(expr)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::ops::Range;
+
+    use ria_parser::parse_module;
+
+    use super::{Ast, Expr, Field, Pattern, Span};
+
+    #[test]
+    fn lower_module() {
+        let source = "id = \\x -> x\napply = (f = id\n  f id)";
+        let borrowed = parse_module(source).unwrap();
+
+        let mut ast = Ast::new();
+        let module = ast.lower_module(&borrowed);
+        drop(borrowed);
+
+        let defs = ast.defs(module.defs);
+        assert_eq!(defs.len(), 2);
+        assert_eq!(ast.name(defs[0].ident), "id");
+
+        let Expr::Lambda(lambda) = ast.expr(defs[0].expr) else {
+            panic!("expected a lambda");
+        };
+        let Expr::Variable(body) = ast.expr(lambda.body) else {
+            panic!("expected a variable");
+        };
+        // both `x`s are the same symbol
+        assert_eq!(lambda.param.symbol, body.symbol);
+        assert_eq!(body.span, Span { start: 11, end: 12 });
+
+        let Expr::Block(block) = ast.expr(defs[1].expr) else {
+            panic!("expected a block");
+        };
+        assert_eq!(ast.name(ast.defs(block.defs)[0].ident), "f");
+        assert_eq!(ast.span(block.expr.unwrap()), Span { start: 31, end: 35 });
+        assert_eq!(ast.span(defs[1].expr), Span { start: 21, end: 36 });
+
+        // `id` is only interned once
+        assert_eq!(ast.interner.get("id"), Some(defs[0].ident.symbol));
+    }
+
+    #[test]
+    fn lower_types_and_matches() {
+        let source = "type List = Nil | Cons x List\nlen = \\l -> match l with\n  | Cons _ (Cons _ Nil) -> 2\n  | _ -> 0";
+        let borrowed = parse_module(source).unwrap();
+
+        let mut ast = Ast::new();
+        let module = ast.lower_module(&borrowed);
+        drop(borrowed);
+
+        let [list] = ast.types(module.types) else {
+            panic!("expected one type");
+        };
+        let constructors = ast.constructors(list.constructors);
+        assert_eq!(ast.name(constructors[1].name), "Cons");
+        assert_eq!(constructors[1].arity, 2);
+
+        let Expr::Lambda(lambda) = ast.expr(ast.defs(module.defs)[0].expr) else {
+            panic!("expected a lambda");
+        };
+        let Expr::Match(matching) = ast.expr(lambda.body) else {
+            panic!("expected a match");
+        };
+        let arms = ast.arms(matching.arms);
+        assert_eq!(arms.len(), 2);
+        let Pattern::Constructor { args, span, .. } = arms[0].pattern else {
+            panic!("expected a constructor pattern");
+        };
+        assert_eq!(&source[Range::from(span)], "Cons _ (Cons _ Nil)");
+        let Pattern::Constructor { args, .. } = ast.patterns(args)[1] else {
+            panic!("expected a nested constructor pattern");
+        };
+        assert!(matches!(ast.patterns(args)[1], Pattern::Constructor { .. }));
+        assert!(matches!(arms[1].pattern, Pattern::Wildcard(_)));
+        assert_eq!(ast.span(lambda.body).end as usize, source.len());
+    }
+
+    #[test]
+    fn lower_tuples_and_records() {
+        let source = "main = (({ a = 1, b = (2, 3) }.b).1, r.a)";
+        let borrowed = parse_module(source).unwrap();
+
+        let mut ast = Ast::new();
+        let module = ast.lower_module(&borrowed);
+        drop(borrowed);
+
+        let Expr::Tuple(tuple) = ast.expr(ast.defs(module.defs)[0].expr) else {
+            panic!("expected a tuple");
+        };
+        let &[first, second] = ast.items(tuple.items) else {
+            panic!("expected two items");
+        };
+        assert!(matches!(ast.expr(second), Expr::Qualified(_)));
+
+        let Expr::Access(access) = ast.expr(first) else {
+            panic!("expected an access");
+        };
+        assert_eq!(access.field, Field::Index(1));
+        assert_eq!(
+            &source[Range::from(ast.span(first))],
+            "({ a = 1, b = (2, 3) }.b).1"
+        );
+        let Expr::Access(inner) = ast.expr(access.expr) else {
+            panic!("expected a nested access");
+        };
+        let Expr::Record(record) = ast.expr(inner.expr) else {
+            panic!("expected a record");
+        };
+        let fields = ast.fields(record.fields);
+        assert_eq!(inner.field, Field::Name(fields[1].name.symbol));
+        assert!(matches!(ast.expr(fields[1].value), Expr::Tuple(_)));
+    }
+}
@@ -1,29 +1,770 @@
-use std::path::PathBuf;
+use std::{
+    path::{Path, PathBuf},
+    process::ExitCode,
+    time::{Duration, Instant},
+};
 
-use clap::Parser;
+use clap::{Parser, Subcommand, ValueEnum};
 use ria_lexer::Lexer;
-use ria_parser::def::DefList;
+use ria_parser::{
+    deps::DepGraph,
+    diagnostic::Diagnostic,
+    loader::{LoadError, Loader, ModuleId},
+    parse_module,
+    resolve::{check_exports, resolve_with_imports, ModuleNames},
+};
+use ria_vm::{
+    c, compile_loaded, disassemble,
+    ir::{anf, cps, lifted},
+    js::{self, source_map},
+    ski, Evaluation, Limits, Natives, Passes, Program, Vm,
+};
 
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
 struct Args {
-    /// The source filepath
-    #[arg(name = "file")]
-    source_file: PathBuf,
+    #[command(subcommand)]
+    command: Command,
 }
 
-fn main() {
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Print the source, tokens and AST of a file
+    Parse {
+        /// The source filepath
+        #[arg(name = "file")]
+        source_file: PathBuf,
+    },
+    /// Print the dependencies between the top-level definitions of a file,
+    /// grouped into strongly connected components in dependency order
+    Deps {
+        /// The source filepath
+        #[arg(name = "file")]
+        source_file: PathBuf,
+        /// Print the graph in Graphviz DOT format
+        #[arg(long)]
+        dot: bool,
+    },
+    /// Check a file and the modules it imports for errors, and warn about
+    /// exported names that aren't defined
+    Check {
+        /// The source filepath
+        #[arg(name = "file")]
+        source_file: PathBuf,
+        #[command(flatten)]
+        program: ProgramArgs,
+    },
+    /// Compile a file to bytecode and print the value of its `main` def
+    Run {
+        /// The source filepath
+        #[arg(name = "file")]
+        source_file: PathBuf,
+        /// Evaluate arguments and definitions only when they are needed
+        #[arg(long)]
+        lazy: bool,
+        #[command(flatten)]
+        program: ProgramArgs,
+        #[command(flatten)]
+        simplify: SimplifyArgs,
+        #[command(flatten)]
+        limits: LimitArgs,
+    },
+    /// Run the `test` declarations of a file, each on a fresh VM, and report
+    /// the ones that fail
+    Test {
+        /// The source filepath
+        #[arg(name = "file")]
+        source_file: PathBuf,
+        /// Only run the tests whose name contains this
+        filter: Option<String>,
+        /// Evaluate arguments and definitions only when they are needed
+        #[arg(long)]
+        lazy: bool,
+        #[command(flatten)]
+        program: ProgramArgs,
+        #[command(flatten)]
+        simplify: SimplifyArgs,
+        /// Caps on the resources each test may use
+        #[command(flatten)]
+        limits: LimitArgs,
+    },
+    /// Print the bytecode a file compiles to
+    Disasm {
+        /// The source filepath
+        #[arg(name = "file")]
+        source_file: PathBuf,
+        /// Compile for lazy evaluation
+        #[arg(long)]
+        lazy: bool,
+        #[command(flatten)]
+        program: ProgramArgs,
+        #[command(flatten)]
+        simplify: SimplifyArgs,
+    },
+    /// Compile a file for another backend and print the result
+    ///
+    /// The backends compile the lambda calculus with integers and builtins,
+    /// like the tree-walking interpreter: files with imports, data types,
+    /// matches, tuples, records or field accesses are rejected.
+    Compile {
+        /// The source filepath
+        #[arg(name = "file")]
+        source_file: PathBuf,
+        /// What to compile the file to
+        #[arg(long, value_enum)]
+        target: Target,
+        /// Evaluate the compiled `main` def and print its value instead
+        #[arg(long)]
+        run: bool,
+        #[command(flatten)]
+        program: ProgramArgs,
+        #[command(flatten)]
+        limits: LimitArgs,
+    },
+    /// Compile a file to source code for another language's compiler
+    Build {
+        /// The source filepath
+        #[arg(name = "file")]
+        source_file: PathBuf,
+        /// The language to compile the file to
+        #[arg(long, value_enum)]
+        target: Language,
+        /// Where to write the code, instead of standard output
+        #[arg(short, long, value_name = "FILE")]
+        output: Option<PathBuf>,
+        #[command(flatten)]
+        program: ProgramArgs,
+    },
+    /// Print the intermediate representation a file lowers to
+    Ir {
+        /// The source filepath
+        #[arg(name = "file")]
+        source_file: PathBuf,
+        /// Which representation to lower the file to
+        #[arg(long, value_enum)]
+        form: Form,
+        /// Evaluate the lowered `main` def and print its value instead
+        #[arg(long)]
+        run: bool,
+        #[command(flatten)]
+        program: ProgramArgs,
+    },
+}
+
+/// The backends `ria compile` can target.
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum Target {
+    /// A term of S, K, I, B, C and Y combinators, reduced as a graph
+    Ski,
+}
+
+/// The languages `ria build` can target.
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum Language {
+    /// A C program that prints the value of `main`
+    C,
+    /// A JavaScript module that exports the exported defs, with a source map
+    Js,
+}
+
+/// The representations `ria ir` can lower to.
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum Form {
+    /// A-normal form, where every intermediate result is named
+    Anf,
+    /// Continuation-passing style, where no call returns
+    Cps,
+    /// Top-level functions called through closure records
+    Lifted,
+}
+
+/// Where a program's modules come from.
+#[derive(clap::Args, Debug)]
+struct ProgramArgs {
+    /// Don't make the definitions of the standard prelude available
+    #[arg(long)]
+    no_prelude: bool,
+    /// A directory to look for imported modules in, after the directory of
+    /// the importing file
+    #[arg(short = 'I', long = "search-path", value_name = "DIR")]
+    search_path: Vec<PathBuf>,
+}
+
+/// The simplifications to make before compiling.
+#[derive(clap::Args, Debug)]
+struct SimplifyArgs {
+    /// Simplify the program before compiling it, with each pass that isn't
+    /// turned off
+    #[arg(short = 'O', long)]
+    optimize: bool,
+    /// Don't turn `\x -> f x` into `f`
+    #[arg(long, requires = "optimize")]
+    no_eta_reduce: bool,
+    /// Don't turn lambdas that are applied right away into blocks
+    #[arg(long, requires = "optimize")]
+    no_beta_reduce: bool,
+    /// Don't inline block definitions that are used once
+    #[arg(long, requires = "optimize")]
+    no_inline: bool,
+    /// Don't remove block definitions that nothing uses
+    #[arg(long, requires = "optimize")]
+    no_dead_defs: bool,
+}
+
+impl SimplifyArgs {
+    fn passes(&self) -> Passes {
+        if !self.optimize {
+            return Passes::default();
+        }
+        Passes {
+            eta_reduce: !self.no_eta_reduce,
+            beta_reduce: !self.no_beta_reduce,
+            inline: !self.no_inline,
+            dead_defs: !self.no_dead_defs,
+        }
+    }
+}
+
+/// Caps on the resources evaluation may use.
+#[derive(clap::Args, Debug)]
+struct LimitArgs {
+    /// The number of VM instructions to run before giving up
+    #[arg(long)]
+    fuel: Option<u64>,
+    /// The number of calls that may be nested
+    #[arg(long)]
+    max_depth: Option<usize>,
+    /// The number of closures, thunks, cells and data values that may be created
+    #[arg(long)]
+    max_allocations: Option<u64>,
+    /// The number of milliseconds evaluation may take
+    #[arg(long)]
+    timeout: Option<u64>,
+}
+
+impl LimitArgs {
+    fn limits(&self) -> Limits {
+        Limits {
+            fuel: self.fuel,
+            max_depth: self.max_depth,
+            max_allocations: self.max_allocations,
+            deadline: self
+                .timeout
+                .map(|millis| Instant::now() + Duration::from_millis(millis)),
+        }
+    }
+}
+
+/// A source file read from disk.
+struct Source {
+    path: PathBuf,
+    text: String,
+}
+
+impl Source {
+    fn read(path: PathBuf) -> Result<Self, ExitCode> {
+        match std::fs::read_to_string(&path) {
+            Ok(text) => Ok(Self { path, text }),
+            Err(err) => {
+                eprintln!("error: couldn't read {}: {err}", path.display());
+                Err(ExitCode::FAILURE)
+            }
+        }
+    }
+
+    /// Prints `diagnostic` with the line and column it starts at.
+    fn report(&self, severity: &str, diagnostic: &Diagnostic) {
+        report(&self.path, &self.text, severity, diagnostic);
+    }
+}
+
+/// Prints `diagnostic` in the file at `path`, whose text is `text`, with the
+/// line and column it starts at.
+fn report(path: &Path, text: &str, severity: &str, diagnostic: &Diagnostic) {
+    let before = &text[..diagnostic.span.start];
+    let line = before.matches('\n').count() + 1;
+    let column = before.len() - before.rfind('\n').map_or(0, |i| i + 1) + 1;
+    eprintln!(
+        "{}:{line}:{column}: {severity}: {}",
+        path.display(),
+        diagnostic.message
+    );
+}
+
+fn main() -> ExitCode {
     let args = Args::parse();
 
-    let source = std::fs::read_to_string(args.source_file).unwrap();
+    let result = match args.command {
+        Command::Parse { source_file } => parse(source_file),
+        Command::Deps { source_file, dot } => deps(source_file, dot),
+        Command::Check {
+            source_file,
+            program,
+        } => check(source_file, program.search_path, !program.no_prelude),
+        Command::Run {
+            source_file,
+            lazy,
+            program,
+            simplify,
+            limits,
+        } => run(
+            source_file,
+            program.search_path,
+            evaluation(lazy),
+            !program.no_prelude,
+            simplify.passes(),
+            &limits,
+        ),
+        Command::Test {
+            source_file,
+            filter,
+            lazy,
+            program,
+            simplify,
+            limits,
+        } => test(
+            source_file,
+            filter.as_deref(),
+            program.search_path,
+            evaluation(lazy),
+            !program.no_prelude,
+            simplify.passes(),
+            &limits,
+        ),
+        Command::Disasm {
+            source_file,
+            lazy,
+            program,
+            simplify,
+        } => disasm(
+            source_file,
+            program.search_path,
+            evaluation(lazy),
+            !program.no_prelude,
+            simplify.passes(),
+        ),
+        Command::Compile {
+            source_file,
+            target,
+            run,
+            program,
+            limits,
+        } => compile(
+            source_file,
+            target,
+            run,
+            program.search_path,
+            !program.no_prelude,
+            &limits,
+        ),
+        Command::Build {
+            source_file,
+            target,
+            output,
+            program,
+        } => build(
+            source_file,
+            target,
+            output,
+            program.search_path,
+            !program.no_prelude,
+        ),
+        Command::Ir {
+            source_file,
+            form,
+            run,
+            program,
+        } => ir(
+            source_file,
+            form,
+            run,
+            program.search_path,
+            !program.no_prelude,
+        ),
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(code) => code,
+    }
+}
+
+fn parse(path: PathBuf) -> Result<(), ExitCode> {
+    let source = Source::read(path)?;
 
-    println!("source:\n```\n{}\n```\n", source);
+    println!("source:\n```\n{}\n```\n", source.text);
 
-    let tokens = Lexer::new(&source).collect::<Box<_>>();
+    let tokens = Lexer::new(&source.text).collect::<Box<_>>();
 
     println!("lexed:\n{:?}\n", tokens);
 
-    let ast = DefList::parse(&mut tokens.as_ref()).unwrap();
+    let ast = parse_module(&source.text).map_err(|err| {
+        source.report("error", &err);
+        ExitCode::FAILURE
+    })?;
 
     println!("parsed:\n{:?}\n", ast);
+    Ok(())
+}
+
+fn deps(path: PathBuf, dot: bool) -> Result<(), ExitCode> {
+    let source = Source::read(path)?;
+    let module = parse_module(&source.text).map_err(|err| {
+        source.report("error", &err);
+        ExitCode::FAILURE
+    })?;
+
+    let graph = DepGraph::new(module.defs());
+    if dot {
+        print!("{}", graph.to_dot());
+        return Ok(());
+    }
+
+    for component in graph.components() {
+        let names: Vec<_> = component.defs.iter().map(|&def| graph.names[def]).collect();
+        let note = if component.is_mutually_recursive() {
+            " (mutually recursive)"
+        } else if graph.is_recursive(&component) {
+            " (references itself)"
+        } else {
+            ""
+        };
+        println!("{}{note}", names.join(", "));
+
+        for &def in &component.defs {
+            let deps: Vec<_> = graph.edges[def]
+                .iter()
+                .map(|&dep| graph.names[dep])
+                .collect();
+            if !deps.is_empty() {
+                println!("  {} -> {}", graph.names[def], deps.join(", "));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn evaluation(lazy: bool) -> Evaluation {
+    if lazy {
+        Evaluation::Lazy
+    } else {
+        Evaluation::Strict
+    }
+}
+
+/// A file and the modules it imports, loaded from disk.
+struct Loaded<'p> {
+    /// The path the file was given by.
+    path: &'p Path,
+    loader: Loader,
+    main: ModuleId,
+}
+
+impl<'p> Loaded<'p> {
+    /// Loads the file at `path` and the modules it imports from
+    /// `search_path`, reporting any errors.
+    fn load(path: &'p Path, search_path: Vec<PathBuf>) -> Result<Self, ExitCode> {
+        let mut loader = Loader::with_search_path(search_path);
+        match loader.load(path) {
+            Ok(main) => Ok(Self { path, loader, main }),
+            Err(LoadError::Io { path, error }) => {
+                eprintln!("error: couldn't read {}: {error}", path.display());
+                Err(ExitCode::FAILURE)
+            }
+            Err(LoadError::Module {
+                path: file,
+                source,
+                diagnostic,
+            }) => {
+                let shown = match path.canonicalize() {
+                    Ok(main) if main == file => path,
+                    _ => &file,
+                };
+                report(shown, source, "error", &diagnostic);
+                Err(ExitCode::FAILURE)
+            }
+        }
+    }
+
+    /// Prints `diagnostic` in the module `id`. Diagnostics in the file itself
+    /// are reported at the path it was given by.
+    fn report(&self, id: ModuleId, severity: &str, diagnostic: &Diagnostic) {
+        let module = self.loader.module(id);
+        let path = if id == self.main {
+            self.path
+        } else {
+            &module.path
+        };
+        report(path, module.source, severity, diagnostic);
+    }
+
+    /// Prints `err`, in the module it happened in.
+    fn report_runtime_error(&self, err: ria_vm::Error) {
+        let diagnostic = Diagnostic::new(err.span, err.kind.to_string());
+        self.report(err.module.unwrap_or(self.main), "error", &diagnostic);
+    }
+
+    /// Compiles the modules, with the prelude if `prelude` is set, and
+    /// simplified with `passes`, reporting any errors.
+    fn compile(
+        &self,
+        evaluation: Evaluation,
+        prelude: bool,
+        passes: Passes,
+    ) -> Result<Program, ExitCode> {
+        let natives = Natives::new();
+        compile_loaded(
+            &self.loader,
+            self.main,
+            evaluation,
+            &natives,
+            prelude,
+            passes,
+        )
+        .map_err(|errors| {
+            for (id, err) in &errors {
+                self.report(*id, "error", err);
+            }
+            ExitCode::FAILURE
+        })
+    }
+}
+
+fn check(path: PathBuf, search_path: Vec<PathBuf>, prelude: bool) -> Result<(), ExitCode> {
+    let loaded = Loaded::load(&path, search_path)?;
+    for (id, module) in loaded.loader.modules() {
+        for warning in check_exports(&module.module) {
+            loaded.report(id, "warning", &warning);
+        }
+        // resolve errors are reported when the modules are compiled
+        let imports: Vec<_> = module
+            .imports
+            .iter()
+            .map(|&import| Some(ModuleNames::of(&loaded.loader.module(import).module)))
+            .collect();
+        for warning in resolve_with_imports(&module.module, &[], &imports).warnings {
+            loaded.report(id, "warning", &warning);
+        }
+    }
+    loaded.compile(Evaluation::Strict, prelude, Passes::default())?;
+    Ok(())
+}
+
+fn run(
+    path: PathBuf,
+    search_path: Vec<PathBuf>,
+    evaluation: Evaluation,
+    prelude: bool,
+    passes: Passes,
+    limits: &LimitArgs,
+) -> Result<(), ExitCode> {
+    let loaded = Loaded::load(&path, search_path)?;
+    let program = loaded.compile(evaluation, prelude, passes)?;
+
+    match ria_vm::run_with_limits(&program, limits.limits()) {
+        Ok(value) => {
+            println!("{}", value.display(&program));
+            Ok(())
+        }
+        Err(err) => {
+            loaded.report_runtime_error(err);
+            Err(ExitCode::FAILURE)
+        }
+    }
+}
+
+fn test(
+    path: PathBuf,
+    filter: Option<&str>,
+    search_path: Vec<PathBuf>,
+    evaluation: Evaluation,
+    prelude: bool,
+    passes: Passes,
+    limits: &LimitArgs,
+) -> Result<(), ExitCode> {
+    let loaded = Loaded::load(&path, search_path)?;
+    let program = loaded.compile(evaluation, prelude, passes)?;
+
+    let tests: Vec<_> = program
+        .tests
+        .iter()
+        .enumerate()
+        .filter(|(_, test)| filter.is_none_or(|filter| test.name.contains(filter)))
+        .collect();
+    let plural = if tests.len() == 1 { "" } else { "s" };
+    println!("running {} test{plural}", tests.len());
+    let mut failed = 0;
+    for &(index, test) in &tests {
+        // each test gets its own VM, so one can't see what another forced
+        let mut vm = Vm::with_limits(&program, limits.limits());
+        match vm.init().and_then(|()| vm.run_test(index)) {
+            Ok(_) => println!("test {:?} ... ok", test.name),
+            Err(err) => {
+                println!("test {:?} ... FAILED", test.name);
+                loaded.report_runtime_error(err);
+                failed += 1;
+            }
+        }
+    }
+
+    let result = if failed == 0 { "ok" } else { "FAILED" };
+    println!(
+        "\ntest result: {result}. {} passed; {failed} failed; {} filtered out",
+        tests.len() - failed,
+        program.tests.len() - tests.len()
+    );
+    if failed == 0 {
+        Ok(())
+    } else {
+        Err(ExitCode::FAILURE)
+    }
+}
+
+fn disasm(
+    path: PathBuf,
+    search_path: Vec<PathBuf>,
+    evaluation: Evaluation,
+    prelude: bool,
+    passes: Passes,
+) -> Result<(), ExitCode> {
+    let loaded = Loaded::load(&path, search_path)?;
+    let program = loaded.compile(evaluation, prelude, passes)?;
+    print!("{}", disassemble(&program));
+    Ok(())
+}
+
+fn compile(
+    path: PathBuf,
+    target: Target,
+    run: bool,
+    search_path: Vec<PathBuf>,
+    prelude: bool,
+    limits: &LimitArgs,
+) -> Result<(), ExitCode> {
+    let loaded = Loaded::load(&path, search_path)?;
+    // compiling to bytecode reports the errors any backend would
+    loaded.compile(Evaluation::Lazy, prelude, Passes::default())?;
+    let module = &loaded.loader.module(loaded.main).module;
+    let prelude = prelude.then(ria_vm::prelude::prelude);
+    match target {
+        Target::Ski => {
+            let term = ski::compile_module(module, prelude.as_ref()).map_err(|err| {
+                loaded.report(loaded.main, "error", &err);
+                ExitCode::FAILURE
+            })?;
+            if !run {
+                println!("{term}");
+                return Ok(());
+            }
+            // combinators have no spans to point errors at
+            let value = ski::evaluate(&term, limits.limits()).map_err(|err| {
+                eprintln!("error: {}", err.kind);
+                ExitCode::FAILURE
+            })?;
+            println!("{value}");
+        }
+    }
+    Ok(())
+}
+
+fn build(
+    path: PathBuf,
+    target: Language,
+    output: Option<PathBuf>,
+    search_path: Vec<PathBuf>,
+    prelude: bool,
+) -> Result<(), ExitCode> {
+    let loaded = Loaded::load(&path, search_path)?;
+    loaded.compile(Evaluation::Strict, prelude, Passes::default())?;
+    let main = loaded.loader.module(loaded.main);
+    let prelude = prelude.then(ria_vm::prelude::prelude);
+    let report = |(id, err)| {
+        loaded.report(id, "error", &err);
+        ExitCode::FAILURE
+    };
+    let code = match target {
+        Language::C => {
+            c::compile_loaded(&loaded.loader, loaded.main, prelude.as_ref()).map_err(report)?
+        }
+        Language::Js => {
+            let js = js::compile_loaded(&loaded.loader, loaded.main, prelude.as_ref())
+                .map_err(report)?;
+            let map = js.source_map(&path.to_string_lossy(), main.source);
+            // the map goes next to the code, or into it without a file
+            let url = match &output {
+                Some(output) => {
+                    let mut map_path = output.clone().into_os_string();
+                    map_path.push(".map");
+                    let map_path = PathBuf::from(map_path);
+                    write(&map_path, &map)?;
+                    map_path
+                        .file_name()
+                        .unwrap_or_default()
+                        .to_string_lossy()
+                        .into_owned()
+                }
+                None => format!(
+                    "data:application/json;base64,{}",
+                    source_map::base64(map.as_bytes())
+                ),
+            };
+            format!("{}//# sourceMappingURL={url}\n", js.code)
+        }
+    };
+    match output {
+        Some(output) => write(&output, &code),
+        None => {
+            print!("{code}");
+            Ok(())
+        }
+    }
+}
+
+fn write(path: &Path, contents: &str) -> Result<(), ExitCode> {
+    std::fs::write(path, contents).map_err(|err| {
+        eprintln!("error: couldn't write {}: {err}", path.display());
+        ExitCode::FAILURE
+    })
+}
+
+fn ir(
+    path: PathBuf,
+    form: Form,
+    run: bool,
+    search_path: Vec<PathBuf>,
+    prelude: bool,
+) -> Result<(), ExitCode> {
+    let loaded = Loaded::load(&path, search_path)?;
+    loaded.compile(Evaluation::Strict, prelude, Passes::default())?;
+    let prelude = prelude.then(ria_vm::prelude::prelude);
+    let program =
+        anf::lower_loaded(&loaded.loader, loaded.main, prelude.as_ref()).map_err(|(id, err)| {
+            loaded.report(id, "error", &err);
+            ExitCode::FAILURE
+        })?;
+    let result = match (form, run) {
+        (Form::Anf, false) => {
+            print!("{}", anf::print(&program));
+            return Ok(());
+        }
+        (Form::Cps, false) => {
+            print!("{}", cps::print(&cps::lower(&program)));
+            return Ok(());
+        }
+        (Form::Lifted, false) => {
+            print!("{}", lifted::print(&lifted::lift(&program)));
+            return Ok(());
+        }
+        (Form::Anf, true) => anf::evaluate(&program),
+        (Form::Cps, true) => cps::evaluate(&cps::lower(&program)),
+        (Form::Lifted, true) => lifted::evaluate(&lifted::lift(&program)),
+    };
+    // the lowered program has no spans to point errors at
+    let value = result.map_err(|err| {
+        eprintln!("error: {}", err.kind);
+        ExitCode::FAILURE
+    })?;
+    println!("{value}");
+    Ok(())
 }
@@ -0,0 +1,208 @@
+//! Snapshot tests of `.ria` programs.
+//!
+//! Each `tests/ui/<name>.ria` is lexed and parsed, then checked and run by
+//! the `ria` binary, which also runs its tests if it declares any. Its
+//! tokens, AST and outputs are compared with `<name>.stdout`, and the
+//! diagnostics it gets with `<name>.stderr`, where a missing file stands for
+//! no output. A program that fails to check isn't run.
+//!
+//! Set `RIA_BLESS=1` to write the actual outputs to the files instead. Any
+//! argument that isn't a flag runs only the tests whose name contains it.
+
+use std::{
+    fmt::Write as _,
+    fs,
+    path::Path,
+    process::{Command, ExitCode},
+};
+
+use ria_lexer::{Lexer, Spanned};
+use ria_parser::parse_module;
+
+const UI_DIR: &str = "tests/ui";
+
+fn main() -> ExitCode {
+    let bless = std::env::var_os("RIA_BLESS").is_some_and(|bless| bless != "0");
+    let filters: Vec<_> = std::env::args()
+        .skip(1)
+        .filter(|arg| !arg.starts_with('-'))
+        .collect();
+
+    let mut paths: Vec<_> = fs::read_dir(UI_DIR)
+        .expect("tests/ui should exist")
+        .map(|entry| entry.expect("tests/ui should be readable").path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "ria"))
+        .filter(|path| {
+            let path = path.to_string_lossy();
+            filters.is_empty() || filters.iter().any(|filter| path.contains(filter.as_str()))
+        })
+        .collect();
+    paths.sort();
+
+    println!("\nrunning {} ui tests", paths.len());
+    let mut failures = Vec::new();
+    for path in &paths {
+        let outcome = run_test(path, bless);
+        let status = match &outcome {
+            Ok(()) if bless => "blessed",
+            Ok(()) => "ok",
+            Err(_) => "FAILED",
+        };
+        println!("test {} ... {status}", path.display());
+        if let Err(mismatches) = outcome {
+            failures.push((path, mismatches));
+        }
+    }
+
+    for (path, mismatches) in &failures {
+        println!("\n---- {} ----", path.display());
+        for mismatch in mismatches {
+            print!("{mismatch}");
+        }
+    }
+    if !failures.is_empty() {
+        println!("\nfailures:");
+        for (path, _) in &failures {
+            println!("    {}", path.display());
+        }
+        println!("\nrerun with RIA_BLESS=1 to accept the new outputs");
+    }
+
+    let result = if failures.is_empty() { "ok" } else { "FAILED" };
+    println!(
+        "\ntest result: {result}. {} passed; {} failed\n",
+        paths.len() - failures.len(),
+        failures.len()
+    );
+    if failures.is_empty() {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
+
+/// Runs the program at `path`, returning a description of each output that
+/// doesn't match its file, or writing the outputs to the files if `bless`.
+fn run_test(path: &Path, bless: bool) -> Result<(), Vec<String>> {
+    let (stdout, stderr) = outputs(path);
+    let mut mismatches = Vec::new();
+    for (extension, actual) in [("stdout", stdout), ("stderr", stderr)] {
+        let expected_path = path.with_extension(extension);
+        if bless {
+            let written = if actual.is_empty() {
+                fs::remove_file(&expected_path).or_else(|err| match err.kind() {
+                    std::io::ErrorKind::NotFound => Ok(()),
+                    _ => Err(err),
+                })
+            } else {
+                fs::write(&expected_path, &actual)
+            };
+            written.unwrap_or_else(|err| panic!("couldn't bless {extension}: {err}"));
+            continue;
+        }
+
+        let expected = fs::read_to_string(&expected_path).unwrap_or_default();
+        if expected != actual {
+            mismatches.push(format!(
+                "{} differs:\n{}",
+                expected_path.display(),
+                diff(&expected, &actual)
+            ));
+        }
+    }
+
+    if mismatches.is_empty() {
+        Ok(())
+    } else {
+        Err(mismatches)
+    }
+}
+
+/// Returns what the stages print for the program at `path`: the dumps and
+/// program output, and the diagnostics.
+fn outputs(path: &Path) -> (String, String) {
+    let source = fs::read_to_string(path).expect("test should be readable");
+    let mut stdout = String::new();
+    let mut stderr = String::new();
+
+    stdout.push_str("tokens:\n");
+    for Spanned(token, span) in Lexer::new(&source) {
+        writeln!(stdout, "{span:?} {token:?}").unwrap();
+    }
+    let module = parse_module(&source).ok();
+    if let Some(module) = &module {
+        writeln!(stdout, "\nast:\n{module:#?}").unwrap();
+    }
+
+    // a parse error is reported by `ria check`, along with any warnings
+    let check = ria("check", path);
+    stderr.push_str(&check.stderr);
+    if check.success {
+        let run = ria("run", path);
+        writeln!(stdout, "\noutput:\n{}", run.stdout.trim_end()).unwrap();
+        stderr.push_str(&run.stderr);
+    }
+    if check.success && module.is_some_and(|module| !module.tests().is_empty()) {
+        let test = ria("test", path);
+        writeln!(stdout, "\ntests:\n{}", test.stdout.trim_end()).unwrap();
+        stderr.push_str(&test.stderr);
+    }
+    (stdout, stderr)
+}
+
+/// What a run of `ria` printed.
+struct Output {
+    success: bool,
+    stdout: String,
+    stderr: String,
+}
+
+/// Runs the `ria` subcommand `command` on the file at `path`.
+fn ria(command: &str, path: &Path) -> Output {
+    let output = Command::new(env!("CARGO_BIN_EXE_ria"))
+        .arg(command)
+        .arg(path)
+        .output()
+        .expect("ria should run");
+    Output {
+        success: output.status.success(),
+        stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+        stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+    }
+}
+
+/// Returns a line diff turning `expected` into `actual`.
+fn diff(expected: &str, actual: &str) -> String {
+    let old: Vec<_> = expected.lines().collect();
+    let new: Vec<_> = actual.lines().collect();
+
+    // common[i][j] is the length of the longest common subsequence of
+    // old[i..] and new[j..]
+    let mut common = vec![vec![0; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            common[i][j] = if old[i] == new[j] {
+                common[i + 1][j + 1] + 1
+            } else {
+                common[i + 1][j].max(common[i][j + 1])
+            };
+        }
+    }
+
+    let mut out = String::new();
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            writeln!(out, " {}", old[i]).unwrap();
+            i += 1;
+            j += 1;
+        } else if j < new.len() && (i == old.len() || common[i][j + 1] >= common[i + 1][j]) {
+            writeln!(out, "+{}", new[j]).unwrap();
+            j += 1;
+        } else {
+            writeln!(out, "-{}", old[i]).unwrap();
+            i += 1;
+        }
+    }
+    out
+}
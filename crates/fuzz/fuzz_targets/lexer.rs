@@ -0,0 +1,3 @@
+fn main() {
+    ria_fuzz::fuzz_main("lexer", ria_fuzz::lex);
+}
@@ -0,0 +1,3 @@
+fn main() {
+    ria_fuzz::fuzz_main("parser", ria_fuzz::parse);
+}
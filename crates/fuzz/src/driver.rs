@@ -0,0 +1,240 @@
+//! A mutation fuzzer that runs offline on a stable toolchain.
+//!
+//! It takes the flags of libFuzzer that make sense without coverage
+//! feedback, so the targets could move to `cargo fuzz` unchanged. Inputs are
+//! the seed corpus mutated with byte edits, splices and tokens of the
+//! language, and a crashing input is saved under `artifacts/<target>`.
+
+use std::{
+    collections::hash_map::DefaultHasher,
+    fs,
+    hash::{Hash, Hasher},
+    panic::{self, AssertUnwindSafe},
+    path::{Path, PathBuf},
+    process,
+    time::{Instant, SystemTime},
+};
+
+/// Fragments of source that random bytes are unlikely to hit.
+const DICTIONARY: &[&str] = &[
+    "\\",
+    "->",
+    "=",
+    "(",
+    ")",
+    ".",
+    "/",
+    ",",
+    "|",
+    "{",
+    "}",
+    ";",
+    "\n",
+    " ",
+    "_",
+    "match",
+    "with",
+    "type",
+    "import",
+    "export",
+    "test",
+    "\"",
+    "x",
+    "Cons",
+    "0",
+    "9223372036854775808",
+];
+
+/// Bytes to insert, mostly ones the lexer knows.
+const BYTES: &[u8] = b" \t\r\n;\\->=()./,|{}_\"axZ09\xc3\xa9\xff";
+
+/// Runs `target` on inputs mutated from the corpus of `name`, as configured
+/// by the command line, and exits with a failure once an input crashes it.
+///
+/// Arguments that are files are run once each instead, to reproduce a crash.
+/// Directories are added to the corpus.
+pub fn fuzz_main(name: &str, target: fn(&[u8])) {
+    let mut runs = 100_000;
+    let mut seed = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(1, |time| time.as_nanos() as u64);
+    let mut max_len = 256;
+    let mut inputs = Vec::new();
+    let mut dirs = Vec::new();
+    for arg in std::env::args().skip(1) {
+        let flag = |prefix| arg.strip_prefix(prefix).and_then(|n: &str| n.parse().ok());
+        if let Some(n) = flag("-runs=") {
+            runs = n;
+        } else if let Some(n) = flag("-seed=") {
+            seed = n;
+        } else if let Some(n) = flag("-max_len=") {
+            max_len = n as usize;
+        } else if arg.starts_with('-') {
+            eprintln!("unknown flag `{arg}`");
+            process::exit(2);
+        } else if Path::new(&arg).is_dir() {
+            dirs.push(PathBuf::from(arg));
+        } else {
+            inputs.push(PathBuf::from(arg));
+        }
+    }
+
+    if !inputs.is_empty() {
+        for path in &inputs {
+            let data = fs::read(path).unwrap_or_else(|err| {
+                eprintln!("couldn't read {}: {err}", path.display());
+                process::exit(2);
+            });
+            println!("Running: {}", path.display());
+            target(&data);
+        }
+        return;
+    }
+
+    let mut fuzzer = Fuzzer::new(name, seed, max_len);
+    for dir in &dirs {
+        fuzzer.load(dir);
+    }
+    println!(
+        "Fuzzing {name} with seed {seed}: {} inputs in the corpus",
+        fuzzer.corpus().len()
+    );
+    let start = Instant::now();
+    if let Some(crash) = fuzzer.run(target, runs) {
+        let path = save_crash(name, &crash);
+        eprintln!("Crash saved to {}", path.display());
+        process::exit(1);
+    }
+    println!("Done {runs} runs in {:.1?}", start.elapsed());
+}
+
+fn save_crash(name: &str, data: &[u8]) -> PathBuf {
+    let mut hasher = DefaultHasher::new();
+    data.hash(&mut hasher);
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("artifacts")
+        .join(name);
+    let path = dir.join(format!("crash-{:016x}", hasher.finish()));
+    fs::create_dir_all(&dir)
+        .and_then(|()| fs::write(&path, data))
+        .unwrap_or_else(|err| eprintln!("couldn't save the crash: {err}"));
+    path
+}
+
+/// Generates inputs for a target by mutating its corpus.
+pub struct Fuzzer {
+    corpus: Vec<Vec<u8>>,
+    rng: Rng,
+    max_len: usize,
+}
+
+impl Fuzzer {
+    /// Creates a fuzzer with the seed corpus in `corpus/<name>`, generating
+    /// inputs of at most `max_len` bytes.
+    pub fn new(name: &str, seed: u64, max_len: usize) -> Self {
+        let mut fuzzer = Self {
+            corpus: Vec::new(),
+            rng: Rng::new(seed),
+            max_len,
+        };
+        fuzzer.load(
+            &Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join("corpus")
+                .join(name),
+        );
+        fuzzer
+    }
+
+    /// Adds the files in `dir` to the corpus.
+    pub fn load(&mut self, dir: &Path) {
+        let Ok(entries) = fs::read_dir(dir) else {
+            return;
+        };
+        let mut paths: Vec<_> = entries.flatten().map(|entry| entry.path()).collect();
+        paths.sort();
+        self.corpus
+            .extend(paths.iter().filter_map(|path| fs::read(path).ok()));
+    }
+
+    pub fn corpus(&self) -> &[Vec<u8>] {
+        &self.corpus
+    }
+
+    /// Runs `target` on `runs` generated inputs, returning the first input
+    /// that makes it panic.
+    pub fn run(&mut self, target: fn(&[u8]), runs: u64) -> Option<Vec<u8>> {
+        (0..runs).find_map(|_| {
+            let input = self.generate();
+            panic::catch_unwind(AssertUnwindSafe(|| target(&input)))
+                .is_err()
+                .then_some(input)
+        })
+    }
+
+    fn generate(&mut self) -> Vec<u8> {
+        let mut data = match self.corpus.len() {
+            0 => Vec::new(),
+            len => self.corpus[self.rng.below(len)].clone(),
+        };
+        for _ in 0..=self.rng.below(4) {
+            self.mutate(&mut data);
+        }
+        data.truncate(self.max_len);
+        data
+    }
+
+    fn mutate(&mut self, data: &mut Vec<u8>) {
+        let rng = &mut self.rng;
+        match rng.below(6) {
+            0 if !data.is_empty() => {
+                let at = rng.below(data.len());
+                data[at] = BYTES[rng.below(BYTES.len())];
+            }
+            1 if !data.is_empty() => {
+                let start = rng.below(data.len());
+                let end = (start + 1 + rng.below(8)).min(data.len());
+                data.drain(start..end);
+            }
+            2 if !data.is_empty() => {
+                let start = rng.below(data.len());
+                let end = (start + 1 + rng.below(16)).min(data.len());
+                let copy = data[start..end].to_vec();
+                let at = rng.below(data.len() + 1);
+                data.splice(at..at, copy);
+            }
+            3 => {
+                let token = DICTIONARY[rng.below(DICTIONARY.len())];
+                let at = rng.below(data.len() + 1);
+                data.splice(at..at, token.bytes());
+            }
+            4 if !self.corpus.is_empty() => {
+                let other = &self.corpus[rng.below(self.corpus.len())];
+                let at = rng.below(data.len() + 1);
+                let from = rng.below(other.len() + 1);
+                data.truncate(at);
+                data.extend_from_slice(&other[from..]);
+            }
+            _ => {
+                let at = rng.below(data.len() + 1);
+                data.insert(at, BYTES[rng.below(BYTES.len())]);
+            }
+        }
+    }
+}
+
+/// A xorshift generator, which is all the randomness mutation needs.
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        Self(seed.max(1))
+    }
+
+    /// Returns a number in `0..n`, where `n` is positive.
+    fn below(&mut self, n: usize) -> usize {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 % n as u64) as usize
+    }
+}
@@ -0,0 +1,94 @@
+//! Fuzz targets for the lexer and the parser.
+//!
+//! Each target takes arbitrary bytes and panics if an invariant doesn't hold
+//! for them. The binaries in `fuzz_targets` run a target under [`fuzz_main`],
+//! a mutation driver that needs no instrumentation or nightly toolchain:
+//!
+//! ```text
+//! cargo run --release -p ria_fuzz --bin parser -- -runs=100000
+//! ```
+
+use ria_lexer::{Lexer, Spanned};
+use ria_parser::{parse_module, print::print_module};
+
+pub use self::driver::fuzz_main;
+
+mod driver;
+
+/// Lexes `data`, checking that every token span lies within the input, on
+/// character boundaries, after the span of the token before it.
+pub fn lex(data: &[u8]) {
+    let Ok(source) = std::str::from_utf8(data) else {
+        return;
+    };
+
+    let mut lexer = Lexer::new(source);
+    let mut end = 0;
+    for Spanned(token, span) in lexer.by_ref() {
+        assert!(
+            end <= span.start && span.start < span.end && span.end <= source.len(),
+            "{token:?} at {span:?} is out of order or outside {} bytes",
+            source.len()
+        );
+        assert!(
+            source.is_char_boundary(span.start) && source.is_char_boundary(span.end),
+            "{token:?} at {span:?} splits a character"
+        );
+        end = span.end;
+    }
+    assert!(
+        end <= lexer.offset() && lexer.offset() <= source.len(),
+        "lexer stopped at {} after a token ending at {end}",
+        lexer.offset()
+    );
+}
+
+/// Parses `data` as a module. An error must point within the input, and a
+/// module must print as source that parses back to the same module.
+pub fn parse(data: &[u8]) {
+    let Ok(source) = std::str::from_utf8(data) else {
+        return;
+    };
+
+    match parse_module(source) {
+        Ok(module) => {
+            let printed = print_module(&module);
+            let reparsed = parse_module(&printed)
+                .unwrap_or_else(|err| panic!("printed module should parse: {err:?}\n{printed}"));
+            assert_eq!(print_module(&reparsed), printed);
+        }
+        Err(err) => assert!(
+            err.span.start <= err.span.end && err.span.end <= source.len(),
+            "error at {:?} is outside {} bytes",
+            err.span,
+            source.len()
+        ),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{driver::Fuzzer, lex, parse};
+
+    fn fuzz(name: &str, target: fn(&[u8])) {
+        let mut fuzzer = Fuzzer::new(name, 0x5eed, 128);
+        for input in fuzzer.corpus() {
+            target(input);
+        }
+        if let Some(crash) = fuzzer.run(target, 2_000) {
+            panic!("{name} crashed on {:?}", String::from_utf8_lossy(&crash));
+        }
+    }
+
+    #[test]
+    fn fuzz_lexer() {
+        fuzz("lexer", lex);
+        lex("a\u{e9}b = 1;\n\n2x".as_bytes());
+    }
+
+    #[test]
+    fn fuzz_parser() {
+        fuzz("parser", parse);
+        parse(b"main = (1, 2).3 \xff");
+    }
+}
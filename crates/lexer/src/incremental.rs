@@ -0,0 +1,199 @@
+use std::ops::Range;
+
+use crate::{Lexer, Spanned, Token};
+
+/// A change to a source text: the bytes in `range` are replaced with `text`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextEdit<'e> {
+    pub range: Range<usize>,
+    pub text: &'e str,
+}
+
+impl<'e> TextEdit<'e> {
+    /// Creates a new `TextEdit` replacing `range` with `text`.
+    pub fn new(range: Range<usize>, text: &'e str) -> Self {
+        Self { range, text }
+    }
+
+    /// Returns `source` with the edit applied.
+    pub fn apply(&self, source: &str) -> String {
+        let mut edited = source.to_string();
+        edited.replace_range(self.range.clone(), self.text);
+        edited
+    }
+
+    /// Returns how far text after the edit moves.
+    pub fn delta(&self) -> isize {
+        self.text.len() as isize - self.range.len() as isize
+    }
+
+    /// Returns the end of the replacement text in the edited source.
+    pub fn new_end(&self) -> usize {
+        self.range.start + self.text.len()
+    }
+
+    /// Maps an offset after the edit in the original source to the edited
+    /// source.
+    pub fn shift(&self, offset: usize) -> usize {
+        offset.wrapping_add_signed(self.delta())
+    }
+}
+
+/// The tokens of a whole source text.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Lexed<'i> {
+    pub tokens: Box<[Spanned<Token<'i>>]>,
+    /// Where lexing stopped - see [`Lexer::offset`].
+    pub offset: usize,
+}
+
+impl<'i> Lexed<'i> {
+    /// Lexes all of `source`.
+    pub fn new(source: &'i str) -> Self {
+        let mut lexer = Lexer::new(source);
+        let tokens = lexer.by_ref().collect();
+        Self {
+            tokens,
+            offset: lexer.offset(),
+        }
+    }
+
+    /// Lexes `source`, the result of applying `edit` to the source these
+    /// tokens were lexed from.
+    ///
+    /// Tokens before the edit are reused as is. Lexing restarts at the edit
+    /// and stops as soon as it produces a token that also starts the same
+    /// token in the old stream, after which the old tokens are reused with
+    /// their spans shifted.
+    pub fn relex<'n>(&self, edit: &TextEdit, source: &'n str) -> Lexed<'n> {
+        // tokens that end before the edit can't be affected by it, though a
+        // token ending right at the edit may continue into the inserted text.
+        // the end of a newline isn't somewhere the lexer could have stopped,
+        // since it eats the whole run of newlines, so restart at the start of
+        // the last unaffected token instead
+        let keep = self
+            .tokens
+            .partition_point(|tok| tok.end() < edit.range.start)
+            .saturating_sub(1);
+        let restart = self
+            .tokens
+            .get(keep)
+            .map_or(0, Spanned::start)
+            .min(edit.range.start);
+
+        let mut tokens: Vec<_> = self.tokens[..keep]
+            .iter()
+            .map(|Spanned(tok, span)| Spanned(tok.rebase(source, span.clone()), span.clone()))
+            .collect();
+
+        let mut lexer = Lexer::new(&source[restart..]);
+        for Spanned(tok, span) in lexer.by_ref() {
+            let span = span.start + restart..span.end + restart;
+
+            if span.start >= edit.new_end() {
+                let old_start = span.start.wrapping_add_signed(-edit.delta());
+                let old = self.tokens[keep..]
+                    .binary_search_by_key(&old_start, Spanned::start)
+                    .map(|index| keep + index);
+                if let Ok(index) = old {
+                    let Spanned(old_tok, old_span) = &self.tokens[index];
+                    if old_span.len() == span.len() && old_tok.same_kind(&tok) {
+                        // resynchronized, so the rest of the stream is unchanged
+                        tokens.extend(self.tokens[index..].iter().map(|Spanned(tok, span)| {
+                            let span = edit.shift(span.start)..edit.shift(span.end);
+                            Spanned(tok.rebase(source, span.clone()), span)
+                        }));
+                        return Lexed {
+                            tokens: tokens.into_boxed_slice(),
+                            offset: edit.shift(self.offset),
+                        };
+                    }
+                }
+            }
+
+            tokens.push(Spanned(tok, span));
+        }
+
+        Lexed {
+            tokens: tokens.into_boxed_slice(),
+            offset: restart + lexer.offset(),
+        }
+    }
+}
+
+impl Token<'_> {
+    /// Returns the same token, borrowing its text from `source` at `span`.
+    fn rebase<'n>(&self, source: &'n str, span: Range<usize>) -> Token<'n> {
+        match self {
+            Token::NewLine => Token::NewLine,
+            Token::Semi => Token::Semi,
+            Token::Symbol(sym) => Token::Symbol(*sym),
+            Token::Ident(_) => Token::Ident(&source[span]),
+            Token::Int(_) => Token::Int(&source[span]),
+            Token::Str(_) => Token::Str(&source[span.start + 1..span.end - 1]),
+        }
+    }
+
+    /// Returns whether both tokens are the same, regardless of where their
+    /// text is borrowed from.
+    fn same_kind(&self, other: &Token) -> bool {
+        match (self, other) {
+            (Token::NewLine, Token::NewLine) | (Token::Semi, Token::Semi) => true,
+            (Token::Symbol(a), Token::Symbol(b)) => a == b,
+            (Token::Ident(a), Token::Ident(b))
+            | (Token::Int(a), Token::Int(b))
+            | (Token::Str(a), Token::Str(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Lexed, TextEdit};
+
+    const SOURCE: &str =
+        "id = \\x -> x\n\nconst = \\x -> \\y -> x;  flip = \\f ->\n  \\a -> \\b -> f b a\n";
+
+    /// Applies `edit` to `SOURCE` and checks that relexing gives the same
+    /// tokens as lexing from scratch.
+    fn check(edit: TextEdit) {
+        let old = Lexed::new(SOURCE);
+        let source = edit.apply(SOURCE);
+        assert_eq!(
+            old.relex(&edit, &source),
+            Lexed::new(&source),
+            "relexing after {edit:?} gave different tokens"
+        );
+    }
+
+    #[test]
+    fn relex_simple_edits() {
+        // rename
+        check(TextEdit::new(0..2, "identity"));
+        // grow an identifier from the end
+        check(TextEdit::new(2..2, "_"));
+        // join two identifiers
+        check(TextEdit::new(11..12, "xx"));
+        // delete a newline
+        check(TextEdit::new(12..14, ""));
+        // unlexable character
+        check(TextEdit::new(5..5, "+"));
+        // append
+        check(TextEdit::new(SOURCE.len()..SOURCE.len(), "x = y"));
+    }
+
+    #[test]
+    fn relex_every_edit() {
+        let replacements = [
+            "", " ", "\n", ";", "x", "\\", "->", "=", "(", "-", "+", "\"",
+        ];
+        for start in 0..=SOURCE.len() {
+            for end in start..=(start + 3).min(SOURCE.len()) {
+                for text in replacements {
+                    check(TextEdit::new(start..end, text));
+                }
+            }
+        }
+    }
+}
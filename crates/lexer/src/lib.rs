@@ -2,13 +2,17 @@ use std::{borrow::Borrow, ops::Range};
 
 use winnow::{
     ascii::newline,
-    combinator::{alt, repeat, trace},
+    combinator::{alt, delimited, not, repeat, terminated, trace},
     error::StrContextValue,
     stream::{AsChar, Compare, Location, Stream, StreamIsPartial},
-    token::{one_of, take_while},
+    token::{one_of, take_till, take_while},
     LocatingSlice, ModalResult, Parser,
 };
 
+pub use self::incremental::{Lexed, TextEdit};
+
+mod incremental;
+
 #[derive(Debug, Clone)]
 pub struct Lexer<'i> {
     remaining: LocatingSlice<&'i str>,
@@ -20,6 +24,15 @@ impl<'i> Lexer<'i> {
             remaining: LocatingSlice::new(input),
         }
     }
+
+    /// Returns the byte offset of the first character that hasn't been lexed
+    /// yet.
+    ///
+    /// Once the lexer is exhausted, this is either the length of the input or
+    /// the position of a character that couldn't be lexed.
+    pub fn offset(&self) -> usize {
+        self.remaining.location()
+    }
 }
 
 impl<'i> Lexer<'i> {
@@ -129,6 +142,11 @@ pub enum Token<'i> {
     Semi,
     Symbol(Symbol),
     Ident(&'i str),
+    /// The digits of a decimal integer literal.
+    Int(&'i str),
+    /// The text between the quotes of a string literal, which can't contain
+    /// quotes or newlines.
+    Str(&'i str),
 }
 
 impl<'i> Token<'i> {
@@ -150,6 +168,24 @@ impl<'i> Token<'i> {
             .parse_next(input)
     }
 
+    fn parse_int<S>(input: &mut S) -> ModalResult<Self>
+    where
+        S: Stream<Token = char, Slice = &'i str> + StreamIsPartial,
+    {
+        trace("ria::parse_int", parse_int)
+            .map(Token::Int)
+            .parse_next(input)
+    }
+
+    fn parse_str<S>(input: &mut S) -> ModalResult<Self>
+    where
+        S: Stream<Token = char, Slice = &'i str> + StreamIsPartial + Compare<char>,
+    {
+        trace("ria::parse_str", parse_str)
+            .map(Token::Str)
+            .parse_next(input)
+    }
+
     fn parse_semi<S>(input: &mut S) -> ModalResult<Self>
     where
         S: Stream + StreamIsPartial + Compare<char>,
@@ -182,6 +218,8 @@ impl<'i> Token<'i> {
             alt((
                 Self::parse_kw,
                 Self::parse_ident,
+                Self::parse_int,
+                Self::parse_str,
                 Self::parse_newline,
                 Self::parse_semi,
             )),
@@ -216,6 +254,27 @@ where
     take_ident().take().parse_next(input)
 }
 
+/// Parses the digits of an integer literal, which can't run into an
+/// identifier: `1a` is neither.
+fn parse_int<'i, S>(input: &mut S) -> ModalResult<&'i str>
+where
+    S: Stream<Token = char, Slice = &'i str> + StreamIsPartial,
+{
+    terminated(
+        take_while(1.., AsChar::is_dec_digit),
+        not(one_of(is_ident_first)),
+    )
+    .parse_next(input)
+}
+
+/// Parses a string literal, returning the text between its quotes.
+fn parse_str<'i, S>(input: &mut S) -> ModalResult<&'i str>
+where
+    S: Stream<Token = char, Slice = &'i str> + StreamIsPartial + Compare<char>,
+{
+    delimited('"', take_till(0.., ['"', '\n']), '"').parse_next(input)
+}
+
 macro_rules! symbols {
     ($str:literal => $sym:ident $(, $strs:literal => $syms:ident)*,) => {
         #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
@@ -256,13 +315,19 @@ symbols! {
     "="  => Define,
     "("  => OpenParen,
     ")"  => CloseParen,
+    "."  => Dot,
+    "/"  => Slash,
+    ","  => Comma,
+    "|"  => Pipe,
+    "{"  => OpenBrace,
+    "}"  => CloseBrace,
 }
 
 #[cfg(test)]
 mod test {
     use winnow::Parser;
 
-    use super::{parse_ident, Lexer, Symbol, Token};
+    use super::{parse_ident, parse_int, parse_str, Lexer, Spanned, Symbol, Token};
 
     #[test]
     fn lex_tokens() {
@@ -320,6 +385,32 @@ mod test {
         works("foo_bar");
     }
 
+    #[test]
+    fn parse_ints() {
+        assert_eq!(parse_int.parse_peek("42 x"), Ok((" x", "42")));
+        assert_eq!(parse_int.parse_peek("007"), Ok(("", "007")));
+        assert!(parse_int.parse_peek("1a").is_err());
+        assert!(parse_int.parse_peek("a1").is_err());
+
+        let tokens: Box<_> = Lexer::new("add 1 23").map(|tok| tok.0).collect();
+        assert_eq!(
+            tokens.as_ref(),
+            [Token::Ident("add"), Token::Int("1"), Token::Int("23")]
+        );
+    }
+
+    #[test]
+    fn parse_strs() {
+        assert_eq!(parse_str.parse_peek("\"a b\" c"), Ok((" c", "a b")));
+        assert_eq!(parse_str.parse_peek("\"\""), Ok(("", "")));
+        assert!(parse_str.parse_peek("\"a\nb\"").is_err());
+
+        let mut lexer = Lexer::new("test \"it\" = 1 \"open");
+        let tokens: Box<_> = lexer.by_ref().collect();
+        assert_eq!(tokens[1], Spanned(Token::Str("it"), 5..9));
+        assert_eq!(lexer.offset(), 14);
+    }
+
     #[test]
     fn parse_symbols() {
         // lambda
@@ -338,6 +429,33 @@ mod test {
             .is_ok_and(|(_, x)| x == Symbol::Define));
     }
 
+    #[test]
+    fn lex_imports() {
+        let tokens: Box<_> = Lexer::new("import a/b (c, d)\ne = b.c")
+            .map(|tok| tok.0)
+            .collect();
+        assert_eq!(
+            tokens.as_ref(),
+            [
+                Token::Ident("import"),
+                Token::Ident("a"),
+                Token::Symbol(Symbol::Slash),
+                Token::Ident("b"),
+                Token::Symbol(Symbol::OpenParen),
+                Token::Ident("c"),
+                Token::Symbol(Symbol::Comma),
+                Token::Ident("d"),
+                Token::Symbol(Symbol::CloseParen),
+                Token::NewLine,
+                Token::Ident("e"),
+                Token::Symbol(Symbol::Define),
+                Token::Ident("b"),
+                Token::Symbol(Symbol::Dot),
+                Token::Ident("c"),
+            ]
+        );
+    }
+
     #[test]
     fn parse_newlines() {
         let tokens: Box<_> = Lexer::new("x\n  y").collect();
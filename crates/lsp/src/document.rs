@@ -0,0 +1,154 @@
+use lsp_types::{
+    Diagnostic, DiagnosticSeverity, DocumentSymbol, Hover, HoverContents, MarkupContent,
+    MarkupKind, Position, Range, SymbolKind,
+};
+use ria_parser::{
+    expr::Expr,
+    module::Module,
+    parse_module,
+    resolve::{check_exports, resolve_with_builtins, Resolution},
+};
+
+use crate::line_index::LineIndex;
+
+/// Resolves `module`, where unbound names may refer to the builtins and the
+/// prelude.
+fn resolve(module: &Module) -> Resolution {
+    let builtins = ria_vm::builtins();
+    let mut names = builtins.names();
+    names.extend(ria_vm::prelude::names());
+    resolve_with_builtins(module, &names)
+}
+
+/// An open text document.
+#[derive(Debug)]
+pub struct Document {
+    text: String,
+    index: LineIndex,
+}
+
+impl Document {
+    /// Creates a new `Document` with the given contents.
+    pub fn new(text: String) -> Self {
+        let index = LineIndex::new(&text);
+        Self { text, index }
+    }
+
+    fn range(&self, span: std::ops::Range<usize>) -> Range {
+        self.index.range(&self.text, span)
+    }
+
+    fn offset(&self, position: Position) -> usize {
+        self.index.offset(&self.text, position)
+    }
+
+    /// Returns the parse and resolve errors in the document, and warnings
+    /// about exported names that aren't defined and unreachable match arms.
+    pub fn diagnostics(&self) -> Vec<Diagnostic> {
+        let (errors, warnings) = match parse_module(&self.text) {
+            Ok(module) => {
+                let resolution = resolve(&module);
+                let mut warnings = check_exports(&module);
+                warnings.extend(resolution.warnings);
+                (resolution.errors, warnings)
+            }
+            Err(err) => (vec![err], Vec::new()),
+        };
+
+        let errors = errors
+            .into_iter()
+            .map(|err| (DiagnosticSeverity::ERROR, err));
+        let warnings = warnings
+            .into_iter()
+            .map(|warning| (DiagnosticSeverity::WARNING, warning));
+        errors
+            .chain(warnings)
+            .map(|(severity, err)| Diagnostic {
+                range: self.range(err.span),
+                severity: Some(severity),
+                source: Some("ria".to_string()),
+                message: err.message,
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    /// Returns the range of the identifier bound by the definition or
+    /// parameter referenced at `position`.
+    pub fn definition(&self, position: Position) -> Option<Range> {
+        let module = parse_module(&self.text).ok()?;
+        let resolution = resolve(&module);
+        let binding = resolution.binding_at(self.offset(position))?;
+        Some(self.range(resolution.bindings[binding].ident.clone()))
+    }
+
+    /// Returns the ranges of every reference to the definition or parameter
+    /// at `position`, including the declaration if `include_declaration` is
+    /// set.
+    pub fn references(&self, position: Position, include_declaration: bool) -> Vec<Range> {
+        let Ok(module) = parse_module(&self.text) else {
+            return Vec::new();
+        };
+        let resolution = resolve(&module);
+        let Some(binding) = resolution.binding_at(self.offset(position)) else {
+            return Vec::new();
+        };
+
+        let declaration = include_declaration.then(|| resolution.bindings[binding].ident.clone());
+        declaration
+            .into_iter()
+            .chain(
+                resolution
+                    .references_to(binding)
+                    .map(|reference| reference.span.clone()),
+            )
+            .map(|span| self.range(span))
+            .collect()
+    }
+
+    /// Returns the source of the definition or lambda that binds the
+    /// identifier at `position`.
+    pub fn hover(&self, position: Position) -> Option<Hover> {
+        let module = parse_module(&self.text).ok()?;
+        let resolution = resolve(&module);
+        let binding = &resolution.bindings[resolution.binding_at(self.offset(position))?];
+
+        Some(Hover {
+            contents: HoverContents::Markup(MarkupContent {
+                kind: MarkupKind::Markdown,
+                value: format!("```ria\n{}\n```", &self.text[binding.source.clone()]),
+            }),
+            range: None,
+        })
+    }
+
+    /// Returns a symbol for each top-level definition.
+    pub fn symbols(&self) -> Vec<DocumentSymbol> {
+        let Ok(module) = parse_module(&self.text) else {
+            return Vec::new();
+        };
+
+        module
+            .defs()
+            .defs
+            .iter()
+            .map(|def| {
+                let kind = match def.expr {
+                    Expr::Lambda(_) => SymbolKind::FUNCTION,
+                    _ => SymbolKind::VARIABLE,
+                };
+                #[allow(deprecated)] // `deprecated` must still be initialized
+                DocumentSymbol {
+                    name: def.ident.0.to_string(),
+                    detail: None,
+                    kind,
+                    tags: None,
+                    deprecated: None,
+                    range: self.range(def.span()),
+                    selection_range: self.range(def.ident.1.clone()),
+                    children: None,
+                }
+            })
+            .collect()
+    }
+}
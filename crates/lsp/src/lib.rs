@@ -0,0 +1,435 @@
+use std::{collections::HashMap, error::Error};
+
+use lsp_server::{Connection, ErrorCode, Message, Notification, Request, Response};
+use lsp_types::{
+    notification::{
+        DidChangeTextDocument, DidCloseTextDocument, DidOpenTextDocument,
+        Notification as NotificationTrait, PublishDiagnostics,
+    },
+    request::{
+        DocumentSymbolRequest, GotoDefinition, HoverRequest, References, Request as RequestTrait,
+    },
+    DocumentSymbolParams, DocumentSymbolResponse, GotoDefinitionParams, GotoDefinitionResponse,
+    Hover, HoverParams, HoverProviderCapability, Location, OneOf, PublishDiagnosticsParams,
+    ReferenceParams, ServerCapabilities, TextDocumentSyncCapability, TextDocumentSyncKind, Url,
+};
+
+use self::document::Document;
+
+mod document;
+mod line_index;
+
+/// Returns the capabilities advertised by the server.
+pub fn capabilities() -> ServerCapabilities {
+    ServerCapabilities {
+        text_document_sync: Some(TextDocumentSyncCapability::Kind(TextDocumentSyncKind::FULL)),
+        definition_provider: Some(OneOf::Left(true)),
+        references_provider: Some(OneOf::Left(true)),
+        hover_provider: Some(HoverProviderCapability::Simple(true)),
+        document_symbol_provider: Some(OneOf::Left(true)),
+        ..Default::default()
+    }
+}
+
+/// Runs the language server on `connection` until the client asks it to
+/// exit.
+pub fn run(connection: &Connection) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let capabilities = serde_json::to_value(capabilities())?;
+    connection.initialize(capabilities)?;
+
+    let mut server = Server::default();
+
+    for message in &connection.receiver {
+        match message {
+            Message::Request(request) => {
+                if connection.handle_shutdown(&request)? {
+                    return Ok(());
+                }
+                connection
+                    .sender
+                    .send(server.handle_request(request).into())?;
+            }
+            Message::Notification(notification) => {
+                for notification in server.handle_notification(notification) {
+                    connection.sender.send(notification.into())?;
+                }
+            }
+            Message::Response(_) => {}
+        }
+    }
+
+    Ok(())
+}
+
+/// The state of the language server: every document the client has open.
+#[derive(Debug, Default)]
+struct Server {
+    documents: HashMap<Url, Document>,
+}
+
+impl Server {
+    fn handle_request(&self, request: Request) -> Response {
+        match request.method.as_str() {
+            GotoDefinition::METHOD => self.respond::<GotoDefinition>(request, Self::definition),
+            References::METHOD => self.respond::<References>(request, Self::references),
+            HoverRequest::METHOD => self.respond::<HoverRequest>(request, Self::hover),
+            DocumentSymbolRequest::METHOD => {
+                self.respond::<DocumentSymbolRequest>(request, Self::symbols)
+            }
+            _ => Response::new_err(
+                request.id,
+                ErrorCode::MethodNotFound as i32,
+                format!("unhandled method `{}`", request.method),
+            ),
+        }
+    }
+
+    /// Deserializes the parameters of `request` and responds with the result
+    /// of `handler`.
+    fn respond<R>(&self, request: Request, handler: fn(&Self, R::Params) -> R::Result) -> Response
+    where
+        R: RequestTrait,
+    {
+        match serde_json::from_value(request.params) {
+            Ok(params) => Response::new_ok(request.id, handler(self, params)),
+            Err(err) => {
+                Response::new_err(request.id, ErrorCode::InvalidParams as i32, err.to_string())
+            }
+        }
+    }
+
+    /// Handles a notification, returning any notifications to send back.
+    fn handle_notification(&mut self, notification: Notification) -> Vec<Notification> {
+        match notification.method.as_str() {
+            DidOpenTextDocument::METHOD => {
+                let Ok(params) = notification
+                    .extract::<lsp_types::DidOpenTextDocumentParams>(DidOpenTextDocument::METHOD)
+                else {
+                    return Vec::new();
+                };
+                let uri = params.text_document.uri;
+                self.documents
+                    .insert(uri.clone(), Document::new(params.text_document.text));
+                self.publish_diagnostics(uri)
+            }
+            DidChangeTextDocument::METHOD => {
+                let Ok(params) = notification.extract::<lsp_types::DidChangeTextDocumentParams>(
+                    DidChangeTextDocument::METHOD,
+                ) else {
+                    return Vec::new();
+                };
+                // with full sync, the last change holds the whole document
+                let Some(change) = params.content_changes.into_iter().last() else {
+                    return Vec::new();
+                };
+                let uri = params.text_document.uri;
+                self.documents
+                    .insert(uri.clone(), Document::new(change.text));
+                self.publish_diagnostics(uri)
+            }
+            DidCloseTextDocument::METHOD => {
+                if let Ok(params) = notification
+                    .extract::<lsp_types::DidCloseTextDocumentParams>(DidCloseTextDocument::METHOD)
+                {
+                    self.documents.remove(&params.text_document.uri);
+                }
+                Vec::new()
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    fn publish_diagnostics(&self, uri: Url) -> Vec<Notification> {
+        let diagnostics = self.documents[&uri].diagnostics();
+        let params = PublishDiagnosticsParams::new(uri, diagnostics, None);
+        vec![Notification::new(
+            PublishDiagnostics::METHOD.to_string(),
+            params,
+        )]
+    }
+
+    fn definition(&self, params: GotoDefinitionParams) -> Option<GotoDefinitionResponse> {
+        let params = params.text_document_position_params;
+        let uri = params.text_document.uri;
+        let range = self.documents.get(&uri)?.definition(params.position)?;
+        Some(GotoDefinitionResponse::Scalar(Location::new(uri, range)))
+    }
+
+    fn references(&self, params: ReferenceParams) -> Option<Vec<Location>> {
+        let position = params.text_document_position;
+        let uri = position.text_document.uri;
+        let ranges = self
+            .documents
+            .get(&uri)?
+            .references(position.position, params.context.include_declaration);
+        Some(
+            ranges
+                .into_iter()
+                .map(|range| Location::new(uri.clone(), range))
+                .collect(),
+        )
+    }
+
+    fn hover(&self, params: HoverParams) -> Option<Hover> {
+        let params = params.text_document_position_params;
+        self.documents
+            .get(&params.text_document.uri)?
+            .hover(params.position)
+    }
+
+    fn symbols(&self, params: DocumentSymbolParams) -> Option<DocumentSymbolResponse> {
+        let symbols = self.documents.get(&params.text_document.uri)?.symbols();
+        Some(DocumentSymbolResponse::Nested(symbols))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::thread::JoinHandle;
+
+    use lsp_server::{Connection, Message, Notification, Request, RequestId, Response};
+    use lsp_types::{
+        notification::{
+            DidChangeTextDocument, DidOpenTextDocument, Exit, Initialized,
+            Notification as NotificationTrait, PublishDiagnostics,
+        },
+        request::{
+            DocumentSymbolRequest, GotoDefinition, HoverRequest, Initialize, References,
+            Request as RequestTrait, Shutdown,
+        },
+        DidChangeTextDocumentParams, DidOpenTextDocumentParams, DocumentSymbolResponse,
+        GotoDefinitionResponse, HoverContents, Location, Position, PublishDiagnosticsParams, Range,
+        TextDocumentContentChangeEvent, TextDocumentIdentifier, TextDocumentItem,
+        TextDocumentPositionParams, Url, VersionedTextDocumentIdentifier,
+    };
+    use serde_json::json;
+
+    /// An in-process LSP client connected to a server running on another
+    /// thread.
+    struct Client {
+        connection: Connection,
+        server: Option<JoinHandle<()>>,
+        next_id: i32,
+        uri: Url,
+    }
+
+    impl Client {
+        fn new() -> Self {
+            let (server, connection) = Connection::memory();
+            let server = std::thread::spawn(move || super::run(&server).unwrap());
+            let mut client = Self {
+                connection,
+                server: Some(server),
+                next_id: 0,
+                uri: Url::parse("file:///test.ria").unwrap(),
+            };
+
+            client.request::<Initialize>(json!({ "capabilities": {} }));
+            client.notify::<Initialized>(json!({}));
+            client
+        }
+
+        fn request<R: RequestTrait>(&mut self, params: serde_json::Value) -> serde_json::Value {
+            self.next_id += 1;
+            let id = RequestId::from(self.next_id);
+            let request = Request::new(id.clone(), R::METHOD.to_string(), params);
+            self.connection.sender.send(request.into()).unwrap();
+
+            match self.connection.receiver.recv().unwrap() {
+                Message::Response(Response {
+                    id: response_id,
+                    result: Some(result),
+                    error: None,
+                }) if response_id == id => result,
+                message => panic!("expected a response to {}, got {message:?}", R::METHOD),
+            }
+        }
+
+        fn notify<N: NotificationTrait>(&self, params: impl serde::Serialize) {
+            let notification = Notification::new(N::METHOD.to_string(), params);
+            self.connection.sender.send(notification.into()).unwrap();
+        }
+
+        fn diagnostics(&self) -> PublishDiagnosticsParams {
+            match self.connection.receiver.recv().unwrap() {
+                Message::Notification(notification)
+                    if notification.method == PublishDiagnostics::METHOD =>
+                {
+                    serde_json::from_value(notification.params).unwrap()
+                }
+                message => panic!("expected diagnostics, got {message:?}"),
+            }
+        }
+
+        fn open(&self, text: &str) -> PublishDiagnosticsParams {
+            self.notify::<DidOpenTextDocument>(DidOpenTextDocumentParams {
+                text_document: TextDocumentItem::new(
+                    self.uri.clone(),
+                    "ria".to_string(),
+                    0,
+                    text.to_string(),
+                ),
+            });
+            self.diagnostics()
+        }
+
+        fn position(&self, line: u32, character: u32) -> TextDocumentPositionParams {
+            TextDocumentPositionParams::new(
+                TextDocumentIdentifier::new(self.uri.clone()),
+                Position::new(line, character),
+            )
+        }
+    }
+
+    impl Drop for Client {
+        fn drop(&mut self) {
+            self.request::<Shutdown>(serde_json::Value::Null);
+            self.notify::<Exit>(serde_json::Value::Null);
+            self.server.take().unwrap().join().unwrap();
+        }
+    }
+
+    fn range(start: (u32, u32), end: (u32, u32)) -> Range {
+        Range::new(Position::new(start.0, start.1), Position::new(end.0, end.1))
+    }
+
+    #[test]
+    fn publish_diagnostics_on_change() {
+        let client = Client::new();
+
+        let diagnostics = client.open("id = \\x -> x\ninc = add 1\nb = not true");
+        // builtins and prelude defs aren't unbound
+        assert!(diagnostics.diagnostics.is_empty());
+
+        client.notify::<DidChangeTextDocument>(DidChangeTextDocumentParams {
+            text_document: VersionedTextDocumentIdentifier::new(client.uri.clone(), 1),
+            content_changes: vec![TextDocumentContentChangeEvent {
+                range: None,
+                range_length: None,
+                text: "id = \\x -> y".to_string(),
+            }],
+        });
+        let diagnostics = client.diagnostics().diagnostics;
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].message, "cannot find `y` in this scope");
+        assert_eq!(diagnostics[0].range, range((0, 11), (0, 12)));
+
+        client.notify::<DidChangeTextDocument>(DidChangeTextDocumentParams {
+            text_document: VersionedTextDocumentIdentifier::new(client.uri.clone(), 2),
+            content_changes: vec![TextDocumentContentChangeEvent {
+                range: None,
+                range_length: None,
+                text: "id = ".to_string(),
+            }],
+        });
+        let diagnostics = client.diagnostics().diagnostics;
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].range, range((0, 5), (0, 5)));
+    }
+
+    #[test]
+    fn warn_about_missing_exports() {
+        let client = Client::new();
+
+        let diagnostics = client.open("export (id, no)\nid = \\x -> x").diagnostics;
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].message, "`no` is exported but not defined");
+        assert_eq!(
+            diagnostics[0].severity,
+            Some(lsp_types::DiagnosticSeverity::WARNING)
+        );
+        assert_eq!(diagnostics[0].range, range((0, 12), (0, 14)));
+    }
+
+    #[test]
+    fn warn_about_unreachable_arms() {
+        let client = Client::new();
+
+        let diagnostics = client
+            .open("type T = A | B\nf = \\t -> match t with\n  | _ -> 0\n  | A -> 1")
+            .diagnostics;
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(
+            diagnostics[0].severity,
+            Some(lsp_types::DiagnosticSeverity::WARNING)
+        );
+        assert_eq!(diagnostics[0].range, range((3, 4), (3, 5)));
+    }
+
+    #[test]
+    fn goto_definition_and_references() {
+        let mut client = Client::new();
+        client.open("id = \\x -> x\nself = id id");
+
+        let params = client.position(1, 10);
+        let response: GotoDefinitionResponse =
+            serde_json::from_value(client.request::<GotoDefinition>(json!(params))).unwrap();
+        assert_eq!(
+            response,
+            GotoDefinitionResponse::Scalar(Location::new(
+                client.uri.clone(),
+                range((0, 0), (0, 2))
+            ))
+        );
+
+        let mut params = json!(client.position(0, 1));
+        params["context"] = json!({ "includeDeclaration": true });
+        let locations: Vec<Location> =
+            serde_json::from_value(client.request::<References>(params)).unwrap();
+        let ranges: Vec<_> = locations.into_iter().map(|loc| loc.range).collect();
+        assert_eq!(
+            ranges,
+            [
+                range((0, 0), (0, 2)),
+                range((1, 7), (1, 9)),
+                range((1, 10), (1, 12)),
+            ]
+        );
+
+        // the parameter `x` is only referenced in the lambda body
+        let mut params = json!(client.position(0, 11));
+        params["context"] = json!({ "includeDeclaration": false });
+        let locations: Vec<Location> =
+            serde_json::from_value(client.request::<References>(params)).unwrap();
+        assert_eq!(locations.len(), 1);
+        assert_eq!(locations[0].range, range((0, 11), (0, 12)));
+    }
+
+    #[test]
+    fn hover_shows_definition_source() {
+        let mut client = Client::new();
+        client.open("id = \\x -> x\nself = (y = id\n  y)");
+
+        let hover: lsp_types::Hover =
+            serde_json::from_value(client.request::<HoverRequest>(json!(client.position(2, 2))))
+                .unwrap();
+        let HoverContents::Markup(markup) = hover.contents else {
+            panic!("expected markup");
+        };
+        assert_eq!(markup.value, "```ria\ny = id\n```");
+    }
+
+    #[test]
+    fn list_top_level_symbols() {
+        let mut client = Client::new();
+        client.open("id = \\x -> x\nself = (y = id\n  y)");
+
+        let params = json!({ "textDocument": { "uri": client.uri } });
+        let response: DocumentSymbolResponse =
+            serde_json::from_value(client.request::<DocumentSymbolRequest>(params)).unwrap();
+        let DocumentSymbolResponse::Nested(symbols) = response else {
+            panic!("expected nested symbols");
+        };
+        let names: Vec<_> = symbols
+            .iter()
+            .map(|symbol| (symbol.name.as_str(), symbol.range))
+            .collect();
+        assert_eq!(
+            names,
+            [
+                ("id", range((0, 0), (0, 12))),
+                ("self", range((1, 0), (2, 4))),
+            ]
+        );
+    }
+}
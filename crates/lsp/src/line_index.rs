@@ -0,0 +1,78 @@
+use std::ops::Range;
+
+use lsp_types::Position;
+
+/// Converts between byte offsets and LSP positions, which count lines and
+/// UTF-16 code units.
+#[derive(Debug)]
+pub struct LineIndex {
+    /// The byte offset of the start of each line.
+    line_starts: Box<[usize]>,
+}
+
+impl LineIndex {
+    /// Creates a new `LineIndex` for `text`.
+    pub fn new(text: &str) -> Self {
+        let line_starts = std::iter::once(0)
+            .chain(text.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+        Self { line_starts }
+    }
+
+    /// Returns the position of the byte `offset` in `text`.
+    pub fn position(&self, text: &str, offset: usize) -> Position {
+        let line = self.line_starts.partition_point(|&start| start <= offset) - 1;
+        let start = self.line_starts[line];
+        let character = text[start..offset].encode_utf16().count();
+        Position::new(line as u32, character as u32)
+    }
+
+    /// Returns the byte offset of `position` in `text`.
+    ///
+    /// Positions past the end of a line are clamped to the end of the line.
+    pub fn offset(&self, text: &str, position: Position) -> usize {
+        let Some(&start) = self.line_starts.get(position.line as usize) else {
+            return text.len();
+        };
+        let line = text[start..].split('\n').next().unwrap_or_default();
+
+        let mut units = 0;
+        for (i, c) in line.char_indices() {
+            if units >= position.character as usize {
+                return start + i;
+            }
+            units += c.len_utf16();
+        }
+        start + line.len()
+    }
+
+    /// Returns the LSP range of the byte range `span` in `text`.
+    pub fn range(&self, text: &str, span: Range<usize>) -> lsp_types::Range {
+        lsp_types::Range::new(
+            self.position(text, span.start),
+            self.position(text, span.end),
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use lsp_types::Position;
+
+    use super::LineIndex;
+
+    #[test]
+    fn convert_positions() {
+        let text = "a = b\nλ = \\x -> x\n";
+        let index = LineIndex::new(text);
+
+        assert_eq!(index.position(text, 4), Position::new(0, 4));
+        assert_eq!(index.position(text, 6), Position::new(1, 0));
+        // `λ` is two bytes but one UTF-16 code unit
+        assert_eq!(index.position(text, 10), Position::new(1, 3));
+
+        assert_eq!(index.offset(text, Position::new(1, 3)), 10);
+        assert_eq!(index.offset(text, Position::new(0, 99)), 5);
+        assert_eq!(index.offset(text, Position::new(9, 0)), text.len());
+    }
+}
@@ -0,0 +1,11 @@
+use std::error::Error;
+
+use lsp_server::Connection;
+
+fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
+    let (connection, io_threads) = Connection::stdio();
+    ria_lsp::run(&connection)?;
+    drop(connection);
+    io_threads.join()?;
+    Ok(())
+}
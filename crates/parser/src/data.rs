@@ -0,0 +1,232 @@
+//! Declarations of data types, like `type Option a = None | Some a`.
+
+use std::ops::Range;
+
+use ria_lexer::{Spanned, Symbol, Token};
+use winnow::{
+    combinator::{alt, cut_err, opt, peek, preceded, repeat, separated},
+    error::{StrContext, StrContextValue},
+    stream::Stream,
+    ModalResult, Parser,
+};
+
+use crate::{ident, keyword, newline, symbol, too_deep, Nesting, HEIGHT, MAX_NESTING};
+
+/// Returns whether `name` can name a type or a constructor: whether it starts
+/// with a capital letter.
+pub fn is_constructor_name(name: &str) -> bool {
+    name.starts_with(char::is_uppercase)
+}
+
+/// A `type` declaration, which defines a type and the constructors of its
+/// values.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TypeDef<'i> {
+    pub name: Spanned<&'i str>,
+    /// The type variables the type is generic over.
+    pub params: Box<[Spanned<&'i str>]>,
+    /// The constructors, in the order they're declared.
+    pub constructors: Box<[Constructor<'i>]>,
+    /// The span of the whole declaration, from `type` to the end of the last
+    /// constructor.
+    pub span: Range<usize>,
+}
+
+impl<'i> TypeDef<'i> {
+    pub fn parse<S>(input: &mut S) -> ModalResult<Self>
+    where
+        S: Stream<Token = Spanned<Token<'i>>>,
+    {
+        let start = keyword("type").parse_next(input)?;
+        // a def called `type` is followed by `=` rather than a name
+        peek(ident).parse_next(input)?;
+        let (name, params, _, _, _, constructors): (_, Vec<_>, _, _, _, Vec<_>) = cut_err((
+            ident
+                .verify(|name: &Spanned<&str>| is_constructor_name(name.0))
+                .context(StrContext::Expected(StrContextValue::Description(
+                    "a type name starting with a capital letter",
+                ))),
+            repeat(0.., ident),
+            symbol(&Symbol::Define),
+            opt(newline),
+            opt(symbol(&Symbol::Pipe)),
+            separated(
+                1..,
+                Constructor::parse,
+                (opt(newline), symbol(&Symbol::Pipe)),
+            ),
+        ))
+        .parse_next(input)?;
+
+        let end = constructors.last().map_or(name.end(), |last| last.span.end);
+        Ok(Self {
+            name,
+            params: params.into(),
+            constructors: constructors.into(),
+            span: start.start()..end,
+        })
+    }
+}
+
+/// One of the alternatives of a [`TypeDef`], like `Some a`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Constructor<'i> {
+    pub name: Spanned<&'i str>,
+    /// The types of the values the constructor holds.
+    ///
+    /// These aren't checked yet: only how many there are matters.
+    pub fields: Box<[Type<'i>]>,
+    /// The span of the constructor, from its name to the end of its last
+    /// field.
+    pub span: Range<usize>,
+}
+
+impl<'i> Constructor<'i> {
+    pub fn parse<S>(input: &mut S) -> ModalResult<Self>
+    where
+        S: Stream<Token = Spanned<Token<'i>>>,
+    {
+        let name = ident
+            .verify(|name: &Spanned<&str>| is_constructor_name(name.0))
+            .context(StrContext::Expected(StrContextValue::Description(
+                "a constructor name starting with a capital letter",
+            )))
+            .parse_next(input)?;
+        let fields: Vec<Spanned<_>> = repeat(0.., Type::parse_atom).parse_next(input)?;
+        let end = fields.last().map_or(name.end(), Spanned::end);
+        Ok(Self {
+            span: name.start()..end,
+            name,
+            fields: fields.into_iter().map(|field| field.0).collect(),
+        })
+    }
+
+    /// Returns the number of fields the constructor has.
+    pub fn arity(&self) -> usize {
+        self.fields.len()
+    }
+}
+
+/// The type of a constructor's field.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Type<'i> {
+    /// A type or a type variable, like `Int` or `a`.
+    Name(Spanned<&'i str>),
+    /// A type applied to an argument, like `List a`.
+    Apply(Box<Type<'i>>, Box<Type<'i>>),
+    /// The type of functions, like `a -> b`.
+    Function(Box<Type<'i>>, Box<Type<'i>>),
+}
+
+impl<'i> Type<'i> {
+    /// Parses a `Type`, where `->` associates to the right.
+    pub fn parse<S>(input: &mut S) -> ModalResult<Self>
+    where
+        S: Stream<Token = Spanned<Token<'i>>>,
+    {
+        let _nesting = Nesting::enter(input)?;
+        let outer = HEIGHT.replace(0);
+
+        let Spanned(head, _) = Type::parse_atom.parse_next(input)?;
+        let args: Vec<_> = repeat(0.., Type::parse_atom).parse_next(input)?;
+        // one more than the tallest type in parentheses, plus one per argument
+        let mut height = HEIGHT.get() + 1 + args.len();
+        let applied = args.into_iter().fold(head, |func, Spanned(arg, _)| {
+            Type::Apply(Box::new(func), Box::new(arg))
+        });
+
+        let ty =
+            match opt(preceded(symbol(&Symbol::Arrow), cut_err(Type::parse))).parse_next(input)? {
+                Some(result) => {
+                    height = height.max(HEIGHT.get()) + 1;
+                    Type::Function(Box::new(applied), Box::new(result))
+                }
+                None => applied,
+            };
+        if height > MAX_NESTING {
+            return too_deep(input);
+        }
+        HEIGHT.set(outer.max(height));
+        Ok(ty)
+    }
+
+    /// Parses a name or a parenthesized type, spanning any parentheses.
+    fn parse_atom<S>(input: &mut S) -> ModalResult<Spanned<Self>>
+    where
+        S: Stream<Token = Spanned<Token<'i>>>,
+    {
+        alt((
+            ident.map(|name| Spanned(Type::Name(name.clone()), name.1)),
+            (
+                symbol(&Symbol::OpenParen),
+                cut_err(Type::parse),
+                cut_err(symbol(&Symbol::CloseParen)),
+            )
+                .map(|(open, ty, close)| Spanned(ty, open.start()..close.end())),
+        ))
+        .parse_next(input)
+    }
+
+    /// Returns the span of the type, not counting parentheses around it.
+    pub fn span(&self) -> Range<usize> {
+        match self {
+            Type::Name(Spanned(_, span)) => span.clone(),
+            Type::Apply(func, arg) | Type::Function(func, arg) => func.span().start..arg.span().end,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use ria_lexer::{Lexer, Spanned};
+    use winnow::Parser;
+
+    use super::{Type, TypeDef};
+
+    #[test]
+    fn parse_type_defs() {
+        let source = "type List a = Nil\n  | Cons a (List a)";
+        let tokens: Box<_> = Lexer::new(source).collect();
+        let list = TypeDef::parse.parse(tokens.as_ref()).unwrap();
+        assert_eq!(list.name, Spanned("List", 5..9));
+        assert_eq!(&*list.params, [Spanned("a", 10..11)]);
+        assert_eq!(list.span, 0..source.len());
+
+        let [nil, cons] = &*list.constructors else {
+            panic!("expected two constructors");
+        };
+        assert_eq!(nil.name.0, "Nil");
+        assert_eq!(nil.arity(), 0);
+        assert_eq!(cons.arity(), 2);
+        assert_eq!(&source[cons.span.clone()], "Cons a (List a)");
+        assert_eq!(
+            cons.fields[1],
+            Type::Apply(
+                Type::Name(Spanned("List", 30..34)).into(),
+                Type::Name(Spanned("a", 35..36)).into(),
+            )
+        );
+
+        let source = "type F = | F (Int -> Int -> Int)";
+        let tokens: Box<_> = Lexer::new(source).collect();
+        let f = TypeDef::parse.parse(tokens.as_ref()).unwrap();
+        assert!(matches!(
+            &f.constructors[0].fields[0],
+            Type::Function(_, result) if matches!(**result, Type::Function(..))
+        ));
+    }
+
+    #[test]
+    fn reject_bad_type_defs() {
+        for source in [
+            "type T =",
+            "type T = a",
+            "type t = A",
+            "type T = A |",
+            "type T A",
+        ] {
+            let tokens: Box<_> = Lexer::new(source).collect();
+            assert!(TypeDef::parse.parse(tokens.as_ref()).is_err(), "{source}");
+        }
+    }
+}
@@ -1,35 +1,83 @@
+use std::ops::Range;
+
 use ria_lexer::{Spanned, Symbol, Token};
 use winnow::{
-    combinator::separated,
+    combinator::{alt, cut_err, opt, separated},
     error::{StrContext, StrContextValue},
     stream::Stream,
     ModalResult, Parser,
 };
 
-use crate::newline;
+use crate::{data::TypeDef, newline, test_def::TestDef};
 
 use super::{expr::Expr, ident, symbol};
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct DefList<'i> {
+    /// The type declarations among the defs, in source order.
+    pub types: Box<[TypeDef<'i>]>,
     pub defs: Box<[Def<'i>]>,
 }
 
+/// A line of a [`DefList`].
+enum Item<'i> {
+    Type(TypeDef<'i>),
+    Def(Def<'i>),
+    Test(TestDef<'i>),
+}
+
 impl<'i> DefList<'i> {
     pub fn parse<S>(input: &mut S) -> ModalResult<DefList<'i>>
     where
         S: Stream<Token = Spanned<Token<'i>>>,
     {
-        let defs: Vec<_> = separated(0.., Def::parse, newline)
+        Self::parse_items(input, false).map(|(defs, _)| defs)
+    }
+
+    /// Parses the defs at the top level of a module, where tests may be
+    /// declared among them.
+    pub(crate) fn parse_with_tests<S>(input: &mut S) -> ModalResult<(DefList<'i>, Vec<TestDef<'i>>)>
+    where
+        S: Stream<Token = Spanned<Token<'i>>>,
+    {
+        Self::parse_items(input, true)
+    }
+
+    fn parse_items<S>(input: &mut S, tests: bool) -> ModalResult<(DefList<'i>, Vec<TestDef<'i>>)>
+    where
+        S: Stream<Token = Spanned<Token<'i>>>,
+    {
+        let item = |input: &mut S| {
+            if tests {
+                if let Some(test) = opt(TestDef::parse).parse_next(input)? {
+                    return Ok(Item::Test(test));
+                }
+            }
+            alt((TypeDef::parse.map(Item::Type), Def::parse.map(Item::Def))).parse_next(input)
+        };
+        let items: Vec<_> = separated(0.., item, newline)
             .context(StrContext::Label("def list"))
             .parse_next(input)?;
-        Ok(DefList {
-            defs: defs.into_boxed_slice(),
-        })
+
+        let mut types = Vec::new();
+        let mut defs = Vec::new();
+        let mut test_defs = Vec::new();
+        for item in items {
+            match item {
+                Item::Type(type_def) => types.push(type_def),
+                Item::Def(def) => defs.push(def),
+                Item::Test(test) => test_defs.push(test),
+            }
+        }
+        let defs = DefList {
+            types: types.into(),
+            defs: defs.into(),
+        };
+        Ok((defs, test_defs))
     }
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Def<'i> {
     /// The identifier that is being assigned to.
     pub ident: Spanned<&'i str>,
@@ -46,14 +94,20 @@ impl<'i> Def<'i> {
             .context(StrContext::Label("identifier"))
             .parse_next(input)?;
         symbol(&Symbol::Define).parse_next(input)?;
-        let expr = Expr::parse
-            .context(StrContext::Expected(StrContextValue::Description(
-                "an expression",
-            )))
-            .parse_next(input)?;
+        // once we've seen `=`, this can only be a def
+        let expr = cut_err(Expr::parse.context(StrContext::Expected(
+            StrContextValue::Description("an expression"),
+        )))
+        .parse_next(input)?;
 
         Ok(Self { ident, expr })
     }
+
+    /// Returns the span of the whole definition, from the identifier to the
+    /// end of the expression.
+    pub fn span(&self) -> Range<usize> {
+        self.ident.start()..self.expr.span().end
+    }
 }
 
 #[cfg(test)]
@@ -94,4 +148,19 @@ mod test {
         assert_def_eq!(&def_list.defs[0], x = Expr::Variable(Spanned("y", _)));
         assert_def_eq!(&def_list.defs[1], y = Expr::Variable(Spanned("z", _)));
     }
+
+    #[test]
+    fn parse_types_among_defs() {
+        let input = "x = y
+type T = A | B x
+type = z";
+        let tokens: Box<[Spanned<Token>]> = Lexer::new(input).collect();
+
+        let def_list = DefList::parse.parse(tokens.as_ref()).unwrap();
+
+        assert_eq!(def_list.types.len(), 1);
+        assert_eq!(def_list.types[0].name.0, "T");
+        assert_def_eq!(&def_list.defs[0], x = Expr::Variable(Spanned("y", _)));
+        assert_def_eq!(&def_list.defs[1], type = Expr::Variable(Spanned("z", _)));
+    }
 }
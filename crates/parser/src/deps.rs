@@ -0,0 +1,299 @@
+//! Free variables and the dependencies between definitions.
+
+use std::{collections::HashMap, fmt::Write};
+
+use ria_lexer::Spanned;
+
+use crate::{
+    def::DefList,
+    expr::{Arm, Block, Expr, Lambda, Qualified},
+    visit::{walk_arm, walk_block, walk_lambda, Visitor},
+};
+
+/// Returns the variables used in `expr` that aren't bound inside it, in the
+/// order they first appear.
+///
+/// The `a` of a qualified name `a.b` is one of them, since it is a record
+/// unless it names an imported module.
+pub fn free_variables<'i>(expr: &Expr<'i>) -> Vec<&'i str> {
+    let mut free = FreeVariables::default();
+    free.visit_expr(expr);
+    free.free
+}
+
+#[derive(Default)]
+struct FreeVariables<'i> {
+    /// The names bound at the current point, innermost last.
+    bound: Vec<&'i str>,
+    free: Vec<&'i str>,
+}
+
+impl<'i> Visitor<'i> for FreeVariables<'i> {
+    fn visit_variable(&mut self, Spanned(name, _): &Spanned<&'i str>) {
+        if !self.bound.contains(name) && !self.free.contains(name) {
+            self.free.push(name);
+        }
+    }
+
+    fn visit_qualified(&mut self, qualified: &Qualified<'i>) {
+        self.visit_variable(&qualified.module);
+    }
+
+    fn visit_lambda(&mut self, lambda: &Lambda<'i>) {
+        self.bound.push(lambda.param.0);
+        walk_lambda(self, lambda);
+        self.bound.pop();
+    }
+
+    fn visit_block(&mut self, block: &Block<'i>) {
+        let depth = self.bound.len();
+        let constructors = block
+            .defs
+            .types
+            .iter()
+            .flat_map(|ty| ty.constructors.iter());
+        self.bound
+            .extend(constructors.map(|constructor| constructor.name.0));
+        self.bound
+            .extend(block.defs.defs.iter().map(|def| def.ident.0));
+        walk_block(self, block);
+        self.bound.truncate(depth);
+    }
+
+    fn visit_arm(&mut self, arm: &Arm<'i>) {
+        let depth = self.bound.len();
+        arm.pattern
+            .for_each_variable(&mut |name| self.bound.push(name.0));
+        walk_arm(self, arm);
+        self.bound.truncate(depth);
+    }
+}
+
+/// A group of definitions that depend on each other.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Component {
+    /// The indices of the defs in the group, in source order.
+    pub defs: Vec<usize>,
+}
+
+impl Component {
+    /// Returns whether the group is more than one definition, each depending
+    /// on the others.
+    pub fn is_mutually_recursive(&self) -> bool {
+        self.defs.len() > 1
+    }
+}
+
+/// The dependencies between the definitions of a [`DefList`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DepGraph<'i> {
+    /// The name of each def.
+    pub names: Vec<&'i str>,
+    /// The indices of the defs that each def refers to.
+    pub edges: Vec<Vec<usize>>,
+}
+
+impl<'i> DepGraph<'i> {
+    /// Builds the dependency graph of `defs`.
+    ///
+    /// If a name is defined more than once, references go to the first
+    /// definition.
+    pub fn new(defs: &DefList<'i>) -> Self {
+        let names: Vec<_> = defs.defs.iter().map(|def| def.ident.0).collect();
+        let mut indices = HashMap::new();
+        for (index, name) in names.iter().enumerate() {
+            indices.entry(*name).or_insert(index);
+        }
+
+        let edges = defs
+            .defs
+            .iter()
+            .map(|def| {
+                free_variables(&def.expr)
+                    .into_iter()
+                    .filter_map(|name| indices.get(name).copied())
+                    .collect()
+            })
+            .collect();
+
+        Self { names, edges }
+    }
+
+    /// Returns whether the def at `index` refers to itself directly.
+    pub fn references_itself(&self, index: usize) -> bool {
+        self.edges[index].contains(&index)
+    }
+
+    /// Returns whether `component` is recursive: either mutually recursive,
+    /// or a single def that refers to itself.
+    pub fn is_recursive(&self, component: &Component) -> bool {
+        component.is_mutually_recursive() || self.references_itself(component.defs[0])
+    }
+
+    /// Returns the strongly connected components of the graph in topological
+    /// order: every component comes after the components it depends on.
+    pub fn components(&self) -> Vec<Component> {
+        Tarjan::new(self).run()
+    }
+
+    /// Renders the graph in Graphviz DOT format, with an edge from each def
+    /// to the defs it depends on and a cluster around each mutually
+    /// recursive group.
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph deps {\n");
+        for (index, component) in self.components().iter().enumerate() {
+            if component.is_mutually_recursive() {
+                let _ = writeln!(dot, "  subgraph cluster_{index} {{");
+                let _ = writeln!(dot, "    label = \"mutually recursive\";");
+                for &def in &component.defs {
+                    let _ = writeln!(dot, "    \"{}\";", self.names[def]);
+                }
+                dot.push_str("  }\n");
+            } else {
+                let _ = writeln!(dot, "  \"{}\";", self.names[component.defs[0]]);
+            }
+        }
+        for (from, edges) in self.edges.iter().enumerate() {
+            for &to in edges {
+                let _ = writeln!(dot, "  \"{}\" -> \"{}\";", self.names[from], self.names[to]);
+            }
+        }
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+/// Tarjan's strongly connected components algorithm, which finds each
+/// component only after every component reachable from it.
+struct Tarjan<'g> {
+    edges: &'g [Vec<usize>],
+    index: Vec<Option<usize>>,
+    low_link: Vec<usize>,
+    on_stack: Vec<bool>,
+    stack: Vec<usize>,
+    next_index: usize,
+    components: Vec<Component>,
+}
+
+impl<'g> Tarjan<'g> {
+    fn new(graph: &'g DepGraph) -> Self {
+        let count = graph.names.len();
+        Self {
+            edges: &graph.edges,
+            index: vec![None; count],
+            low_link: vec![0; count],
+            on_stack: vec![false; count],
+            stack: Vec::new(),
+            next_index: 0,
+            components: Vec::new(),
+        }
+    }
+
+    fn run(mut self) -> Vec<Component> {
+        for node in 0..self.edges.len() {
+            if self.index[node].is_none() {
+                self.connect(node);
+            }
+        }
+        self.components
+    }
+
+    fn connect(&mut self, node: usize) {
+        self.index[node] = Some(self.next_index);
+        self.low_link[node] = self.next_index;
+        self.next_index += 1;
+        self.stack.push(node);
+        self.on_stack[node] = true;
+
+        for &next in &self.edges[node] {
+            match self.index[next] {
+                None => {
+                    self.connect(next);
+                    self.low_link[node] = self.low_link[node].min(self.low_link[next]);
+                }
+                Some(index) if self.on_stack[next] => {
+                    self.low_link[node] = self.low_link[node].min(index);
+                }
+                Some(_) => {}
+            }
+        }
+
+        if Some(self.low_link[node]) == self.index[node] {
+            let mut defs = Vec::new();
+            loop {
+                let member = self.stack.pop().expect("node is on the stack");
+                self.on_stack[member] = false;
+                defs.push(member);
+                if member == node {
+                    break;
+                }
+            }
+            defs.sort_unstable();
+            self.components.push(Component { defs });
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::parse_module;
+
+    use super::{free_variables, Component, DepGraph};
+
+    #[test]
+    fn find_free_variables() {
+        let module = parse_module("f = \\x -> (y = x z\n  g y x w z)").unwrap();
+        let free = free_variables(&module.defs().defs[0].expr);
+        assert_eq!(free, ["z", "g", "w"]);
+
+        let source = "f = match (type T = A x\n  A) with\n  | A y -> y x\n  | B -> y";
+        let module = parse_module(source).unwrap();
+        let free = free_variables(&module.defs().defs[0].expr);
+        assert_eq!(free, ["x", "y"]);
+
+        let module = parse_module(
+            "f = (r = { a = b }
+  (r.a, p.q, s.0))",
+        )
+        .unwrap();
+        let free = free_variables(&module.defs().defs[0].expr);
+        assert_eq!(free, ["b", "p", "s"]);
+    }
+
+    #[test]
+    fn order_components() {
+        let source =
+            "main = even id\neven = \\n -> odd n\nodd = \\n -> even n\nid = \\x -> x\nloop = loop";
+        let module = parse_module(source).unwrap();
+        let graph = DepGraph::new(module.defs());
+
+        assert_eq!(graph.edges, [vec![1, 3], vec![2], vec![1], vec![], vec![4]]);
+
+        let components = graph.components();
+        assert_eq!(
+            components,
+            [
+                Component { defs: vec![1, 2] },
+                Component { defs: vec![3] },
+                Component { defs: vec![0] },
+                Component { defs: vec![4] },
+            ]
+        );
+
+        assert!(components[0].is_mutually_recursive());
+        assert!(graph.is_recursive(&components[0]));
+        assert!(!graph.is_recursive(&components[1]));
+        assert!(graph.references_itself(4));
+        assert!(graph.is_recursive(&components[3]));
+    }
+
+    #[test]
+    fn render_dot() {
+        let module = parse_module("a = b\nb = a\nc = a").unwrap();
+        let dot = DepGraph::new(module.defs()).to_dot();
+        assert_eq!(
+            dot,
+            "digraph deps {\n  subgraph cluster_0 {\n    label = \"mutually recursive\";\n    \"a\";\n    \"b\";\n  }\n  \"c\";\n  \"a\" -> \"b\";\n  \"b\" -> \"a\";\n  \"c\" -> \"a\";\n}\n"
+        );
+    }
+}
@@ -0,0 +1,20 @@
+use std::ops::Range;
+
+/// A problem found in a source file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    /// The span of source text the problem concerns.
+    pub span: Range<usize>,
+    /// A human-readable description of the problem.
+    pub message: String,
+}
+
+impl Diagnostic {
+    /// Creates a new `Diagnostic` with the given `span` and `message`.
+    pub fn new(span: Range<usize>, message: impl Into<String>) -> Self {
+        Self {
+            span,
+            message: message.into(),
+        }
+    }
+}
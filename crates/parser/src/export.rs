@@ -0,0 +1,74 @@
+use std::ops::Range;
+
+use ria_lexer::{Spanned, Symbol, Token};
+use winnow::{
+    combinator::{cut_err, separated},
+    error::{StrContext, StrContextValue},
+    stream::Stream,
+    ModalResult, Parser,
+};
+
+use crate::{ident, keyword, symbol};
+
+/// An `export (a, b)` declaration at the top of a module, listing the defs
+/// other modules may import. Without one, every def is exported.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Export<'i> {
+    /// The names of the exported defs.
+    pub names: Box<[Spanned<&'i str>]>,
+    /// The span of the whole declaration, from `export` to the closing
+    /// parenthesis.
+    pub span: Range<usize>,
+}
+
+impl<'i> Export<'i> {
+    pub fn parse<S>(input: &mut S) -> ModalResult<Self>
+    where
+        S: Stream<Token = Spanned<Token<'i>>>,
+    {
+        let start = keyword("export").parse_next(input)?;
+        // a def called `export` is followed by `=` rather than a list
+        symbol(&Symbol::OpenParen).parse_next(input)?;
+        let (names, close): (Vec<_>, _) = cut_err((
+            separated(0.., ident, symbol(&Symbol::Comma)),
+            symbol(&Symbol::CloseParen),
+        ))
+        .context(StrContext::Expected(StrContextValue::Description(
+            "a list of names",
+        )))
+        .parse_next(input)?;
+
+        Ok(Self {
+            names: names.into(),
+            span: start.start()..close.end(),
+        })
+    }
+
+    /// Returns whether `name` is exported.
+    pub fn contains(&self, name: &str) -> bool {
+        self.names.iter().any(|export| export.0 == name)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use ria_lexer::{Lexer, Spanned};
+    use winnow::Parser;
+
+    use super::Export;
+
+    #[test]
+    fn parse_exports() {
+        let tokens: Box<_> = Lexer::new("export (a, b)").collect();
+        let export = Export::parse.parse(tokens.as_ref()).unwrap();
+        assert_eq!(&*export.names, [Spanned("a", 8..9), Spanned("b", 11..12)]);
+        assert_eq!(export.span, 0..13);
+        assert!(export.contains("b"));
+        assert!(!export.contains("c"));
+
+        for source in ["export (a b)", "export a", "export = a"] {
+            let tokens: Box<_> = Lexer::new(source).collect();
+            assert!(Export::parse.parse(tokens.as_ref()).is_err(), "{source}");
+        }
+    }
+}
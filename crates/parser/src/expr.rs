@@ -1,25 +1,51 @@
-use ria_lexer::{Spanned, Token};
+use std::ops::Range;
+
+use ria_lexer::{Spanned, Symbol, Token};
 use winnow::{
-    combinator::{alt, trace},
+    combinator::{alt, opt, preceded, repeat, trace},
     error::StrContext,
     stream::Stream,
     ModalResult, Parser,
 };
 
-use self::{block::Block, call::Call, lambda::Lambda};
+pub use self::{
+    access::{Access, Field},
+    block::Block,
+    call::Call,
+    lambda::Lambda,
+    matching::{Arm, Match},
+    qualified::Qualified,
+    record::{Record, RecordField},
+    tuple::Tuple,
+};
 
-use super::ident;
+use super::{ident, int, symbol, too_deep, Nesting, HEIGHT, MAX_NESTING};
 
+mod access;
 mod block;
 mod call;
 mod lambda;
+mod matching;
+mod qualified;
+mod record;
+mod tuple;
 
-#[derive(Debug, PartialEq, Eq)]
+/// The names that can't be variables, since they start or continue an
+/// expression.
+pub const KEYWORDS: &[&str] = &["match", "with"];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Expr<'i> {
     Variable(Spanned<&'i str>),
+    Qualified(Qualified<'i>),
+    Int(Spanned<i64>),
     Lambda(Lambda<'i>),
     Block(Block<'i>),
     Call(Call<'i>),
+    Match(Match<'i>),
+    Tuple(Tuple<'i>),
+    Record(Record<'i>),
+    Access(Access<'i>),
 }
 
 impl<'i> Expr<'i> {
@@ -27,42 +53,113 @@ impl<'i> Expr<'i> {
     where
         S: Stream<Token = Spanned<Token<'i>>>,
     {
-        let mut expr = trace(
+        let _nesting = Nesting::enter(input)?;
+        let outer = HEIGHT.replace(0);
+
+        // each atom comes with its span, which takes in the parentheses
+        // around a group, since the expression in them doesn't keep them
+        let atom = trace(
             "expression alt",
             alt((
-                ident
-                    .map(Expr::Variable)
-                    .context(StrContext::Label("variable")),
-                Lambda::parse
-                    .map(Expr::Lambda)
-                    .context(StrContext::Label("lambda")),
-                Block::parse
-                    .map(Expr::Block)
-                    .context(StrContext::Label("block")),
+                alt((
+                    Match::parse
+                        .map(Expr::Match)
+                        .context(StrContext::Label("match")),
+                    // `a.b` is tried first, since `a` alone is a variable
+                    Qualified::parse
+                        .map(Expr::Qualified)
+                        .context(StrContext::Label("qualified name")),
+                    ident
+                        .verify(|name: &Spanned<&str>| !KEYWORDS.contains(&name.0))
+                        .map(Expr::Variable)
+                        .context(StrContext::Label("variable")),
+                    int.map(Expr::Int).context(StrContext::Label("integer")),
+                    Lambda::parse
+                        .map(Expr::Lambda)
+                        .context(StrContext::Label("lambda")),
+                    Block::parse
+                        .map(Expr::Block)
+                        .context(StrContext::Label("block")),
+                    Record::parse
+                        .map(Expr::Record)
+                        .context(StrContext::Label("record")),
+                ))
+                .map(|expr: Expr<'i>| {
+                    let span = expr.span();
+                    (expr, span)
+                }),
+                // a `(` that doesn't start a block groups or makes a tuple
+                tuple::parenthesized.context(StrContext::Label("parentheses")),
             )),
         )
         .context(StrContext::Label("expression"));
+        // `.` binds tighter than calls, so `f r.a` is `f (r.a)`. each
+        // expression comes with its span and its height, one more than the
+        // tallest expression inside its atom, plus one per field
+        let mut expr = (
+            atom,
+            repeat(0.., preceded(symbol(&Symbol::Dot), Field::parse)),
+        )
+            .map(|((expr, span), fields): (_, Vec<_>)| {
+                let height = HEIGHT.replace(0) + 1 + fields.len();
+                let start = span.start;
+                let (expr, span) = fields.into_iter().fold((expr, span), |(expr, _), field| {
+                    let span = start..field.end();
+                    let access = Access {
+                        expr: expr.into(),
+                        field,
+                        span: span.clone(),
+                    };
+                    (Expr::Access(access), span)
+                });
+                (expr, span, height)
+            });
 
         // parse first expression
-        let mut lhs = expr.parse_next(input)?;
+        let (mut lhs, mut span, mut height) = expr.parse_next(input)?;
 
         loop {
-            // try to parse another expression
-            let Ok(rhs) = expr.parse_next(input) else {
+            if height > MAX_NESTING {
+                return too_deep(input);
+            }
+
+            // try to parse another expression. `opt` rewinds the input if
+            // there isn't one, but errors after a cut point are still errors
+            let Some((rhs, rhs_span, rhs_height)) = opt(expr.by_ref()).parse_next(input)? else {
                 // if we couldn't, just return what we have
+                HEIGHT.set(outer.max(height));
                 return Ok(lhs);
             };
+            height = height.max(rhs_height) + 1;
 
             // we parsed another expression, so we must be calling `lhs` with
             // an argument of `rhs`
+            span = span.start..rhs_span.end;
             lhs = Expr::Call(Call {
                 func: lhs.into(),
                 arg: rhs.into(),
+                span: span.clone(),
             });
 
             // loop to see if we call again
         }
     }
+
+    /// Returns the span of source text covered by the expression.
+    pub fn span(&self) -> Range<usize> {
+        match self {
+            Expr::Variable(Spanned(_, span)) => span.clone(),
+            Expr::Qualified(qualified) => qualified.span(),
+            Expr::Int(Spanned(_, span)) => span.clone(),
+            Expr::Lambda(lambda) => lambda.span(),
+            Expr::Block(block) => block.span.clone(),
+            Expr::Call(call) => call.span.clone(),
+            Expr::Match(matching) => matching.span.clone(),
+            Expr::Tuple(tuple) => tuple.span.clone(),
+            Expr::Record(record) => record.span.clone(),
+            Expr::Access(access) => access.span.clone(),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -72,7 +169,8 @@ mod test {
 
     use crate::{
         def::{Def, DefList},
-        expr::{Block, Call, Expr, Lambda},
+        expr::{Access, Block, Call, Expr, Field, Lambda, Record, RecordField, Tuple},
+        pattern::Pattern,
     };
 
     #[test]
@@ -120,6 +218,7 @@ mod test {
             expr,
             Expr::Block(Block {
                 defs: DefList {
+                    types: [].into(),
                     defs: [Def {
                         ident: Spanned("x", 1..2),
                         expr: Expr::Variable(Spanned("y", 5..6)),
@@ -127,10 +226,98 @@ mod test {
                     .into(),
                 },
                 expr: Some(Expr::Variable(Spanned("x", 8..9)).into()),
+                span: 0..10,
             }),
         );
     }
 
+    #[test]
+    fn parse_groups() {
+        let tokens: Box<_> = Lexer::new("f (g x)").collect();
+        let expr = Expr::parse
+            .parse(tokens.as_ref())
+            .expect("grouped argument should parse");
+        assert_eq!(
+            expr,
+            Expr::Call(Call {
+                func: Expr::Variable(Spanned("f", 0..1)).into(),
+                arg: Expr::Call(Call {
+                    func: Expr::Variable(Spanned("g", 3..4)).into(),
+                    arg: Expr::Variable(Spanned("x", 5..6)).into(),
+                    span: 3..6,
+                })
+                .into(),
+                span: 0..7,
+            })
+        );
+
+        let tokens: Box<_> = Lexer::new("(\\x -> x) y").collect();
+        let expr = Expr::parse
+            .parse(tokens.as_ref())
+            .expect("grouped lambda should parse");
+        let Expr::Call(call) = &expr else {
+            panic!("expected a call, found {expr:?}");
+        };
+        assert!(matches!(*call.func, Expr::Lambda(_)));
+        assert_eq!(call.span, 0..11);
+
+        // groups nest, and can hold a block
+        let tokens: Box<_> = Lexer::new("((f ((x)))) (\n  (y = x; y)\n)").collect();
+        let expr = Expr::parse
+            .parse(tokens.as_ref())
+            .expect("nested groups should parse");
+        assert_eq!(
+            expr,
+            Expr::Call(Call {
+                func: Expr::Call(Call {
+                    func: Expr::Variable(Spanned("f", 2..3)).into(),
+                    arg: Expr::Variable(Spanned("x", 6..7)).into(),
+                    span: 2..9,
+                })
+                .into(),
+                arg: Expr::Block(Block {
+                    defs: DefList {
+                        types: [].into(),
+                        defs: [Def {
+                            ident: Spanned("y", 17..18),
+                            expr: Expr::Variable(Spanned("x", 21..22)),
+                        }]
+                        .into(),
+                    },
+                    expr: Some(Expr::Variable(Spanned("y", 24..25)).into()),
+                    span: 16..26,
+                })
+                .into(),
+                span: 0..28,
+            })
+        );
+
+        for source in ["()", "(f x", "f (g x))", "((x)"] {
+            let tokens: Box<_> = Lexer::new(source).collect();
+            assert!(Expr::parse.parse(tokens.as_ref()).is_err(), "{source}");
+        }
+    }
+
+    #[test]
+    fn parse_blocks_without_defs() {
+        // a block may only declare types
+        let tokens: Box<_> = Lexer::new("(type A = A\n  A)").collect();
+        let expr = Expr::parse.parse(tokens.as_ref()).unwrap();
+        let Expr::Block(block) = &expr else {
+            panic!("expected a block, found {expr:?}");
+        };
+        assert_eq!(block.defs.types.len(), 1);
+        assert!(block.defs.defs.is_empty());
+
+        // without a def or a type, the parentheses only group, however the
+        // expression is laid out
+        for source in ["(; x)", "(\n  x\n)", "(\n  ;\n  x)"] {
+            let tokens: Box<_> = Lexer::new(source).collect();
+            let expr = Expr::parse.parse(tokens.as_ref()).unwrap();
+            assert!(matches!(expr, Expr::Variable(Spanned("x", _))), "{source}");
+        }
+    }
+
     #[test]
     fn parse_arity_1_call() {
         let tokens: Box<_> = Lexer::new("x y").collect();
@@ -143,10 +330,202 @@ mod test {
             Expr::Call(Call {
                 func: Expr::Variable(Spanned("x", 0..1)).into(),
                 arg: Expr::Variable(Spanned("y", 2..3)).into(),
+                span: 0..3,
             })
         );
     }
 
+    #[test]
+    fn parse_int_call() {
+        let tokens: Box<_> = Lexer::new("add 1 23").collect();
+        let expr = Expr::parse
+            .parse(tokens.as_ref())
+            .expect("call with int arguments should parse");
+
+        assert_eq!(
+            expr,
+            Expr::Call(Call {
+                func: Expr::Call(Call {
+                    func: Expr::Variable(Spanned("add", 0..3)).into(),
+                    arg: Expr::Int(Spanned(1, 4..5)).into(),
+                    span: 0..5,
+                })
+                .into(),
+                arg: Expr::Int(Spanned(23, 6..8)).into(),
+                span: 0..8,
+            }),
+        );
+    }
+
+    #[test]
+    fn parse_match() {
+        let source = "match f x with\n  | Some y -> g y\n  | None -> 0";
+        let tokens: Box<_> = Lexer::new(source).collect();
+        let expr = Expr::parse
+            .parse(tokens.as_ref())
+            .expect("match should parse");
+
+        let Expr::Match(matching) = &expr else {
+            panic!("expected a match, found {expr:?}");
+        };
+        assert!(matches!(*matching.scrutinee, Expr::Call(_)));
+        assert_eq!(&source[matching.head_span()], "match f x");
+        assert_eq!(matching.span, 0..source.len());
+        let [some, none] = &*matching.arms else {
+            panic!("expected two arms");
+        };
+        assert!(matches!(some.pattern, Pattern::Constructor(_)));
+        assert!(matches!(some.body, Expr::Call(_)));
+        assert_eq!(
+            none.body,
+            Expr::Int(Spanned(0, source.len() - 1..source.len()))
+        );
+
+        // `with` ends the scrutinee, and can't be a variable
+        for source in ["match x", "match x with", "f with", "match x with | y"] {
+            let tokens: Box<_> = Lexer::new(source).collect();
+            assert!(Expr::parse.parse(tokens.as_ref()).is_err(), "{source}");
+        }
+    }
+
+    #[test]
+    fn parse_tuples_and_groups() {
+        let tokens: Box<_> = Lexer::new("(f x, (y), 1)").collect();
+        let expr = Expr::parse
+            .parse(tokens.as_ref())
+            .expect("tuple should parse");
+
+        assert_eq!(
+            expr,
+            Expr::Tuple(Tuple {
+                items: [
+                    Expr::Call(Call {
+                        func: Expr::Variable(Spanned("f", 1..2)).into(),
+                        arg: Expr::Variable(Spanned("x", 3..4)).into(),
+                        span: 1..4,
+                    }),
+                    Expr::Variable(Spanned("y", 7..8)),
+                    Expr::Int(Spanned(1, 11..12)),
+                ]
+                .into(),
+                span: 0..13,
+            }),
+        );
+
+        for source in ["(x,)", "(, x)", "(x, y"] {
+            let tokens: Box<_> = Lexer::new(source).collect();
+            assert!(Expr::parse.parse(tokens.as_ref()).is_err(), "{source}");
+        }
+    }
+
+    #[test]
+    fn parse_records() {
+        let source = "{ a = 1,
+  b = \\x -> x }";
+        let tokens: Box<_> = Lexer::new(source).collect();
+        let expr = Expr::parse
+            .parse(tokens.as_ref())
+            .expect("record should parse");
+
+        let Expr::Record(Record { fields, span }) = &expr else {
+            panic!("expected a record, found {expr:?}");
+        };
+        assert_eq!(*span, 0..source.len());
+        let [a, b] = &**fields else {
+            panic!("expected two fields");
+        };
+        assert_eq!(
+            *a,
+            RecordField {
+                name: Spanned("a", 2..3),
+                value: Expr::Int(Spanned(1, 6..7)),
+            }
+        );
+        assert!(matches!(b.value, Expr::Lambda(_)));
+
+        let tokens: Box<_> = Lexer::new("{}").collect();
+        assert!(Expr::parse.parse(tokens.as_ref()).is_ok());
+        for source in ["{ a }", "{ a = 1 b = 2 }", "{ 1 = a }"] {
+            let tokens: Box<_> = Lexer::new(source).collect();
+            assert!(Expr::parse.parse(tokens.as_ref()).is_err(), "{source}");
+        }
+    }
+
+    #[test]
+    fn parse_field_access() {
+        // `.` binds tighter than calls
+        let tokens: Box<_> = Lexer::new("f t.0.name").collect();
+        let expr = Expr::parse
+            .parse(tokens.as_ref())
+            .expect("field access should parse");
+
+        assert_eq!(
+            expr,
+            Expr::Call(Call {
+                func: Expr::Variable(Spanned("f", 0..1)).into(),
+                arg: Expr::Access(Access {
+                    expr: Expr::Access(Access {
+                        expr: Expr::Variable(Spanned("t", 2..3)).into(),
+                        field: Spanned(Field::Index(0), 4..5),
+                        span: 2..5,
+                    })
+                    .into(),
+                    field: Spanned(Field::Name("name"), 6..10),
+                    span: 2..10,
+                })
+                .into(),
+                span: 0..10,
+            }),
+        );
+
+        // a name after a variable may be a module's def, so it stays
+        // qualified until it is resolved
+        let tokens: Box<_> = Lexer::new("r.a.b").collect();
+        let expr = Expr::parse.parse(tokens.as_ref()).unwrap();
+        let Expr::Access(access) = &expr else {
+            panic!("expected an access, found {expr:?}");
+        };
+        assert!(matches!(*access.expr, Expr::Qualified(_)));
+        assert_eq!(access.span, 0..5);
+    }
+
+    #[test]
+    fn reject_deep_nesting() {
+        let deep = |open: &str, atom: &str, close: &str, n| {
+            format!("{}{atom}{}", open.repeat(n), close.repeat(n))
+        };
+        let sources = [
+            format!("x = {}", deep("(", "1", ")", 5000)),
+            format!("x = {}", deep("\\y -> ", "y", "", 20000)),
+            format!("x = {}", deep("", "f", " f", 20000)),
+            format!("x = {}", deep("", "r", ".a", 20000)),
+            // each call puts the first argument one level deeper
+            format!(
+                "x = {}",
+                deep("(f ", "f", " f f f f f f f f f f f f f f f)", 100)
+            ),
+            format!("x = match y with\n  | {} -> 1", deep("(", "z", ")", 5000)),
+            format!("type T = A {}", deep("(", "T", " T)", 5000)),
+        ];
+        for source in sources {
+            let err = crate::parse_module(&source).unwrap_err();
+            assert_eq!(err.message, "nested more than 128 levels deep");
+        }
+
+        let source = format!("x = {}", deep("(", "1", ")", 100));
+        assert!(crate::parse_module(&source).is_ok());
+    }
+
+    #[test]
+    fn reject_large_ints() {
+        let source = "x = 9223372036854775808";
+        let err = crate::parse_module(source).unwrap_err();
+        assert_eq!(&source[err.span.clone()], "9223372036854775808");
+        assert!(err.message.contains("an integer that fits in 64 bits"));
+
+        assert!(crate::parse_module("x = 9223372036854775807").is_ok());
+    }
+
     #[test]
     fn parse_arity_2_call() {
         let tokens: Box<_> = Lexer::new("x y z").collect();
@@ -160,9 +539,11 @@ mod test {
                 func: Expr::Call(Call {
                     func: Expr::Variable(Spanned("x", 0..1)).into(),
                     arg: Expr::Variable(Spanned("y", 2..3)).into(),
+                    span: 0..3,
                 })
                 .into(),
                 arg: Expr::Variable(Spanned("z", 4..5)).into(),
+                span: 0..5,
             }),
         );
     }
@@ -0,0 +1,65 @@
+use std::{fmt, ops::Range};
+
+use ria_lexer::{Spanned, Token};
+use winnow::{
+    combinator::{alt, cut_err},
+    error::{StrContext, StrContextValue},
+    stream::Stream,
+    ModalResult, Parser,
+};
+
+use crate::{ident, int};
+
+use super::Expr;
+
+/// A field of a record or a tuple, like `r.name` or `t.0`.
+///
+/// `a.b` where `a` is a name is parsed as a [`Qualified`](super::Qualified)
+/// name instead, and is only a field access if `a` isn't an imported module.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Access<'i> {
+    /// The record or tuple.
+    pub expr: Box<Expr<'i>>,
+    pub field: Spanned<Field<'i>>,
+    /// The span of the access, from the start of the record or tuple,
+    /// including any parentheses around it, to the end of the field.
+    pub span: Range<usize>,
+}
+
+/// The field an [`Access`] reads.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Field<'i> {
+    /// A field of a record.
+    Name(&'i str),
+    /// An item of a tuple, counting from 0.
+    Index(usize),
+}
+
+impl<'i> Field<'i> {
+    /// Parses the field after a `.`.
+    pub fn parse<S>(input: &mut S) -> ModalResult<Spanned<Self>>
+    where
+        S: Stream<Token = Spanned<Token<'i>>>,
+    {
+        cut_err(alt((
+            ident.map(|name| name.map(Field::Name)),
+            int.verify_map(|index| {
+                let Spanned(index, span) = index;
+                Some(Spanned(Field::Index(usize::try_from(index).ok()?), span))
+            }),
+        )))
+        .context(StrContext::Expected(StrContextValue::Description(
+            "a field name or a tuple index",
+        )))
+        .parse_next(input)
+    }
+}
+
+impl fmt::Display for Field<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Field::Name(name) => f.write_str(name),
+            Field::Index(index) => write!(f, "{index}"),
+        }
+    }
+}
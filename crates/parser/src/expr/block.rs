@@ -1,3 +1,5 @@
+use std::ops::Range;
+
 use ria_lexer::{Spanned, Symbol, Token};
 use winnow::{combinator::opt, stream::Stream, ModalResult, Parser};
 
@@ -5,21 +7,28 @@ use crate::{def::DefList, maybe_newline, newline, symbol};
 
 use super::Expr;
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Block<'i> {
     pub defs: DefList<'i>,
     pub expr: Option<Box<Expr<'i>>>,
+    /// The span of the whole block, including the parentheses.
+    pub span: Range<usize>,
 }
 
 impl<'i> Block<'i> {
+    /// Parses a block, which must have at least one def or type declaration.
+    /// Without one, the parentheses only group an expression, which is left
+    /// to the parser of groups and tuples.
     pub fn parse<S>(input: &mut S) -> ModalResult<Self>
     where
         S: Stream<Token = Spanned<Token<'i>>>,
     {
-        symbol(&Symbol::OpenParen).parse_next(input)?;
+        let open = symbol(&Symbol::OpenParen).parse_next(input)?;
         maybe_newline(input);
 
-        let defs = DefList::parse.parse_next(input)?;
+        let defs = DefList::parse
+            .verify(|defs: &DefList| !defs.types.is_empty() || !defs.defs.is_empty())
+            .parse_next(input)?;
 
         newline(input)?;
 
@@ -28,8 +37,12 @@ impl<'i> Block<'i> {
             .parse_next(input)?;
         maybe_newline(input);
 
-        symbol(&Symbol::CloseParen).parse_next(input)?;
+        let close = symbol(&Symbol::CloseParen).parse_next(input)?;
 
-        Ok(Block { defs, expr })
+        Ok(Block {
+            defs,
+            expr,
+            span: open.start()..close.end(),
+        })
     }
 }
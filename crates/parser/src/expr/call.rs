@@ -1,3 +1,5 @@
+use std::ops::Range;
+
 use ria_lexer::{Spanned, Token};
 use winnow::{
     error::{StrContext, StrContextValue},
@@ -7,10 +9,13 @@ use winnow::{
 
 use super::Expr;
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Call<'i> {
     pub func: Box<Expr<'i>>,
     pub arg: Box<Expr<'i>>,
+    /// The span of the call, including any parentheses around the function
+    /// or the argument.
+    pub span: Range<usize>,
 }
 
 impl<'i> Call<'i> {
@@ -30,6 +35,7 @@ impl<'i> Call<'i> {
             )))
             .map(Box::from)
             .parse_next(input)?;
-        Ok(Call { func, arg })
+        let span = func.span().start..arg.span().end;
+        Ok(Call { func, arg, span })
     }
 }
@@ -1,3 +1,5 @@
+use std::ops::Range;
+
 use ria_lexer::{Spanned, Symbol, Token};
 use winnow::{
     combinator::cut_err,
@@ -10,7 +12,7 @@ use crate::{ident, maybe_newline, symbol};
 
 use super::Expr;
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Lambda<'i> {
     pub param: Spanned<&'i str>,
     pub body: Box<Expr<'i>>,
@@ -36,4 +38,10 @@ impl<'i> Lambda<'i> {
             body: Box::new(body),
         })
     }
+
+    /// Returns the span of the lambda, from its parameter to the end of its
+    /// body.
+    pub fn span(&self) -> Range<usize> {
+        self.param.start()..self.body.span().end
+    }
 }
@@ -0,0 +1,81 @@
+use std::ops::Range;
+
+use ria_lexer::{Spanned, Symbol, Token};
+use winnow::{
+    combinator::{cut_err, opt, preceded, repeat},
+    error::{StrContext, StrContextValue},
+    stream::Stream,
+    ModalResult, Parser,
+};
+
+use crate::{keyword, newline, pattern::Pattern, symbol};
+
+use super::Expr;
+
+/// A `match e with | p -> a | q -> b` expression, which evaluates the body
+/// of the first arm whose pattern matches the value of `e`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Match<'i> {
+    /// The expression whose value is matched.
+    pub scrutinee: Box<Expr<'i>>,
+    pub arms: Box<[Arm<'i>]>,
+    /// The span of the whole expression, from `match` to the end of the last
+    /// arm.
+    pub span: Range<usize>,
+}
+
+/// One `| pattern -> body` case of a [`Match`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Arm<'i> {
+    pub pattern: Pattern<'i>,
+    pub body: Expr<'i>,
+}
+
+impl<'i> Match<'i> {
+    pub fn parse<S>(input: &mut S) -> ModalResult<Self>
+    where
+        S: Stream<Token = Spanned<Token<'i>>>,
+    {
+        let start = keyword("match").parse_next(input)?;
+        let (scrutinee, _, arms): (_, _, Vec<_>) = cut_err((
+            Expr::parse,
+            keyword("with"),
+            repeat(1.., Arm::parse).context(StrContext::Expected(StrContextValue::Description(
+                "a `|` and a pattern",
+            ))),
+        ))
+        .parse_next(input)?;
+
+        let end = arms.last().map_or(start.end(), |arm| arm.body.span().end);
+        Ok(Self {
+            scrutinee: scrutinee.into(),
+            arms: arms.into(),
+            span: start.start()..end,
+        })
+    }
+
+    /// Returns the span from `match` to the end of the scrutinee, where
+    /// errors about the whole match are reported.
+    pub fn head_span(&self) -> Range<usize> {
+        self.span.start..self.scrutinee.span().end
+    }
+}
+
+impl<'i> Arm<'i> {
+    /// Parses an `Arm`, which may start on a new line.
+    pub fn parse<S>(input: &mut S) -> ModalResult<Self>
+    where
+        S: Stream<Token = Spanned<Token<'i>>>,
+    {
+        preceded(opt(newline), symbol(&Symbol::Pipe)).parse_next(input)?;
+        let (pattern, _, body) = cut_err((
+            Pattern::parse,
+            symbol(&Symbol::Arrow),
+            Expr::parse.context(StrContext::Expected(StrContextValue::Description(
+                "an expression",
+            ))),
+        ))
+        .parse_next(input)?;
+        Ok(Self { pattern, body })
+    }
+}
@@ -0,0 +1,33 @@
+use std::ops::Range;
+
+use ria_lexer::{Spanned, Symbol, Token};
+use winnow::{stream::Stream, ModalResult, Parser};
+
+use crate::{ident, symbol};
+
+/// A reference to a def of an imported module, like `other.a`.
+///
+/// Where `other` isn't the name of an imported module, this is the field `a`
+/// of the record in the variable `other` instead, like an [`Access`](super::Access).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Qualified<'i> {
+    /// The name the module was imported as.
+    pub module: Spanned<&'i str>,
+    /// The name of the def in that module.
+    pub name: Spanned<&'i str>,
+}
+
+impl<'i> Qualified<'i> {
+    pub fn parse<S>(input: &mut S) -> ModalResult<Self>
+    where
+        S: Stream<Token = Spanned<Token<'i>>>,
+    {
+        let (module, _, name) = (ident, symbol(&Symbol::Dot), ident).parse_next(input)?;
+        Ok(Self { module, name })
+    }
+
+    /// Returns the span of the reference, from the module to the name.
+    pub fn span(&self) -> Range<usize> {
+        self.module.start()..self.name.end()
+    }
+}
@@ -0,0 +1,71 @@
+use std::ops::Range;
+
+use ria_lexer::{Spanned, Symbol, Token};
+use winnow::{
+    combinator::{cut_err, opt, separated},
+    error::{StrContext, StrContextValue},
+    stream::Stream,
+    ModalResult, Parser,
+};
+
+use crate::{ident, maybe_newline, newline, symbol};
+
+use super::Expr;
+
+/// A record `{ name = x, age = y }`, whose fields are looked up by name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record<'i> {
+    /// The fields, in the order they're written.
+    pub fields: Box<[RecordField<'i>]>,
+    /// The span of the record, including the braces.
+    pub span: Range<usize>,
+}
+
+/// One `name = value` field of a [`Record`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecordField<'i> {
+    pub name: Spanned<&'i str>,
+    pub value: Expr<'i>,
+}
+
+impl<'i> Record<'i> {
+    /// Parses a `Record`, whose fields may be on lines of their own.
+    pub fn parse<S>(input: &mut S) -> ModalResult<Self>
+    where
+        S: Stream<Token = Spanned<Token<'i>>>,
+    {
+        let open = symbol(&Symbol::OpenBrace).parse_next(input)?;
+        let (_, fields, _, close): (_, Vec<_>, _, _) = cut_err((
+            opt(newline),
+            separated(
+                0..,
+                RecordField::parse,
+                (opt(newline), symbol(&Symbol::Comma), opt(newline)),
+            ),
+            opt(newline),
+            symbol(&Symbol::CloseBrace),
+        ))
+        .parse_next(input)?;
+        Ok(Self {
+            fields: fields.into(),
+            span: open.start()..close.end(),
+        })
+    }
+}
+
+impl<'i> RecordField<'i> {
+    pub fn parse<S>(input: &mut S) -> ModalResult<Self>
+    where
+        S: Stream<Token = Spanned<Token<'i>>>,
+    {
+        let name = ident.parse_next(input)?;
+        let _ = cut_err(symbol(&Symbol::Define)).parse_next(input)?;
+        maybe_newline(input);
+        let value = cut_err(Expr::parse)
+            .context(StrContext::Expected(StrContextValue::Description(
+                "an expression",
+            )))
+            .parse_next(input)?;
+        Ok(Self { name, value })
+    }
+}
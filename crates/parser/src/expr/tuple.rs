@@ -0,0 +1,59 @@
+use std::ops::Range;
+
+use ria_lexer::{Spanned, Symbol, Token};
+use winnow::{
+    combinator::{cut_err, opt, preceded, repeat},
+    error::{StrContext, StrContextValue},
+    stream::Stream,
+    ModalResult, Parser,
+};
+
+use crate::{maybe_newline, newline, symbol};
+
+use super::Expr;
+
+/// A tuple `(a, b)` of two or more expressions.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tuple<'i> {
+    pub items: Box<[Expr<'i>]>,
+    /// The span of the tuple, including the parentheses.
+    pub span: Range<usize>,
+}
+
+/// Parses an expression in parentheses that isn't a [`Block`](super::Block):
+/// a tuple if its items are separated by commas, and otherwise the
+/// expression itself, which the parentheses only group. Returns it with the
+/// span of the parentheses.
+pub(super) fn parenthesized<'i, S>(input: &mut S) -> ModalResult<(Expr<'i>, Range<usize>)>
+where
+    S: Stream<Token = Spanned<Token<'i>>>,
+{
+    let open = symbol(&Symbol::OpenParen).parse_next(input)?;
+    maybe_newline(input);
+    let first = Expr::parse.parse_next(input)?;
+    let rest: Vec<_> = repeat(
+        0..,
+        preceded(
+            (opt(newline), symbol(&Symbol::Comma), opt(newline)),
+            cut_err(Expr::parse).context(StrContext::Expected(StrContextValue::Description(
+                "an expression",
+            ))),
+        ),
+    )
+    .parse_next(input)?;
+    maybe_newline(input);
+    let close = cut_err(symbol(&Symbol::CloseParen)).parse_next(input)?;
+
+    let span = open.start()..close.end();
+    if rest.is_empty() {
+        return Ok((first, span));
+    }
+    let mut items = Vec::with_capacity(rest.len() + 1);
+    items.push(first);
+    items.extend(rest);
+    let tuple = Tuple {
+        items: items.into(),
+        span: span.clone(),
+    };
+    Ok((Expr::Tuple(tuple), span))
+}
@@ -0,0 +1,256 @@
+//! Rebuilding the AST.
+//!
+//! A [`Fold`] takes ownership of each node and returns its replacement. Like
+//! a [`Visitor`](crate::visit::Visitor), each method defaults to the matching
+//! `walk_*` function, which folds the node's children and rebuilds it. The
+//! methods for each kind of expression return an [`Expr`], so a pass can
+//! replace a node with a different kind of node.
+
+use ria_lexer::Spanned;
+
+use crate::{
+    def::{Def, DefList},
+    expr::{Access, Arm, Block, Call, Expr, Lambda, Match, Qualified, Record, RecordField, Tuple},
+    module::Module,
+    test_def::TestDef,
+};
+
+pub trait Fold<'i> {
+    fn fold_module(&mut self, module: Module<'i>) -> Module<'i> {
+        walk_module(self, module)
+    }
+
+    fn fold_def_list(&mut self, defs: DefList<'i>) -> DefList<'i> {
+        walk_def_list(self, defs)
+    }
+
+    fn fold_def(&mut self, def: Def<'i>) -> Def<'i> {
+        walk_def(self, def)
+    }
+
+    fn fold_test(&mut self, test: TestDef<'i>) -> TestDef<'i> {
+        walk_test(self, test)
+    }
+
+    fn fold_expr(&mut self, expr: Expr<'i>) -> Expr<'i> {
+        walk_expr(self, expr)
+    }
+
+    fn fold_variable(&mut self, ident: Spanned<&'i str>) -> Expr<'i> {
+        Expr::Variable(ident)
+    }
+
+    fn fold_qualified(&mut self, qualified: Qualified<'i>) -> Expr<'i> {
+        Expr::Qualified(qualified)
+    }
+
+    fn fold_int(&mut self, int: Spanned<i64>) -> Expr<'i> {
+        Expr::Int(int)
+    }
+
+    fn fold_lambda(&mut self, lambda: Lambda<'i>) -> Expr<'i> {
+        walk_lambda(self, lambda)
+    }
+
+    fn fold_block(&mut self, block: Block<'i>) -> Expr<'i> {
+        walk_block(self, block)
+    }
+
+    fn fold_call(&mut self, call: Call<'i>) -> Expr<'i> {
+        walk_call(self, call)
+    }
+
+    fn fold_match(&mut self, matching: Match<'i>) -> Expr<'i> {
+        walk_match(self, matching)
+    }
+
+    fn fold_tuple(&mut self, tuple: Tuple<'i>) -> Expr<'i> {
+        walk_tuple(self, tuple)
+    }
+
+    fn fold_record(&mut self, record: Record<'i>) -> Expr<'i> {
+        walk_record(self, record)
+    }
+
+    fn fold_access(&mut self, access: Access<'i>) -> Expr<'i> {
+        walk_access(self, access)
+    }
+}
+
+pub fn walk_module<'i, F: Fold<'i> + ?Sized>(folder: &mut F, module: Module<'i>) -> Module<'i> {
+    let (header, defs, tests) = module.into_parts();
+    let defs = folder.fold_def_list(defs);
+    let tests = tests
+        .into_vec()
+        .into_iter()
+        .map(|test| folder.fold_test(test))
+        .collect();
+    Module::with_header(header, defs).with_tests(tests)
+}
+
+pub fn walk_def_list<'i, F: Fold<'i> + ?Sized>(folder: &mut F, defs: DefList<'i>) -> DefList<'i> {
+    DefList {
+        types: defs.types,
+        defs: defs
+            .defs
+            .into_vec()
+            .into_iter()
+            .map(|def| folder.fold_def(def))
+            .collect(),
+    }
+}
+
+pub fn walk_def<'i, F: Fold<'i> + ?Sized>(folder: &mut F, def: Def<'i>) -> Def<'i> {
+    Def {
+        ident: def.ident,
+        expr: folder.fold_expr(def.expr),
+    }
+}
+
+pub fn walk_test<'i, F: Fold<'i> + ?Sized>(folder: &mut F, test: TestDef<'i>) -> TestDef<'i> {
+    TestDef {
+        expr: folder.fold_expr(test.expr),
+        ..test
+    }
+}
+
+pub fn walk_expr<'i, F: Fold<'i> + ?Sized>(folder: &mut F, expr: Expr<'i>) -> Expr<'i> {
+    match expr {
+        Expr::Variable(ident) => folder.fold_variable(ident),
+        Expr::Qualified(qualified) => folder.fold_qualified(qualified),
+        Expr::Int(int) => folder.fold_int(int),
+        Expr::Lambda(lambda) => folder.fold_lambda(lambda),
+        Expr::Block(block) => folder.fold_block(block),
+        Expr::Call(call) => folder.fold_call(call),
+        Expr::Match(matching) => folder.fold_match(matching),
+        Expr::Tuple(tuple) => folder.fold_tuple(tuple),
+        Expr::Record(record) => folder.fold_record(record),
+        Expr::Access(access) => folder.fold_access(access),
+    }
+}
+
+pub fn walk_lambda<'i, F: Fold<'i> + ?Sized>(folder: &mut F, lambda: Lambda<'i>) -> Expr<'i> {
+    Expr::Lambda(Lambda {
+        param: lambda.param,
+        body: folder.fold_expr(*lambda.body).into(),
+    })
+}
+
+pub fn walk_block<'i, F: Fold<'i> + ?Sized>(folder: &mut F, block: Block<'i>) -> Expr<'i> {
+    Expr::Block(Block {
+        defs: folder.fold_def_list(block.defs),
+        expr: block.expr.map(|expr| folder.fold_expr(*expr).into()),
+        span: block.span,
+    })
+}
+
+pub fn walk_call<'i, F: Fold<'i> + ?Sized>(folder: &mut F, call: Call<'i>) -> Expr<'i> {
+    Expr::Call(Call {
+        func: folder.fold_expr(*call.func).into(),
+        arg: folder.fold_expr(*call.arg).into(),
+        span: call.span,
+    })
+}
+
+pub fn walk_match<'i, F: Fold<'i> + ?Sized>(folder: &mut F, matching: Match<'i>) -> Expr<'i> {
+    Expr::Match(Match {
+        scrutinee: folder.fold_expr(*matching.scrutinee).into(),
+        arms: matching
+            .arms
+            .into_vec()
+            .into_iter()
+            .map(|arm| Arm {
+                pattern: arm.pattern,
+                body: folder.fold_expr(arm.body),
+            })
+            .collect(),
+        span: matching.span,
+    })
+}
+
+pub fn walk_tuple<'i, F: Fold<'i> + ?Sized>(folder: &mut F, tuple: Tuple<'i>) -> Expr<'i> {
+    Expr::Tuple(Tuple {
+        items: tuple
+            .items
+            .into_vec()
+            .into_iter()
+            .map(|item| folder.fold_expr(item))
+            .collect(),
+        span: tuple.span,
+    })
+}
+
+pub fn walk_record<'i, F: Fold<'i> + ?Sized>(folder: &mut F, record: Record<'i>) -> Expr<'i> {
+    Expr::Record(Record {
+        fields: record
+            .fields
+            .into_vec()
+            .into_iter()
+            .map(|field| RecordField {
+                name: field.name,
+                value: folder.fold_expr(field.value),
+            })
+            .collect(),
+        span: record.span,
+    })
+}
+
+pub fn walk_access<'i, F: Fold<'i> + ?Sized>(folder: &mut F, access: Access<'i>) -> Expr<'i> {
+    Expr::Access(Access {
+        expr: folder.fold_expr(*access.expr).into(),
+        field: access.field,
+        span: access.span,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        def::DefList,
+        expr::{Block, Expr},
+        parse_module,
+    };
+
+    use super::{walk_block, Fold};
+
+    /// Replaces blocks without defs with their expression, and drops defs
+    /// named `_`.
+    struct Simplify;
+
+    impl<'i> Fold<'i> for Simplify {
+        fn fold_def_list(&mut self, defs: DefList<'i>) -> DefList<'i> {
+            let types = defs.types;
+            let defs = defs
+                .defs
+                .into_vec()
+                .into_iter()
+                .filter(|def| def.ident.0 != "_")
+                .map(|def| self.fold_def(def))
+                .collect();
+            DefList { types, defs }
+        }
+
+        fn fold_block(&mut self, block: Block<'i>) -> Expr<'i> {
+            match walk_block(self, block) {
+                Expr::Block(Block {
+                    defs,
+                    expr: Some(expr),
+                    ..
+                }) if defs.defs.is_empty() => *expr,
+                expr => expr,
+            }
+        }
+    }
+
+    #[test]
+    fn fold_rebuilds_tree() {
+        let module = parse_module("x = (_ = y\n  (_ = z\n  y))").unwrap();
+        let module = Simplify.fold_module(module);
+
+        assert_eq!(module.defs().defs.len(), 1);
+        assert!(matches!(
+            &module.defs().defs[0].expr,
+            Expr::Variable(ident) if ident.0 == "y"
+        ));
+    }
+}
@@ -0,0 +1,106 @@
+use std::ops::Range;
+
+use ria_lexer::{Spanned, Symbol, Token};
+use winnow::{
+    combinator::{cut_err, opt, separated},
+    error::{StrContext, StrContextValue},
+    stream::Stream,
+    ModalResult, Parser,
+};
+
+use crate::{ident, keyword, symbol};
+
+/// An `import` declaration at the top of a module.
+///
+/// `import path/to/other` makes the defs of `other` available as `other.a`,
+/// and `import path/to/other (a, b)` also makes `a` and `b` available
+/// unqualified.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Import<'i> {
+    /// The segments of the imported path.
+    pub path: Box<[Spanned<&'i str>]>,
+    /// The names imported unqualified, if they are listed.
+    pub names: Option<Box<[Spanned<&'i str>]>>,
+    /// The span of the whole declaration, from `import` to its end.
+    pub span: Range<usize>,
+}
+
+impl<'i> Import<'i> {
+    pub fn parse<S>(input: &mut S) -> ModalResult<Self>
+    where
+        S: Stream<Token = Spanned<Token<'i>>>,
+    {
+        let start = keyword("import").parse_next(input)?;
+        // a def called `import` is followed by `=` rather than a path
+        let path: Vec<_> = separated(1.., ident, symbol(&Symbol::Slash)).parse_next(input)?;
+
+        let names = opt((
+            symbol(&Symbol::OpenParen),
+            cut_err((
+                separated(0.., ident, symbol(&Symbol::Comma)),
+                symbol(&Symbol::CloseParen),
+            ))
+            .context(StrContext::Expected(StrContextValue::Description(
+                "a list of names",
+            ))),
+        ))
+        .parse_next(input)?;
+
+        let (names, end) = match names {
+            Some((_, (names, close))) => (Some(Vec::into_boxed_slice(names)), close.end()),
+            None => (None, path.last().expect("paths aren't empty").end()),
+        };
+        Ok(Self {
+            path: path.into(),
+            names,
+            span: start.start()..end,
+        })
+    }
+
+    /// Returns the name the imported module is referred to by, the last
+    /// segment of its path.
+    pub fn alias(&self) -> &'i str {
+        self.path.last().expect("paths aren't empty").0
+    }
+
+    /// Returns the path as written, with segments separated by `/`.
+    pub fn path_string(&self) -> String {
+        let segments: Vec<_> = self.path.iter().map(|segment| segment.0).collect();
+        segments.join("/")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use ria_lexer::{Lexer, Spanned};
+    use winnow::Parser;
+
+    use super::Import;
+
+    #[test]
+    fn parse_imports() {
+        let tokens: Box<_> = Lexer::new("import path/to/other").collect();
+        let import = Import::parse.parse(tokens.as_ref()).unwrap();
+        assert_eq!(import.path_string(), "path/to/other");
+        assert_eq!(import.alias(), "other");
+        assert_eq!(import.names, None);
+        assert_eq!(import.span, 0..20);
+
+        let tokens: Box<_> = Lexer::new("import other (a, b)").collect();
+        let import = Import::parse.parse(tokens.as_ref()).unwrap();
+        assert_eq!(import.path_string(), "other");
+        assert_eq!(
+            import.names.as_deref(),
+            Some([Spanned("a", 14..15), Spanned("b", 17..18)].as_slice())
+        );
+        assert_eq!(import.span, 0..19);
+    }
+
+    #[test]
+    fn reject_bad_imports() {
+        for source in ["import a/", "import a (b c)", "import"] {
+            let tokens: Box<_> = Lexer::new(source).collect();
+            assert!(Import::parse.parse(tokens.as_ref()).is_err(), "{source}");
+        }
+    }
+}
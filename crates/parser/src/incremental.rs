@@ -0,0 +1,536 @@
+use std::ops::Range;
+
+use ria_lexer::{Lexed, Spanned, TextEdit, Token};
+use winnow::{combinator::opt, Parser};
+
+use crate::{
+    data::{Constructor, Type, TypeDef},
+    def::{Def, DefList},
+    diagnostic::Diagnostic,
+    export::Export,
+    expr::{
+        Access, Arm, Block, Call, Expr, Field, Lambda, Match, Qualified, Record, RecordField, Tuple,
+    },
+    import::Import,
+    module::{Header, Module},
+    newline, parse_lexed,
+    pattern::{ConstructorPattern, Pattern},
+    test_def::TestDef,
+};
+
+/// A lexed and parsed source text that can be updated after an edit without
+/// starting over.
+#[derive(Debug)]
+pub struct Parsed<'i> {
+    pub lexed: Lexed<'i>,
+    pub module: Result<Module<'i>, Diagnostic>,
+}
+
+impl<'i> Parsed<'i> {
+    /// Lexes and parses all of `source`.
+    pub fn new(source: &'i str) -> Self {
+        let lexed = Lexed::new(source);
+        let module = parse_lexed(source, &lexed);
+        Self { lexed, module }
+    }
+
+    /// Lexes and parses `source`, the result of applying `edit` to the source
+    /// this was parsed from.
+    ///
+    /// Only the affected tokens are relexed (see [`Lexed::relex`]) and only
+    /// the top-level defs, type declarations and tests enclosing the edit are
+    /// reparsed. If the edit changes the structure of the module beyond
+    /// those, or the module didn't parse before, the whole module is
+    /// reparsed.
+    pub fn update<'n>(&self, edit: &TextEdit, source: &'n str) -> Parsed<'n> {
+        let lexed = self.lexed.relex(edit, source);
+        let module = match &self.module {
+            Ok(module) => reparse(module, &self.lexed, &lexed, edit, source)
+                .map_or_else(|| parse_lexed(source, &lexed), Ok),
+            Err(_) => parse_lexed(source, &lexed),
+        };
+        Parsed { lexed, module }
+    }
+}
+
+/// A def, type declaration or test at the top level of a module.
+#[derive(Clone, Copy)]
+enum Item<'a, 'i> {
+    Type(&'a TypeDef<'i>),
+    Def(&'a Def<'i>),
+    Test(&'a TestDef<'i>),
+}
+
+impl Item<'_, '_> {
+    fn span(self) -> Range<usize> {
+        match self {
+            Item::Type(type_def) => type_def.span.clone(),
+            Item::Def(def) => def.span(),
+            Item::Test(test) => test.span.clone(),
+        }
+    }
+}
+
+/// The top-level items of a module being put back together.
+#[derive(Default)]
+struct Items<'n> {
+    types: Vec<TypeDef<'n>>,
+    defs: Vec<Def<'n>>,
+    tests: Vec<TestDef<'n>>,
+}
+
+impl<'n> Items<'n> {
+    fn push(&mut self, item: Item, rebase: &Rebase<'n>) {
+        match item {
+            Item::Type(type_def) => self.types.push(rebase.type_def(type_def)),
+            Item::Def(def) => self.defs.push(rebase.def(def)),
+            Item::Test(test) => self.tests.push(rebase.test(test)),
+        }
+    }
+}
+
+/// Reparses the top-level items of `module` touched by `edit`, reusing the
+/// rest.
+///
+/// Returns `None` if a full reparse is needed.
+fn reparse<'n>(
+    module: &Module,
+    old: &Lexed,
+    new: &Lexed<'n>,
+    edit: &TextEdit,
+    source: &'n str,
+) -> Option<Module<'n>> {
+    if new.offset < source.len() {
+        // the lexer got stuck, which is reported by a full parse
+        return None;
+    }
+
+    // the header is only reparsed with the whole module, so the region
+    // starts after the separator following it
+    let header = module.header();
+    let first_token = match header.end() {
+        Some(end) => {
+            let index = old.tokens.partition_point(|tok| tok.start() < end);
+            let separator = old.tokens.get(index)?;
+            if edit.range.start <= separator.end() {
+                return None;
+            }
+            index + 1
+        }
+        None => 0,
+    };
+
+    // the items in source order, each kind being in source order already
+    let mut items: Vec<_> = module
+        .defs()
+        .types
+        .iter()
+        .map(Item::Type)
+        .chain(module.defs().defs.iter().map(Item::Def))
+        .chain(module.tests().iter().map(Item::Test))
+        .collect();
+    items.sort_by_key(|item| item.span().start);
+    let count = items.len();
+
+    // the items touching the edit. an edit between two items touches
+    // neither, so both are taken
+    let first = items
+        .iter()
+        .position(|item| item.span().end >= edit.range.start)
+        .unwrap_or(count.checked_sub(1)?);
+    let last = items
+        .iter()
+        .rposition(|item| item.span().start <= edit.range.end)
+        .unwrap_or(0);
+    let (mut lo, mut hi) = (first.min(last), first.max(last));
+
+    // grow the region until it is bounded by separators the edit didn't touch
+    let before = loop {
+        if lo == 0 {
+            break None;
+        }
+        match separator_after(items[lo - 1].span().end, old, new, edit) {
+            Some(index) => break Some(index),
+            None => lo -= 1,
+        }
+    };
+    let after = loop {
+        if hi + 1 == count {
+            break None;
+        }
+        match separator_after(items[hi].span().end, old, new, edit) {
+            Some(index) => break Some(index),
+            None => hi += 1,
+        }
+    };
+
+    let start = before.map_or(first_token, |index| index + 1);
+    let (region, region_tests) = match after {
+        Some(end) => DefList::parse_with_tests
+            .parse(new.tokens.get(start..end)?)
+            .ok()?,
+        // the last item may be followed by a trailing newline
+        None => (DefList::parse_with_tests, opt(newline))
+            .map(|(items, _)| items)
+            .parse(&new.tokens[start..])
+            .ok()?,
+    };
+
+    let unchanged = Rebase { source, delta: 0 };
+    let header = unchanged.header(header);
+    let shifted = Rebase {
+        source,
+        delta: edit.delta(),
+    };
+    let mut rebuilt = Items::default();
+    for &item in &items[..lo] {
+        rebuilt.push(item, &unchanged);
+    }
+    rebuilt.types.extend(region.types.into_vec());
+    rebuilt.defs.extend(region.defs.into_vec());
+    rebuilt.tests.extend(region_tests);
+    for &item in &items[hi + 1..] {
+        rebuilt.push(item, &shifted);
+    }
+
+    let defs = DefList {
+        types: rebuilt.types.into(),
+        defs: rebuilt.defs.into(),
+    };
+    Some(Module::with_header(header, defs).with_tests(rebuilt.tests.into()))
+}
+
+/// Returns the index in `new` of the separator following the item that ends
+/// at `end`, if the edit left it in place.
+fn separator_after(end: usize, old: &Lexed, new: &Lexed, edit: &TextEdit) -> Option<usize> {
+    let index = old.tokens.partition_point(|tok| tok.start() < end);
+    let Spanned(_, span) = old.tokens.get(index)?;
+
+    let span = if span.end <= edit.range.start {
+        span.clone()
+    } else if span.start >= edit.range.end {
+        edit.shift(span.start)..edit.shift(span.end)
+    } else {
+        return None;
+    };
+
+    let index = new
+        .tokens
+        .binary_search_by_key(&span.start, Spanned::start)
+        .ok()?;
+    match &new.tokens[index] {
+        Spanned(Token::NewLine | Token::Semi, new_span) if *new_span == span => Some(index),
+        _ => None,
+    }
+}
+
+/// Copies an AST into one borrowing from another source, moving every span
+/// by `delta`.
+struct Rebase<'n> {
+    source: &'n str,
+    delta: isize,
+}
+
+impl<'n> Rebase<'n> {
+    fn span(&self, span: &Range<usize>) -> Range<usize> {
+        span.start.wrapping_add_signed(self.delta)..span.end.wrapping_add_signed(self.delta)
+    }
+
+    fn ident(&self, Spanned(_, span): &Spanned<&str>) -> Spanned<&'n str> {
+        let span = self.span(span);
+        Spanned(&self.source[span.clone()], span)
+    }
+
+    fn header(&self, header: &Header) -> Header<'n> {
+        Header {
+            export: header.export.as_ref().map(|export| Export {
+                names: export.names.iter().map(|name| self.ident(name)).collect(),
+                span: self.span(&export.span),
+            }),
+            imports: header
+                .imports
+                .iter()
+                .map(|import| self.import(import))
+                .collect(),
+        }
+    }
+
+    fn import(&self, import: &Import) -> Import<'n> {
+        Import {
+            path: import
+                .path
+                .iter()
+                .map(|segment| self.ident(segment))
+                .collect(),
+            names: import
+                .names
+                .as_ref()
+                .map(|names| names.iter().map(|name| self.ident(name)).collect()),
+            span: self.span(&import.span),
+        }
+    }
+
+    fn def_list(&self, defs: &DefList) -> DefList<'n> {
+        DefList {
+            types: defs.types.iter().map(|ty| self.type_def(ty)).collect(),
+            defs: defs.defs.iter().map(|def| self.def(def)).collect(),
+        }
+    }
+
+    fn type_def(&self, type_def: &TypeDef) -> TypeDef<'n> {
+        TypeDef {
+            name: self.ident(&type_def.name),
+            params: type_def
+                .params
+                .iter()
+                .map(|param| self.ident(param))
+                .collect(),
+            constructors: type_def
+                .constructors
+                .iter()
+                .map(|constructor| Constructor {
+                    name: self.ident(&constructor.name),
+                    fields: constructor.fields.iter().map(|ty| self.ty(ty)).collect(),
+                    span: self.span(&constructor.span),
+                })
+                .collect(),
+            span: self.span(&type_def.span),
+        }
+    }
+
+    fn ty(&self, ty: &Type) -> Type<'n> {
+        match ty {
+            Type::Name(name) => Type::Name(self.ident(name)),
+            Type::Apply(func, arg) => Type::Apply(self.ty(func).into(), self.ty(arg).into()),
+            Type::Function(param, result) => {
+                Type::Function(self.ty(param).into(), self.ty(result).into())
+            }
+        }
+    }
+
+    fn pattern(&self, pattern: &Pattern) -> Pattern<'n> {
+        match pattern {
+            Pattern::Wildcard(span) => Pattern::Wildcard(self.span(span)),
+            Pattern::Variable(name) => Pattern::Variable(self.ident(name)),
+            Pattern::Int(Spanned(value, span)) => Pattern::Int(Spanned(*value, self.span(span))),
+            Pattern::Constructor(constructor) => Pattern::Constructor(ConstructorPattern {
+                name: self.ident(&constructor.name),
+                args: constructor
+                    .args
+                    .iter()
+                    .map(|arg| self.pattern(arg))
+                    .collect(),
+                span: self.span(&constructor.span),
+            }),
+        }
+    }
+
+    fn def(&self, def: &Def) -> Def<'n> {
+        Def {
+            ident: self.ident(&def.ident),
+            expr: self.expr(&def.expr),
+        }
+    }
+
+    fn test(&self, test: &TestDef) -> TestDef<'n> {
+        // the span of the name includes its quotes
+        let span = self.span(&test.name.1);
+        TestDef {
+            name: Spanned(&self.source[span.start + 1..span.end - 1], span),
+            expr: self.expr(&test.expr),
+            span: self.span(&test.span),
+        }
+    }
+
+    fn expr(&self, expr: &Expr) -> Expr<'n> {
+        match expr {
+            Expr::Variable(ident) => Expr::Variable(self.ident(ident)),
+            Expr::Qualified(qualified) => Expr::Qualified(Qualified {
+                module: self.ident(&qualified.module),
+                name: self.ident(&qualified.name),
+            }),
+            Expr::Int(Spanned(value, span)) => Expr::Int(Spanned(*value, self.span(span))),
+            Expr::Lambda(lambda) => Expr::Lambda(Lambda {
+                param: self.ident(&lambda.param),
+                body: self.expr(&lambda.body).into(),
+            }),
+            Expr::Block(block) => Expr::Block(Block {
+                defs: self.def_list(&block.defs),
+                expr: block.expr.as_ref().map(|expr| self.expr(expr).into()),
+                span: self.span(&block.span),
+            }),
+            Expr::Call(call) => Expr::Call(Call {
+                func: self.expr(&call.func).into(),
+                arg: self.expr(&call.arg).into(),
+                span: self.span(&call.span),
+            }),
+            Expr::Match(matching) => Expr::Match(Match {
+                scrutinee: self.expr(&matching.scrutinee).into(),
+                arms: matching
+                    .arms
+                    .iter()
+                    .map(|arm| Arm {
+                        pattern: self.pattern(&arm.pattern),
+                        body: self.expr(&arm.body),
+                    })
+                    .collect(),
+                span: self.span(&matching.span),
+            }),
+            Expr::Tuple(tuple) => Expr::Tuple(Tuple {
+                items: tuple.items.iter().map(|item| self.expr(item)).collect(),
+                span: self.span(&tuple.span),
+            }),
+            Expr::Record(record) => Expr::Record(Record {
+                fields: record
+                    .fields
+                    .iter()
+                    .map(|field| RecordField {
+                        name: self.ident(&field.name),
+                        value: self.expr(&field.value),
+                    })
+                    .collect(),
+                span: self.span(&record.span),
+            }),
+            Expr::Access(access) => Expr::Access(Access {
+                expr: self.expr(&access.expr).into(),
+                field: {
+                    let span = self.span(&access.field.1);
+                    let field = match access.field.0 {
+                        Field::Name(_) => Field::Name(&self.source[span.clone()]),
+                        Field::Index(index) => Field::Index(index),
+                    };
+                    Spanned(field, span)
+                },
+                span: self.span(&access.span),
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use ria_lexer::TextEdit;
+
+    use super::{reparse, Parsed};
+
+    const SOURCE: &str =
+        "id = \\x -> x\nconst = \\x -> \\y -> x; flip = \\f ->\n  \\a -> \\b -> f b a\napply = (f = id\n  f const)\n";
+
+    /// Applies `edit` to `SOURCE` and checks that updating gives the same
+    /// tokens and module as parsing from scratch.
+    fn check(edit: TextEdit) {
+        check_source(SOURCE, edit);
+    }
+
+    fn check_source(source: &str, edit: TextEdit) {
+        let old = Parsed::new(source);
+        let source = edit.apply(source);
+        let updated = old.update(&edit, &source);
+        let full = Parsed::new(&source);
+
+        assert_eq!(updated.lexed, full.lexed, "tokens differ after {edit:?}");
+        assert_eq!(updated.module, full.module, "modules differ after {edit:?}");
+    }
+
+    #[test]
+    fn reparse_only_enclosing_def() {
+        let old = Parsed::new(SOURCE);
+        let edit = TextEdit::new(11..12, "const x");
+        let source = edit.apply(SOURCE);
+        let new = old.lexed.relex(&edit, &source);
+
+        let module = reparse(
+            old.module.as_ref().unwrap(),
+            &old.lexed,
+            &new,
+            &edit,
+            &source,
+        )
+        .expect("editing inside a def shouldn't need a full reparse");
+        assert_eq!(module.defs().defs.len(), 4);
+        check(edit);
+    }
+
+    #[test]
+    fn reparse_structural_edits() {
+        // split a def in two
+        check(TextEdit::new(12..12, "\nz = id"));
+        // join two defs
+        check(TextEdit::new(35..37, " "));
+        // delete a def
+        check(TextEdit::new(0..13, ""));
+        // unbalanced parentheses
+        check(TextEdit::new(73..74, ""));
+        check(TextEdit::new(5..5, "("));
+    }
+
+    #[test]
+    fn reparse_after_header() {
+        let source = "export (f)\nimport a/b\nimport c (d, e)\nf = b.g\nh = d\n";
+        let replacements = ["", " ", "\n", "x", ".", "(", "/"];
+        for start in 0..=source.len() {
+            for text in replacements {
+                check_source(source, TextEdit::new(start..start, text));
+            }
+        }
+    }
+
+    #[test]
+    fn reparse_types_and_matches() {
+        let source =
+            "f = \\x -> match x with\n  | Some (Pair a _) -> a\n  | _ -> 0\ng = (type T = A | B f\n  B 1)\n";
+        let replacements = ["", " ", "\n", "|", "A", "_", "type T = A\n"];
+        for start in 0..=source.len() {
+            for text in replacements {
+                check_source(source, TextEdit::new(start..start, text));
+            }
+        }
+    }
+
+    #[test]
+    fn reparse_top_level_types_and_tests() {
+        let source = "type T = A | B f\nf = \\x -> x\ntest \"f\" = f 1\ng = f (B 2)\n";
+        let old = Parsed::new(source);
+        let edit = TextEdit::new(27..28, "f x");
+        let new_source = edit.apply(source);
+        let new = old.lexed.relex(&edit, &new_source);
+        let module = reparse(
+            old.module.as_ref().unwrap(),
+            &old.lexed,
+            &new,
+            &edit,
+            &new_source,
+        )
+        .expect("editing inside a def shouldn't need a full reparse");
+        assert_eq!(module.defs().types.len(), 1);
+        assert_eq!(module.tests().len(), 1);
+
+        let replacements = [
+            "",
+            " ",
+            "\n",
+            "x",
+            "\"",
+            "|",
+            "type T = A\n",
+            "test \"t\" = 1\n",
+        ];
+        for start in 0..=source.len() {
+            for text in replacements {
+                check_source(source, TextEdit::new(start..start, text));
+            }
+        }
+    }
+
+    #[test]
+    fn reparse_every_edit() {
+        let replacements = ["", " ", "\n", ";", "x", "\\", "->", "=", "(", ")", "+"];
+        for start in 0..=SOURCE.len() {
+            for end in start..=(start + 2).min(SOURCE.len()) {
+                for text in replacements {
+                    check(TextEdit::new(start..end, text));
+                }
+            }
+        }
+    }
+}
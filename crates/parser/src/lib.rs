@@ -1,15 +1,128 @@
 #![allow(dead_code)]
 
-use ria_lexer::{Spanned, Symbol, Token};
+use std::{cell::Cell, fmt};
+
+use module::Module;
+use ria_lexer::{Lexed, Spanned, Symbol, Token};
 use winnow::{
-    error::{ContextError, ErrMode, StrContext, StrContextValue},
+    combinator::{cut_err, empty},
+    error::{AddContext, ContextError, ErrMode, StrContext, StrContextValue},
     stream::Stream,
     ModalResult, Parser,
 };
 
+pub mod data;
 pub mod def;
+pub mod deps;
+pub mod diagnostic;
+pub mod export;
 pub mod expr;
+pub mod fold;
+pub mod import;
+pub mod incremental;
+pub mod loader;
 pub mod module;
+pub mod pattern;
+pub mod print;
+pub mod resolve;
+#[cfg(any(test, feature = "test-util"))]
+pub mod temp_dir;
+pub mod test_def;
+pub mod visit;
+
+use diagnostic::Diagnostic;
+
+/// Lexes and parses `source` into a [`Module`].
+///
+/// Returns a [`Diagnostic`] pointing at the first character that couldn't be
+/// lexed or the first token that couldn't be parsed.
+pub fn parse_module(source: &str) -> Result<Module<'_>, Diagnostic> {
+    parse_lexed(source, &Lexed::new(source))
+}
+
+/// Parses the tokens lexed from `source` into a [`Module`].
+pub fn parse_lexed<'i>(source: &'i str, lexed: &Lexed<'i>) -> Result<Module<'i>, Diagnostic> {
+    let Lexed { tokens, offset } = lexed;
+    if let Some(c) = source[*offset..].chars().next() {
+        return Err(Diagnostic::new(
+            *offset..offset + c.len_utf8(),
+            format!("unexpected character `{c}`"),
+        ));
+    }
+
+    Module::parse.parse(tokens.as_ref()).map_err(|err| {
+        let (span, found) = match tokens.get(err.offset()) {
+            Some(Spanned(_, span)) => (span.clone(), "unexpected token"),
+            None => (source.len()..source.len(), "unexpected end of input"),
+        };
+        let context = err.inner().to_string();
+        let cause = err.inner().cause();
+        let message = if cause.is_some_and(|cause| cause.is::<TooDeep>()) {
+            TooDeep.to_string()
+        } else if context.is_empty() {
+            found.to_string()
+        } else {
+            format!("{found}: {}", context.replace('\n', "; "))
+        };
+        Diagnostic::new(span, message)
+    })
+}
+
+/// How deeply expressions, patterns and types may nest. Deeper input is an
+/// error rather than a stack overflow, in the parser or in the passes that
+/// walk what it parses.
+pub const MAX_NESTING: usize = 128;
+
+thread_local! {
+    /// How many expressions, patterns and types the parser is inside.
+    static DEPTH: Cell<usize> = const { Cell::new(0) };
+    /// The height of the tallest expression parsed since it was last taken.
+    /// Calls and field accesses nest to the left without the parser
+    /// recursing, so the depth alone doesn't bound the syntax tree.
+    static HEIGHT: Cell<usize> = const { Cell::new(0) };
+}
+
+/// The cause of an error for input nested more than [`MAX_NESTING`] levels
+/// deep.
+#[derive(Debug)]
+struct TooDeep;
+
+impl fmt::Display for TooDeep {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "nested more than {MAX_NESTING} levels deep")
+    }
+}
+
+impl std::error::Error for TooDeep {}
+
+/// Fails with [`TooDeep`], without backtracking.
+fn too_deep<S: Stream, O>(input: &mut S) -> ModalResult<O> {
+    cut_err(empty.try_map(|()| Err(TooDeep))).parse_next(input)
+}
+
+/// One level of nesting in the parser, left when dropped.
+struct Nesting {
+    depth: usize,
+}
+
+impl Nesting {
+    /// Enters a level of nesting, failing if that is more than
+    /// [`MAX_NESTING`].
+    fn enter<S: Stream>(input: &mut S) -> ModalResult<Self> {
+        let depth = DEPTH.get();
+        if depth == MAX_NESTING {
+            return too_deep(input);
+        }
+        DEPTH.set(depth + 1);
+        Ok(Self { depth })
+    }
+}
+
+impl Drop for Nesting {
+    fn drop(&mut self) {
+        DEPTH.set(self.depth);
+    }
+}
 
 /// Parses any token.
 fn token<'i, S>(input: &mut S) -> ModalResult<Spanned<Token<'i>>>
@@ -37,6 +150,56 @@ where
         .parse_next(input)
 }
 
+/// Parses an integer literal.
+///
+/// A literal too large for an `i64` is an error rather than a different kind
+/// of expression.
+fn int<'i, S>(input: &mut S) -> ModalResult<Spanned<i64>>
+where
+    S: Stream<Token = Spanned<Token<'i>>>,
+{
+    let checkpoint = input.checkpoint();
+    let Spanned(digits, span) = token
+        .verify_map(|Spanned(tok, span)| match tok {
+            Token::Int(digits) => Some(Spanned::new(digits, span)),
+            _ => None,
+        })
+        .context(StrContext::Expected(StrContextValue::Description(
+            "an integer",
+        )))
+        .parse_next(input)?;
+
+    match digits.parse() {
+        Ok(value) => Ok(Spanned::new(value, span)),
+        Err(_) => {
+            input.reset(&checkpoint);
+            Err(ErrMode::Cut(ContextError::new().add_context(
+                input,
+                &checkpoint,
+                StrContext::Expected(StrContextValue::Description(
+                    "an integer that fits in 64 bits",
+                )),
+            )))
+        }
+    }
+}
+
+/// Parses a string literal, returning the text between its quotes.
+fn string<'i, S>(input: &mut S) -> ModalResult<Spanned<&'i str>>
+where
+    S: Stream<Token = Spanned<Token<'i>>>,
+{
+    token
+        .verify_map(|Spanned(tok, span)| match tok {
+            Token::Str(text) => Some(Spanned::new(text, span)),
+            _ => None,
+        })
+        .context(StrContext::Expected(StrContextValue::Description(
+            "a string",
+        )))
+        .parse_next(input)
+}
+
 /// Parse the given keyword.
 fn keyword<'i, S>(kw: &'static str) -> impl FnMut(&mut S) -> ModalResult<Spanned<()>>
 where
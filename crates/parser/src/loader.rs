@@ -0,0 +1,312 @@
+//! Loading a module and the modules it imports from files.
+//!
+//! `import path/to/other` refers to the file `path/to/other.ria`, looked up
+//! relative to the directory of the importing file first and then in each
+//! directory of the search path. Each file is read and parsed once, however
+//! many modules import it.
+
+use std::{
+    collections::HashMap,
+    fmt, fs, io,
+    path::{Path, PathBuf},
+};
+
+use crate::{diagnostic::Diagnostic, import::Import, module::Module, parse_module};
+
+/// The extension of ria source files.
+pub const EXTENSION: &str = "ria";
+
+/// The index of a module in a [`Loader`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ModuleId(usize);
+
+/// A parsed file, and the modules its imports refer to.
+#[derive(Debug)]
+pub struct LoadedModule {
+    /// The canonical path of the file.
+    pub path: PathBuf,
+    /// The name of the file without its extension.
+    pub name: String,
+    /// The text of the file.
+    pub source: &'static str,
+    pub module: Module<'static>,
+    /// The module each of `module.imports()` refers to, in order.
+    pub imports: Box<[ModuleId]>,
+}
+
+/// Why a module couldn't be loaded.
+#[derive(Debug)]
+pub enum LoadError {
+    /// A file couldn't be read.
+    Io { path: PathBuf, error: io::Error },
+    /// A file was read, but doesn't parse or imports a module that can't be
+    /// found or would make a cycle.
+    Module {
+        path: PathBuf,
+        source: &'static str,
+        diagnostic: Diagnostic,
+    },
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadError::Io { path, error } => write!(f, "{}: {error}", path.display()),
+            LoadError::Module {
+                path, diagnostic, ..
+            } => write!(f, "{}: {}", path.display(), diagnostic.message),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {}
+
+/// Loads modules and their imports, caching them by path.
+///
+/// The sources of loaded modules are leaked, so their ASTs can borrow from
+/// them for the rest of the program. A `Loader` is meant to load a program
+/// once, not to reload files as they change.
+#[derive(Debug, Default)]
+pub struct Loader {
+    search_path: Vec<PathBuf>,
+    /// The loaded modules, each after the modules it imports.
+    modules: Vec<LoadedModule>,
+    cache: HashMap<PathBuf, ModuleId>,
+}
+
+impl Loader {
+    /// Creates a `Loader` that only finds imports relative to the importing
+    /// file.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a `Loader` that also looks for imports in each directory of
+    /// `search_path`, in order.
+    pub fn with_search_path(search_path: Vec<PathBuf>) -> Self {
+        Self {
+            search_path,
+            ..Self::default()
+        }
+    }
+
+    /// Loads the module at `path` and every module it imports, directly or
+    /// not.
+    pub fn load(&mut self, path: impl AsRef<Path>) -> Result<ModuleId, LoadError> {
+        let path = canonicalize(path.as_ref())?;
+        self.load_canonical(path, &mut Vec::new())
+    }
+
+    /// Returns the module with the given id.
+    pub fn module(&self, id: ModuleId) -> &LoadedModule {
+        &self.modules[id.0]
+    }
+
+    /// Returns every loaded module, each after the modules it imports.
+    pub fn modules(&self) -> impl Iterator<Item = (ModuleId, &LoadedModule)> {
+        self.modules
+            .iter()
+            .enumerate()
+            .map(|(index, module)| (ModuleId(index), module))
+    }
+
+    /// Loads the module at the canonical `path`, which is imported through
+    /// the modules being loaded in `stack`.
+    fn load_canonical(
+        &mut self,
+        path: PathBuf,
+        stack: &mut Vec<PathBuf>,
+    ) -> Result<ModuleId, LoadError> {
+        if let Some(&id) = self.cache.get(&path) {
+            return Ok(id);
+        }
+
+        let source = fs::read_to_string(&path).map_err(|error| LoadError::Io {
+            path: path.clone(),
+            error,
+        })?;
+        let source: &'static str = Box::leak(source.into_boxed_str());
+        let error = |path: &Path, diagnostic| LoadError::Module {
+            path: path.to_owned(),
+            source,
+            diagnostic,
+        };
+        let module = parse_module(source).map_err(|diagnostic| error(&path, diagnostic))?;
+
+        stack.push(path);
+        let mut imports = Vec::with_capacity(module.imports().len());
+        for import in module.imports() {
+            let importer = stack.last().expect("the module was pushed");
+            let Some(imported) = self.find(importer, import) else {
+                let message = format!("cannot find module `{}`", import.path_string());
+                return Err(error(
+                    importer,
+                    Diagnostic::new(import.span.clone(), message),
+                ));
+            };
+            if let Some(start) = stack.iter().position(|path| *path == imported) {
+                let cycle: Vec<_> = stack[start..]
+                    .iter()
+                    .chain([&imported])
+                    .map(|path| module_name(path))
+                    .collect();
+                let message = format!("import cycle: {}", cycle.join(" -> "));
+                return Err(error(
+                    importer,
+                    Diagnostic::new(import.span.clone(), message),
+                ));
+            }
+            imports.push(self.load_canonical(imported, stack)?);
+        }
+        let path = stack.pop().expect("the module was pushed");
+
+        let id = ModuleId(self.modules.len());
+        self.cache.insert(path.clone(), id);
+        self.modules.push(LoadedModule {
+            name: module_name(&path),
+            path,
+            source,
+            module,
+            imports: imports.into(),
+        });
+        Ok(id)
+    }
+
+    /// Returns the canonical path of the file `import` in `importer` refers
+    /// to, if there is one.
+    fn find(&self, importer: &Path, import: &Import) -> Option<PathBuf> {
+        let mut relative: PathBuf = import.path.iter().map(|segment| segment.0).collect();
+        relative.set_extension(EXTENSION);
+
+        importer
+            .parent()
+            .into_iter()
+            .chain(self.search_path.iter().map(PathBuf::as_path))
+            .map(|dir| dir.join(&relative))
+            .find(|path| path.is_file())
+            .and_then(|path| path.canonicalize().ok())
+    }
+}
+
+fn canonicalize(path: &Path) -> Result<PathBuf, LoadError> {
+    path.canonicalize().map_err(|error| LoadError::Io {
+        path: path.to_owned(),
+        error,
+    })
+}
+
+fn module_name(path: &Path) -> String {
+    path.file_stem()
+        .map_or_else(String::new, |stem| stem.to_string_lossy().into_owned())
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::{Path, PathBuf};
+
+    use crate::temp_dir::TempDir;
+
+    use super::{LoadError, Loader};
+
+    fn names(loader: &Loader) -> Vec<&str> {
+        loader
+            .modules()
+            .map(|(_, module)| module.name.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn load_each_file_once() {
+        let dir = TempDir::new(
+            "loader-once",
+            &[
+                ("main.ria", "import lib/a\nimport lib/b\nmain = a.x"),
+                ("lib/a.ria", "import c\nx = c.z"),
+                ("lib/b.ria", "import c (z)\ny = z"),
+                ("lib/c.ria", "z = 1"),
+            ],
+        );
+        let mut loader = Loader::new();
+        let main = loader.load(dir.join("main.ria")).unwrap();
+
+        // `c` is imported twice, but loaded once and before its importers
+        assert_eq!(names(&loader), ["c", "a", "b", "main"]);
+        let main = loader.module(main);
+        let a = loader.module(main.imports[0]);
+        let b = loader.module(main.imports[1]);
+        assert_eq!(a.imports, b.imports);
+        assert_eq!(a.path, dir.join("lib/a.ria").canonicalize().unwrap());
+
+        // loading again reuses the cache
+        loader.load(dir.join("lib/../main.ria")).unwrap();
+        assert_eq!(loader.modules().count(), 4);
+    }
+
+    #[test]
+    fn use_the_search_path() {
+        let dir = TempDir::new(
+            "loader-search",
+            &[
+                (
+                    "src/main.ria",
+                    "import util\nimport std/list\nmain = util.x",
+                ),
+                ("src/util.ria", "x = 1"),
+                ("lib/util.ria", "x = 2"),
+                ("lib/std/list.ria", "y = 3"),
+            ],
+        );
+        let mut loader = Loader::with_search_path(vec![dir.join("lib")]);
+        let main = loader.load(dir.join("src/main.ria")).unwrap();
+
+        // files next to the importer come first
+        let imports = &loader.module(main).imports;
+        let util = loader.module(imports[0]);
+        assert_eq!(util.path, dir.join("src/util.ria").canonicalize().unwrap());
+        let list = loader.module(imports[1]);
+        assert_eq!(
+            list.path,
+            dir.join("lib/std/list.ria").canonicalize().unwrap()
+        );
+    }
+
+    fn load_error(dir: &Path, path: &str) -> (PathBuf, String, String) {
+        match Loader::new().load(dir.join(path)) {
+            Err(LoadError::Module {
+                path,
+                source,
+                diagnostic,
+            }) => (path, source[diagnostic.span].to_owned(), diagnostic.message),
+            result => panic!("expected a module error, found {result:?}"),
+        }
+    }
+
+    #[test]
+    fn report_import_cycles() {
+        let dir = TempDir::new(
+            "loader-cycle",
+            &[
+                ("main.ria", "import a\nmain = a.x"),
+                ("a.ria", "import b\nx = b.y"),
+                ("b.ria", "import a (x)\ny = x"),
+            ],
+        );
+        let (path, span, message) = load_error(&dir, "main.ria");
+        assert_eq!(path, dir.join("b.ria").canonicalize().unwrap());
+        assert_eq!(span, "import a (x)");
+        assert_eq!(message, "import cycle: a -> b -> a");
+    }
+
+    #[test]
+    fn report_missing_modules() {
+        let dir = TempDir::new("loader-missing", &[("main.ria", "import x/y\nmain = y.z")]);
+        let (_, span, message) = load_error(&dir, "main.ria");
+        assert_eq!(span, "import x/y");
+        assert_eq!(message, "cannot find module `x/y`");
+
+        assert!(matches!(
+            Loader::new().load(dir.join("other.ria")),
+            Err(LoadError::Io { .. })
+        ));
+    }
+}
@@ -1,23 +1,135 @@
 use ria_lexer::{Spanned, Token};
-use winnow::{stream::Stream, ModalResult, Parser};
+use winnow::{
+    combinator::{opt, repeat, terminated},
+    stream::Stream,
+    ModalResult, Parser,
+};
 
-use crate::{def::DefList, maybe_newline};
+use crate::{
+    def::DefList, export::Export, import::Import, maybe_newline, newline, test_def::TestDef,
+};
+
+/// The declarations at the top of a module, before its definitions.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Header<'i> {
+    /// The defs other modules may import, or `None` if they may import any.
+    pub export: Option<Export<'i>>,
+    pub imports: Box<[Import<'i>]>,
+}
+
+impl<'i> Header<'i> {
+    /// Parses a `Header`: an optional export, then imports, each ending with
+    /// a newline.
+    pub fn parse<S>(input: &mut S) -> ModalResult<Self>
+    where
+        S: Stream<Token = Spanned<Token<'i>>>,
+    {
+        let export = opt(terminated(Export::parse, newline)).parse_next(input)?;
+        let imports: Vec<_> = repeat(0.., terminated(Import::parse, newline)).parse_next(input)?;
+        Ok(Self {
+            export,
+            imports: imports.into(),
+        })
+    }
+
+    /// Returns the end of the last declaration, if there are any.
+    pub fn end(&self) -> Option<usize> {
+        match self.imports.last() {
+            Some(import) => Some(import.span.end),
+            None => self.export.as_ref().map(|export| export.span.end),
+        }
+    }
+}
 
 /// A module - a file.
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Module<'i> {
+    header: Header<'i>,
     /// The top-level definitions in the file.
     defs: DefList<'i>,
+    /// The tests declared among the definitions, in source order.
+    tests: Box<[TestDef<'i>]>,
 }
 
 impl<'i> Module<'i> {
+    /// Creates a new `Module` from its top-level definitions.
+    pub fn new(defs: DefList<'i>) -> Self {
+        Self::with_header(Header::default(), defs)
+    }
+
+    /// Creates a new `Module` from its header and top-level definitions.
+    pub fn with_header(header: Header<'i>, defs: DefList<'i>) -> Self {
+        Self {
+            header,
+            defs,
+            tests: Box::new([]),
+        }
+    }
+
+    /// Returns the module with `tests` instead of its tests.
+    pub fn with_tests(self, tests: Box<[TestDef<'i>]>) -> Self {
+        Self { tests, ..self }
+    }
+
     /// Parses a `Module`.
     pub fn parse<S>(input: &mut S) -> ModalResult<Self>
     where
         S: Stream<Token = Spanned<Token<'i>>>,
     {
-        let defs = DefList::parse.parse_next(input)?;
+        let header = Header::parse.parse_next(input)?;
+        let (defs, tests) = DefList::parse_with_tests.parse_next(input)?;
         maybe_newline(input);
-        Ok(Self { defs })
+        Ok(Self::with_header(header, defs).with_tests(tests.into()))
+    }
+
+    /// Returns the declarations at the top of the module.
+    pub fn header(&self) -> &Header<'i> {
+        &self.header
+    }
+
+    /// Returns the imports of the module.
+    pub fn imports(&self) -> &[Import<'i>] {
+        &self.header.imports
+    }
+
+    /// Returns whether other modules may import the def called `name`.
+    ///
+    /// This doesn't check that the module defines `name`.
+    pub fn is_exported(&self, name: &str) -> bool {
+        self.header
+            .export
+            .as_ref()
+            .is_none_or(|export| export.contains(name))
+    }
+
+    /// Returns the top-level definitions in the module.
+    pub fn defs(&self) -> &DefList<'i> {
+        &self.defs
+    }
+
+    /// Returns the top-level definitions in the module, mutably.
+    pub fn defs_mut(&mut self) -> &mut DefList<'i> {
+        &mut self.defs
+    }
+
+    /// Returns the tests declared in the module.
+    pub fn tests(&self) -> &[TestDef<'i>] {
+        &self.tests
+    }
+
+    /// Returns the tests declared in the module, mutably.
+    pub fn tests_mut(&mut self) -> &mut [TestDef<'i>] {
+        &mut self.tests
+    }
+
+    /// Consumes the module, returning its top-level definitions.
+    pub fn into_defs(self) -> DefList<'i> {
+        self.defs
+    }
+
+    /// Consumes the module, returning its header, top-level definitions and
+    /// tests.
+    pub fn into_parts(self) -> (Header<'i>, DefList<'i>, Box<[TestDef<'i>]>) {
+        (self.header, self.defs, self.tests)
     }
 }
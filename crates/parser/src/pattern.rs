@@ -0,0 +1,181 @@
+//! The patterns of `match` arms.
+
+use std::ops::Range;
+
+use ria_lexer::{Spanned, Symbol, Token};
+use winnow::{
+    combinator::{alt, cut_err, repeat},
+    error::{StrContext, StrContextValue},
+    stream::Stream,
+    ModalResult, Parser,
+};
+
+use crate::{data::is_constructor_name, ident, int, symbol, Nesting};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Pattern<'i> {
+    /// `_`, which matches any value.
+    Wildcard(Range<usize>),
+    /// A name, which matches any value and binds it.
+    Variable(Spanned<&'i str>),
+    /// An integer literal, which matches that integer.
+    Int(Spanned<i64>),
+    /// A constructor and patterns for its fields, like `Some (Cons x _)`.
+    Constructor(ConstructorPattern<'i>),
+}
+
+/// A [`Pattern`] that matches the values built with one constructor.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConstructorPattern<'i> {
+    pub name: Spanned<&'i str>,
+    /// The patterns for the constructor's fields, in order.
+    pub args: Box<[Pattern<'i>]>,
+    /// The span of the pattern, from the constructor to the end of its last
+    /// argument.
+    pub span: Range<usize>,
+}
+
+impl<'i> Pattern<'i> {
+    /// Parses a `Pattern`: a constructor applied to arguments, or an atom.
+    pub fn parse<S>(input: &mut S) -> ModalResult<Self>
+    where
+        S: Stream<Token = Spanned<Token<'i>>>,
+    {
+        let _nesting = Nesting::enter(input)?;
+        alt((
+            (constructor, repeat(1.., Pattern::parse_atom)).map(
+                |(name, args): (Spanned<_>, Vec<Spanned<_>>)| {
+                    let end = args.last().map_or(name.end(), Spanned::end);
+                    Pattern::Constructor(ConstructorPattern {
+                        span: name.start()..end,
+                        name,
+                        args: args.into_iter().map(|arg| arg.0).collect(),
+                    })
+                },
+            ),
+            Pattern::parse_atom.map(|atom| atom.0),
+        ))
+        .context(StrContext::Expected(StrContextValue::Description(
+            "a pattern",
+        )))
+        .parse_next(input)
+    }
+
+    /// Parses a pattern that can be an argument without parentheses,
+    /// spanning any parentheses.
+    fn parse_atom<S>(input: &mut S) -> ModalResult<Spanned<Self>>
+    where
+        S: Stream<Token = Spanned<Token<'i>>>,
+    {
+        alt((
+            ident.map(|Spanned(name, span)| {
+                let pattern = if name == "_" {
+                    Pattern::Wildcard(span.clone())
+                } else if is_constructor_name(name) {
+                    Pattern::Constructor(ConstructorPattern {
+                        name: Spanned(name, span.clone()),
+                        args: [].into(),
+                        span: span.clone(),
+                    })
+                } else {
+                    Pattern::Variable(Spanned(name, span.clone()))
+                };
+                Spanned(pattern, span)
+            }),
+            int.map(|int| Spanned(Pattern::Int(int.clone()), int.1)),
+            (
+                symbol(&Symbol::OpenParen),
+                cut_err(Pattern::parse),
+                cut_err(symbol(&Symbol::CloseParen)),
+            )
+                .map(|(open, pattern, close)| Spanned(pattern, open.start()..close.end())),
+        ))
+        .parse_next(input)
+    }
+
+    /// Returns the span of the pattern, not counting parentheses around the
+    /// whole of it.
+    pub fn span(&self) -> Range<usize> {
+        match self {
+            Pattern::Wildcard(span) => span.clone(),
+            Pattern::Variable(Spanned(_, span)) => span.clone(),
+            Pattern::Int(Spanned(_, span)) => span.clone(),
+            Pattern::Constructor(constructor) => constructor.span.clone(),
+        }
+    }
+
+    /// Calls `f` with each variable the pattern binds, from left to right.
+    pub fn for_each_variable(&self, f: &mut impl FnMut(&Spanned<&'i str>)) {
+        match self {
+            Pattern::Variable(name) => f(name),
+            Pattern::Constructor(constructor) => {
+                for arg in constructor.args.iter() {
+                    arg.for_each_variable(f);
+                }
+            }
+            Pattern::Wildcard(_) | Pattern::Int(_) => {}
+        }
+    }
+}
+
+/// Parses the name of a constructor.
+fn constructor<'i, S>(input: &mut S) -> ModalResult<Spanned<&'i str>>
+where
+    S: Stream<Token = Spanned<Token<'i>>>,
+{
+    ident
+        .verify(|name: &Spanned<&str>| is_constructor_name(name.0))
+        .parse_next(input)
+}
+
+#[cfg(test)]
+mod test {
+    use ria_lexer::{Lexer, Spanned};
+    use winnow::Parser;
+
+    use super::{ConstructorPattern, Pattern};
+
+    fn parse(source: &str) -> Pattern<'_> {
+        let tokens: Box<_> = Lexer::new(source).collect();
+        Pattern::parse.parse(tokens.as_ref()).unwrap()
+    }
+
+    #[test]
+    fn parse_patterns() {
+        assert_eq!(parse("_"), Pattern::Wildcard(0..1));
+        assert_eq!(parse("x"), Pattern::Variable(Spanned("x", 0..1)));
+        assert_eq!(parse("(7)"), Pattern::Int(Spanned(7, 1..2)));
+        assert_eq!(
+            parse("None"),
+            Pattern::Constructor(ConstructorPattern {
+                name: Spanned("None", 0..4),
+                args: [].into(),
+                span: 0..4,
+            })
+        );
+
+        let source = "Cons x (Cons _ Nil)";
+        let pattern = parse(source);
+        assert_eq!(&source[pattern.span()], source);
+        let Pattern::Constructor(cons) = &pattern else {
+            panic!("expected a constructor pattern");
+        };
+        assert_eq!(cons.args.len(), 2);
+        assert!(matches!(
+            &cons.args[1],
+            Pattern::Constructor(inner) if inner.args.len() == 2
+        ));
+
+        let mut variables = Vec::new();
+        parse("Pair a (Pair b _)").for_each_variable(&mut |name| variables.push(name.0));
+        assert_eq!(variables, ["a", "b"]);
+    }
+
+    #[test]
+    fn reject_bad_patterns() {
+        for source in ["x y", "(Some x", "->"] {
+            let tokens: Box<_> = Lexer::new(source).collect();
+            assert!(Pattern::parse.parse(tokens.as_ref()).is_err(), "{source}");
+        }
+    }
+}
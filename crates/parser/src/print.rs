@@ -0,0 +1,359 @@
+//! Printing an AST back to source.
+//!
+//! The printer puts each declaration, def and test of a module on a line of
+//! its own, and everything inside a def on that line, with blocks separated by
+//! `;`. It adds parentheses only where the parser needs them, so the source
+//! parses back to the same AST, apart from spans.
+
+use crate::{
+    data::{Type, TypeDef},
+    def::DefList,
+    export::Export,
+    expr::{Expr, Field},
+    import::Import,
+    module::Module,
+    pattern::Pattern,
+};
+
+/// Prints `module` as source, ending each line with a newline.
+pub fn print_module(module: &Module) -> String {
+    let mut printer = Printer::default();
+    printer.module(module);
+    printer.out
+}
+
+/// Prints `expr` as source.
+pub fn print_expr(expr: &Expr) -> String {
+    let mut printer = Printer::default();
+    printer.expr(expr, Position::Open);
+    printer.out
+}
+
+/// What may follow an expression where it is printed, which decides which
+/// expressions need parentheses there.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Position {
+    /// Nothing that could continue the expression, so it may end with a
+    /// lambda or a match.
+    Open,
+    /// More arms of a match, which a match at the end would take as its own.
+    Arm,
+    /// The function of a call, which can't be a lambda or a match, since
+    /// its body would take the argument.
+    Func,
+    /// An argument of a call, or what a field is read from, which must be
+    /// an atom.
+    Atom,
+}
+
+#[derive(Default)]
+struct Printer {
+    out: String,
+}
+
+impl Printer {
+    fn module(&mut self, module: &Module) {
+        let header = module.header();
+        if let Some(export) = &header.export {
+            self.export(export);
+            self.out.push('\n');
+        }
+        for import in header.imports.iter() {
+            self.import(import);
+            self.out.push('\n');
+        }
+        self.def_list(module.defs(), "\n");
+        if !module.defs().types.is_empty() || !module.defs().defs.is_empty() {
+            self.out.push('\n');
+        }
+        for test in module.tests() {
+            self.out.push_str("test \"");
+            self.out.push_str(test.name.0);
+            self.out.push_str("\" = ");
+            self.expr(&test.expr, Position::Open);
+            self.out.push('\n');
+        }
+    }
+
+    fn export(&mut self, export: &Export) {
+        self.out.push_str("export (");
+        self.names(export.names.iter().map(|name| name.0));
+        self.out.push(')');
+    }
+
+    fn import(&mut self, import: &Import) {
+        self.out.push_str("import ");
+        self.out.push_str(&import.path_string());
+        if let Some(names) = &import.names {
+            self.out.push_str(" (");
+            self.names(names.iter().map(|name| name.0));
+            self.out.push(')');
+        }
+    }
+
+    fn names<'a>(&mut self, names: impl Iterator<Item = &'a str>) {
+        for (index, name) in names.enumerate() {
+            if index > 0 {
+                self.out.push_str(", ");
+            }
+            self.out.push_str(name);
+        }
+    }
+
+    /// Prints the type declarations of `defs`, then its defs, each followed
+    /// by `separator` but the last.
+    fn def_list(&mut self, defs: &DefList, separator: &str) {
+        let mut first = true;
+        let mut separate = |out: &mut String| {
+            if !first {
+                out.push_str(separator);
+            }
+            first = false;
+        };
+        for type_def in defs.types.iter() {
+            separate(&mut self.out);
+            self.type_def(type_def);
+        }
+        for def in defs.defs.iter() {
+            separate(&mut self.out);
+            self.out.push_str(def.ident.0);
+            self.out.push_str(" = ");
+            self.expr(&def.expr, Position::Open);
+        }
+    }
+
+    fn type_def(&mut self, type_def: &TypeDef) {
+        self.out.push_str("type ");
+        self.out.push_str(type_def.name.0);
+        for param in type_def.params.iter() {
+            self.out.push(' ');
+            self.out.push_str(param.0);
+        }
+        self.out.push_str(" =");
+        for (index, constructor) in type_def.constructors.iter().enumerate() {
+            if index > 0 {
+                self.out.push_str(" |");
+            }
+            self.out.push(' ');
+            self.out.push_str(constructor.name.0);
+            for field in constructor.fields.iter() {
+                self.out.push(' ');
+                self.type_atom(field);
+            }
+        }
+    }
+
+    fn ty(&mut self, ty: &Type) {
+        match ty {
+            Type::Name(name) => self.out.push_str(name.0),
+            Type::Apply(func, arg) => {
+                match **func {
+                    Type::Function(..) => self.type_atom(func),
+                    _ => self.ty(func),
+                }
+                self.out.push(' ');
+                self.type_atom(arg);
+            }
+            Type::Function(param, result) => {
+                match **param {
+                    Type::Function(..) => self.type_atom(param),
+                    _ => self.ty(param),
+                }
+                self.out.push_str(" -> ");
+                self.ty(result);
+            }
+        }
+    }
+
+    fn type_atom(&mut self, ty: &Type) {
+        match ty {
+            Type::Name(name) => self.out.push_str(name.0),
+            _ => {
+                self.out.push('(');
+                self.ty(ty);
+                self.out.push(')');
+            }
+        }
+    }
+
+    fn expr(&mut self, expr: &Expr, position: Position) {
+        let parenthesize = match expr {
+            Expr::Call(_) => position == Position::Atom,
+            Expr::Lambda(_) => matches!(position, Position::Func | Position::Atom),
+            Expr::Match(_) => position != Position::Open,
+            _ => false,
+        };
+        if parenthesize {
+            self.out.push('(');
+            self.expr(expr, Position::Open);
+            self.out.push(')');
+            return;
+        }
+
+        match expr {
+            Expr::Variable(name) => self.out.push_str(name.0),
+            Expr::Qualified(qualified) => {
+                self.out.push_str(qualified.module.0);
+                self.out.push('.');
+                self.out.push_str(qualified.name.0);
+            }
+            Expr::Int(int) => self.out.push_str(&int.0.to_string()),
+            Expr::Lambda(lambda) => {
+                self.out.push('\\');
+                self.out.push_str(lambda.param.0);
+                self.out.push_str(" -> ");
+                self.expr(&lambda.body, position);
+            }
+            Expr::Block(block) => {
+                self.out.push('(');
+                self.def_list(&block.defs, "; ");
+                self.out.push(';');
+                if let Some(expr) = &block.expr {
+                    self.out.push(' ');
+                    self.expr(expr, Position::Open);
+                }
+                self.out.push(')');
+            }
+            Expr::Call(call) => {
+                self.expr(&call.func, Position::Func);
+                self.out.push(' ');
+                self.expr(&call.arg, Position::Atom);
+            }
+            Expr::Match(matching) => {
+                self.out.push_str("match ");
+                self.expr(&matching.scrutinee, Position::Arm);
+                self.out.push_str(" with");
+                for (index, arm) in matching.arms.iter().enumerate() {
+                    self.out.push_str(" | ");
+                    self.pattern(&arm.pattern, false);
+                    self.out.push_str(" -> ");
+                    let last = index == matching.arms.len() - 1;
+                    let position = if last { position } else { Position::Arm };
+                    self.expr(&arm.body, position);
+                }
+            }
+            Expr::Tuple(tuple) => {
+                self.out.push('(');
+                for (index, item) in tuple.items.iter().enumerate() {
+                    if index > 0 {
+                        self.out.push_str(", ");
+                    }
+                    self.expr(item, Position::Open);
+                }
+                self.out.push(')');
+            }
+            Expr::Record(record) => {
+                if record.fields.is_empty() {
+                    self.out.push_str("{}");
+                    return;
+                }
+                self.out.push_str("{ ");
+                for (index, field) in record.fields.iter().enumerate() {
+                    if index > 0 {
+                        self.out.push_str(", ");
+                    }
+                    self.out.push_str(field.name.0);
+                    self.out.push_str(" = ");
+                    self.expr(&field.value, Position::Open);
+                }
+                self.out.push_str(" }");
+            }
+            Expr::Access(access) => {
+                // `a.b` would be qualified, so a variable is grouped instead
+                match (&*access.expr, access.field.0) {
+                    (Expr::Variable(_), Field::Name(_)) => {
+                        self.out.push('(');
+                        self.expr(&access.expr, Position::Open);
+                        self.out.push(')');
+                    }
+                    _ => self.expr(&access.expr, Position::Atom),
+                }
+                self.out.push('.');
+                self.out.push_str(&access.field.0.to_string());
+            }
+        }
+    }
+
+    /// Prints `pattern`, in parentheses if it is an `atom` that needs them.
+    fn pattern(&mut self, pattern: &Pattern, atom: bool) {
+        match pattern {
+            Pattern::Wildcard(_) => self.out.push('_'),
+            Pattern::Variable(name) => self.out.push_str(name.0),
+            Pattern::Int(int) => self.out.push_str(&int.0.to_string()),
+            Pattern::Constructor(constructor) => {
+                let parenthesize = atom && !constructor.args.is_empty();
+                if parenthesize {
+                    self.out.push('(');
+                }
+                self.out.push_str(constructor.name.0);
+                for arg in constructor.args.iter() {
+                    self.out.push(' ');
+                    self.pattern(arg, true);
+                }
+                if parenthesize {
+                    self.out.push(')');
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod arbitrary;
+
+#[cfg(test)]
+mod test {
+    use proptest::prelude::*;
+    use ria_lexer::Lexer;
+    use winnow::Parser;
+
+    use crate::{expr::Expr, parse_module, visit::VisitorMut};
+
+    use super::{
+        arbitrary::{self, EraseSpans},
+        print_expr, print_module,
+    };
+
+    /// Parses `source` as an expression, with every span erased.
+    fn parse_expr(source: &str) -> Expr<'_> {
+        let tokens: Box<_> = Lexer::new(source).collect();
+        let mut expr = Expr::parse
+            .parse(tokens.as_ref())
+            .unwrap_or_else(|err| panic!("`{source}` should parse: {err:?}"));
+        EraseSpans.visit_expr_mut(&mut expr);
+        expr
+    }
+
+    #[test]
+    fn print_only_needed_parentheses() {
+        for source in [
+            "f (g x) (\\y -> y)",
+            "(\\x -> x) (match a with | A -> \\b -> b | _ -> 0)",
+            "match (match x with | A -> 1) with | B (C _) 2 -> \\y -> match y with | _ -> y",
+            "(a).b.0 (f x).c t.0 m.d.e",
+            "(type T a = A (a -> a) | B (T a); x = { a = 1, b = (2, 3) }; x)",
+            "(x = 1;)",
+        ] {
+            assert_eq!(print_expr(&parse_expr(source)), source);
+        }
+
+        let source = "export (a)\nimport x/y (B)\ntype T = A\nf = \\x -> x\ntest \"f a\" = f A\n";
+        assert_eq!(print_module(&parse_module(source).unwrap()), source);
+    }
+
+    proptest! {
+        #[test]
+        fn exprs_round_trip(expr in arbitrary::expr()) {
+            let source = print_expr(&expr);
+            prop_assert_eq!(parse_expr(&source), expr, "printed as `{}`", source);
+        }
+
+        #[test]
+        fn modules_round_trip(module in arbitrary::module()) {
+            let source = print_module(&module);
+            let parsed = parse_module(&source)
+                .unwrap_or_else(|err| panic!("`{source}` should parse: {err:?}"));
+            prop_assert_eq!(EraseSpans.module(parsed), module, "printed as `{}`", source);
+        }
+    }
+}
@@ -0,0 +1,334 @@
+//! Strategies that generate arbitrary ASTs, for property tests.
+//!
+//! Every span is `0..0`, so a generated AST can be compared with a parsed
+//! one once [`EraseSpans`] has been run over that.
+
+use proptest::{collection::vec, option, prelude::*};
+use ria_lexer::Spanned;
+
+use crate::{
+    data::{Constructor, Type, TypeDef},
+    def::{Def, DefList},
+    export::Export,
+    expr::{
+        Access, Arm, Block, Call, Expr, Field, Lambda, Match, Qualified, Record, RecordField, Tuple,
+    },
+    import::Import,
+    module::{Header, Module},
+    pattern::{ConstructorPattern, Pattern},
+    test_def::TestDef,
+    visit::{
+        walk_access_mut, walk_block_mut, walk_call_mut, walk_def_mut, walk_lambda_mut,
+        walk_match_mut, walk_record_mut, walk_tuple_mut, VisitorMut,
+    },
+};
+
+/// Names of variables, params, defs and fields, none of them keywords.
+const LOWER: &[&str] = &["a", "b", "f", "x", "go", "n1", "_t"];
+
+/// Names of tests.
+const TEST_NAMES: &[&str] = &["", "a", "adds 1 to x", "it's (not) = x -> y"];
+
+/// Names of types and constructors.
+const UPPER: &[&str] = &["A", "B", "Cons", "Nil", "T"];
+
+fn spanned<T>(value: T) -> Spanned<T> {
+    Spanned(value, 0..0)
+}
+
+fn lower() -> impl Strategy<Value = Spanned<&'static str>> {
+    prop::sample::select(LOWER).prop_map(spanned)
+}
+
+fn upper() -> impl Strategy<Value = Spanned<&'static str>> {
+    prop::sample::select(UPPER).prop_map(spanned)
+}
+
+/// Any name that can be a variable in an expression.
+fn name() -> impl Strategy<Value = Spanned<&'static str>> {
+    prop_oneof![lower(), upper()]
+}
+
+fn int() -> impl Strategy<Value = Spanned<i64>> {
+    (0..=i64::MAX).prop_map(spanned)
+}
+
+pub fn ty() -> impl Strategy<Value = Type<'static>> {
+    let leaf = prop_oneof![lower(), upper()].prop_map(Type::Name);
+    leaf.prop_recursive(3, 8, 2, |inner| {
+        prop_oneof![
+            (inner.clone(), inner.clone())
+                .prop_map(|(func, arg)| Type::Apply(func.into(), arg.into())),
+            (inner.clone(), inner)
+                .prop_map(|(param, result)| Type::Function(param.into(), result.into())),
+        ]
+    })
+}
+
+pub fn type_def() -> impl Strategy<Value = TypeDef<'static>> {
+    let constructor = (upper(), vec(ty(), 0..3)).prop_map(|(name, fields)| Constructor {
+        name,
+        fields: fields.into(),
+        span: 0..0,
+    });
+    (upper(), vec(lower(), 0..3), vec(constructor, 1..4)).prop_map(
+        |(name, params, constructors)| TypeDef {
+            name,
+            params: params.into(),
+            constructors: constructors.into(),
+            span: 0..0,
+        },
+    )
+}
+
+pub fn pattern() -> impl Strategy<Value = Pattern<'static>> {
+    let leaf = prop_oneof![
+        Just(Pattern::Wildcard(0..0)),
+        lower().prop_map(Pattern::Variable),
+        int().prop_map(Pattern::Int),
+        upper().prop_map(|name| Pattern::Constructor(ConstructorPattern {
+            name,
+            args: [].into(),
+            span: 0..0,
+        })),
+    ];
+    leaf.prop_recursive(3, 12, 3, |inner| {
+        (upper(), vec(inner, 1..4)).prop_map(|(name, args)| {
+            Pattern::Constructor(ConstructorPattern {
+                name,
+                args: args.into(),
+                span: 0..0,
+            })
+        })
+    })
+}
+
+/// Generates def lists whose defs have values from `expr`. Unless `empty`,
+/// they have at least one def or type, like the def list of a block.
+fn def_list(
+    expr: impl Strategy<Value = Expr<'static>>,
+    empty: bool,
+) -> impl Strategy<Value = DefList<'static>> {
+    let def = (lower(), expr).prop_map(|(ident, expr)| Def { ident, expr });
+    (vec(type_def(), 0..2), vec(def, 0..3))
+        .prop_filter("blocks have a def or a type", move |(types, defs)| {
+            empty || !types.is_empty() || !defs.is_empty()
+        })
+        .prop_map(|(types, defs)| DefList {
+            types: types.into(),
+            defs: defs.into(),
+        })
+}
+
+pub fn expr() -> impl Strategy<Value = Expr<'static>> {
+    let leaf = prop_oneof![
+        name().prop_map(Expr::Variable),
+        (lower(), name()).prop_map(|(module, name)| Expr::Qualified(Qualified { module, name })),
+        int().prop_map(Expr::Int),
+    ];
+    leaf.prop_recursive(4, 32, 4, |inner| {
+        let field = prop_oneof![
+            lower().prop_map(|name| name.map(Field::Name)),
+            (0..20usize).prop_map(|index| spanned(Field::Index(index))),
+        ];
+        prop_oneof![
+            (lower(), inner.clone()).prop_map(|(param, body)| Expr::Lambda(Lambda {
+                param,
+                body: body.into(),
+            })),
+            (inner.clone(), inner.clone()).prop_map(|(func, arg)| Expr::Call(Call {
+                func: func.into(),
+                arg: arg.into(),
+                span: 0..0,
+            })),
+            (def_list(inner.clone(), false), option::of(inner.clone())).prop_map(|(defs, expr)| {
+                Expr::Block(Block {
+                    defs,
+                    expr: expr.map(Box::new),
+                    span: 0..0,
+                })
+            }),
+            (inner.clone(), vec((pattern(), inner.clone()), 1..4)).prop_map(|(scrutinee, arms)| {
+                Expr::Match(Match {
+                    scrutinee: scrutinee.into(),
+                    arms: arms
+                        .into_iter()
+                        .map(|(pattern, body)| Arm { pattern, body })
+                        .collect(),
+                    span: 0..0,
+                })
+            }),
+            vec(inner.clone(), 2..4).prop_map(|items| Expr::Tuple(Tuple {
+                items: items.into(),
+                span: 0..0,
+            })),
+            vec((lower(), inner.clone()), 0..3).prop_map(|fields| Expr::Record(Record {
+                fields: fields
+                    .into_iter()
+                    .map(|(name, value)| RecordField { name, value })
+                    .collect(),
+                span: 0..0,
+            })),
+            (inner, field).prop_map(|(expr, field)| Expr::Access(Access {
+                expr: expr.into(),
+                field,
+                span: 0..0,
+            })),
+        ]
+    })
+}
+
+pub fn module() -> impl Strategy<Value = Module<'static>> {
+    let export = vec(lower(), 0..3).prop_map(|names| Export {
+        names: names.into(),
+        span: 0..0,
+    });
+    let import =
+        (vec(lower(), 1..3), option::of(vec(name(), 0..3))).prop_map(|(path, names)| Import {
+            path: path.into(),
+            names: names.map(Vec::into_boxed_slice),
+            span: 0..0,
+        });
+    let test = (prop::sample::select(TEST_NAMES), expr()).prop_map(|(name, expr)| TestDef {
+        name: spanned(name),
+        expr,
+        span: 0..0,
+    });
+    (
+        option::of(export),
+        vec(import, 0..3),
+        def_list(expr(), true),
+        vec(test, 0..3),
+    )
+        .prop_map(|(export, imports, defs, tests)| {
+            let header = Header {
+                export,
+                imports: imports.into(),
+            };
+            Module::with_header(header, defs).with_tests(tests.into())
+        })
+}
+
+/// Sets every span in an AST to `0..0`.
+pub struct EraseSpans;
+
+impl EraseSpans {
+    pub fn module<'i>(&mut self, module: Module<'i>) -> Module<'i> {
+        let (mut header, mut defs, mut tests) = module.into_parts();
+        if let Some(export) = &mut header.export {
+            export.span = 0..0;
+            export.names.iter_mut().for_each(erase);
+        }
+        for import in header.imports.iter_mut() {
+            import.span = 0..0;
+            import.path.iter_mut().for_each(erase);
+            import.names.iter_mut().flatten().for_each(erase);
+        }
+        self.visit_def_list_mut(&mut defs);
+        for test in tests.iter_mut() {
+            test.span = 0..0;
+            erase(&mut test.name);
+            self.visit_expr_mut(&mut test.expr);
+        }
+        Module::with_header(header, defs).with_tests(tests)
+    }
+}
+
+fn erase<T>(spanned: &mut Spanned<T>) {
+    spanned.1 = 0..0;
+}
+
+fn erase_type(ty: &mut Type) {
+    match ty {
+        Type::Name(name) => erase(name),
+        Type::Apply(func, arg) | Type::Function(func, arg) => {
+            erase_type(func);
+            erase_type(arg);
+        }
+    }
+}
+
+impl<'i> VisitorMut<'i> for EraseSpans {
+    fn visit_type_def_mut(&mut self, type_def: &mut TypeDef<'i>) {
+        type_def.span = 0..0;
+        erase(&mut type_def.name);
+        type_def.params.iter_mut().for_each(erase);
+        for constructor in type_def.constructors.iter_mut() {
+            constructor.span = 0..0;
+            erase(&mut constructor.name);
+            constructor.fields.iter_mut().for_each(erase_type);
+        }
+    }
+
+    fn visit_def_mut(&mut self, def: &mut Def<'i>) {
+        erase(&mut def.ident);
+        walk_def_mut(self, def);
+    }
+
+    fn visit_variable_mut(&mut self, ident: &mut Spanned<&'i str>) {
+        erase(ident);
+    }
+
+    fn visit_qualified_mut(&mut self, qualified: &mut Qualified<'i>) {
+        erase(&mut qualified.module);
+        erase(&mut qualified.name);
+    }
+
+    fn visit_int_mut(&mut self, int: &mut Spanned<i64>) {
+        erase(int);
+    }
+
+    fn visit_lambda_mut(&mut self, lambda: &mut Lambda<'i>) {
+        erase(&mut lambda.param);
+        walk_lambda_mut(self, lambda);
+    }
+
+    fn visit_block_mut(&mut self, block: &mut Block<'i>) {
+        block.span = 0..0;
+        walk_block_mut(self, block);
+    }
+
+    fn visit_match_mut(&mut self, matching: &mut Match<'i>) {
+        matching.span = 0..0;
+        walk_match_mut(self, matching);
+    }
+
+    fn visit_pattern_mut(&mut self, pattern: &mut Pattern<'i>) {
+        match pattern {
+            Pattern::Wildcard(span) => *span = 0..0,
+            Pattern::Variable(name) => erase(name),
+            Pattern::Int(int) => erase(int),
+            Pattern::Constructor(constructor) => {
+                constructor.span = 0..0;
+                erase(&mut constructor.name);
+                for arg in constructor.args.iter_mut() {
+                    self.visit_pattern_mut(arg);
+                }
+            }
+        }
+    }
+
+    fn visit_tuple_mut(&mut self, tuple: &mut Tuple<'i>) {
+        tuple.span = 0..0;
+        walk_tuple_mut(self, tuple);
+    }
+
+    fn visit_record_mut(&mut self, record: &mut Record<'i>) {
+        record.span = 0..0;
+        for field in record.fields.iter_mut() {
+            erase(&mut field.name);
+        }
+        walk_record_mut(self, record);
+    }
+
+    fn visit_call_mut(&mut self, call: &mut Call<'i>) {
+        call.span = 0..0;
+        walk_call_mut(self, call);
+    }
+
+    fn visit_access_mut(&mut self, access: &mut Access<'i>) {
+        access.span = 0..0;
+        erase(&mut access.field);
+        walk_access_mut(self, access);
+    }
+}
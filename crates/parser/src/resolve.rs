@@ -0,0 +1,971 @@
+use std::{
+    collections::{hash_map::Entry, HashMap, HashSet},
+    ops::Range,
+};
+
+use ria_lexer::Spanned;
+
+use crate::{
+    def::DefList,
+    diagnostic::Diagnostic,
+    expr::{Access, Block, Expr, Field, Lambda, Match, Qualified, Record},
+    module::Module,
+    pattern::Pattern,
+    visit::{
+        walk_access, walk_block, walk_def_list, walk_lambda, walk_module, walk_record, Visitor,
+    },
+};
+
+use self::exhaustive::{DataType, Pat, PatKind};
+
+mod exhaustive;
+
+/// The kind of construct that introduced a [`Binding`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BindingKind {
+    /// A top-level or block `Def`.
+    Def,
+    /// A `Lambda` parameter.
+    Param,
+    /// A name listed in an `import`.
+    Import,
+    /// A constructor of a `type` declaration.
+    Constructor,
+    /// A variable in the pattern of a `match` arm.
+    Pattern,
+}
+
+/// A name introduced by a `Def`, a `Lambda` parameter, an `import`, a
+/// constructor or a pattern.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Binding {
+    pub kind: BindingKind,
+    /// The span of the identifier being bound.
+    pub ident: Range<usize>,
+    /// The span of the `Def`, `Lambda`, `import`, `type` declaration or
+    /// `match` arm that introduced the binding.
+    pub source: Range<usize>,
+}
+
+/// A use of a variable that was resolved to a [`Binding`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Reference {
+    /// The span of the variable.
+    pub span: Range<usize>,
+    /// The index of the binding in [`Resolution::bindings`].
+    pub binding: usize,
+}
+
+/// The result of resolving every variable in a [`Module`].
+#[derive(Debug, Default)]
+pub struct Resolution {
+    pub bindings: Vec<Binding>,
+    pub references: Vec<Reference>,
+    pub errors: Vec<Diagnostic>,
+    /// Problems that don't stop the module from running, like unreachable
+    /// `match` arms.
+    pub warnings: Vec<Diagnostic>,
+}
+
+impl Resolution {
+    /// Returns the index of the binding whose identifier or reference covers
+    /// `offset`, if any.
+    pub fn binding_at(&self, offset: usize) -> Option<usize> {
+        let covers = |span: &Range<usize>| span.start <= offset && offset <= span.end;
+        self.bindings
+            .iter()
+            .position(|binding| covers(&binding.ident))
+            .or_else(|| {
+                self.references
+                    .iter()
+                    .find(|reference| covers(&reference.span))
+                    .map(|reference| reference.binding)
+            })
+    }
+
+    /// Returns every reference to the binding at index `binding`.
+    pub fn references_to(&self, binding: usize) -> impl Iterator<Item = &Reference> {
+        self.references
+            .iter()
+            .filter(move |reference| reference.binding == binding)
+    }
+}
+
+/// Resolves every variable in `module` to the `Def` or `Lambda` parameter it
+/// refers to.
+///
+/// Definitions in a `DefList` are in scope for each other, so they may be
+/// recursive. Inner bindings shadow outer ones.
+///
+/// The patterns of each `match` are checked too: a match must cover every
+/// value of the type it matches, and arms that can never be reached are
+/// warnings. So are the fields read from record and tuple literals, and
+/// from defs bound to them.
+pub fn resolve(module: &Module) -> Resolution {
+    resolve_with_builtins(module, &[])
+}
+
+/// Like [`resolve`], but variables that aren't bound in `module` may also
+/// refer to `builtins`, names the host provides. Such variables resolve to
+/// no binding and aren't errors.
+///
+/// The module's imports aren't loaded, so any name they import is accepted.
+pub fn resolve_with_builtins(module: &Module, builtins: &[&str]) -> Resolution {
+    resolve_with_imports(module, builtins, &vec![None; module.imports().len()])
+}
+
+/// The top-level defs of a module, as seen by the modules importing it.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ModuleNames<'a> {
+    /// Every def and constructor of the module.
+    pub defs: Vec<&'a str>,
+    /// The defs and constructors other modules may import.
+    pub exported: Vec<&'a str>,
+    /// The name of each type the module declares, with the name and number
+    /// of fields of each of its constructors.
+    pub types: Vec<(&'a str, Vec<(&'a str, usize)>)>,
+}
+
+impl<'a> ModuleNames<'a> {
+    /// Returns the names of the defs of `module`.
+    ///
+    /// A type's constructors are exported if the type is.
+    pub fn of(module: &Module<'a>) -> Self {
+        let mut names = Self::default();
+        for type_def in module.defs().types.iter() {
+            let exported = module.is_exported(type_def.name.0);
+            let mut constructors = Vec::new();
+            for constructor in type_def.constructors.iter() {
+                names.defs.push(constructor.name.0);
+                if exported {
+                    names.exported.push(constructor.name.0);
+                }
+                constructors.push((constructor.name.0, constructor.arity()));
+            }
+            names.types.push((type_def.name.0, constructors));
+        }
+        for def in module.defs().defs.iter() {
+            names.defs.push(def.ident.0);
+            if module.is_exported(def.ident.0) {
+                names.exported.push(def.ident.0);
+            }
+        }
+        names
+    }
+
+    /// Returns the type with a constructor called `name`, and the index of
+    /// the constructor in it.
+    fn constructor(&self, name: &str) -> Option<(usize, usize)> {
+        self.types
+            .iter()
+            .enumerate()
+            .find_map(|(ty, (_, constructors))| {
+                let index = constructors
+                    .iter()
+                    .position(|constructor| constructor.0 == name)?;
+                Some((ty, index))
+            })
+    }
+}
+
+/// Like [`resolve_with_builtins`], but also checks the names used from each
+/// of the module's imports against `imports`, the defs of each imported
+/// module, in order. Only exported defs may be used. An import whose module
+/// is `None` accepts any name.
+///
+/// Names listed in an import are bound outside the module's defs, which
+/// shadow them.
+pub fn resolve_with_imports(
+    module: &Module,
+    builtins: &[&str],
+    imports: &[Option<ModuleNames>],
+) -> Resolution {
+    let mut resolver = Resolver {
+        scopes: vec![HashMap::new()],
+        builtins,
+        modules: HashMap::new(),
+        types: Vec::new(),
+        constructors: HashMap::new(),
+        unknown: HashSet::new(),
+        shapes: HashMap::new(),
+        resolution: Resolution::default(),
+    };
+
+    for (import, names) in module.imports().iter().zip(imports) {
+        let names = names.as_ref();
+        // the types of the imported module, in `resolver.types`
+        let types = resolver.types.len();
+        if let Some(names) = names {
+            resolver
+                .types
+                .extend(names.types.iter().map(|(name, constructors)| {
+                    DataType {
+                        name: name.to_string(),
+                        constructors: constructors
+                            .iter()
+                            .map(|(name, arity)| (name.to_string(), *arity))
+                            .collect(),
+                    }
+                }));
+        }
+        let alias = import.path.last().expect("paths aren't empty");
+        match resolver.modules.entry(alias.0) {
+            Entry::Vacant(entry) => {
+                entry.insert(names);
+            }
+            Entry::Occupied(_) => resolver.resolution.errors.push(Diagnostic::new(
+                alias.1.clone(),
+                format!("a module called `{}` is imported more than once", alias.0),
+            )),
+        }
+        for name in import.names.iter().flatten() {
+            resolver.check_member(alias.0, names, name);
+            let binding = resolver.bind(BindingKind::Import, name, import.span.clone());
+            match names {
+                Some(names) => {
+                    if let Some((ty, index)) = names.constructor(name.0) {
+                        resolver.constructors.insert(binding, (types + ty, index));
+                    }
+                }
+                None => {
+                    resolver.unknown.insert(binding);
+                }
+            }
+        }
+    }
+
+    // a top-level name can't be told apart from a module in `a.b`
+    let defs = module.defs();
+    let names = module
+        .imports()
+        .iter()
+        .flat_map(|import| import.names.iter().flatten())
+        .chain(
+            defs.types
+                .iter()
+                .flat_map(|ty| ty.constructors.iter().map(|constructor| &constructor.name)),
+        )
+        .chain(defs.defs.iter().map(|def| &def.ident));
+    for name in names {
+        if resolver.modules.contains_key(name.0) {
+            resolver.resolution.errors.push(Diagnostic::new(
+                name.1.clone(),
+                format!("`{}` shadows the module imported as `{}`", name.0, name.0),
+            ));
+        }
+    }
+
+    // the module's defs shadow imported names
+    resolver.scopes.push(HashMap::new());
+    resolver.visit_module(module);
+    resolver.resolution
+}
+
+/// Returns a warning for each name `module` exports but doesn't define.
+pub fn check_exports(module: &Module) -> Vec<Diagnostic> {
+    let Some(export) = &module.header().export else {
+        return Vec::new();
+    };
+    let defs = module.defs();
+    let defined = |name: &str| {
+        defs.defs.iter().any(|def| def.ident.0 == name)
+            || defs.types.iter().any(|ty| ty.name.0 == name)
+    };
+    export
+        .names
+        .iter()
+        .filter(|name| !defined(name.0))
+        .map(|name| {
+            Diagnostic::new(
+                name.1.clone(),
+                format!("`{}` is exported but not defined", name.0),
+            )
+        })
+        .collect()
+}
+
+/// The fields of a record or tuple literal.
+enum Shape<'i> {
+    Record(Vec<&'i str>),
+    Tuple(usize),
+}
+
+impl<'i> Shape<'i> {
+    fn of(expr: &Expr<'i>) -> Option<Self> {
+        match expr {
+            Expr::Record(record) => Some(Shape::Record(
+                record.fields.iter().map(|field| field.name.0).collect(),
+            )),
+            Expr::Tuple(tuple) => Some(Shape::Tuple(tuple.items.len())),
+            _ => None,
+        }
+    }
+
+    fn has(&self, field: Field) -> bool {
+        match (self, field) {
+            (Shape::Record(names), Field::Name(name)) => names.contains(&name),
+            (Shape::Tuple(len), Field::Index(index)) => index < *len,
+            _ => false,
+        }
+    }
+}
+
+struct Resolver<'i, 'b> {
+    scopes: Vec<HashMap<&'i str, usize>>,
+    builtins: &'b [&'b str],
+    /// The names of the defs of each imported module, by alias.
+    modules: HashMap<&'i str, Option<&'b ModuleNames<'b>>>,
+    /// The types declared so far, and those of imported modules.
+    types: Vec<DataType>,
+    /// The type and index of the constructor each constructor binding is.
+    constructors: HashMap<usize, (usize, usize)>,
+    /// The bindings imported from modules that weren't loaded, which may or
+    /// may not be constructors.
+    unknown: HashSet<usize>,
+    /// The fields of each def bound to a record or tuple literal.
+    shapes: HashMap<usize, Shape<'i>>,
+    resolution: Resolution,
+}
+
+impl<'i> Resolver<'i, '_> {
+    /// Binds `ident` in the current scope, returning the index of the
+    /// binding.
+    fn bind(&mut self, kind: BindingKind, ident: &Spanned<&'i str>, source: Range<usize>) -> usize {
+        let index = self.resolution.bindings.len();
+        self.resolution.bindings.push(Binding {
+            kind,
+            ident: ident.1.clone(),
+            source,
+        });
+
+        let scope = self.scopes.last_mut().expect("there is always a scope");
+        if scope.insert(ident.0, index).is_some() {
+            self.resolution.errors.push(Diagnostic::new(
+                ident.1.clone(),
+                format!("`{}` is defined more than once", ident.0),
+            ));
+        }
+        index
+    }
+
+    /// Reports an error if `names`, the defs of the module imported as
+    /// `alias`, don't include an exported `name`.
+    fn check_member(&mut self, alias: &str, names: Option<&ModuleNames>, name: &Spanned<&str>) {
+        let Some(names) = names else {
+            return;
+        };
+        let message = if !names.defs.contains(&name.0) {
+            format!("`{alias}` has no definition `{}`", name.0)
+        } else if !names.exported.contains(&name.0) {
+            format!("`{}` is private to `{alias}`", name.0)
+        } else {
+            return;
+        };
+        self.resolution
+            .errors
+            .push(Diagnostic::new(name.1.clone(), message));
+    }
+
+    /// Reports an error if `field` isn't a field of the value of `expr`,
+    /// where that is a literal or a def bound to one.
+    fn check_field(&mut self, expr: &Expr<'i>, field: &Spanned<Field<'i>>) {
+        let (shape, owner) = match expr {
+            Expr::Variable(Spanned(name, _)) => {
+                let Some(shape) = self
+                    .lookup(name)
+                    .and_then(|binding| self.shapes.get(&binding))
+                else {
+                    return;
+                };
+                (shape, format!("`{name}`"))
+            }
+            Expr::Record(_) | Expr::Tuple(_) => {
+                let shape = Shape::of(expr).expect("literals have a shape");
+                let owner = match shape {
+                    Shape::Record(_) => "this record",
+                    Shape::Tuple(_) => "this tuple",
+                };
+                if shape.has(field.0) {
+                    return;
+                }
+                return self.resolution.errors.push(Diagnostic::new(
+                    field.1.clone(),
+                    format!("{owner} has no field `{}`", field.0),
+                ));
+            }
+            _ => return,
+        };
+        if !shape.has(field.0) {
+            self.resolution.errors.push(Diagnostic::new(
+                field.1.clone(),
+                format!("{owner} has no field `{}`", field.0),
+            ));
+        }
+    }
+
+    fn lookup(&self, name: &str) -> Option<usize> {
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(name).copied())
+    }
+
+    /// Binds the variables of `pattern`, the pattern of the arm at `source`,
+    /// and resolves its constructors.
+    ///
+    /// Returns `None` if the pattern has errors, or uses a constructor that
+    /// can't be checked.
+    fn pattern(&mut self, pattern: &Pattern<'i>, source: &Range<usize>) -> Option<Pat> {
+        let kind = match pattern {
+            Pattern::Wildcard(_) => PatKind::Any,
+            Pattern::Variable(name) => {
+                self.bind(BindingKind::Pattern, name, source.clone());
+                PatKind::Any
+            }
+            Pattern::Int(Spanned(value, _)) => PatKind::Int(*value),
+            Pattern::Constructor(constructor) => {
+                // resolve every argument, even after an error
+                let args: Vec<_> = constructor
+                    .args
+                    .iter()
+                    .map(|arg| self.pattern(arg, source))
+                    .collect();
+                let (ty, index) = self.constructor(&constructor.name)?;
+                let (name, arity) = &self.types[ty].constructors[index];
+                if constructor.args.len() != *arity {
+                    let fields = if *arity == 1 { "field" } else { "fields" };
+                    let message = format!(
+                        "`{name}` has {arity} {fields}, but the pattern has {}",
+                        constructor.args.len()
+                    );
+                    self.resolution
+                        .errors
+                        .push(Diagnostic::new(constructor.span.clone(), message));
+                    return None;
+                }
+                PatKind::Constructor {
+                    ty,
+                    index,
+                    args: args.into_iter().collect::<Option<_>>()?,
+                }
+            }
+        };
+        Some(Pat {
+            kind,
+            span: pattern.span(),
+        })
+    }
+
+    /// Resolves the constructor called `name` in a pattern, returning its
+    /// type and index.
+    fn constructor(&mut self, Spanned(name, span): &Spanned<&'i str>) -> Option<(usize, usize)> {
+        let Some(binding) = self.lookup(name) else {
+            self.resolution.errors.push(Diagnostic::new(
+                span.clone(),
+                format!("cannot find constructor `{name}` in this scope"),
+            ));
+            return None;
+        };
+        self.resolution.references.push(Reference {
+            span: span.clone(),
+            binding,
+        });
+        if let Some(&constructor) = self.constructors.get(&binding) {
+            return Some(constructor);
+        }
+        if !self.unknown.contains(&binding) {
+            self.resolution.errors.push(Diagnostic::new(
+                span.clone(),
+                format!("`{name}` is not a constructor"),
+            ));
+        }
+        None
+    }
+}
+
+impl<'i> Visitor<'i> for Resolver<'i, '_> {
+    /// Resolves the defs of `module`, then its tests, which see every def.
+    fn visit_module(&mut self, module: &Module<'i>) {
+        walk_module(self, module);
+        let mut names = HashSet::new();
+        for test in module.tests() {
+            if !names.insert(test.name.0) {
+                self.resolution.errors.push(Diagnostic::new(
+                    test.name.1.clone(),
+                    format!("the test \"{}\" is declared more than once", test.name.0),
+                ));
+            }
+        }
+    }
+
+    /// Binds every def in `defs` in the current scope, then resolves their
+    /// expressions.
+    fn visit_def_list(&mut self, defs: &DefList<'i>) {
+        let mut names = HashSet::new();
+        for type_def in defs.types.iter() {
+            if !names.insert(type_def.name.0) {
+                self.resolution.errors.push(Diagnostic::new(
+                    type_def.name.1.clone(),
+                    format!("the type `{}` is declared more than once", type_def.name.0),
+                ));
+            }
+            let ty = self.types.len();
+            self.types.push(DataType {
+                name: type_def.name.0.to_string(),
+                constructors: type_def
+                    .constructors
+                    .iter()
+                    .map(|constructor| (constructor.name.0.to_string(), constructor.arity()))
+                    .collect(),
+            });
+            for (index, constructor) in type_def.constructors.iter().enumerate() {
+                let binding = self.bind(
+                    BindingKind::Constructor,
+                    &constructor.name,
+                    type_def.span.clone(),
+                );
+                self.constructors.insert(binding, (ty, index));
+            }
+        }
+        for def in defs.defs.iter() {
+            let binding = self.bind(BindingKind::Def, &def.ident, def.span());
+            if let Some(shape) = Shape::of(&def.expr) {
+                self.shapes.insert(binding, shape);
+            }
+        }
+        walk_def_list(self, defs);
+    }
+
+    fn visit_variable(&mut self, Spanned(name, span): &Spanned<&'i str>) {
+        match self.lookup(name) {
+            Some(binding) => self.resolution.references.push(Reference {
+                span: span.clone(),
+                binding,
+            }),
+            None if self.builtins.contains(name) => {}
+            None => self.resolution.errors.push(Diagnostic::new(
+                span.clone(),
+                format!("cannot find `{name}` in this scope"),
+            )),
+        }
+    }
+
+    /// Resolves `a.b` to the field `b` of the variable `a` if `a` is bound
+    /// or, failing that, to the def `b` of the module imported as `a`.
+    fn visit_qualified(&mut self, qualified: &Qualified<'i>) {
+        let alias = qualified.module.0;
+        match self.modules.get(alias) {
+            Some(&names) if self.lookup(alias).is_none() => {
+                self.check_member(alias, names, &qualified.name)
+            }
+            _ => {
+                let record = Expr::Variable(qualified.module.clone());
+                self.visit_expr(&record);
+                let Spanned(name, span) = &qualified.name;
+                self.check_field(&record, &Spanned(Field::Name(name), span.clone()));
+            }
+        }
+    }
+
+    fn visit_record(&mut self, record: &Record<'i>) {
+        let mut names = HashSet::new();
+        for field in record.fields.iter() {
+            if !names.insert(field.name.0) {
+                self.resolution.errors.push(Diagnostic::new(
+                    field.name.1.clone(),
+                    format!("the field `{}` is given more than once", field.name.0),
+                ));
+            }
+        }
+        walk_record(self, record);
+    }
+
+    fn visit_access(&mut self, access: &Access<'i>) {
+        walk_access(self, access);
+        self.check_field(&access.expr, &access.field);
+    }
+
+    fn visit_lambda(&mut self, lambda: &Lambda<'i>) {
+        self.scopes.push(HashMap::new());
+        self.bind(BindingKind::Param, &lambda.param, lambda.span());
+        walk_lambda(self, lambda);
+        self.scopes.pop();
+    }
+
+    fn visit_block(&mut self, block: &Block<'i>) {
+        self.scopes.push(HashMap::new());
+        walk_block(self, block);
+        self.scopes.pop();
+    }
+
+    /// Resolves each arm in a scope of its own, with the variables of its
+    /// pattern, then checks the patterns together.
+    fn visit_match(&mut self, matching: &Match<'i>) {
+        self.visit_expr(&matching.scrutinee);
+
+        let mut patterns = Some(Vec::with_capacity(matching.arms.len()));
+        for arm in matching.arms.iter() {
+            let source = arm.pattern.span().start..arm.body.span().end;
+            self.scopes.push(HashMap::new());
+            let pattern = self.pattern(&arm.pattern, &source);
+            self.visit_expr(&arm.body);
+            self.scopes.pop();
+
+            match (pattern, &mut patterns) {
+                (Some(pattern), Some(patterns)) => patterns.push(pattern),
+                _ => patterns = None,
+            }
+        }
+
+        if let Some(patterns) = patterns {
+            exhaustive::check(
+                &self.types,
+                matching.head_span(),
+                &patterns,
+                &mut self.resolution.errors,
+                &mut self.resolution.warnings,
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::parse_module;
+
+    use super::{
+        check_exports, resolve, resolve_with_builtins, resolve_with_imports, BindingKind,
+        ModuleNames,
+    };
+
+    fn names<'a>(defs: &[&'a str], exported: &[&'a str]) -> Option<ModuleNames<'a>> {
+        Some(ModuleNames {
+            defs: defs.to_vec(),
+            exported: exported.to_vec(),
+            types: Vec::new(),
+        })
+    }
+
+    /// Messages of diagnostics, with the text they point at.
+    type Messages<'s> = Vec<(String, &'s str)>;
+
+    /// Resolves `source`, returning its errors and warnings.
+    fn diagnostics(source: &str) -> (Messages<'_>, Messages<'_>) {
+        let module = parse_module(source).unwrap();
+        let resolution = resolve(&module);
+        let messages = |diagnostics: Vec<crate::diagnostic::Diagnostic>| {
+            diagnostics
+                .into_iter()
+                .map(|diagnostic| (diagnostic.message, &source[diagnostic.span]))
+                .collect()
+        };
+        (messages(resolution.errors), messages(resolution.warnings))
+    }
+
+    #[test]
+    fn resolve_recursive_defs() {
+        let module = parse_module("f = \\x -> g x\ng = f").unwrap();
+        let resolution = resolve(&module);
+
+        assert!(resolution.errors.is_empty());
+        // `g` in `f` refers to the second def, declared later
+        let g = resolution.binding_at(11).unwrap();
+        assert_eq!(resolution.bindings[g].ident, 14..15);
+        assert_eq!(resolution.bindings[g].kind, BindingKind::Def);
+    }
+
+    #[test]
+    fn resolve_shadowed_param() {
+        let module = parse_module("x = \\x -> x").unwrap();
+        let resolution = resolve(&module);
+
+        let x = resolution.binding_at(10).unwrap();
+        assert_eq!(resolution.bindings[x].kind, BindingKind::Param);
+        assert_eq!(resolution.bindings[x].ident, 5..6);
+        assert_eq!(resolution.references_to(x).count(), 1);
+    }
+
+    #[test]
+    fn report_unresolved_and_duplicate() {
+        let module = parse_module("x = y\nx = (z = x\n z)").unwrap();
+        let resolution = resolve(&module);
+
+        let messages: Vec<_> = resolution
+            .errors
+            .iter()
+            .map(|err| (err.message.as_str(), err.span.clone()))
+            .collect();
+        assert_eq!(
+            messages,
+            [
+                ("`x` is defined more than once", 6..7),
+                ("cannot find `y` in this scope", 4..5),
+            ]
+        );
+    }
+
+    #[test]
+    fn resolve_tests() {
+        let module = parse_module("test \"a\" = x\nx = 1\ntest \"a\" = y").unwrap();
+        let resolution = resolve(&module);
+
+        let messages: Vec<_> = resolution
+            .errors
+            .iter()
+            .map(|err| (err.message.as_str(), err.span.clone()))
+            .collect();
+        assert_eq!(
+            messages,
+            [
+                ("cannot find `y` in this scope", 30..31),
+                ("the test \"a\" is declared more than once", 24..27),
+            ]
+        );
+        assert_eq!(resolution.references.len(), 1);
+    }
+
+    #[test]
+    fn resolve_builtins() {
+        let module = parse_module("x = \\add -> add y\ny = add x").unwrap();
+        let resolution = resolve_with_builtins(&module, &["add"]);
+
+        assert!(resolution.errors.is_empty());
+        // the param shadows the builtin
+        assert_eq!(resolution.references.len(), 3);
+    }
+
+    #[test]
+    fn resolve_patterns() {
+        let source = "type Option a = None | Some a\nf = \\x -> match x with\n  | Some y -> y\n  | None -> x";
+        let module = parse_module(source).unwrap();
+        let resolution = resolve(&module);
+        assert!(resolution.errors.is_empty());
+        assert!(resolution.warnings.is_empty());
+
+        let y = resolution.binding_at(source.rfind("y").unwrap()).unwrap();
+        assert_eq!(resolution.bindings[y].kind, BindingKind::Pattern);
+        assert_eq!(
+            &source[resolution.bindings[y].source.clone()],
+            "Some y -> y"
+        );
+
+        let some = resolution
+            .binding_at(source.find("Some y").unwrap())
+            .unwrap();
+        assert_eq!(resolution.bindings[some].kind, BindingKind::Constructor);
+        assert_eq!(resolution.bindings[some].ident, 23..27);
+    }
+
+    #[test]
+    fn report_missing_patterns() {
+        let types = "type Option a = None | Some a\ntype List a = Nil | Cons a (List a)\n";
+        let cases = [
+            ("match x with\n  | Some y -> y", "`None`"),
+            (
+                "match x with\n  | Some None -> 0\n  | None -> 1",
+                "`Some (Some _)`",
+            ),
+            (
+                "match x with\n  | Cons 1 Nil -> 0\n  | Nil -> 1",
+                "`Cons _ _`",
+            ),
+            (
+                "match x with\n  | Cons _ Nil -> 0\n  | Nil -> 1",
+                "`Cons _ (Cons _ _)`",
+            ),
+            (
+                "match x with\n  | Some (Cons _ _) -> 0",
+                "`None`, `Some Nil`",
+            ),
+            ("match x with\n  | Cons _ _ -> 0", "`Nil`"),
+            ("match x with\n  | 0 -> 1\n  | 1 -> 0", "`_`"),
+        ];
+        for (matching, missing) in cases {
+            let source = format!("{types}f = \\x -> {matching}");
+            let (errors, warnings) = diagnostics(&source);
+            assert_eq!(
+                errors,
+                [(format!("this match doesn't cover {missing}"), "match x")],
+                "{matching}"
+            );
+            assert!(warnings.is_empty(), "{matching}");
+        }
+
+        let source = format!("{types}f = \\x -> match x with\n  | Some (Cons _ _) -> 0\n  | Some Nil -> 1\n  | None -> 2\n  | _ -> 3");
+        let (errors, warnings) = diagnostics(&source);
+        assert!(errors.is_empty());
+        assert_eq!(
+            warnings,
+            [(
+                "this pattern is unreachable, since the arms before it match every value it does"
+                    .to_string(),
+                "_"
+            )]
+        );
+    }
+
+    #[test]
+    fn report_bad_patterns() {
+        let source = "type T = A | B T\ntype U = C\nf = \\x -> match x with\n  | B (B y z) -> y\n  | D -> x\n  | x -> x\n  | f -> f\ng = \\x -> match x with\n  | B A -> 0\n  | B C -> 1\nh = \\x -> match x with\n  | A -> 0\n  | 2 -> 2";
+        let (errors, _) = diagnostics(source);
+        let errors: Vec<_> = errors
+            .iter()
+            .map(|(message, span)| (message.as_str(), *span))
+            .collect();
+        assert_eq!(
+            errors,
+            [
+                ("`B` has 1 field, but the pattern has 2", "B y z"),
+                ("cannot find constructor `D` in this scope", "D"),
+                (
+                    "expected a constructor of `T` like `A`, found a constructor of `U`",
+                    "C"
+                ),
+                (
+                    "expected a constructor of `T` like `A`, found an integer",
+                    "2"
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn resolve_imported_constructors() {
+        let source = "import option (None, Some)\nf = \\x -> match x with\n  | Some _ -> 1";
+        let module = parse_module(source).unwrap();
+        let option = ModuleNames {
+            types: vec![("Option", vec![("None", 0), ("Some", 1)])],
+            ..names(&["None", "Some"], &["None", "Some"]).unwrap()
+        };
+        let resolution = resolve_with_imports(&module, &[], &[Some(option)]);
+        let messages: Vec<_> = resolution
+            .errors
+            .iter()
+            .map(|err| err.message.as_str())
+            .collect();
+        assert_eq!(messages, ["this match doesn't cover `None`"]);
+
+        // constructors of modules that weren't loaded aren't checked
+        let resolution = resolve_with_imports(&module, &[], &[None]);
+        assert!(resolution.errors.is_empty());
+    }
+
+    #[test]
+    fn check_record_fields() {
+        let source = "p = { x = 1, y = 2, x = 3 }\nt = (p, 1)\na = p.y\nb = p.z\nc = t.0.x\nd = t.2\ne = { w = 1 }.v\nf = \\p -> p.z\ng = q.x";
+        let (errors, warnings) = diagnostics(source);
+        assert_eq!(
+            errors,
+            [
+                ("the field `x` is given more than once".to_owned(), "x"),
+                ("`p` has no field `z`".to_owned(), "z"),
+                ("`t` has no field `2`".to_owned(), "2"),
+                ("this record has no field `v`".to_owned(), "v"),
+                ("cannot find `q` in this scope".to_owned(), "q"),
+            ]
+        );
+        assert!(warnings.is_empty());
+
+        // `p.y` refers to the def `p`
+        let module = parse_module(source).unwrap();
+        let resolution = resolve(&module);
+        let p = resolution.binding_at(source.find("p.y").unwrap()).unwrap();
+        assert_eq!(resolution.bindings[p].ident, 0..1);
+    }
+
+    #[test]
+    fn resolve_imports() {
+        let source = "import lib/list (map, fold)\nimport other\nfold = list.map\nx = map other.y";
+        let module = parse_module(source).unwrap();
+        let imports = [names(&["map", "fold"], &["map", "fold"]), None];
+        let resolution = resolve_with_imports(&module, &[], &imports);
+        assert!(resolution.errors.is_empty());
+
+        // the def shadows the imported `fold`, but `map` is imported
+        let map = resolution.binding_at(source.rfind("map").unwrap()).unwrap();
+        assert_eq!(resolution.bindings[map].kind, BindingKind::Import);
+        assert_eq!(resolution.bindings[map].source, 0..27);
+        assert_eq!(resolution.references_to(map).count(), 1);
+    }
+
+    #[test]
+    fn report_missing_imports() {
+        let source = "import a (x, y)\nimport b/a\nz = a.w\nw = c.w\nv = a.u";
+        let module = parse_module(source).unwrap();
+        let imports = [names(&["x", "u"], &["x"]), None];
+        let resolution = resolve_with_imports(&module, &[], &imports);
+
+        let messages: Vec<_> = resolution
+            .errors
+            .iter()
+            .map(|err| (err.message.as_str(), &source[err.span.clone()]))
+            .collect();
+        assert_eq!(
+            messages,
+            [
+                ("`a` has no definition `y`", "y"),
+                ("a module called `a` is imported more than once", "a"),
+                ("`a` has no definition `w`", "w"),
+                ("cannot find `c` in this scope", "c"),
+                ("`u` is private to `a`", "u"),
+            ]
+        );
+    }
+
+    #[test]
+    fn resolve_locals_before_modules() {
+        let source = "import lib/m\nf = \\m -> m.x\ng = (m = { x = 1 }; m.x)\nh = m.y\nm = 1";
+        let module = parse_module(source).unwrap();
+        let imports = [names(&["y"], &["y"])];
+        let resolution = resolve_with_imports(&module, &[], &imports);
+
+        let param = resolution.binding_at(source.find("m.x").unwrap()).unwrap();
+        assert_eq!(resolution.bindings[param].kind, BindingKind::Param);
+        let local = resolution.binding_at(source.rfind("m.x").unwrap()).unwrap();
+        assert_eq!(resolution.bindings[local].kind, BindingKind::Def);
+
+        let messages: Vec<_> = resolution
+            .errors
+            .iter()
+            .map(|err| (err.message.as_str(), &source[err.span.clone()]))
+            .collect();
+        assert_eq!(messages, [("`m` shadows the module imported as `m`", "m")]);
+        assert_eq!(
+            resolution.errors[0].span,
+            source.len() - 5..source.len() - 4
+        );
+    }
+
+    #[test]
+    fn check_export_lists() {
+        let module = parse_module("export (a, c)\nimport x\na = b\nb = x.y").unwrap();
+        assert_eq!(
+            ModuleNames::of(&module),
+            names(&["a", "b"], &["a"]).unwrap()
+        );
+
+        // constructors are exported with their type
+        let types = parse_module("export (T)\ntype T = A | B T\ntype U = C").unwrap();
+        assert_eq!(
+            ModuleNames::of(&types),
+            ModuleNames {
+                types: vec![("T", vec![("A", 0), ("B", 1)]), ("U", vec![("C", 0)])],
+                ..names(&["A", "B", "C"], &["A", "B"]).unwrap()
+            }
+        );
+        assert!(check_exports(&types).is_empty());
+
+        let warnings: Vec<_> = check_exports(&module)
+            .into_iter()
+            .map(|warning| (warning.message, warning.span))
+            .collect();
+        assert_eq!(
+            warnings,
+            [("`c` is exported but not defined".to_string(), 11..12)]
+        );
+
+        let module = parse_module("a = 1").unwrap();
+        assert!(check_exports(&module).is_empty());
+        assert_eq!(ModuleNames::of(&module), names(&["a"], &["a"]).unwrap());
+    }
+}
@@ -0,0 +1,340 @@
+//! Checking that the arms of a `match` cover every value, and that each arm
+//! matches some value the arms before it don't.
+//!
+//! This is the usefulness algorithm from Maranget's "Warnings for pattern
+//! matching": a row of patterns is useful against a matrix of rows if some
+//! value matches it but no row of the matrix. An arm is unreachable if it
+//! isn't useful against the arms before it, and a match is exhaustive if a
+//! wildcard isn't useful against all of its arms.
+
+use std::{collections::BTreeSet, fmt, ops::Range};
+
+use crate::diagnostic::Diagnostic;
+
+/// A type declared with `type`, as far as patterns are concerned.
+#[derive(Debug)]
+pub(super) struct DataType {
+    pub name: String,
+    /// The name and number of fields of each constructor.
+    pub constructors: Vec<(String, usize)>,
+}
+
+/// A resolved pattern.
+#[derive(Debug, Clone)]
+pub(super) struct Pat {
+    pub kind: PatKind,
+    pub span: Range<usize>,
+}
+
+#[derive(Debug, Clone)]
+pub(super) enum PatKind {
+    /// A wildcard or a variable.
+    Any,
+    Int(i64),
+    /// The constructor at `index` in the type at `ty`.
+    Constructor {
+        ty: usize,
+        index: usize,
+        args: Vec<Pat>,
+    },
+}
+
+/// The constructor a pattern starts with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Head {
+    Int(i64),
+    Constructor { ty: usize, index: usize },
+}
+
+impl Pat {
+    fn any(span: Range<usize>) -> Self {
+        Self {
+            kind: PatKind::Any,
+            span,
+        }
+    }
+
+    fn head(&self) -> Option<Head> {
+        match self.kind {
+            PatKind::Any => None,
+            PatKind::Int(value) => Some(Head::Int(value)),
+            PatKind::Constructor { ty, index, .. } => Some(Head::Constructor { ty, index }),
+        }
+    }
+}
+
+/// Checks the patterns of the arms of a match, whose head is at `span`.
+///
+/// Patterns of different types in the same position are errors, and so is a
+/// match that doesn't cover every value. Arms that can't match are warnings.
+pub(super) fn check(
+    types: &[DataType],
+    span: Range<usize>,
+    arms: &[Pat],
+    errors: &mut Vec<Diagnostic>,
+    warnings: &mut Vec<Diagnostic>,
+) {
+    let column: Vec<_> = arms.iter().collect();
+    if let Err(err) = check_types(types, &column) {
+        errors.push(err);
+        return;
+    }
+
+    let mut rows: Vec<Vec<Pat>> = Vec::with_capacity(arms.len());
+    for arm in arms {
+        let row = vec![arm.clone()];
+        if !useful(types, &rows, &row) {
+            warnings.push(Diagnostic::new(
+                arm.span.clone(),
+                "this pattern is unreachable, since the arms before it match every value it does",
+            ));
+        }
+        rows.push(row);
+    }
+
+    let missing = missing(types, &rows);
+    if !missing.is_empty() {
+        let missing: Vec<_> = missing.iter().map(|w| format!("`{w}`")).collect();
+        errors.push(Diagnostic::new(
+            span,
+            format!("this match doesn't cover {}", missing.join(", ")),
+        ));
+    }
+}
+
+/// Checks that the patterns of `column`, which match the same value, are
+/// all of one type, and so are their arguments.
+fn check_types(types: &[DataType], column: &[&Pat]) -> Result<(), Diagnostic> {
+    let Some(first) = column
+        .iter()
+        .find_map(|pat| pat.head().map(|head| (head, *pat)))
+    else {
+        return Ok(());
+    };
+    let describe = |head: Head| match head {
+        Head::Int(_) => "an integer".to_string(),
+        Head::Constructor { ty, .. } => format!("a constructor of `{}`", types[ty].name),
+    };
+    for pat in column {
+        let mismatched = match (first.0, pat.head()) {
+            (Head::Int(_), Some(Head::Constructor { .. }))
+            | (Head::Constructor { .. }, Some(Head::Int(_))) => true,
+            (Head::Constructor { ty, .. }, Some(Head::Constructor { ty: other, .. })) => {
+                ty != other
+            }
+            _ => false,
+        };
+        if mismatched {
+            let head = pat.head().expect("mismatched patterns have a head");
+            return Err(Diagnostic::new(
+                pat.span.clone(),
+                format!(
+                    "expected {} like `{}`, found {}",
+                    describe(first.0),
+                    Witness::of(types, first.1),
+                    describe(head)
+                ),
+            ));
+        }
+    }
+
+    // the arguments of each constructor are columns of their own
+    let Head::Constructor { ty, .. } = first.0 else {
+        return Ok(());
+    };
+    for (index, (_, arity)) in types[ty].constructors.iter().enumerate() {
+        let args: Vec<_> = column
+            .iter()
+            .filter_map(|pat| match &pat.kind {
+                PatKind::Constructor { index: i, args, .. } if *i == index => Some(args),
+                _ => None,
+            })
+            .collect();
+        for field in 0..*arity {
+            let column: Vec<_> = args.iter().map(|args| &args[field]).collect();
+            check_types(types, &column)?;
+        }
+    }
+    Ok(())
+}
+
+/// Returns the rows of `matrix` that can match a value starting with
+/// `head`, with the first pattern replaced by the `arity` patterns of its
+/// arguments.
+fn specialize(matrix: &[Vec<Pat>], head: Head, arity: usize) -> Vec<Vec<Pat>> {
+    matrix
+        .iter()
+        .filter_map(|row| {
+            let (first, rest) = row.split_first().expect("rows aren't empty");
+            let mut specialized = match &first.kind {
+                PatKind::Any => vec![Pat::any(first.span.clone()); arity],
+                PatKind::Constructor { args, .. } if first.head() == Some(head) => args.clone(),
+                PatKind::Int(_) if first.head() == Some(head) => Vec::new(),
+                _ => return None,
+            };
+            specialized.extend_from_slice(rest);
+            Some(specialized)
+        })
+        .collect()
+}
+
+/// Returns the rows of `matrix` that start with a wildcard, without it.
+fn default(matrix: &[Vec<Pat>]) -> Vec<Vec<Pat>> {
+    matrix
+        .iter()
+        .filter(|row| matches!(row[0].kind, PatKind::Any))
+        .map(|row| row[1..].to_vec())
+        .collect()
+}
+
+/// Returns the type of the constructors in the first column of `matrix`, if
+/// there are any, and the indices of those constructors.
+fn first_column(matrix: &[Vec<Pat>]) -> Option<(usize, BTreeSet<usize>)> {
+    let mut ty = None;
+    let mut used = BTreeSet::new();
+    for row in matrix {
+        if let PatKind::Constructor { ty: t, index, .. } = row[0].kind {
+            ty = Some(t);
+            used.insert(index);
+        }
+    }
+    ty.map(|ty| (ty, used))
+}
+
+/// Returns whether some value matches `row` but no row of `matrix`.
+fn useful(types: &[DataType], matrix: &[Vec<Pat>], row: &[Pat]) -> bool {
+    let Some((first, rest)) = row.split_first() else {
+        return matrix.is_empty();
+    };
+    match &first.kind {
+        PatKind::Constructor { args, .. } => {
+            let head = first.head().expect("constructors have a head");
+            let mut row = args.clone();
+            row.extend_from_slice(rest);
+            useful(types, &specialize(matrix, head, args.len()), &row)
+        }
+        PatKind::Int(_) => {
+            let head = first.head().expect("integers have a head");
+            useful(types, &specialize(matrix, head, 0), rest)
+        }
+        PatKind::Any => match first_column(matrix) {
+            Some((ty, used)) if used.len() == types[ty].constructors.len() => types[ty]
+                .constructors
+                .iter()
+                .enumerate()
+                .any(|(index, (_, arity))| {
+                    let mut row = vec![Pat::any(first.span.clone()); *arity];
+                    row.extend_from_slice(rest);
+                    let head = Head::Constructor { ty, index };
+                    useful(types, &specialize(matrix, head, *arity), &row)
+                }),
+            _ => useful(types, &default(matrix), rest),
+        },
+    }
+}
+
+/// A value that a match doesn't cover, for error messages.
+#[derive(Debug, Clone)]
+enum Witness {
+    Any,
+    Constructor(String, Vec<Witness>),
+}
+
+impl Witness {
+    fn of(types: &[DataType], pat: &Pat) -> Self {
+        match &pat.kind {
+            PatKind::Any => Witness::Any,
+            PatKind::Int(value) => Witness::Constructor(value.to_string(), Vec::new()),
+            PatKind::Constructor { ty, index, args } => Witness::Constructor(
+                types[*ty].constructors[*index].0.clone(),
+                args.iter().map(|arg| Witness::of(types, arg)).collect(),
+            ),
+        }
+    }
+
+    fn constructor(types: &[DataType], ty: usize, index: usize) -> Self {
+        let (name, arity) = &types[ty].constructors[index];
+        Witness::Constructor(name.clone(), vec![Witness::Any; *arity])
+    }
+}
+
+impl fmt::Display for Witness {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Witness::Any => f.write_str("_"),
+            Witness::Constructor(name, args) => {
+                f.write_str(name)?;
+                for arg in args {
+                    match arg {
+                        Witness::Constructor(_, args) if !args.is_empty() => write!(f, " ({arg})")?,
+                        _ => write!(f, " {arg}")?,
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Returns a row of `width` values that no row of `matrix` matches, if there
+/// is one.
+fn witness(types: &[DataType], matrix: &[Vec<Pat>], width: usize) -> Option<Vec<Witness>> {
+    if width == 0 {
+        return matrix.is_empty().then(Vec::new);
+    }
+    match first_column(matrix) {
+        Some((ty, used)) if used.len() == types[ty].constructors.len() => types[ty]
+            .constructors
+            .iter()
+            .enumerate()
+            .find_map(|(index, (name, arity))| {
+                let head = Head::Constructor { ty, index };
+                let specialized = specialize(matrix, head, *arity);
+                let mut row = witness(types, &specialized, arity + width - 1)?;
+                let rest = row.split_off(*arity);
+                let mut witness = vec![Witness::Constructor(name.clone(), row)];
+                witness.extend(rest);
+                Some(witness)
+            }),
+        column => {
+            let rest = witness(types, &default(matrix), width - 1)?;
+            let first = match column {
+                Some((ty, used)) => {
+                    let index = (0..types[ty].constructors.len())
+                        .find(|index| !used.contains(index))
+                        .expect("the column is incomplete");
+                    Witness::constructor(types, ty, index)
+                }
+                None => Witness::Any,
+            };
+            let mut witness = vec![first];
+            witness.extend(rest);
+            Some(witness)
+        }
+    }
+}
+
+/// Returns the values of the single column of `matrix` that no row
+/// matches: one for each constructor that isn't covered.
+fn missing(types: &[DataType], matrix: &[Vec<Pat>]) -> Vec<Witness> {
+    let Some((ty, _)) = first_column(matrix) else {
+        return witness(types, matrix, 1)
+            .map(|mut row| row.remove(0))
+            .into_iter()
+            .collect();
+    };
+    if !default(matrix).is_empty() {
+        // an arm that starts with a wildcard matches everything
+        return Vec::new();
+    }
+    types[ty]
+        .constructors
+        .iter()
+        .enumerate()
+        .filter_map(|(index, (name, arity))| {
+            let head = Head::Constructor { ty, index };
+            let args = witness(types, &specialize(matrix, head, *arity), *arity)?;
+            Some(Witness::Constructor(name.clone(), args))
+        })
+        .collect()
+}
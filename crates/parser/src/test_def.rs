@@ -0,0 +1,83 @@
+//! Tests written in ria, like `test "id returns its argument" = assert_eq (id 1) 1`.
+
+use std::ops::Range;
+
+use ria_lexer::{Spanned, Symbol, Token};
+use winnow::{
+    combinator::cut_err,
+    error::{StrContext, StrContextValue},
+    stream::Stream,
+    ModalResult, Parser,
+};
+
+use crate::{expr::Expr, keyword, string, symbol};
+
+/// A `test` declaration at the top level of a module. The test passes if its
+/// expression evaluates without an error.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TestDef<'i> {
+    /// The name of the test, without its quotes. Its span includes them.
+    pub name: Spanned<&'i str>,
+    pub expr: Expr<'i>,
+    /// The span of the whole declaration, from `test` to the end of the
+    /// expression.
+    pub span: Range<usize>,
+}
+
+impl<'i> TestDef<'i> {
+    pub fn parse<S>(input: &mut S) -> ModalResult<Self>
+    where
+        S: Stream<Token = Spanned<Token<'i>>>,
+    {
+        let start = keyword("test").parse_next(input)?;
+        // a def called `test` is followed by `=` rather than a name
+        let name = string.parse_next(input)?;
+        let (_, expr) = cut_err((
+            symbol(&Symbol::Define),
+            Expr::parse.context(StrContext::Expected(StrContextValue::Description(
+                "an expression",
+            ))),
+        ))
+        .parse_next(input)?;
+
+        let span = start.start()..expr.span().end;
+        Ok(Self { name, expr, span })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use ria_lexer::{Lexer, Spanned};
+    use winnow::Parser;
+
+    use crate::{expr::Expr, parse_module};
+
+    use super::TestDef;
+
+    #[test]
+    fn parse_test_def() {
+        let tokens: Box<_> = Lexer::new("test \"a b\" = f x").collect();
+        let test = TestDef::parse.parse(tokens.as_ref()).unwrap();
+
+        assert_eq!(test.name, Spanned("a b", 5..10));
+        assert!(matches!(test.expr, Expr::Call(_)));
+        assert_eq!(test.span, 0..16);
+
+        let tokens: Box<_> = Lexer::new("test = 1").collect();
+        assert!(TestDef::parse.parse(tokens.as_ref()).is_err());
+    }
+
+    #[test]
+    fn parse_tests_among_defs() {
+        let module =
+            parse_module("test \"one\" = x\nx = 1\ntest = 2\ntest \"two\" = test").unwrap();
+
+        let names: Vec<_> = module.tests().iter().map(|test| test.name.0).collect();
+        assert_eq!(names, ["one", "two"]);
+        assert_eq!(module.defs().defs.len(), 2);
+
+        // tests are only declared at the top level
+        assert!(parse_module("x = (test \"a\" = 1\n  1)").is_err());
+        assert!(parse_module("test \"a\" 1").is_err());
+    }
+}
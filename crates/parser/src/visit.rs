@@ -0,0 +1,375 @@
+//! Traversal of the AST.
+//!
+//! A [`Visitor`] (or [`VisitorMut`]) has a method for each kind of node, and
+//! each method defaults to calling the matching `walk_*` function, which
+//! visits the node's children. Passes override only the methods for the
+//! nodes they care about, calling `walk_*` themselves to keep descending.
+
+use ria_lexer::Spanned;
+
+use crate::{
+    data::TypeDef,
+    def::{Def, DefList},
+    expr::{Access, Arm, Block, Call, Expr, Lambda, Match, Qualified, Record, Tuple},
+    module::Module,
+    pattern::Pattern,
+    test_def::TestDef,
+};
+
+/// Visits an AST by reference.
+pub trait Visitor<'i> {
+    fn visit_module(&mut self, module: &Module<'i>) {
+        walk_module(self, module);
+    }
+
+    fn visit_def_list(&mut self, defs: &DefList<'i>) {
+        walk_def_list(self, defs);
+    }
+
+    fn visit_type_def(&mut self, _type_def: &TypeDef<'i>) {}
+
+    fn visit_def(&mut self, def: &Def<'i>) {
+        walk_def(self, def);
+    }
+
+    fn visit_test(&mut self, test: &TestDef<'i>) {
+        walk_test(self, test);
+    }
+
+    fn visit_expr(&mut self, expr: &Expr<'i>) {
+        walk_expr(self, expr);
+    }
+
+    fn visit_variable(&mut self, _ident: &Spanned<&'i str>) {}
+
+    fn visit_qualified(&mut self, _qualified: &Qualified<'i>) {}
+
+    fn visit_int(&mut self, _int: &Spanned<i64>) {}
+
+    fn visit_lambda(&mut self, lambda: &Lambda<'i>) {
+        walk_lambda(self, lambda);
+    }
+
+    fn visit_block(&mut self, block: &Block<'i>) {
+        walk_block(self, block);
+    }
+
+    fn visit_call(&mut self, call: &Call<'i>) {
+        walk_call(self, call);
+    }
+
+    fn visit_match(&mut self, matching: &Match<'i>) {
+        walk_match(self, matching);
+    }
+
+    fn visit_arm(&mut self, arm: &Arm<'i>) {
+        walk_arm(self, arm);
+    }
+
+    fn visit_pattern(&mut self, _pattern: &Pattern<'i>) {}
+
+    fn visit_tuple(&mut self, tuple: &Tuple<'i>) {
+        walk_tuple(self, tuple);
+    }
+
+    fn visit_record(&mut self, record: &Record<'i>) {
+        walk_record(self, record);
+    }
+
+    fn visit_access(&mut self, access: &Access<'i>) {
+        walk_access(self, access);
+    }
+}
+
+pub fn walk_module<'i, V: Visitor<'i> + ?Sized>(visitor: &mut V, module: &Module<'i>) {
+    visitor.visit_def_list(module.defs());
+    for test in module.tests() {
+        visitor.visit_test(test);
+    }
+}
+
+pub fn walk_def_list<'i, V: Visitor<'i> + ?Sized>(visitor: &mut V, defs: &DefList<'i>) {
+    for type_def in defs.types.iter() {
+        visitor.visit_type_def(type_def);
+    }
+    for def in defs.defs.iter() {
+        visitor.visit_def(def);
+    }
+}
+
+pub fn walk_def<'i, V: Visitor<'i> + ?Sized>(visitor: &mut V, def: &Def<'i>) {
+    visitor.visit_expr(&def.expr);
+}
+
+pub fn walk_test<'i, V: Visitor<'i> + ?Sized>(visitor: &mut V, test: &TestDef<'i>) {
+    visitor.visit_expr(&test.expr);
+}
+
+pub fn walk_expr<'i, V: Visitor<'i> + ?Sized>(visitor: &mut V, expr: &Expr<'i>) {
+    match expr {
+        Expr::Variable(ident) => visitor.visit_variable(ident),
+        Expr::Qualified(qualified) => visitor.visit_qualified(qualified),
+        Expr::Int(int) => visitor.visit_int(int),
+        Expr::Lambda(lambda) => visitor.visit_lambda(lambda),
+        Expr::Block(block) => visitor.visit_block(block),
+        Expr::Call(call) => visitor.visit_call(call),
+        Expr::Match(matching) => visitor.visit_match(matching),
+        Expr::Tuple(tuple) => visitor.visit_tuple(tuple),
+        Expr::Record(record) => visitor.visit_record(record),
+        Expr::Access(access) => visitor.visit_access(access),
+    }
+}
+
+pub fn walk_lambda<'i, V: Visitor<'i> + ?Sized>(visitor: &mut V, lambda: &Lambda<'i>) {
+    visitor.visit_expr(&lambda.body);
+}
+
+pub fn walk_block<'i, V: Visitor<'i> + ?Sized>(visitor: &mut V, block: &Block<'i>) {
+    visitor.visit_def_list(&block.defs);
+    if let Some(expr) = &block.expr {
+        visitor.visit_expr(expr);
+    }
+}
+
+pub fn walk_call<'i, V: Visitor<'i> + ?Sized>(visitor: &mut V, call: &Call<'i>) {
+    visitor.visit_expr(&call.func);
+    visitor.visit_expr(&call.arg);
+}
+
+pub fn walk_match<'i, V: Visitor<'i> + ?Sized>(visitor: &mut V, matching: &Match<'i>) {
+    visitor.visit_expr(&matching.scrutinee);
+    for arm in matching.arms.iter() {
+        visitor.visit_arm(arm);
+    }
+}
+
+pub fn walk_arm<'i, V: Visitor<'i> + ?Sized>(visitor: &mut V, arm: &Arm<'i>) {
+    visitor.visit_pattern(&arm.pattern);
+    visitor.visit_expr(&arm.body);
+}
+
+pub fn walk_tuple<'i, V: Visitor<'i> + ?Sized>(visitor: &mut V, tuple: &Tuple<'i>) {
+    for item in tuple.items.iter() {
+        visitor.visit_expr(item);
+    }
+}
+
+pub fn walk_record<'i, V: Visitor<'i> + ?Sized>(visitor: &mut V, record: &Record<'i>) {
+    for field in record.fields.iter() {
+        visitor.visit_expr(&field.value);
+    }
+}
+
+pub fn walk_access<'i, V: Visitor<'i> + ?Sized>(visitor: &mut V, access: &Access<'i>) {
+    visitor.visit_expr(&access.expr);
+}
+
+/// Visits an AST by mutable reference, for passes that edit it in place.
+pub trait VisitorMut<'i> {
+    fn visit_module_mut(&mut self, module: &mut Module<'i>) {
+        walk_module_mut(self, module);
+    }
+
+    fn visit_def_list_mut(&mut self, defs: &mut DefList<'i>) {
+        walk_def_list_mut(self, defs);
+    }
+
+    fn visit_type_def_mut(&mut self, _type_def: &mut TypeDef<'i>) {}
+
+    fn visit_def_mut(&mut self, def: &mut Def<'i>) {
+        walk_def_mut(self, def);
+    }
+
+    fn visit_test_mut(&mut self, test: &mut TestDef<'i>) {
+        walk_test_mut(self, test);
+    }
+
+    fn visit_expr_mut(&mut self, expr: &mut Expr<'i>) {
+        walk_expr_mut(self, expr);
+    }
+
+    fn visit_variable_mut(&mut self, _ident: &mut Spanned<&'i str>) {}
+
+    fn visit_qualified_mut(&mut self, _qualified: &mut Qualified<'i>) {}
+
+    fn visit_int_mut(&mut self, _int: &mut Spanned<i64>) {}
+
+    fn visit_lambda_mut(&mut self, lambda: &mut Lambda<'i>) {
+        walk_lambda_mut(self, lambda);
+    }
+
+    fn visit_block_mut(&mut self, block: &mut Block<'i>) {
+        walk_block_mut(self, block);
+    }
+
+    fn visit_call_mut(&mut self, call: &mut Call<'i>) {
+        walk_call_mut(self, call);
+    }
+
+    fn visit_match_mut(&mut self, matching: &mut Match<'i>) {
+        walk_match_mut(self, matching);
+    }
+
+    fn visit_arm_mut(&mut self, arm: &mut Arm<'i>) {
+        walk_arm_mut(self, arm);
+    }
+
+    fn visit_pattern_mut(&mut self, _pattern: &mut Pattern<'i>) {}
+
+    fn visit_tuple_mut(&mut self, tuple: &mut Tuple<'i>) {
+        walk_tuple_mut(self, tuple);
+    }
+
+    fn visit_record_mut(&mut self, record: &mut Record<'i>) {
+        walk_record_mut(self, record);
+    }
+
+    fn visit_access_mut(&mut self, access: &mut Access<'i>) {
+        walk_access_mut(self, access);
+    }
+}
+
+pub fn walk_module_mut<'i, V: VisitorMut<'i> + ?Sized>(visitor: &mut V, module: &mut Module<'i>) {
+    visitor.visit_def_list_mut(module.defs_mut());
+    for test in module.tests_mut() {
+        visitor.visit_test_mut(test);
+    }
+}
+
+pub fn walk_def_list_mut<'i, V: VisitorMut<'i> + ?Sized>(visitor: &mut V, defs: &mut DefList<'i>) {
+    for type_def in defs.types.iter_mut() {
+        visitor.visit_type_def_mut(type_def);
+    }
+    for def in defs.defs.iter_mut() {
+        visitor.visit_def_mut(def);
+    }
+}
+
+pub fn walk_def_mut<'i, V: VisitorMut<'i> + ?Sized>(visitor: &mut V, def: &mut Def<'i>) {
+    visitor.visit_expr_mut(&mut def.expr);
+}
+
+pub fn walk_test_mut<'i, V: VisitorMut<'i> + ?Sized>(visitor: &mut V, test: &mut TestDef<'i>) {
+    visitor.visit_expr_mut(&mut test.expr);
+}
+
+pub fn walk_expr_mut<'i, V: VisitorMut<'i> + ?Sized>(visitor: &mut V, expr: &mut Expr<'i>) {
+    match expr {
+        Expr::Variable(ident) => visitor.visit_variable_mut(ident),
+        Expr::Qualified(qualified) => visitor.visit_qualified_mut(qualified),
+        Expr::Int(int) => visitor.visit_int_mut(int),
+        Expr::Lambda(lambda) => visitor.visit_lambda_mut(lambda),
+        Expr::Block(block) => visitor.visit_block_mut(block),
+        Expr::Call(call) => visitor.visit_call_mut(call),
+        Expr::Match(matching) => visitor.visit_match_mut(matching),
+        Expr::Tuple(tuple) => visitor.visit_tuple_mut(tuple),
+        Expr::Record(record) => visitor.visit_record_mut(record),
+        Expr::Access(access) => visitor.visit_access_mut(access),
+    }
+}
+
+pub fn walk_lambda_mut<'i, V: VisitorMut<'i> + ?Sized>(visitor: &mut V, lambda: &mut Lambda<'i>) {
+    visitor.visit_expr_mut(&mut lambda.body);
+}
+
+pub fn walk_block_mut<'i, V: VisitorMut<'i> + ?Sized>(visitor: &mut V, block: &mut Block<'i>) {
+    visitor.visit_def_list_mut(&mut block.defs);
+    if let Some(expr) = &mut block.expr {
+        visitor.visit_expr_mut(expr);
+    }
+}
+
+pub fn walk_call_mut<'i, V: VisitorMut<'i> + ?Sized>(visitor: &mut V, call: &mut Call<'i>) {
+    visitor.visit_expr_mut(&mut call.func);
+    visitor.visit_expr_mut(&mut call.arg);
+}
+
+pub fn walk_match_mut<'i, V: VisitorMut<'i> + ?Sized>(visitor: &mut V, matching: &mut Match<'i>) {
+    visitor.visit_expr_mut(&mut matching.scrutinee);
+    for arm in matching.arms.iter_mut() {
+        visitor.visit_arm_mut(arm);
+    }
+}
+
+pub fn walk_arm_mut<'i, V: VisitorMut<'i> + ?Sized>(visitor: &mut V, arm: &mut Arm<'i>) {
+    visitor.visit_pattern_mut(&mut arm.pattern);
+    visitor.visit_expr_mut(&mut arm.body);
+}
+
+pub fn walk_tuple_mut<'i, V: VisitorMut<'i> + ?Sized>(visitor: &mut V, tuple: &mut Tuple<'i>) {
+    for item in tuple.items.iter_mut() {
+        visitor.visit_expr_mut(item);
+    }
+}
+
+pub fn walk_record_mut<'i, V: VisitorMut<'i> + ?Sized>(visitor: &mut V, record: &mut Record<'i>) {
+    for field in record.fields.iter_mut() {
+        visitor.visit_expr_mut(&mut field.value);
+    }
+}
+
+pub fn walk_access_mut<'i, V: VisitorMut<'i> + ?Sized>(visitor: &mut V, access: &mut Access<'i>) {
+    visitor.visit_expr_mut(&mut access.expr);
+}
+
+#[cfg(test)]
+mod test {
+    use ria_lexer::Spanned;
+
+    use crate::{expr::Lambda, parse_module};
+
+    use super::{walk_lambda, Visitor, VisitorMut};
+
+    /// Collects the names of every variable, and the params of lambdas
+    /// separately.
+    #[derive(Default)]
+    struct Names<'i> {
+        variables: Vec<&'i str>,
+        params: Vec<&'i str>,
+    }
+
+    impl<'i> Visitor<'i> for Names<'i> {
+        fn visit_variable(&mut self, ident: &Spanned<&'i str>) {
+            self.variables.push(ident.0);
+        }
+
+        fn visit_lambda(&mut self, lambda: &Lambda<'i>) {
+            self.params.push(lambda.param.0);
+            walk_lambda(self, lambda);
+        }
+    }
+
+    #[test]
+    fn visit_every_variable() {
+        let module = parse_module("id = \\x -> x\napply = (f = id\n  f \\y -> y x)").unwrap();
+        let mut names = Names::default();
+        names.visit_module(&module);
+
+        assert_eq!(names.variables, ["x", "id", "f", "y", "x"]);
+        assert_eq!(names.params, ["x", "y"]);
+    }
+
+    /// Renames every variable called `from` to `to`.
+    struct Rename<'i> {
+        from: &'i str,
+        to: &'i str,
+    }
+
+    impl<'i> VisitorMut<'i> for Rename<'i> {
+        fn visit_variable_mut(&mut self, ident: &mut Spanned<&'i str>) {
+            if ident.0 == self.from {
+                ident.0 = self.to;
+            }
+        }
+    }
+
+    #[test]
+    fn visit_mut_renames() {
+        let mut module = parse_module("a = (b = x\n  x b)").unwrap();
+        Rename { from: "x", to: "y" }.visit_module_mut(&mut module);
+
+        let mut names = Names::default();
+        names.visit_module(&module);
+        assert_eq!(names.variables, ["y", "y", "b"]);
+    }
+}
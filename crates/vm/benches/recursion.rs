@@ -0,0 +1,108 @@
+//! Compares the bytecode VM with direct AST interpretation on recursion-heavy
+//! programs over Church numerals.
+//!
+//! Run with `cargo bench -p ria_vm`. Pass a number to change the size of the
+//! numerals the programs count down from.
+
+use std::{
+    hint::black_box,
+    time::{Duration, Instant},
+};
+
+use ria_parser::parse_module;
+use ria_vm::{compile, interp::Interpreter, Evaluation, Vm};
+
+const CHURCH: &str = include_str!("church.ria");
+
+/// The benchmarked programs, each defining `main` in terms of a numeral `n`.
+const PROGRAMS: &[(&str, &str)] = &[
+    (
+        "countdown (tail calls)",
+        "count = \\n -> (stop = \\u -> true
+  go = \\u -> (p = pred n
+    count p)
+  is_zero n stop go zero)
+main = count n",
+    ),
+    (
+        "even/odd (mutual recursion)",
+        "even = \\n -> (stop = \\u -> true
+  go = \\u -> (p = pred n
+    odd p)
+  is_zero n stop go zero)
+odd = \\n -> (stop = \\u -> false
+  go = \\u -> (p = pred n
+    even p)
+  is_zero n stop go zero)
+main = even n",
+    ),
+    (
+        "parity (non-tail recursion)",
+        "not = \\b -> b false true
+parity = \\n -> (stop = \\u -> true
+  go = \\u -> (p = pred n
+    even = parity p
+    not even)
+  is_zero n stop go zero)
+main = parity n",
+    ),
+];
+
+/// Builds the numeral `n` out of doublings and increments.
+fn numeral(n: usize) -> String {
+    let mut source = String::from("n0 = zero\n");
+    let bits = usize::BITS - n.leading_zeros();
+    for (step, bit) in (0..bits).rev().enumerate() {
+        let doubled = format!("d{step} = double n{step}");
+        let next = if (n >> bit) & 1 == 1 {
+            format!("n{} = succ d{step}", step + 1)
+        } else {
+            format!("n{} = d{step}", step + 1)
+        };
+        source.push_str(&format!("{doubled}\n{next}\n"));
+    }
+    source.push_str(&format!("n = n{bits}\n"));
+    source
+}
+
+/// Runs `f` `iterations` times, returning the average time per run.
+fn time<T>(iterations: u32, mut f: impl FnMut() -> T) -> Duration {
+    let start = Instant::now();
+    for _ in 0..iterations {
+        black_box(f());
+    }
+    start.elapsed() / iterations
+}
+
+fn main() {
+    let n = std::env::args()
+        .skip(1)
+        .find_map(|arg| arg.parse().ok())
+        .unwrap_or(200);
+    let iterations = 3;
+    println!("n = {n}\n");
+
+    for (name, main) in PROGRAMS {
+        let source = format!("{CHURCH}{}{main}", numeral(n));
+        let module = parse_module(&source).unwrap();
+        let program = compile(&module, Evaluation::Strict).unwrap();
+
+        let vm = time(iterations, || {
+            let mut vm = Vm::new(&program);
+            vm.init().unwrap();
+            vm.global("main").unwrap().clone()
+        });
+        let ast = time(iterations, || {
+            Interpreter::new(&module).unwrap().main().unwrap()
+        });
+        let compiling = time(iterations, || compile(&module, Evaluation::Strict).unwrap());
+
+        println!("{name}");
+        println!("  vm:      {vm:?} (plus {compiling:?} to compile)");
+        println!("  ast:     {ast:?}");
+        println!(
+            "  speedup: {:.2}x",
+            ast.as_secs_f64() / (vm + compiling).as_secs_f64()
+        );
+    }
+}
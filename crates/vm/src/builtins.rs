@@ -0,0 +1,285 @@
+//! The natives every program is compiled against.
+//!
+//! Arithmetic works on integers and fails on overflow and division by zero
+//! instead of wrapping. Comparisons return the Church booleans `true` and
+//! `false`, which take two arguments and return the first or the second, so
+//! they can be used like any other boolean in ria.
+//!
+//! `assert_eq` returns `true` if its arguments are equal, as decided by
+//! [`Vm::equal`](crate::vm::Vm::equal), and fails with
+//! [`ErrorKind::AssertionFailed`] otherwise.
+
+use std::rc::Rc;
+
+use crate::{
+    natives::{FromValue, Intrinsic, Natives},
+    vm::{Error, ErrorKind, Partial, Value},
+};
+
+/// The index of the `true` native, which comes first in every program.
+const TRUE: u32 = 0;
+/// The index of the `false` native.
+const FALSE: u32 = 1;
+
+/// A builtin that takes two integers, which backends without natives
+/// implement as a primitive operation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Prim {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+/// What a [`Prim`] returns.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PrimValue {
+    Int(i64),
+    Bool(bool),
+}
+
+impl Prim {
+    /// Every builtin, in the order they're registered.
+    pub const ALL: [Prim; 11] = [
+        Prim::Add,
+        Prim::Sub,
+        Prim::Mul,
+        Prim::Div,
+        Prim::Mod,
+        Prim::Eq,
+        Prim::Ne,
+        Prim::Lt,
+        Prim::Le,
+        Prim::Gt,
+        Prim::Ge,
+    ];
+
+    /// Returns the name of the builtin.
+    pub fn name(self) -> &'static str {
+        match self {
+            Prim::Add => "add",
+            Prim::Sub => "sub",
+            Prim::Mul => "mul",
+            Prim::Div => "div",
+            Prim::Mod => "mod",
+            Prim::Eq => "eq",
+            Prim::Ne => "ne",
+            Prim::Lt => "lt",
+            Prim::Le => "le",
+            Prim::Gt => "gt",
+            Prim::Ge => "ge",
+        }
+    }
+
+    /// Returns the builtin called `name`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|prim| prim.name() == name)
+    }
+
+    /// Applies the builtin to `a` and `b`.
+    pub fn apply(self, a: i64, b: i64) -> Result<PrimValue, ErrorKind> {
+        let int = match self {
+            Prim::Add => a.checked_add(b),
+            Prim::Sub => a.checked_sub(b),
+            Prim::Mul => a.checked_mul(b),
+            Prim::Div | Prim::Mod if b == 0 => return Err(ErrorKind::DivisionByZero),
+            // both round towards zero, like Rust's `/` and `%`
+            Prim::Div => a.checked_div(b),
+            Prim::Mod => a.checked_rem(b),
+            Prim::Eq => return Ok(PrimValue::Bool(a == b)),
+            Prim::Ne => return Ok(PrimValue::Bool(a != b)),
+            Prim::Lt => return Ok(PrimValue::Bool(a < b)),
+            Prim::Le => return Ok(PrimValue::Bool(a <= b)),
+            Prim::Gt => return Ok(PrimValue::Bool(a > b)),
+            Prim::Ge => return Ok(PrimValue::Bool(a >= b)),
+        };
+        int.map(PrimValue::Int).ok_or(ErrorKind::Overflow)
+    }
+}
+
+/// Returns the builtin natives.
+pub fn builtins() -> Natives {
+    let mut natives = Natives::new();
+    natives
+        .register_lazy("true", 2, |args| Ok(args[0].clone()))
+        .register_lazy("false", 2, |args| Ok(args[1].clone()));
+    debug_assert_eq!(natives.index("true"), Some(TRUE));
+    debug_assert_eq!(natives.index("false"), Some(FALSE));
+
+    for prim in Prim::ALL {
+        natives.register(prim.name(), 2, move |args| {
+            let (a, b) = ints(args)?;
+            match prim.apply(a, b).map_err(error)? {
+                PrimValue::Int(int) => Ok(Value::Int(int)),
+                PrimValue::Bool(value) => Ok(boolean(value)),
+            }
+        });
+    }
+
+    natives.register_intrinsic("assert_eq", 2, Intrinsic::AssertEq);
+    natives
+}
+
+fn ints(args: &[Value]) -> Result<(i64, i64), Error> {
+    Ok((
+        i64::from_value(args[0].clone())?,
+        i64::from_value(args[1].clone())?,
+    ))
+}
+
+fn error(kind: ErrorKind) -> Error {
+    Error::new(kind)
+}
+
+/// Returns the Church boolean for `value`.
+pub(crate) fn boolean(value: bool) -> Value {
+    Value::Native(Rc::new(Partial {
+        native: if value { TRUE } else { FALSE },
+        args: Box::new([]),
+    }))
+}
+
+#[cfg(test)]
+mod test {
+    use ria_parser::parse_module;
+
+    use crate::{
+        bytecode::Evaluation,
+        compile::{compile, compile_with_natives},
+        natives::{IntoValue, Natives},
+        vm::{run, Error, ErrorKind, Value, Vm},
+    };
+
+    /// Runs `main` in both evaluation modes, checking that they agree.
+    fn run_main(source: &str) -> Result<i64, Error> {
+        let module = parse_module(source).unwrap();
+        let results: Vec<_> = [Evaluation::Strict, Evaluation::Lazy]
+            .into_iter()
+            .map(|evaluation| {
+                let program = compile(&module, evaluation).unwrap();
+                run(&program).map(|value| match value {
+                    Value::Int(int) => int,
+                    value => panic!("expected an integer, found {}", value.type_name()),
+                })
+            })
+            .collect();
+        assert_eq!(results[0], results[1], "strict and lazy results differ");
+        results[0].clone()
+    }
+
+    #[test]
+    fn do_arithmetic() {
+        assert_eq!(run_main("main = add 1 2"), Ok(3));
+        assert_eq!(run_main("main = (x = mul 6 7\n  sub x 2)"), Ok(40));
+        assert_eq!(run_main("main = (x = sub 0 7\n  div x 2)"), Ok(-3));
+        assert_eq!(run_main("main = (x = sub 0 7\n  mod x 2)"), Ok(-1));
+    }
+
+    #[test]
+    fn partially_apply_builtins() {
+        let source = "twice = \\f -> \\x -> (y = f x\n  f y)\ninc = add 1\nmain = twice inc 5";
+        assert_eq!(run_main(source), Ok(7));
+    }
+
+    #[test]
+    fn compare_to_church_booleans() {
+        assert_eq!(run_main("main = lt 1 2 10 20"), Ok(10));
+        assert_eq!(run_main("main = ge 1 2 10 20"), Ok(20));
+        assert_eq!(run_main("main = eq 3 3 1 0"), Ok(1));
+        assert_eq!(run_main("main = ne 3 3 1 0"), Ok(0));
+        assert_eq!(run_main("main = true 1 0"), Ok(1));
+    }
+
+    #[test]
+    fn recurse_on_integers() {
+        // the strict program delays each branch with a lambda
+        let source = "fact = \\n -> (is_zero = eq n 0
+  base = \\_ -> 1
+  step = \\_ -> (m = sub n 1
+    r = fact m
+    mul n r)
+  branch = is_zero base step
+  branch n)
+main = fact 20";
+        assert_eq!(run_main(source), Ok(2_432_902_008_176_640_000));
+    }
+
+    #[test]
+    fn branch_lazily() {
+        // `false` doesn't evaluate the branch it drops
+        let source = "loop = \\x -> loop x\nmain = (x = loop 0\n  false x 1)";
+        let module = parse_module(source).unwrap();
+        let program = compile(&module, Evaluation::Lazy).unwrap();
+        assert!(matches!(run(&program), Ok(Value::Int(1))));
+    }
+
+    #[test]
+    fn report_overflow_at_the_call() {
+        let source = "big = 9223372036854775807\nmain = add big 1";
+        let err = run_main(source).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::Overflow);
+        assert_eq!(&source[err.span], "add big 1");
+
+        let source =
+            "min = (x = sub 0 9223372036854775807\n  sub x 1)\nmain = (m = sub 0 1\n  div min m)";
+        let err = run_main(source).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::Overflow);
+        assert_eq!(&source[err.span], "div min m");
+    }
+
+    #[test]
+    fn report_division_by_zero_at_the_call() {
+        let source = "main = (half = div 1\n  half 0)";
+        let err = run_main(source).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::DivisionByZero);
+        assert_eq!(&source[err.span], "half 0");
+
+        let err = run_main("main = mod 1 0").unwrap_err();
+        assert_eq!(err.kind, ErrorKind::DivisionByZero);
+    }
+
+    #[test]
+    fn report_type_errors() {
+        let source = "id = \\x -> x\nmain = add 1 id";
+        let err = run_main(source).unwrap_err();
+        assert_eq!(
+            err.kind,
+            ErrorKind::Type {
+                expected: "an integer",
+                found: "a function"
+            }
+        );
+        assert_eq!(&source[err.span], "add 1 id");
+
+        let err = run_main("main = 1 2").unwrap_err();
+        assert_eq!(
+            err.kind,
+            ErrorKind::Type {
+                expected: "a function",
+                found: "an integer"
+            }
+        );
+    }
+
+    #[test]
+    fn shadow_builtins() {
+        // defs shadow builtins, and host natives replace them
+        assert_eq!(run_main("add = \\a -> \\b -> a\nmain = add 1 2"), Ok(1));
+
+        let mut natives = Natives::new();
+        natives.register("sub", 2, |_| Ok(0.into_value()));
+        let module = parse_module("main = sub 5 2").unwrap();
+        let program = compile_with_natives(&module, Evaluation::Strict, &natives).unwrap();
+        let mut vm = Vm::new(&program);
+        vm.init().unwrap();
+        let result: i64 = vm.call_global("main", []).unwrap();
+        assert_eq!(result, 0);
+    }
+}
@@ -0,0 +1,190 @@
+use std::ops::Range;
+
+use ria_parser::loader::ModuleId;
+
+use crate::natives::Native;
+
+/// The index of a [`Function`] in a [`Program`].
+pub type FunctionId = u32;
+
+/// A single VM instruction.
+///
+/// Instructions operate on the operand stack of the VM and the local slots of
+/// the current frame. A slot may hold a cell instead of a value, for defs
+/// that are captured before they are initialized; such slots are read and
+/// written with the `*Cell` instructions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    /// Pushes an integer.
+    Int(i64),
+    /// Pushes the value in a local slot.
+    Local(u16),
+    /// Pushes the value in the cell in a local slot.
+    LocalCell(u16),
+    /// Pushes a captured value of the current closure.
+    Capture(u16),
+    /// Pushes the value in a captured cell of the current closure.
+    CaptureCell(u16),
+    /// Pushes the value of a global.
+    Global(u32),
+    /// Pushes a native function.
+    Native(u32),
+    /// Pushes a constructor, which is a data value if it has no fields and
+    /// a function taking its fields otherwise.
+    Construct(u32),
+    /// Creates a closure of a function, capturing values from the current
+    /// frame as listed in [`Function::captures`].
+    Closure(FunctionId),
+    /// Creates a thunk of a function without a parameter, capturing values
+    /// like [`Op::Closure`].
+    Thunk(FunctionId),
+    /// Replaces the value on top of the stack with its value, if it is a
+    /// thunk. The thunk is updated with the result, so it is only run once.
+    Force,
+    /// Pops a value into a local slot.
+    StoreLocal(u16),
+    /// Puts a new, empty cell in a local slot.
+    NewCell(u16),
+    /// Pops a value into the cell in a local slot.
+    StoreCell(u16),
+    /// Pops an argument and a function, and calls the function.
+    Call,
+    /// Like [`Op::Call`], but replaces the current frame.
+    TailCall,
+    /// Pops the result and returns from the current frame.
+    Return,
+    /// Continues at an instruction of the current function.
+    Jump(u32),
+    /// Pops a data value, and jumps to `otherwise` unless it was built with
+    /// `constructor`.
+    MatchConstructor { constructor: u32, otherwise: u32 },
+    /// Pops an integer, and jumps to `otherwise` unless it is `value`.
+    MatchInt { value: i64, otherwise: u32 },
+    /// Pops a data value and pushes one of its fields.
+    Field(u16),
+    /// Pops this many values and pushes a tuple of them, in the order they
+    /// were pushed.
+    Tuple(u16),
+    /// Pops the values of the fields of a record shape, in the order they
+    /// were pushed, and pushes a record of them.
+    Record(u32),
+    /// Pops a record and pushes the field with a name in
+    /// [`Program::fields`].
+    GetField(u32),
+    /// Pops a tuple and pushes one of its items.
+    GetItem(u16),
+    /// Fails because no arm of a match matched the value.
+    NoMatch,
+}
+
+/// Where a closure gets one of its captured values from when it is created.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CaptureSource {
+    /// A local slot of the enclosing frame.
+    Local(u16),
+    /// A captured value of the enclosing closure.
+    Capture(u16),
+}
+
+/// How a program evaluates the arguments of calls and the values of defs.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Evaluation {
+    /// Arguments and defs are evaluated before they are used.
+    #[default]
+    Strict,
+    /// Arguments and defs are thunks, evaluated at most once, when their
+    /// value is first needed.
+    Lazy,
+}
+
+/// The source the spans of a [`Function`] are in.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Origin {
+    /// The module that was compiled, or the main module of a program of
+    /// several.
+    #[default]
+    Main,
+    /// A module the main module imports, directly or not.
+    Import(ModuleId),
+    /// The prelude, whose source isn't shown, so an error there is reported
+    /// at the innermost call from a module instead.
+    Prelude,
+}
+
+/// A compiled lambda, the initializer of a global or the body of a thunk.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Function {
+    /// The name of the def the function was bound to, if any.
+    pub name: Option<String>,
+    /// Whether slot 0 holds the parameter. Initializers and thunks take no
+    /// parameter.
+    pub has_param: bool,
+    /// The source the function's spans are in.
+    pub origin: Origin,
+    /// The number of local slots, including the parameter.
+    pub locals: u16,
+    pub captures: Box<[CaptureSource]>,
+    pub code: Box<[Op]>,
+    /// The span of the expression each instruction was compiled from.
+    pub spans: Box<[Range<usize>]>,
+}
+
+/// A top-level definition.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Global {
+    pub name: String,
+    /// The function that computes the global's value, which is the body of
+    /// a thunk in a lazy program.
+    pub init: FunctionId,
+}
+
+/// A `test` declaration of the main module.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Test {
+    pub name: String,
+    /// The function that computes the test's value, which runs after the
+    /// globals are initialized.
+    pub init: FunctionId,
+    /// The span of the declaration in the main module.
+    pub span: Range<usize>,
+}
+
+/// A constructor of a data type.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Constructor {
+    pub name: String,
+    /// The number of fields of the values it builds.
+    pub arity: u32,
+}
+
+/// A compiled module.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Program {
+    pub evaluation: Evaluation,
+    pub functions: Box<[Function]>,
+    pub globals: Box<[Global]>,
+    /// The natives the program was compiled against.
+    pub natives: Box<[Native]>,
+    /// The constructors of every data type declared in the program.
+    pub constructors: Box<[Constructor]>,
+    /// The names of the fields of every record in the program.
+    pub fields: Box<[String]>,
+    /// The fields of each shape of record built by the program, as indices
+    /// into [`Program::fields`], in the order they were written.
+    pub shapes: Box<[Box<[u32]>]>,
+    /// The order to initialize the globals in, so each is initialized after
+    /// the globals it depends on.
+    pub init_order: Box<[u32]>,
+    /// The tests of the main module, in the order they were declared.
+    pub tests: Box<[Test]>,
+}
+
+impl Program {
+    /// Returns the index of the global called `name`.
+    pub fn global(&self, name: &str) -> Option<u32> {
+        self.globals
+            .iter()
+            .position(|global| global.name == name)
+            .map(|index| index as u32)
+    }
+}
@@ -0,0 +1,744 @@
+//! A backend that compiles a module to a self-contained C program.
+//!
+//! The module is lowered to [`anf`] and its lambdas are [`lifted`], so each
+//! function becomes a C function taking its closure and its argument. The
+//! variables of a function live in slots on the C stack that the garbage
+//! collector of the runtime can find. A match becomes a chain of `if`s, one
+//! for each arm, that test the value against the arm's pattern. The program
+//! prints the value of `main`, or reports a runtime error on standard error
+//! and exits with 1.
+
+use std::{collections::HashMap, fmt::Write as _, rc::Rc};
+
+use ria_parser::{
+    diagnostic::Diagnostic,
+    loader::{Loader, ModuleId},
+    module::Module,
+};
+
+use crate::ir::{
+    anf,
+    lifted::{self, Bind, Closure, Expr, Match, Program},
+    Atom, Builtin, ConstructorId, Field, Op, Pattern, Var,
+};
+
+/// The objects, garbage collector and builtins every program starts with.
+const RUNTIME: &str = include_str!("c/runtime.c");
+
+/// Compiles `module` to C. The defs of `prelude`, if any, are in scope,
+/// shadowed by the module's own.
+pub fn compile_module<'i>(
+    module: &Module<'i>,
+    prelude: Option<&Module<'i>>,
+) -> Result<String, Diagnostic> {
+    Ok(emit(&lifted::lift(&anf::lower_module(module, prelude)?)))
+}
+
+/// Compiles the module `main` of `loader` and the modules it imports to one
+/// C program, with the defs of `prelude`, if any, in scope in each of them.
+/// Returns the first error, with the module it is in.
+pub fn compile_loaded(
+    loader: &Loader,
+    main: ModuleId,
+    prelude: Option<&Module<'static>>,
+) -> Result<String, (ModuleId, Diagnostic)> {
+    Ok(emit(&lifted::lift(&anf::lower_loaded(
+        loader, main, prelude,
+    )?)))
+}
+
+fn emit(program: &Program) -> String {
+    let mut names = Names::default();
+    let mut functions = String::new();
+    for (index, function) in program.functions.iter().enumerate() {
+        let mut emitter = Emitter::new(program, &mut names);
+        emitter.bind(function.closure);
+        emitter.bind(function.param);
+        emitter.line("slots[0] = self;");
+        emitter.line("slots[1] = arg;");
+        emitter.expr(&function.body, &End::Return { tail: true });
+        let name = program.names.display(function.name);
+        writeln!(functions, "\n/* {name} */").unwrap();
+        writeln!(
+            functions,
+            "static ria_value ria_f{index}(ria_value self, ria_value arg) {{"
+        )
+        .unwrap();
+        emitter.finish(&mut functions);
+    }
+    let mut emitter = Emitter::new(program, &mut names);
+    emitter.expr(&program.body, &End::Return { tail: false });
+    writeln!(functions, "\nstatic ria_value ria_main(void) {{").unwrap();
+    emitter.finish(&mut functions);
+
+    let mut out = String::from(RUNTIME);
+    out.push('\n');
+    out.push_str(&names.definitions);
+    for index in 0..program.functions.len() {
+        writeln!(
+            out,
+            "static ria_value ria_f{index}(ria_value self, ria_value arg);"
+        )
+        .unwrap();
+    }
+    out.push_str(&functions);
+    out
+}
+
+/// The names of the constructors, fields and records the program uses, each
+/// defined once so the runtime can compare them by address.
+#[derive(Default)]
+struct Names {
+    constructors: HashMap<ConstructorId, String>,
+    fields: HashMap<Rc<str>, String>,
+    /// The names of the fields of each kind of record, by their C names.
+    records: HashMap<Vec<String>, String>,
+    definitions: String,
+}
+
+impl Names {
+    fn constructor(&mut self, program: &Program, constructor: ConstructorId) -> String {
+        if let Some(name) = self.constructors.get(&constructor) {
+            return name.clone();
+        }
+        let name = format!("ria_constructor_{}", constructor.0);
+        let value = program.names.constructor_name(constructor);
+        writeln!(
+            self.definitions,
+            "static const char {name}[] = \"{value}\";"
+        )
+        .unwrap();
+        self.constructors.insert(constructor, name.clone());
+        name
+    }
+
+    fn field(&mut self, field: &Rc<str>) -> String {
+        if let Some(name) = self.fields.get(field) {
+            return name.clone();
+        }
+        let name = format!("ria_field_{}", self.fields.len());
+        writeln!(
+            self.definitions,
+            "static const char {name}[] = \"{field}\";"
+        )
+        .unwrap();
+        self.fields.insert(field.clone(), name.clone());
+        name
+    }
+
+    /// Returns the array of the names of a record's fields, or `NULL` if it
+    /// has none.
+    fn record(&mut self, fields: &[(Rc<str>, Atom)]) -> String {
+        if fields.is_empty() {
+            return "NULL".to_owned();
+        }
+        let fields: Vec<_> = fields.iter().map(|(field, _)| self.field(field)).collect();
+        if let Some(name) = self.records.get(&fields) {
+            return name.clone();
+        }
+        let name = format!("ria_record_{}", self.records.len());
+        writeln!(
+            self.definitions,
+            "static const char *const {name}[] = {{{}}};",
+            fields.join(", ")
+        )
+        .unwrap();
+        self.records.insert(fields, name.clone());
+        name
+    }
+}
+
+/// Where the value of an expression goes.
+enum End {
+    /// It's returned from the function. A call in `tail` position returns
+    /// to be made by the caller.
+    Return { tail: bool },
+    /// It's stored in a slot, and the function goes on.
+    Store(String),
+}
+
+/// Writes the body of a C function.
+struct Emitter<'p> {
+    program: &'p Program,
+    names: &'p mut Names,
+    /// The slot of each variable the function binds.
+    slots: HashMap<Var, usize>,
+    /// How many blocks the next line is nested in.
+    depth: usize,
+    body: String,
+}
+
+impl<'p> Emitter<'p> {
+    fn new(program: &'p Program, names: &'p mut Names) -> Self {
+        Self {
+            program,
+            names,
+            slots: HashMap::new(),
+            depth: 1,
+            body: String::new(),
+        }
+    }
+
+    fn line(&mut self, line: &str) {
+        writeln!(self.body, "{}{line}", "    ".repeat(self.depth)).unwrap();
+    }
+
+    /// Gives `var` a slot, and returns how it's referred to.
+    fn bind(&mut self, var: Var) -> String {
+        let slot = self.slots.len();
+        self.slots.insert(var, slot);
+        format!("slots[{slot}]")
+    }
+
+    fn var(&self, var: Var) -> String {
+        format!("slots[{}]", self.slots[&var])
+    }
+
+    fn int(int: i64) -> String {
+        match int {
+            i64::MIN => "INT64_MIN".to_owned(),
+            int => format!("INT64_C({int})"),
+        }
+    }
+
+    fn atom(&self, atom: &Atom) -> String {
+        match atom {
+            Atom::Var(var) => self.var(*var),
+            Atom::Int(int) => format!("ria_int({})", Self::int(*int)),
+            Atom::Builtin(Builtin::True) => "ria_closure_value(&ria_true)".to_owned(),
+            Atom::Builtin(Builtin::False) => "ria_closure_value(&ria_false)".to_owned(),
+            Atom::Builtin(Builtin::Prim(prim)) => {
+                format!("ria_closure_value(&ria_{})", prim.name())
+            }
+        }
+    }
+
+    /// Allocates `closure` into `slot`, leaving its fields to fill in.
+    fn alloc(&mut self, slot: &str, var: Var, closure: &Closure) {
+        let name = self.program.names.display(var);
+        let (function, len) = (closure.function.0, closure.captures.len());
+        self.line(&format!(
+            "{slot} = ria_alloc(ria_f{function}, {len}); /* {name} */"
+        ));
+    }
+
+    fn fill(&mut self, slot: &str, closure: &Closure) {
+        for (index, &captured) in closure.captures.iter().enumerate() {
+            let value = self.var(captured);
+            self.line(&format!("{slot}.as.o->fields[{index}] = {value};"));
+        }
+    }
+
+    /// Writes `op` to store its value in `slot`. An object is allocated
+    /// before its fields are stored in it, since their values are in slots
+    /// already.
+    fn op(&mut self, slot: &str, var: Var, op: &Op) {
+        let name = self.program.names.display(var);
+        let (alloc, fields) = match op {
+            Op::Data(constructor, fields) => {
+                let constructor = self.names.constructor(self.program, *constructor);
+                let alloc = format!("ria_data({constructor}, {})", fields.len());
+                (alloc, fields.iter().collect::<Vec<_>>())
+            }
+            Op::Tuple(items) => (
+                format!("ria_tuple({})", items.len()),
+                items.iter().collect(),
+            ),
+            Op::Record(fields) => {
+                let record = self.names.record(fields);
+                let alloc = format!("ria_record({record}, {})", fields.len());
+                (alloc, fields.iter().map(|(_, atom)| atom).collect())
+            }
+            Op::Get(value, field) => {
+                let value = self.atom(value);
+                let get = match field {
+                    Field::Name(field) => {
+                        format!("ria_field({value}, {})", self.names.field(field))
+                    }
+                    Field::Index(index) => format!("ria_item({value}, {index})"),
+                };
+                self.line(&format!("{slot} = {get}; /* {name} */"));
+                return;
+            }
+        };
+        self.line(&format!("{slot} = {alloc}; /* {name} */"));
+        for (index, field) in fields.into_iter().enumerate() {
+            let value = self.atom(field);
+            self.line(&format!("{slot}.as.o->fields[{index}] = {value};"));
+        }
+    }
+
+    /// Ends the expression with `value`.
+    fn end(&mut self, end: &End, value: &str) {
+        match end {
+            End::Return { .. } => self.line(&format!("RIA_RETURN({value});")),
+            End::Store(slot) => self.line(&format!("{slot} = {value};")),
+        }
+    }
+
+    /// Writes `expr`, whose value goes to `end`.
+    fn expr(&mut self, mut expr: &Expr, end: &End) {
+        loop {
+            match expr {
+                Expr::Let(var, bind, rest) => {
+                    let slot = self.bind(*var);
+                    let name = self.program.names.display(*var);
+                    match bind {
+                        Bind::Atom(atom) => {
+                            let value = self.atom(atom);
+                            self.line(&format!("{slot} = {value}; /* {name} */"));
+                        }
+                        Bind::Call(func, arg) => {
+                            let (func, arg) = (self.atom(func), self.atom(arg));
+                            self.line(&format!("{slot} = ria_apply({func}, {arg}); /* {name} */"));
+                        }
+                        Bind::Closure(closure) => {
+                            self.alloc(&slot, *var, closure);
+                            self.fill(&slot, closure);
+                        }
+                        Bind::Field(closure, index) => {
+                            let closure = self.var(*closure);
+                            let field = format!("{closure}.as.o->fields[{index}]");
+                            self.line(&format!("{slot} = {field}; /* {name} */"));
+                        }
+                        Bind::Op(op) => self.op(&slot, *var, op),
+                        Bind::Match(matching) => {
+                            self.line(&format!("/* {name} */"));
+                            self.matching(matching, &End::Store(slot));
+                        }
+                    }
+                    expr = rest;
+                }
+                Expr::LetRec(closures, rest) => {
+                    // every closure exists before any captures another
+                    let slots: Vec<_> = closures.iter().map(|(var, _)| self.bind(*var)).collect();
+                    for ((var, closure), slot) in closures.iter().zip(&slots) {
+                        self.alloc(slot, *var, closure);
+                    }
+                    for ((_, closure), slot) in closures.iter().zip(&slots) {
+                        self.fill(slot, closure);
+                    }
+                    expr = rest;
+                }
+                Expr::Call(func, arg) => {
+                    let (func, arg) = (self.atom(func), self.atom(arg));
+                    let call = match end {
+                        End::Return { tail: true } => "ria_tail",
+                        _ => "ria_apply",
+                    };
+                    self.end(end, &format!("{call}({func}, {arg})"));
+                    return;
+                }
+                Expr::Atom(atom) => {
+                    let value = self.atom(atom);
+                    self.end(end, &value);
+                    return;
+                }
+                Expr::Match(matching) => {
+                    self.matching(matching, end);
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Writes `matching` as an `if` for each arm, up to the first that
+    /// matches anything, and an `else` that reports no arm matches if none
+    /// does.
+    fn matching(&mut self, matching: &Match, end: &End) {
+        let scrutinee = self.atom(&matching.scrutinee);
+        for (index, (pattern, body)) in matching.arms.iter().enumerate() {
+            let mut tests = Vec::new();
+            self.tests(pattern, &scrutinee, &mut tests);
+            let open = match (index, tests.is_empty()) {
+                (0, true) => "{".to_owned(),
+                (0, false) => format!("if ({}) {{", tests.join(" && ")),
+                (_, true) => "} else {".to_owned(),
+                (_, false) => format!("}} else if ({}) {{", tests.join(" && ")),
+            };
+            self.line(&open);
+            self.depth += 1;
+            self.bind_pattern(pattern, &scrutinee);
+            self.expr(body, end);
+            self.depth -= 1;
+            if tests.is_empty() {
+                self.line("}");
+                return;
+            }
+        }
+        self.line("} else {");
+        self.depth += 1;
+        self.end(end, "ria_no_match()");
+        self.depth -= 1;
+        self.line("}");
+    }
+
+    /// Adds the tests of whether `value` matches `pattern` to `tests`, in
+    /// the order they are made. Each field of a data value is only read
+    /// once the tests before it have passed.
+    fn tests(&mut self, pattern: &Pattern, value: &str, tests: &mut Vec<String>) {
+        match pattern {
+            Pattern::Wildcard | Pattern::Var(_) => {}
+            Pattern::Int(int) => tests.push(format!("ria_is_int({value}, {})", Self::int(*int))),
+            Pattern::Data(constructor, fields) => {
+                let constructor = self.names.constructor(self.program, *constructor);
+                tests.push(format!("ria_is({value}, {constructor})"));
+                for (index, field) in fields.iter().enumerate() {
+                    self.tests(field, &format!("{value}.as.o->fields[{index}]"), tests);
+                }
+            }
+        }
+    }
+
+    /// Stores the parts of `value` that the variables of `pattern` bind.
+    fn bind_pattern(&mut self, pattern: &Pattern, value: &str) {
+        match pattern {
+            Pattern::Wildcard | Pattern::Int(_) => {}
+            Pattern::Var(var) => {
+                let (slot, name) = (self.bind(*var), self.program.names.display(*var));
+                self.line(&format!("{slot} = {value}; /* {name} */"));
+            }
+            Pattern::Data(_, fields) => {
+                for (index, field) in fields.iter().enumerate() {
+                    self.bind_pattern(field, &format!("{value}.as.o->fields[{index}]"));
+                }
+            }
+        }
+    }
+
+    /// Writes the function to `out`, starting with its slots.
+    fn finish(self, out: &mut String) {
+        let slots = self.slots.len().max(1);
+        writeln!(out, "    RIA_ENTER({slots});").unwrap();
+        out.push_str(&self.body);
+        out.push_str("}\n");
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::process::Command;
+
+    use ria_parser::{loader::Loader, parse_module, temp_dir::TempDir};
+
+    use crate::{
+        bytecode::Evaluation,
+        compile::compile_with_prelude,
+        natives::Natives,
+        prelude::prelude,
+        vm::{run, run_with_limits, ErrorKind, Limits},
+    };
+
+    use super::{compile_loaded, compile_module};
+
+    /// Compiles `source` with the prelude to C, builds it with `cc` with
+    /// `flags`, and returns what the program prints and whether it
+    /// succeeded. Returns `None` if there's no `cc` to build with.
+    fn run_c(test: &str, source: &str, flags: &[&str]) -> Option<(String, String, bool)> {
+        let module = parse_module(source).unwrap();
+        let code = compile_module(&module, Some(&prelude())).unwrap();
+        build_and_run(test, source, &code, flags)
+    }
+
+    fn build_and_run(
+        test: &str,
+        source: &str,
+        code: &str,
+        flags: &[&str],
+    ) -> Option<(String, String, bool)> {
+        let dir = TempDir::new(&format!("c-build-{test}"), &[("main.c", code)]);
+        let (file, binary) = (dir.join("main.c"), dir.join("main"));
+        let built = Command::new("cc")
+            .args(["-std=c99", "-O1", "-Wall", "-Wextra", "-Werror"])
+            .args(flags)
+            .arg("-o")
+            .arg(&binary)
+            .arg(&file)
+            .output();
+        let built = match built {
+            Ok(built) => built,
+            Err(err) => {
+                eprintln!("skipping `{test}`: can't run cc: {err}");
+                return None;
+            }
+        };
+        assert!(
+            built.status.success(),
+            "cc failed on `{source}`:\n{}",
+            String::from_utf8_lossy(&built.stderr)
+        );
+
+        let output = Command::new(&binary).output().unwrap();
+        Some((
+            String::from_utf8(output.stdout).unwrap(),
+            String::from_utf8(output.stderr).unwrap(),
+            output.status.success(),
+        ))
+    }
+
+    /// Returns what the VM displays `source` evaluating to.
+    fn expected(source: &str) -> String {
+        let module = parse_module(source).unwrap();
+        let program = compile_with_prelude(&module, Evaluation::Strict, &Natives::new()).unwrap();
+        match run(&program) {
+            Ok(value) => format!("{}\n", value.display(&program)),
+            Err(err) => panic!("`{source}` failed: {err:?}"),
+        }
+    }
+
+    const LIST: &str = "type List a = Nil | Cons a (List a)
+range = \\n -> match n with
+  | 0 -> Nil
+  | _ -> Cons n (range (sub n 1))
+";
+
+    #[test]
+    fn match_the_vm() {
+        for (test, source) in [
+            ("arith", "main = sub (mul 6 7) (div 9 (mod 7 4))"),
+            ("church", "main = to_int (times (from_int 6) (from_int 7))"),
+            (
+                "fact",
+                "fact = \\n -> le n 1 (\\_ -> 1) (\\_ -> mul n (fact (sub n 1))) 0\nmain = fact 20",
+            ),
+            (
+                "sum",
+                "main = (sum = \\n -> \\acc -> eq n 0 (\\_ -> acc) (\\_ -> sum (sub n 1) (add acc n)) 0\n  \
+                 sum 1000000 0)",
+            ),
+            (
+                "captures",
+                "main = (k = 7\n  \
+                 even = \\n -> eq n 0 (\\_ -> k) (\\_ -> odd (sub n 1)) 0\n  \
+                 odd = \\n -> eq n 0 (\\_ -> sub 0 k) (\\_ -> even (sub n 1)) 0\n  \
+                 add (even 10) (odd 11))",
+            ),
+            ("twice", "twice = \\f -> \\x -> f (f x)\nmain = twice (twice (mul 3)) 1"),
+            ("list", &format!("{LIST}main = range 3")),
+            (
+                "map",
+                &format!(
+                    "{LIST}map = \\f -> \\list -> match list with\n  \
+                     | Nil -> Nil\n  \
+                     | Cons x rest -> Cons (f x) (map f rest)\n\
+                     main = (wrap = Cons 0\n  map (\\x -> wrap Nil) (range 2))"
+                ),
+            ),
+            (
+                "nested",
+                &format!(
+                    "{LIST}type Option a = None | Some a\n\
+                     second = \\list -> match list with\n  \
+                     | Cons _ (Cons x _) -> Some x\n  \
+                     | _ -> None\n\
+                     main = Cons (second (range 1)) (Cons (second (range 5)) Nil)"
+                ),
+            ),
+            (
+                "bound_match",
+                "main = (x = match 2 with\n    | 1 -> 10\n    | n -> mul n 100\n  add x 1)",
+            ),
+            (
+                "local_type",
+                "main = (type Pair a b = Pair a b\n  \
+                 swap = \\p -> match p with\n    | Pair a b -> Pair b a\n  \
+                 swap (Pair 1 2))",
+            ),
+            ("records", "main = (1, { a = 2, b = (3, 4) }, {})"),
+            (
+                "access",
+                "type Option a = None | Some a\n\
+                 swap = \\p -> (p.1, p.0)\n\
+                 point = { x = 1, y = Some 2 }\n\
+                 main = (p = swap (point.x, point)\n  p.0.y)",
+            ),
+        ] {
+            let expected = expected(source);
+            let Some((stdout, stderr, success)) = run_c(test, source, &[]) else {
+                return;
+            };
+            assert!(success, "`{source}` failed: {stderr}");
+            assert_eq!(stdout, expected, "running `{source}`");
+        }
+    }
+
+    #[test]
+    fn keep_live_closures_through_collections() {
+        // each step builds a chain of closures that a collection must keep
+        let source = "compose = \\f -> \\g -> \\x -> f (g x)\n\
+                      chain = \\n -> \\f -> eq n 0 (\\_ -> f) (\\_ -> chain (sub n 1) (compose (add 1) f)) 0\n\
+                      main = chain 200 (\\x -> x) 0";
+        let expected = expected(source);
+        let Some((stdout, _, _)) = run_c("stress", source, &["-DRIA_GC_STRESS"]) else {
+            return;
+        };
+        assert_eq!(stdout, expected);
+
+        // and a list whose cells must outlive the collections that building
+        // and matching it make
+        let source = format!(
+            "{LIST}sum = \\list -> match list with\n  \
+             | Nil -> (0, {{ n = 0 }})\n  \
+             | Cons x rest -> (s = sum rest\n    (add x s.0, {{ n = add 1 s.1.n }}))\n\
+             main = sum (range 300)"
+        );
+        let expected = self::expected(&source);
+        let Some((stdout, _, _)) = run_c("stress_data", &source, &["-DRIA_GC_STRESS"]) else {
+            return;
+        };
+        assert_eq!(stdout, expected);
+    }
+
+    #[test]
+    fn report_calls_nested_too_deeply() {
+        let deep = |n: u32| {
+            format!(
+                "deep = \\n -> eq n 0 (\\_ -> 0) (\\_ -> add 1 (deep (sub n 1))) 0\n\
+                 main = deep {n}"
+            )
+        };
+        // within the limit of the C stack, the program agrees with the VM
+        let source = deep(1000);
+        let expected = expected(&source);
+        let Some((stdout, _, _)) = run_c("shallow", &source, &[]) else {
+            return;
+        };
+        assert_eq!(stdout, expected);
+
+        // past it, it stops with an error where the VM reports its own limit,
+        // rather than overflowing the C stack
+        let source = deep(100000);
+        let module = parse_module(&source).unwrap();
+        let program = compile_with_prelude(&module, Evaluation::Strict, &Natives::new()).unwrap();
+        let limits = Limits {
+            max_depth: Some(10000),
+            ..Limits::default()
+        };
+        let err = run_with_limits(&program, limits).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::TooDeep);
+        let Some((stdout, stderr, success)) = run_c("deep", &source, &[]) else {
+            return;
+        };
+        assert!(!success && stdout.is_empty(), "`{source}` succeeded");
+        assert_eq!(stderr, "error: stack depth exceeded\n");
+    }
+
+    #[test]
+    fn compile_long_straight_line_programs() {
+        let tuple = |n: u32| {
+            let items: Vec<_> = (0..n).map(|i| format!("add {i} 1")).collect();
+            format!("main = ({})", items.join(", "))
+        };
+        // lowering and lifting walk a long chain of lets without recursing,
+        // though cc takes too long on one that long to build it here
+        let source = tuple(20_000);
+        let module = parse_module(&source).unwrap();
+        assert!(compile_module(&module, None).is_ok());
+
+        let source = tuple(1000);
+        let module = parse_module(&source).unwrap();
+        let code = compile_module(&module, None).unwrap();
+        let Some((stdout, _, _)) = build_and_run("long", &source, &code, &[]) else {
+            return;
+        };
+        assert_eq!(stdout, expected(&source));
+    }
+
+    #[test]
+    fn compile_imported_modules() {
+        let dir = TempDir::new(
+            "c-imports",
+            &[
+                (
+                    "main.ria",
+                    "import lib/m (Box)\nmain = match m.wrap (Box 2) with\n  | Box (Box n) -> (add n m.x, Box n)",
+                ),
+                (
+                    "lib/m.ria",
+                    "export (Box, wrap, x)\ntype Box a = Box a\nwrap = Box\nx = 40",
+                ),
+            ],
+        );
+        let mut loader = Loader::new();
+        let main = loader.load(dir.join("main.ria")).unwrap();
+
+        let code = compile_loaded(&loader, main, Some(&prelude())).unwrap();
+        let Some((stdout, stderr, success)) = build_and_run("imports", "main.ria", &code, &[])
+        else {
+            return;
+        };
+        assert!(success, "main.ria failed: {stderr}");
+        assert_eq!(stdout, "(42, Box 2)\n");
+    }
+
+    #[test]
+    fn report_runtime_errors() {
+        for (test, source, message) in [
+            ("div", "main = div 1 0", "division by zero"),
+            (
+                "overflow",
+                "main = mul 4611686018427387904 2",
+                "integer overflow",
+            ),
+            (
+                "call",
+                "main = (f = 1\n  f 2)",
+                "expected a function, found an integer",
+            ),
+            (
+                "add",
+                "main = add id 1",
+                "expected an integer, found a function",
+            ),
+            (
+                "no_match",
+                "type A = A\ntype B = B\nf = \\x -> match x with\n  | A -> 1\nmain = f B",
+                "no pattern matches the value",
+            ),
+            (
+                "pattern",
+                "type A = A\nmain = match 1 with\n  | A -> 1",
+                "expected a data value, found an integer",
+            ),
+            (
+                "int_pattern",
+                "type A = A\nmain = match A with\n  | 1 -> 1\n  | _ -> 2",
+                "expected an integer, found a data value",
+            ),
+            (
+                "field",
+                "get = \\r -> r.b\nmain = get { a = 1 }",
+                "no field `b`",
+            ),
+            (
+                "item",
+                "third = \\t -> t.2\nmain = third (1, 2)",
+                "no field `2`",
+            ),
+            (
+                "tuple",
+                "second = \\t -> t.1\nmain = second { a = 1 }",
+                "expected a tuple, found a record",
+            ),
+            (
+                "call_tuple",
+                "main = (1, 2) 3",
+                "expected a function, found a tuple",
+            ),
+        ] {
+            let Some((stdout, stderr, success)) = run_c(test, source, &[]) else {
+                return;
+            };
+            assert!(!success && stdout.is_empty(), "`{source}` succeeded");
+            assert_eq!(stderr, format!("error: {message}\n"));
+        }
+    }
+
+    #[test]
+    fn print_functions() {
+        let Some((stdout, _, _)) = run_c("function", "main = add 1", &[]) else {
+            return;
+        };
+        assert_eq!(stdout, "<function>\n");
+    }
+}
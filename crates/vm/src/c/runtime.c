@@ -0,0 +1,517 @@
+/* The runtime of a ria program compiled to C.
+ *
+ * A value is an integer or an object: a closure, data value, tuple or
+ * record. A closure is a code pointer and the values the closure captured.
+ * Builtins are closures too, whose code takes the first argument into a new
+ * closure and computes on the second. A data value points to the name of its
+ * constructor and a record to the names of its fields, which the program
+ * defines once each, so comparing the pointers compares the names.
+ *
+ * Every function keeps its variables in an array of slots on the C stack,
+ * linked into a chain of frames that the garbage collector marks from, so
+ * nothing the program still uses is swept. A call in tail position returns
+ * to `ria_apply`, which makes it from there, so loops don't grow the C
+ * stack. Other calls do, so their depth is limited to report an error
+ * before the C stack overflows.
+ *
+ * The functions and builtins generated code refers to aren't static, so a
+ * program that doesn't use one of them still compiles without warnings.
+ *
+ * Define RIA_GC_STRESS to collect garbage before every allocation. */
+
+#include <inttypes.h>
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+
+/* How many objects may be live before the first collection. */
+#ifndef RIA_GC_THRESHOLD
+#define RIA_GC_THRESHOLD 4096
+#endif
+
+/* How many functions may be running at once. */
+#ifndef RIA_MAX_DEPTH
+#define RIA_MAX_DEPTH 10000
+#endif
+
+typedef struct ria_object ria_object;
+
+typedef enum {
+    RIA_INT,
+    /* what a function returns to have `ria_apply` make its tail call */
+    RIA_TAIL,
+    RIA_CLOSURE,
+    RIA_DATA,
+    RIA_TUPLE,
+    RIA_RECORD
+} ria_tag;
+
+typedef struct {
+    ria_tag tag;
+    union {
+        int64_t i;
+        ria_object *o;
+    } as;
+} ria_value;
+
+typedef ria_value (*ria_code)(ria_value self, ria_value arg);
+
+struct ria_object {
+    /* the object allocated before this one, or NULL for builtins, which
+     * aren't allocated */
+    ria_object *next;
+    bool marked;
+    union {
+        ria_code code;
+        const char *constructor;
+        /* NULL for a record without fields */
+        const char *const *names;
+    } as;
+    size_t len;
+    ria_value fields[];
+};
+
+typedef struct ria_frame {
+    struct ria_frame *prev;
+    size_t len;
+    ria_value *slots;
+} ria_frame;
+
+static ria_frame *ria_roots;
+static size_t ria_depth;
+static ria_object *ria_heap;
+static size_t ria_live;
+static size_t ria_threshold = RIA_GC_THRESHOLD;
+static ria_value ria_pending_func, ria_pending_arg;
+
+/* Starts a function with `n` slots, all holding 0. */
+#define RIA_ENTER(n)                                                         \
+    ria_value slots[n] = {{0}};                                              \
+    ria_frame frame = {ria_roots, n, slots};                                 \
+    if (++ria_depth > RIA_MAX_DEPTH) {                                       \
+        ria_error("stack depth exceeded");                                   \
+    }                                                                        \
+    ria_roots = &frame
+
+/* Ends a function with `value`, which is computed before the slots go. */
+#define RIA_RETURN(value)                                                    \
+    do {                                                                     \
+        ria_value result_ = (value);                                         \
+        ria_roots = frame.prev;                                              \
+        ria_depth--;                                                         \
+        return result_;                                                      \
+    } while (0)
+
+static void ria_error(const char *format, ...) {
+    va_list args;
+    va_start(args, format);
+    fprintf(stderr, "error: ");
+    vfprintf(stderr, format, args);
+    fprintf(stderr, "\n");
+    va_end(args);
+    exit(1);
+}
+
+static const char *ria_type_name(ria_value value) {
+    switch (value.tag) {
+    case RIA_INT:
+        return "an integer";
+    case RIA_DATA:
+        return "a data value";
+    case RIA_TUPLE:
+        return "a tuple";
+    case RIA_RECORD:
+        return "a record";
+    default:
+        return "a function";
+    }
+}
+
+/* Reports that `value` isn't of the type `expected` names. */
+static void ria_type_error(const char *expected, ria_value value) {
+    ria_error("expected %s, found %s", expected, ria_type_name(value));
+}
+
+ria_value ria_int(int64_t i) {
+    ria_value value;
+    value.tag = RIA_INT;
+    value.as.i = i;
+    return value;
+}
+
+static ria_value ria_object_value(ria_tag tag, ria_object *o) {
+    ria_value value;
+    value.tag = tag;
+    value.as.o = o;
+    return value;
+}
+
+ria_value ria_closure_value(ria_object *c) {
+    return ria_object_value(RIA_CLOSURE, c);
+}
+
+static ria_object **ria_marks;
+static size_t ria_marks_len, ria_marks_cap;
+
+static void ria_mark(ria_value value) {
+    if (value.tag == RIA_INT || value.tag == RIA_TAIL || value.as.o->marked) {
+        return;
+    }
+    value.as.o->marked = true;
+    if (ria_marks_len == ria_marks_cap) {
+        ria_marks_cap = ria_marks_cap ? 2 * ria_marks_cap : 256;
+        ria_marks = realloc(ria_marks, ria_marks_cap * sizeof *ria_marks);
+        if (!ria_marks) {
+            ria_error("out of memory");
+        }
+    }
+    ria_marks[ria_marks_len++] = value.as.o;
+}
+
+static void ria_collect(void) {
+    ria_frame *frame;
+    ria_object **link = &ria_heap;
+    size_t i;
+
+    ria_mark(ria_pending_func);
+    ria_mark(ria_pending_arg);
+    for (frame = ria_roots; frame; frame = frame->prev) {
+        for (i = 0; i < frame->len; i++) {
+            ria_mark(frame->slots[i]);
+        }
+    }
+    /* marking what an object holds from a stack instead of recursing keeps
+     * long chains of objects, like lists, from overflowing the C stack */
+    while (ria_marks_len > 0) {
+        ria_object *o = ria_marks[--ria_marks_len];
+        for (i = 0; i < o->len; i++) {
+            ria_mark(o->fields[i]);
+        }
+    }
+
+    while (*link) {
+        ria_object *o = *link;
+        if (o->marked) {
+            o->marked = false;
+            link = &o->next;
+        } else {
+            *link = o->next;
+            free(o);
+            ria_live--;
+        }
+    }
+    ria_threshold = 2 * ria_live > RIA_GC_THRESHOLD ? 2 * ria_live : RIA_GC_THRESHOLD;
+}
+
+/* Allocates an object with `len` fields, all holding 0. */
+static ria_object *ria_new(size_t len) {
+    ria_object *o;
+    size_t i;
+
+#ifdef RIA_GC_STRESS
+    ria_collect();
+#else
+    if (ria_live >= ria_threshold) {
+        ria_collect();
+    }
+#endif
+    o = malloc(sizeof *o + len * sizeof(ria_value));
+    if (!o) {
+        ria_error("out of memory");
+    }
+    o->next = ria_heap;
+    o->marked = false;
+    o->len = len;
+    for (i = 0; i < len; i++) {
+        o->fields[i] = ria_int(0);
+    }
+    ria_heap = o;
+    ria_live++;
+    return o;
+}
+
+/* Allocates a closure of `code` with `len` fields, all holding 0. */
+ria_value ria_alloc(ria_code code, size_t len) {
+    ria_object *c = ria_new(len);
+    c->as.code = code;
+    return ria_closure_value(c);
+}
+
+/* Allocates a value of `constructor` with `len` fields, all holding 0. */
+ria_value ria_data(const char *constructor, size_t len) {
+    ria_object *o = ria_new(len);
+    o->as.constructor = constructor;
+    return ria_object_value(RIA_DATA, o);
+}
+
+/* Allocates a tuple of `len` items, all holding 0. */
+ria_value ria_tuple(size_t len) {
+    return ria_object_value(RIA_TUPLE, ria_new(len));
+}
+
+/* Allocates a record of the `len` fields `names`, all holding 0. */
+ria_value ria_record(const char *const *names, size_t len) {
+    ria_object *o = ria_new(len);
+    o->as.names = names;
+    return ria_object_value(RIA_RECORD, o);
+}
+
+/* Returns the field `name` of the record `value`. */
+ria_value ria_field(ria_value value, const char *name) {
+    size_t i;
+    if (value.tag != RIA_RECORD) {
+        ria_type_error("a record", value);
+    }
+    for (i = 0; i < value.as.o->len; i++) {
+        if (value.as.o->as.names[i] == name) {
+            return value.as.o->fields[i];
+        }
+    }
+    ria_error("no field `%s`", name);
+    return value;
+}
+
+/* Returns the item at `index` of the tuple `value`. */
+ria_value ria_item(ria_value value, size_t index) {
+    if (value.tag != RIA_TUPLE) {
+        ria_type_error("a tuple", value);
+    }
+    if (index >= value.as.o->len) {
+        ria_error("no field `%zu`", index);
+    }
+    return value.as.o->fields[index];
+}
+
+/* Returns whether `value` is of `constructor`. */
+bool ria_is(ria_value value, const char *constructor) {
+    if (value.tag != RIA_DATA) {
+        ria_type_error("a data value", value);
+    }
+    return value.as.o->as.constructor == constructor;
+}
+
+/* Returns whether `value` is the integer `i`. */
+bool ria_is_int(ria_value value, int64_t i) {
+    if (value.tag != RIA_INT) {
+        ria_type_error("an integer", value);
+    }
+    return value.as.i == i;
+}
+
+/* Reports that no arm of a match matches its value. */
+ria_value ria_no_match(void) {
+    ria_error("no pattern matches the value");
+    return ria_int(0);
+}
+
+ria_value ria_apply(ria_value func, ria_value arg) {
+    for (;;) {
+        ria_value result;
+        if (func.tag != RIA_CLOSURE) {
+            ria_type_error("a function", func);
+        }
+        result = func.as.o->as.code(func, arg);
+        if (result.tag != RIA_TAIL) {
+            return result;
+        }
+        func = ria_pending_func;
+        arg = ria_pending_arg;
+        ria_pending_func = ria_pending_arg = ria_int(0);
+    }
+}
+
+/* Returns to `ria_apply` to call `func` with `arg` from there. */
+ria_value ria_tail(ria_value func, ria_value arg) {
+    ria_value tail;
+    ria_pending_func = func;
+    ria_pending_arg = arg;
+    tail.tag = RIA_TAIL;
+    tail.as.i = 0;
+    return tail;
+}
+
+/* Returns the builtin `self` applied to its first argument, `arg`. */
+static ria_value ria_partial(ria_value self, ria_value arg) {
+    ria_value partial;
+    RIA_ENTER(1);
+    slots[0] = arg;
+    partial = ria_alloc(self.as.o->as.code, 1);
+    partial.as.o->fields[0] = slots[0];
+    RIA_RETURN(partial);
+}
+
+static ria_value ria_true_code(ria_value self, ria_value arg) {
+    return self.as.o->len == 0 ? ria_partial(self, arg) : self.as.o->fields[0];
+}
+
+static ria_value ria_false_code(ria_value self, ria_value arg) {
+    return self.as.o->len == 0 ? ria_partial(self, arg) : arg;
+}
+
+ria_object ria_true = {NULL, false, {ria_true_code}, 0};
+ria_object ria_false = {NULL, false, {ria_false_code}, 0};
+
+static ria_value ria_bool(bool value) {
+    return ria_closure_value(value ? &ria_true : &ria_false);
+}
+
+static int64_t ria_int_arg(ria_value value) {
+    if (value.tag != RIA_INT) {
+        ria_type_error("an integer", value);
+    }
+    return value.as.i;
+}
+
+static bool ria_add_overflows(int64_t a, int64_t b) {
+    return b > 0 ? a > INT64_MAX - b : a < INT64_MIN - b;
+}
+
+static bool ria_sub_overflows(int64_t a, int64_t b) {
+    return b < 0 ? a > INT64_MAX + b : a < INT64_MIN + b;
+}
+
+static bool ria_mul_overflows(int64_t a, int64_t b) {
+    if (a == 0 || b == 0) {
+        return false;
+    }
+    if (a > 0) {
+        return b > 0 ? a > INT64_MAX / b : b < INT64_MIN / a;
+    }
+    return b > 0 ? a < INT64_MIN / b : a < INT64_MAX / b;
+}
+
+static void ria_check_divisor(int64_t a, int64_t b) {
+    if (b == 0) {
+        ria_error("division by zero");
+    }
+    if (a == INT64_MIN && b == -1) {
+        ria_error("integer overflow");
+    }
+}
+
+/* Defines the code of a builtin that runs `body` on the integers `a` and
+ * `b`. */
+#define RIA_PRIM(name, body)                                                 \
+    static ria_value ria_##name##_code(ria_value self, ria_value arg) {      \
+        int64_t a, b;                                                        \
+        if (self.as.o->len == 0) {                                           \
+            return ria_partial(self, arg);                                   \
+        }                                                                    \
+        a = ria_int_arg(self.as.o->fields[0]);                               \
+        b = ria_int_arg(arg);                                                \
+        body                                                                 \
+    }                                                                        \
+    ria_object ria_##name = {NULL, false, {ria_##name##_code}, 0};
+
+RIA_PRIM(add, if (ria_add_overflows(a, b)) ria_error("integer overflow"); return ria_int(a + b);)
+RIA_PRIM(sub, if (ria_sub_overflows(a, b)) ria_error("integer overflow"); return ria_int(a - b);)
+RIA_PRIM(mul, if (ria_mul_overflows(a, b)) ria_error("integer overflow"); return ria_int(a * b);)
+RIA_PRIM(div, ria_check_divisor(a, b); return ria_int(a / b);)
+RIA_PRIM(mod, ria_check_divisor(a, b); return ria_int(a % b);)
+RIA_PRIM(eq, return ria_bool(a == b);)
+RIA_PRIM(ne, return ria_bool(a != b);)
+RIA_PRIM(lt, return ria_bool(a < b);)
+RIA_PRIM(le, return ria_bool(a <= b);)
+RIA_PRIM(gt, return ria_bool(a > b);)
+RIA_PRIM(ge, return ria_bool(a >= b);)
+
+/* The value of `main`, defined by the program. */
+static ria_value ria_main(void);
+
+/* What is left to print of a value: text, or a value, which is in
+ * parentheses if it's a field of a data value and has fields of its own. */
+typedef struct {
+    const char *text;
+    ria_value value;
+    bool field;
+} ria_piece;
+
+static ria_piece *ria_pieces;
+static size_t ria_pieces_len, ria_pieces_cap;
+
+static void ria_push(const char *text, ria_value value, bool field) {
+    if (ria_pieces_len == ria_pieces_cap) {
+        ria_pieces_cap = ria_pieces_cap ? 2 * ria_pieces_cap : 256;
+        ria_pieces = realloc(ria_pieces, ria_pieces_cap * sizeof *ria_pieces);
+        if (!ria_pieces) {
+            ria_error("out of memory");
+        }
+    }
+    ria_pieces[ria_pieces_len].text = text;
+    ria_pieces[ria_pieces_len].value = value;
+    ria_pieces[ria_pieces_len].field = field;
+    ria_pieces_len++;
+}
+
+static void ria_push_text(const char *text) {
+    ria_push(text, ria_int(0), false);
+}
+
+/* Prints `value` the way the VM displays it. Data values can be as deep as
+ * a long list, so the pieces are printed from a stack rather than by
+ * recursion. */
+static void ria_print(ria_value value) {
+    ria_push(NULL, value, false);
+    while (ria_pieces_len > 0) {
+        ria_piece piece = ria_pieces[--ria_pieces_len];
+        ria_object *o = piece.value.as.o;
+        size_t i;
+        if (piece.text) {
+            fputs(piece.text, stdout);
+            continue;
+        }
+        switch (piece.value.tag) {
+        case RIA_INT:
+            printf("%" PRId64, piece.value.as.i);
+            break;
+        case RIA_DATA:
+            if (o->len > 0 && piece.field) {
+                putchar('(');
+                ria_push_text(")");
+            }
+            fputs(o->as.constructor, stdout);
+            for (i = o->len; i > 0; i--) {
+                ria_push(NULL, o->fields[i - 1], true);
+                ria_push_text(" ");
+            }
+            break;
+        case RIA_TUPLE:
+            putchar('(');
+            ria_push_text(")");
+            for (i = o->len; i > 0; i--) {
+                ria_push(NULL, o->fields[i - 1], false);
+                if (i > 1) {
+                    ria_push_text(", ");
+                }
+            }
+            break;
+        case RIA_RECORD:
+            if (o->len == 0) {
+                fputs("{}", stdout);
+                break;
+            }
+            fputs("{ ", stdout);
+            ria_push_text(" }");
+            for (i = o->len; i > 0; i--) {
+                ria_push(NULL, o->fields[i - 1], false);
+                ria_push_text(" = ");
+                ria_push_text(o->as.names[i - 1]);
+                if (i > 1) {
+                    ria_push_text(", ");
+                }
+            }
+            break;
+        default:
+            fputs("<function>", stdout);
+        }
+    }
+    putchar('\n');
+}
+
+int main(void) {
+    ria_print(ria_main());
+    return 0;
+}
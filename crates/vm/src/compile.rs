@@ -0,0 +1,1256 @@
+//! Compiling a resolved [`Module`] to bytecode.
+
+use std::{
+    collections::{HashMap, HashSet},
+    ops::Range,
+};
+
+use ria_parser::{
+    data::TypeDef,
+    def::DefList,
+    deps::{free_variables, DepGraph},
+    diagnostic::Diagnostic,
+    expr::{Access, Block, Expr, Field, Lambda, Match, Record},
+    loader::{Loader, ModuleId},
+    module::Module,
+    pattern::Pattern,
+    resolve::{resolve_with_imports, ModuleNames},
+};
+
+use crate::{
+    builtins::builtins,
+    bytecode::{
+        CaptureSource, Constructor, Evaluation, Function, FunctionId, Global, Op, Origin, Program,
+        Test,
+    },
+    natives::Natives,
+    prelude::prelude,
+    simplify::{simplify_module, Passes},
+};
+
+/// Compiles `module` to a [`Program`] that evaluates it with `evaluation`.
+/// Variables that aren't bound in `module` may refer to the [`builtins`].
+///
+/// Returns the resolution errors of the module if it has any, since only a
+/// module where every variable is bound can be compiled.
+pub fn compile(module: &Module, evaluation: Evaluation) -> Result<Program, Vec<Diagnostic>> {
+    compile_with_natives(module, evaluation, &Natives::new())
+}
+
+/// Like [`compile`], but variables that aren't bound in `module` may refer to
+/// `natives` as well as the [`builtins`]. Defs in the module shadow natives
+/// with the same name, and `natives` replace builtins with the same name.
+pub fn compile_with_natives(
+    module: &Module,
+    evaluation: Evaluation,
+    natives: &Natives,
+) -> Result<Program, Vec<Diagnostic>> {
+    compile_single(module, None, evaluation, natives)
+}
+
+/// Like [`compile_with_natives`], but variables may also refer to the defs of
+/// the [prelude](crate::prelude), which defs in `module` shadow. Only the
+/// prelude defs that `module` uses are compiled.
+pub fn compile_with_prelude(
+    module: &Module,
+    evaluation: Evaluation,
+    natives: &Natives,
+) -> Result<Program, Vec<Diagnostic>> {
+    compile_single(module, Some(&prelude()), evaluation, natives)
+}
+
+/// Compiles the module `main` of `loader` and the modules it imports to a
+/// [`Program`], with the prelude if `prelude` is set. Once they are resolved,
+/// the modules are simplified with `passes`.
+///
+/// The globals of `main` come first and keep their names. The globals of an
+/// imported module are named after the module, like `other.a`. Modules are
+/// initialized after the modules they import.
+///
+/// Returns the errors of every module that has any, with the module they
+/// are in.
+pub fn compile_loaded(
+    loader: &Loader,
+    main: ModuleId,
+    evaluation: Evaluation,
+    natives: &Natives,
+    prelude: bool,
+    passes: Passes,
+) -> Result<Program, Vec<(ModuleId, Diagnostic)>> {
+    let mut ids = Vec::new();
+    dependency_order(loader, main, &mut ids);
+
+    let units: Vec<_> = ids
+        .iter()
+        .map(|&id| {
+            let loaded = loader.module(id);
+            Unit {
+                module: &loaded.module,
+                name: (id != main).then_some(loaded.name.as_str()),
+                id: (id != main).then_some(id),
+                imports: loaded
+                    .imports
+                    .iter()
+                    .map(|import| {
+                        ids.iter()
+                            .position(|id| id == import)
+                            .expect("imports are loaded")
+                    })
+                    .collect(),
+            }
+        })
+        .collect();
+    let prelude = prelude.then(crate::prelude::prelude);
+    compile_units(&units, prelude.as_ref(), evaluation, natives, passes).map_err(|errors| {
+        errors
+            .into_iter()
+            .map(|(unit, err)| (ids[unit], err))
+            .collect()
+    })
+}
+
+/// Adds `id` to `order` after the modules it imports, directly or not,
+/// unless it is there already.
+pub(crate) fn dependency_order(loader: &Loader, id: ModuleId, order: &mut Vec<ModuleId>) {
+    if order.contains(&id) {
+        return;
+    }
+    for &import in loader.module(id).imports.iter() {
+        dependency_order(loader, import, order);
+    }
+    order.push(id);
+}
+
+fn compile_single<'i>(
+    module: &Module<'i>,
+    prelude: Option<&Module<'i>>,
+    evaluation: Evaluation,
+    natives: &Natives,
+) -> Result<Program, Vec<Diagnostic>> {
+    // the modules an import refers to are only known to a loader
+    if !module.imports().is_empty() {
+        return Err(module
+            .imports()
+            .iter()
+            .map(|import| {
+                Diagnostic::new(
+                    import.span.clone(),
+                    "imports can only be compiled from files",
+                )
+            })
+            .collect());
+    }
+
+    let unit = Unit {
+        module,
+        name: None,
+        id: None,
+        imports: Vec::new(),
+    };
+    compile_units(&[unit], prelude, evaluation, natives, Passes::default())
+        .map_err(|errors| errors.into_iter().map(|(_, err)| err).collect())
+}
+
+/// A module to compile as part of a program.
+struct Unit<'m, 'i> {
+    module: &'m Module<'i>,
+    /// The name the module's globals are prefixed with, or `None` for the
+    /// main module.
+    name: Option<&'m str>,
+    /// The id of the module in its loader, or `None` for the main module.
+    id: Option<ModuleId>,
+    /// The index of the unit each of the module's imports refers to.
+    imports: Vec<usize>,
+}
+
+/// Compiles `units`, where each unit comes after the units it imports and
+/// the last unit is the main module, simplifying them with `passes` once
+/// they are resolved.
+fn compile_units<'i>(
+    units: &[Unit<'_, 'i>],
+    prelude: Option<&Module<'i>>,
+    evaluation: Evaluation,
+    natives: &Natives,
+    passes: Passes,
+) -> Result<Program, Vec<(usize, Diagnostic)>> {
+    let mut all = builtins();
+    for native in natives.iter() {
+        all.insert(native.clone());
+    }
+    let natives = &all;
+    let mut names = natives.names();
+    if let Some(prelude) = prelude {
+        names.extend(prelude.defs().defs.iter().map(|def| def.ident.0));
+    }
+
+    let mut errors = Vec::new();
+    for (index, unit) in units.iter().enumerate() {
+        let imports: Vec<_> = unit
+            .imports
+            .iter()
+            .map(|&import| Some(ModuleNames::of(units[import].module)))
+            .collect();
+        let resolution = resolve_with_imports(unit.module, &names, &imports);
+        errors.extend(resolution.errors.into_iter().map(|err| (index, err)));
+    }
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    let simplified: Vec<_>;
+    let simplified_units: Vec<_>;
+    let units = if passes == Passes::default() {
+        units
+    } else {
+        simplified = units
+            .iter()
+            .map(|unit| simplify_module(unit.module.clone(), evaluation, passes))
+            .collect();
+        simplified_units = units
+            .iter()
+            .zip(&simplified)
+            .map(|(unit, module)| Unit {
+                module,
+                name: unit.name,
+                id: unit.id,
+                imports: unit.imports.clone(),
+            })
+            .collect();
+        &simplified_units
+    };
+
+    let mut compiler = Compiler {
+        evaluation,
+        natives,
+        functions: Vec::new(),
+        globals: HashMap::new(),
+        qualified: HashMap::new(),
+        constructors: Vec::new(),
+        fields: Vec::new(),
+        shapes: Vec::new(),
+        stack: Vec::new(),
+        origin: Origin::Main,
+        unit: 0,
+        errors: Vec::new(),
+    };
+    let program = compiler.program(units, prelude);
+    if compiler.errors.is_empty() {
+        Ok(program)
+    } else {
+        Err(compiler.errors)
+    }
+}
+
+/// Returns the indices of the defs of `prelude` that the defs and tests of
+/// `modules` use, directly or through other prelude defs, in order.
+fn used_prelude_defs<'m, 'i: 'm>(
+    prelude: &DefList,
+    modules: impl IntoIterator<Item = &'m Module<'i>>,
+) -> Vec<usize> {
+    let mut pending = Vec::new();
+    for module in modules {
+        // names the module defines or imports shadow the prelude's
+        let defined: HashSet<_> = module
+            .defs()
+            .defs
+            .iter()
+            .map(|def| def.ident.0)
+            .chain(constructor_names(&module.defs().types))
+            .chain(
+                module
+                    .imports()
+                    .iter()
+                    .flat_map(|import| import.names.iter().flatten())
+                    .map(|name| name.0),
+            )
+            .collect();
+        pending.extend(
+            module
+                .defs()
+                .defs
+                .iter()
+                .map(|def| &def.expr)
+                .chain(module.tests().iter().map(|test| &test.expr))
+                .flat_map(free_variables)
+                .filter(|name| !defined.contains(name)),
+        );
+    }
+
+    let mut used = vec![false; prelude.defs.len()];
+    while let Some(name) = pending.pop() {
+        let Some(index) = prelude.defs.iter().position(|def| def.ident.0 == name) else {
+            continue;
+        };
+        if !used[index] {
+            used[index] = true;
+            pending.extend(free_variables(&prelude.defs[index].expr));
+        }
+    }
+    (0..used.len()).filter(|&index| used[index]).collect()
+}
+
+/// Returns the names of the constructors of `types`.
+fn constructor_names<'a, 'i>(types: &'a [TypeDef<'i>]) -> impl Iterator<Item = &'i str> + 'a {
+    types
+        .iter()
+        .flat_map(|ty| ty.constructors.iter())
+        .map(|constructor| constructor.name.0)
+}
+
+/// The order to evaluate `defs` in, so each def comes after the defs it
+/// depends on, and whether each def is part of a recursive group.
+fn evaluation_order(defs: &DefList) -> (Vec<usize>, Vec<bool>) {
+    let graph = DepGraph::new(defs);
+    let mut order = Vec::with_capacity(defs.defs.len());
+    let mut recursive = vec![false; defs.defs.len()];
+    for component in graph.components() {
+        let is_recursive = graph.is_recursive(&component);
+        for &def in &component.defs {
+            recursive[def] = is_recursive;
+        }
+        order.extend(component.defs);
+    }
+    (order, recursive)
+}
+
+/// A local slot of the function being compiled.
+#[derive(Debug, Clone, Copy)]
+struct Local {
+    slot: u16,
+    /// Whether the slot holds a cell, because the def in it may be captured
+    /// before it is initialized.
+    boxed: bool,
+}
+
+/// Where a variable lives, from the point of view of one function.
+#[derive(Debug, Clone, Copy)]
+enum Place {
+    Local(Local),
+    Capture {
+        index: u16,
+        boxed: bool,
+    },
+    Global(u32),
+    Native(u32),
+    /// A constructor, which is the same everywhere and so is never captured.
+    Constructor(u32),
+}
+
+impl Place {
+    /// Returns the instruction that pushes the value of the variable.
+    fn load(self) -> Op {
+        match self {
+            Place::Local(Local { slot, boxed: false }) => Op::Local(slot),
+            Place::Local(Local { slot, boxed: true }) => Op::LocalCell(slot),
+            Place::Capture {
+                index,
+                boxed: false,
+            } => Op::Capture(index),
+            Place::Capture { index, boxed: true } => Op::CaptureCell(index),
+            Place::Global(index) => Op::Global(index),
+            Place::Native(index) => Op::Native(index),
+            Place::Constructor(index) => Op::Construct(index),
+        }
+    }
+}
+
+/// A function that is being compiled.
+struct FunctionState<'i> {
+    name: Option<String>,
+    has_param: bool,
+    origin: Origin,
+    /// The names bound in the function, innermost scope last. Each is a
+    /// local or a constructor declared in a block.
+    scopes: Vec<HashMap<&'i str, Place>>,
+    captures: Vec<(&'i str, CaptureSource, bool)>,
+    locals: u16,
+    /// Whether the function has run out of locals, so that it's only
+    /// reported once.
+    too_many_locals: bool,
+    code: Vec<Op>,
+    spans: Vec<Range<usize>>,
+}
+
+impl FunctionState<'_> {
+    fn new(name: Option<String>, has_param: bool, origin: Origin) -> Self {
+        Self {
+            name,
+            has_param,
+            origin,
+            scopes: Vec::new(),
+            captures: Vec::new(),
+            locals: 0,
+            too_many_locals: false,
+            code: Vec::new(),
+            spans: Vec::new(),
+        }
+    }
+
+    fn alloc_local(&mut self) -> Option<u16> {
+        let slot = self.locals;
+        self.locals = self.locals.checked_add(1)?;
+        Some(slot)
+    }
+
+    fn finish(self) -> Function {
+        Function {
+            name: self.name,
+            has_param: self.has_param,
+            origin: self.origin,
+            locals: self.locals,
+            captures: self.captures.iter().map(|(_, source, _)| *source).collect(),
+            code: self.code.into(),
+            spans: self.spans.into(),
+        }
+    }
+}
+
+struct Compiler<'i, 'n> {
+    evaluation: Evaluation,
+    natives: &'n Natives,
+    /// The compiled functions, with `None` for the ones still being compiled.
+    functions: Vec<Option<Function>>,
+    /// The globals and constructors the module being compiled can use
+    /// unqualified.
+    globals: HashMap<&'i str, Place>,
+    /// The globals and constructors of each module imported by the module
+    /// being compiled, by the name it is imported as.
+    qualified: HashMap<&'i str, HashMap<&'i str, Place>>,
+    /// The constructors of every type declared so far.
+    constructors: Vec<Constructor>,
+    /// The names of the record fields seen so far.
+    fields: Vec<String>,
+    /// The shapes of the record literals seen so far.
+    shapes: Vec<Box<[u32]>>,
+    /// The functions being compiled, innermost last.
+    stack: Vec<FunctionState<'i>>,
+    /// Where the defs being compiled are from.
+    origin: Origin,
+    /// The index of the unit being compiled.
+    unit: usize,
+    errors: Vec<(usize, Diagnostic)>,
+}
+
+impl<'i> Compiler<'i, '_> {
+    /// Compiles the defs of `units` to globals, starting with the main
+    /// module's, followed by the defs of `prelude` they use, which are
+    /// initialized first.
+    fn program(&mut self, units: &[Unit<'_, 'i>], prelude: Option<&Module<'i>>) -> Program {
+        // the main module's globals come first, so their indices don't
+        // depend on what it imports
+        let main = units.len() - 1;
+        let mut offsets = Vec::with_capacity(units.len());
+        let mut count = units[main].module.defs().defs.len();
+        for unit in &units[..main] {
+            offsets.push(count);
+            count += unit.module.defs().defs.len();
+        }
+        offsets.push(0);
+        let members: Vec<HashMap<_, _>> = units
+            .iter()
+            .zip(&offsets)
+            .map(|(unit, &offset)| {
+                let defs = unit.module.defs().defs.iter().enumerate();
+                let mut members: HashMap<_, _> = defs
+                    .map(|(index, def)| (def.ident.0, Place::Global((offset + index) as u32)))
+                    .collect();
+                members.extend(self.declare(&unit.module.defs().types));
+                members
+            })
+            .collect();
+
+        let used = match prelude {
+            Some(prelude) => {
+                used_prelude_defs(prelude.defs(), units.iter().map(|unit| unit.module))
+            }
+            None => Vec::new(),
+        };
+        let mut globals: Vec<Option<Global>> = (0..count + used.len()).map(|_| None).collect();
+        let mut init_order = Vec::with_capacity(globals.len());
+
+        // prelude defs only see each other, even where a module shadows them
+        if let Some(prelude) = prelude {
+            let prelude_defs = &prelude.defs().defs;
+            for (offset, &index) in used.iter().enumerate() {
+                let global = (count + offset) as u32;
+                self.globals
+                    .insert(prelude_defs[index].ident.0, Place::Global(global));
+            }
+            self.origin = Origin::Prelude;
+            for (offset, &index) in used.iter().enumerate() {
+                let def = &prelude_defs[index];
+                let name = def.ident.0.to_owned();
+                let init = self.function(Some(name.clone()), None, &def.expr);
+                globals[count + offset] = Some(Global { name, init });
+            }
+
+            let (order, _) = evaluation_order(prelude.defs());
+            init_order.extend(order.into_iter().filter_map(|index| {
+                let offset = used.binary_search(&index).ok()?;
+                Some((count + offset) as u32)
+            }));
+        }
+        let prelude_globals = std::mem::take(&mut self.globals);
+
+        for (index, unit) in units.iter().enumerate() {
+            // the module's own defs shadow the names it imports, which
+            // shadow the prelude
+            let mut scope = prelude_globals.clone();
+            self.qualified.clear();
+            for (import, &imported) in unit.module.imports().iter().zip(&unit.imports) {
+                let members = &members[imported];
+                for name in import.names.iter().flatten() {
+                    scope.insert(name.0, members[name.0]);
+                }
+                self.qualified.insert(import.alias(), members.clone());
+            }
+            scope.extend(&members[index]);
+            self.globals = scope;
+            self.origin = unit.id.map_or(Origin::Main, Origin::Import);
+            self.unit = index;
+
+            for (def, offset) in unit.module.defs().defs.iter().zip(offsets[index]..) {
+                let name = match unit.name {
+                    Some(module) => format!("{module}.{}", def.ident.0),
+                    None => def.ident.0.to_owned(),
+                };
+                let init = self.function(Some(name.clone()), None, &def.expr);
+                globals[offset] = Some(Global { name, init });
+            }
+            let (order, _) = evaluation_order(unit.module.defs());
+            init_order.extend(order.into_iter().map(|def| (offsets[index] + def) as u32));
+        }
+
+        // the scope is still the main module's
+        let tests = units[main]
+            .module
+            .tests()
+            .iter()
+            .map(|test| Test {
+                name: test.name.0.to_owned(),
+                init: self.function(Some(format!("test \"{}\"", test.name.0)), None, &test.expr),
+                span: test.span.clone(),
+            })
+            .collect();
+
+        Program {
+            evaluation: self.evaluation,
+            functions: self
+                .functions
+                .drain(..)
+                .map(|function| function.expect("every function is finished"))
+                .collect(),
+            globals: globals
+                .into_iter()
+                .map(|global| global.expect("every global is compiled"))
+                .collect(),
+            natives: self.natives.iter().cloned().collect(),
+            constructors: std::mem::take(&mut self.constructors).into(),
+            fields: std::mem::take(&mut self.fields).into(),
+            shapes: std::mem::take(&mut self.shapes).into(),
+            init_order: init_order.into(),
+            tests,
+        }
+    }
+
+    /// Gives each constructor of `types` an index in the program, and
+    /// returns their names and places.
+    fn declare(&mut self, types: &[TypeDef<'i>]) -> Vec<(&'i str, Place)> {
+        let constructors = types.iter().flat_map(|ty| ty.constructors.iter());
+        constructors
+            .map(|constructor| {
+                let index = self.constructors.len() as u32;
+                self.constructors.push(Constructor {
+                    name: constructor.name.0.to_owned(),
+                    arity: constructor.arity() as u32,
+                });
+                (constructor.name.0, Place::Constructor(index))
+            })
+            .collect()
+    }
+
+    /// Compiles a function with an optional parameter and returns its id.
+    ///
+    /// In a lazy program, a function without a parameter is the body of a
+    /// thunk, and forces its result so thunks are only ever updated with
+    /// values.
+    fn function(
+        &mut self,
+        name: Option<String>,
+        param: Option<&'i str>,
+        body: &Expr<'i>,
+    ) -> FunctionId {
+        let id = self.functions.len() as FunctionId;
+        self.functions.push(None);
+
+        // The body of an initializer is the def's own value, so a lambda
+        // there is named after the def.
+        let body_name = if param.is_none() { name.clone() } else { None };
+        self.stack
+            .push(FunctionState::new(name, param.is_some(), self.origin));
+        let mut scope = HashMap::new();
+        if let Some(param) = param {
+            let slot = self.alloc_local(body.span());
+            scope.insert(param, Place::Local(Local { slot, boxed: false }));
+        }
+        self.current().scopes.push(scope);
+
+        if param.is_none() && self.evaluation == Evaluation::Lazy {
+            self.expr(body, false, body_name.as_deref());
+            self.emit(Op::Force, body.span());
+        } else {
+            self.expr(body, true, body_name.as_deref());
+        }
+        self.emit(Op::Return, body.span());
+
+        let state = self.stack.pop().expect("function state was pushed");
+        self.functions[id as usize] = Some(state.finish());
+        id
+    }
+
+    fn current(&mut self) -> &mut FunctionState<'i> {
+        self.stack.last_mut().expect("a function is being compiled")
+    }
+
+    /// Allocates a local slot in the current function, reporting an error at
+    /// `span` if it has run out of them.
+    fn alloc_local(&mut self, span: Range<usize>) -> u16 {
+        let state = self.current();
+        if let Some(slot) = state.alloc_local() {
+            return slot;
+        }
+        if !state.too_many_locals {
+            state.too_many_locals = true;
+            self.errors.push((
+                self.unit,
+                Diagnostic::new(span, "this function has too many locals"),
+            ));
+        }
+        0
+    }
+
+    fn emit(&mut self, op: Op, span: Range<usize>) {
+        let state = self.current();
+        state.code.push(op);
+        state.spans.push(span);
+    }
+
+    /// Compiles `expr`, leaving its value on the stack. `tail` is whether the
+    /// value is returned from the function straight away, and `name` is the
+    /// def the value is bound to, if any.
+    fn expr(&mut self, expr: &Expr<'i>, tail: bool, name: Option<&str>) {
+        match expr {
+            Expr::Variable(ident) => {
+                let place = self
+                    .lookup(self.stack.len() - 1, ident.0)
+                    .expect("resolved variables are bound");
+                self.emit(place.load(), ident.1.clone());
+            }
+            Expr::Qualified(qualified) => match self.module(qualified.module.0) {
+                Some(members) => {
+                    let place = members[qualified.name.0];
+                    self.emit(place.load(), qualified.span());
+                }
+                // resolution made it a field of a variable, since a local
+                // binding shadows the module or none is imported by that name
+                None => {
+                    let record = Expr::Variable(qualified.module.clone());
+                    self.expr(&record, false, None);
+                    if self.evaluation == Evaluation::Lazy {
+                        self.emit(Op::Force, record.span());
+                    }
+                    let field = self.field(qualified.name.0);
+                    self.emit(Op::GetField(field), qualified.span());
+                }
+            },
+            Expr::Int(int) => self.emit(Op::Int(int.0), int.1.clone()),
+            Expr::Lambda(lambda) => self.lambda(lambda, name),
+            Expr::Block(block) => self.block(block, tail),
+            Expr::Match(matching) => self.matching(matching, tail),
+            Expr::Tuple(tuple) => {
+                for item in tuple.items.iter() {
+                    self.delayed(item, None);
+                }
+                match u16::try_from(tuple.items.len()) {
+                    Ok(len) => self.emit(Op::Tuple(len), tuple.span.clone()),
+                    Err(_) => self.errors.push((
+                        self.unit,
+                        Diagnostic::new(
+                            tuple.span.clone(),
+                            format!("a tuple can have at most {} items", u16::MAX),
+                        ),
+                    )),
+                }
+            }
+            Expr::Record(record) => self.record(record),
+            Expr::Access(access) => self.access(access),
+            Expr::Call(call) => {
+                self.expr(&call.func, false, None);
+                if self.evaluation == Evaluation::Lazy {
+                    self.emit(Op::Force, call.func.span());
+                }
+                match &*call.arg {
+                    // a variable already holds a value or a shared thunk
+                    Expr::Variable(_) | Expr::Int(_) => self.expr(&call.arg, false, None),
+                    Expr::Qualified(qualified) if self.module(qualified.module.0).is_some() => {
+                        self.expr(&call.arg, false, None)
+                    }
+                    arg => self.delayed(arg, None),
+                }
+                let op = if tail { Op::TailCall } else { Op::Call };
+                self.emit(op, expr.span());
+            }
+        }
+    }
+
+    /// Compiles `expr` to a thunk in a lazy program, unless it is already a
+    /// value. In a strict program, compiles it as usual.
+    fn delayed(&mut self, expr: &Expr<'i>, name: Option<&str>) {
+        match (self.evaluation, expr) {
+            (Evaluation::Strict, _) | (_, Expr::Lambda(_) | Expr::Int(_)) => {
+                self.expr(expr, false, name)
+            }
+            (Evaluation::Lazy, _) => {
+                let id = self.function(name.map(str::to_owned), None, expr);
+                self.emit(Op::Thunk(id), expr.span());
+            }
+        }
+    }
+
+    /// Compiles a record literal, whose fields are evaluated in the order
+    /// they are written. A lambda in a field is named after the field.
+    fn record(&mut self, record: &Record<'i>) {
+        let mut shape = Vec::with_capacity(record.fields.len());
+        for field in record.fields.iter() {
+            self.delayed(&field.value, Some(field.name.0));
+            shape.push(self.field(field.name.0));
+        }
+        let shape = match self.shapes.iter().position(|known| **known == *shape) {
+            Some(index) => index,
+            None => {
+                self.shapes.push(shape.into());
+                self.shapes.len() - 1
+            }
+        };
+        self.emit(Op::Record(shape as u32), record.span.clone());
+    }
+
+    /// Compiles a field access. In a lazy program, the record or tuple is
+    /// forced, but the field itself is not.
+    fn access(&mut self, access: &Access<'i>) {
+        self.expr(&access.expr, false, None);
+        if self.evaluation == Evaluation::Lazy {
+            self.emit(Op::Force, access.expr.span());
+        }
+        let op = match access.field.0 {
+            Field::Name(name) => Op::GetField(self.field(name)),
+            Field::Index(index) => match u16::try_from(index) {
+                Ok(index) => Op::GetItem(index),
+                Err(_) => {
+                    self.errors.push((
+                        self.unit,
+                        Diagnostic::new(
+                            access.field.1.clone(),
+                            format!("a tuple can have at most {} items", u16::MAX),
+                        ),
+                    ));
+                    return;
+                }
+            },
+        };
+        self.emit(op, access.span.clone());
+    }
+
+    /// Returns the index of the field called `name` in the program.
+    fn field(&mut self, name: &str) -> u32 {
+        match self.fields.iter().position(|field| field == name) {
+            Some(index) => index as u32,
+            None => {
+                self.fields.push(name.to_owned());
+                self.fields.len() as u32 - 1
+            }
+        }
+    }
+
+    fn lambda(&mut self, lambda: &Lambda<'i>, name: Option<&str>) {
+        let id = self.function(name.map(str::to_owned), Some(lambda.param.0), &lambda.body);
+        self.emit(Op::Closure(id), lambda.span());
+    }
+
+    /// Compiles a block, putting each of its defs in a local slot.
+    ///
+    /// The defs are evaluated in dependency order. Defs in a recursive group
+    /// can be captured before they are initialized, so their slots hold
+    /// cells that are filled in once the def is evaluated. In a lazy program,
+    /// the slots hold thunks of the defs instead.
+    fn block(&mut self, block: &Block<'i>, tail: bool) {
+        let defs = &block.defs.defs;
+        let (order, recursive) = evaluation_order(&block.defs);
+
+        // Resolution rejects defining a name twice, so each def gets a slot.
+        let mut scope: HashMap<_, _> = self.declare(&block.defs.types).into_iter().collect();
+        let mut slots = Vec::with_capacity(defs.len());
+        for (def, &boxed) in defs.iter().zip(&recursive) {
+            let slot = self.alloc_local(def.ident.1.clone());
+            if boxed {
+                self.emit(Op::NewCell(slot), def.ident.1.clone());
+            }
+            let local = Local { slot, boxed };
+            scope.insert(def.ident.0, Place::Local(local));
+            slots.push(local);
+        }
+        self.current().scopes.push(scope);
+
+        for index in order {
+            let def = &defs[index];
+            let Local { slot, boxed } = slots[index];
+            self.delayed(&def.expr, Some(def.ident.0));
+            let op = if boxed {
+                Op::StoreCell(slot)
+            } else {
+                Op::StoreLocal(slot)
+            };
+            self.emit(op, def.ident.1.clone());
+        }
+
+        match &block.expr {
+            Some(expr) => self.expr(expr, tail, None),
+            None => self.errors.push((
+                self.unit,
+                Diagnostic::new(block.span.clone(), "a block must end with an expression"),
+            )),
+        }
+        self.current().scopes.pop();
+    }
+
+    /// Compiles a match, which keeps the value of the scrutinee in a local
+    /// slot and tests the pattern of each arm against it in turn.
+    ///
+    /// In a lazy program, the scrutinee and the fields that nested patterns
+    /// look into are forced, but fields bound to variables are not.
+    fn matching(&mut self, matching: &Match<'i>, tail: bool) {
+        let span = matching.scrutinee.span();
+        self.expr(&matching.scrutinee, false, None);
+        if self.evaluation == Evaluation::Lazy {
+            self.emit(Op::Force, span.clone());
+        }
+        let slot = self.alloc_local(span.clone());
+        self.emit(Op::StoreLocal(slot), span);
+
+        let mut ends = Vec::with_capacity(matching.arms.len());
+        for arm in matching.arms.iter() {
+            let mut fails = Vec::new();
+            self.current().scopes.push(HashMap::new());
+            self.pattern(&arm.pattern, slot, &mut fails);
+            self.expr(&arm.body, tail, None);
+            self.current().scopes.pop();
+
+            ends.push(self.current().code.len());
+            self.emit(Op::Jump(0), arm.body.span());
+            let next = self.current().code.len() as u32;
+            for fail in fails {
+                self.patch(fail, next);
+            }
+        }
+        // resolution rejects matches that don't cover every value of the
+        // patterns' type, but a value of another type can still get here
+        self.emit(Op::NoMatch, matching.head_span());
+        let end = self.current().code.len() as u32;
+        for jump in ends {
+            self.patch(jump, end);
+        }
+    }
+
+    /// Compiles the tests of `pattern` against the value in `slot`, binding
+    /// its variables in the innermost scope. The tests that jump to the next
+    /// arm when they fail are added to `fails`.
+    fn pattern(&mut self, pattern: &Pattern<'i>, slot: u16, fails: &mut Vec<usize>) {
+        match pattern {
+            Pattern::Wildcard(_) => {}
+            Pattern::Variable(name) => {
+                let scope = self
+                    .current()
+                    .scopes
+                    .last_mut()
+                    .expect("the arm has a scope");
+                scope.insert(name.0, Place::Local(Local { slot, boxed: false }));
+            }
+            Pattern::Int(int) => {
+                self.emit(Op::Local(slot), int.1.clone());
+                fails.push(self.current().code.len());
+                self.emit(
+                    Op::MatchInt {
+                        value: int.0,
+                        otherwise: 0,
+                    },
+                    int.1.clone(),
+                );
+            }
+            Pattern::Constructor(pattern) => {
+                let Some(Place::Constructor(constructor)) =
+                    self.lookup(self.stack.len() - 1, pattern.name.0)
+                else {
+                    unreachable!("resolved constructor patterns name constructors");
+                };
+                self.emit(Op::Local(slot), pattern.span.clone());
+                fails.push(self.current().code.len());
+                self.emit(
+                    Op::MatchConstructor {
+                        constructor,
+                        otherwise: 0,
+                    },
+                    pattern.span.clone(),
+                );
+
+                for (index, arg) in pattern.args.iter().enumerate() {
+                    if let Pattern::Wildcard(_) = arg {
+                        continue;
+                    }
+                    let field = self.alloc_local(arg.span());
+                    self.emit(Op::Local(slot), arg.span());
+                    self.emit(Op::Field(index as u16), arg.span());
+                    if self.evaluation == Evaluation::Lazy && !matches!(arg, Pattern::Variable(_)) {
+                        self.emit(Op::Force, arg.span());
+                    }
+                    self.emit(Op::StoreLocal(field), arg.span());
+                    self.pattern(arg, field, fails);
+                }
+            }
+        }
+    }
+
+    /// Points the jump at `at` in the current function to `target`.
+    fn patch(&mut self, at: usize, target: u32) {
+        match &mut self.current().code[at] {
+            Op::Jump(to)
+            | Op::MatchConstructor { otherwise: to, .. }
+            | Op::MatchInt { otherwise: to, .. } => *to = target,
+            op => unreachable!("{op:?} isn't a jump"),
+        }
+    }
+
+    /// Returns the globals and constructors of the module imported as
+    /// `alias`, unless a local binding shadows it.
+    fn module(&self, alias: &str) -> Option<&HashMap<&'i str, Place>> {
+        let bound = self
+            .stack
+            .iter()
+            .flat_map(|state| &state.scopes)
+            .any(|scope| scope.contains_key(alias));
+        if bound {
+            None
+        } else {
+            self.qualified.get(alias)
+        }
+    }
+
+    /// Finds where `name` lives from the point of view of the function at
+    /// `depth` in the stack, capturing it from the enclosing functions if
+    /// needed.
+    fn lookup(&mut self, depth: usize, name: &'i str) -> Option<Place> {
+        let state = &mut self.stack[depth];
+        if let Some(place) = state.scopes.iter().rev().find_map(|scope| scope.get(name)) {
+            return Some(*place);
+        }
+        if let Some(index) = state.captures.iter().position(|(n, ..)| *n == name) {
+            let boxed = state.captures[index].2;
+            return Some(Place::Capture {
+                index: index as u16,
+                boxed,
+            });
+        }
+        if depth == 0 {
+            if let Some(&place) = self.globals.get(name) {
+                return Some(place);
+            }
+            return self.natives.index(name).map(Place::Native);
+        }
+
+        let (source, boxed) = match self.lookup(depth - 1, name)? {
+            place @ (Place::Global(_) | Place::Native(_) | Place::Constructor(_)) => {
+                return Some(place)
+            }
+            Place::Local(Local { slot, boxed }) => (CaptureSource::Local(slot), boxed),
+            Place::Capture { index, boxed } => (CaptureSource::Capture(index), boxed),
+        };
+        let captures = &mut self.stack[depth].captures;
+        captures.push((name, source, boxed));
+        Some(Place::Capture {
+            index: captures.len() as u16 - 1,
+            boxed,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use ria_parser::{loader::Loader, parse_module, temp_dir::TempDir};
+
+    use crate::{
+        bytecode::{CaptureSource, Evaluation, Op},
+        natives::Natives,
+        simplify::Passes,
+        vm::{run, Value},
+    };
+
+    use super::{compile, compile_loaded};
+
+    #[test]
+    fn compile_tail_calls_and_captures() {
+        let module = parse_module("const = \\x -> \\y -> x\nmain = const const const").unwrap();
+        let program = compile(&module, Evaluation::Strict).unwrap();
+
+        assert_eq!(program.globals.len(), 2);
+        assert_eq!(&*program.init_order, [0, 1]);
+
+        let inner = &program.functions[2];
+        assert_eq!(&*inner.captures, [CaptureSource::Local(0)]);
+        assert_eq!(&*inner.code, [Op::Capture(0), Op::Return]);
+
+        let main = &program.functions[program.globals[1].init as usize];
+        assert_eq!(
+            &*main.code,
+            [
+                Op::Global(0),
+                Op::Global(0),
+                Op::Call,
+                Op::Global(0),
+                Op::TailCall,
+                Op::Return
+            ]
+        );
+    }
+
+    #[test]
+    fn box_recursive_block_defs() {
+        let module =
+            parse_module("main = (even = \\n -> odd n\n  odd = \\n -> even n\n  x = even\n  x)")
+                .unwrap();
+        let program = compile(&module, Evaluation::Strict).unwrap();
+
+        let main = &program.functions[0];
+        assert_eq!(main.locals, 3);
+        assert_eq!(main.code[..2], [Op::NewCell(0), Op::NewCell(1)]);
+        assert!(main.code.contains(&Op::StoreCell(0)));
+        assert!(main.code.contains(&Op::StoreLocal(2)));
+        assert_eq!(main.code[main.code.len() - 2], Op::Local(2));
+    }
+
+    #[test]
+    fn report_unbound_variables() {
+        let module = parse_module("main = x").unwrap();
+        let errors = compile(&module, Evaluation::Strict).unwrap_err();
+        assert_eq!(errors[0].message, "cannot find `x` in this scope");
+    }
+
+    #[test]
+    fn report_tuples_too_long_for_bytecode() {
+        let source = format!("main = ({})", vec!["1"; 70_000].join(", "));
+        let module = parse_module(&source).unwrap();
+        let errors = compile(&module, Evaluation::Strict).unwrap_err();
+        assert_eq!(errors[0].message, "a tuple can have at most 65535 items");
+        assert_eq!(errors[0].span.start, 7);
+
+        let module = parse_module("main = \\t -> t.70000").unwrap();
+        let errors = compile(&module, Evaluation::Strict).unwrap_err();
+        assert_eq!(errors[0].message, "a tuple can have at most 65535 items");
+        assert_eq!(errors[0].span, 15..20);
+    }
+
+    #[test]
+    fn report_too_many_locals() {
+        let mut source = String::from("main = (\n");
+        for i in 0..70_000 {
+            source += &format!("  x{i} = {i}\n");
+        }
+        source += "  x0)";
+        let module = parse_module(&source).unwrap();
+        let errors = compile(&module, Evaluation::Strict).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].message, "this function has too many locals");
+        assert_eq!(&source[errors[0].span.clone()], "x65535");
+    }
+
+    #[test]
+    fn compile_imports() {
+        let dir = TempDir::new(
+            "compile-imports",
+            &[
+                (
+                    "main.ria",
+                    "import lib/math (double)\nx = math.inc 1\nmain = double x",
+                ),
+                (
+                    "lib/math.ria",
+                    "export (inc, double)\ninc = add one\ndouble = \\n -> mul n 2\none = (n = succ zero\n  to_int n)",
+                ),
+            ],
+        );
+        let mut loader = Loader::new();
+        let main = loader.load(dir.join("main.ria")).unwrap();
+
+        for evaluation in [Evaluation::Strict, Evaluation::Lazy] {
+            let program = compile_loaded(
+                &loader,
+                main,
+                evaluation,
+                &Natives::new(),
+                true,
+                Passes::default(),
+            )
+            .unwrap();
+            let globals: Vec<_> = program.globals.iter().map(|g| g.name.as_str()).collect();
+            assert_eq!(
+                globals[..5],
+                ["x", "main", "math.inc", "math.double", "math.one"]
+            );
+            assert!(matches!(run(&program), Ok(Value::Int(4))));
+        }
+    }
+
+    #[test]
+    fn compile_imported_constructors() {
+        let dir = TempDir::new(
+            "compile-constructors",
+            &[
+                (
+                    "main.ria",
+                    "import option (Some)\nmain = match option.wrap 1 with\n  | Some x -> x\n  | _ -> 0",
+                ),
+                (
+                    "option.ria",
+                    "export (Option, wrap)\ntype Option a = None | Some a\nwrap = Some",
+                ),
+            ],
+        );
+        let mut loader = Loader::new();
+        let main = loader.load(dir.join("main.ria")).unwrap();
+
+        for evaluation in [Evaluation::Strict, Evaluation::Lazy] {
+            let program = compile_loaded(
+                &loader,
+                main,
+                evaluation,
+                &Natives::new(),
+                false,
+                Passes::default(),
+            )
+            .unwrap();
+            let names: Vec<_> = program
+                .constructors
+                .iter()
+                .map(|c| c.name.as_str())
+                .collect();
+            assert_eq!(names, ["None", "Some"]);
+            assert!(matches!(run(&program), Ok(Value::Int(1))));
+        }
+    }
+
+    #[test]
+    fn compile_locals_shadowing_modules() {
+        let dir = TempDir::new(
+            "compile-shadowing",
+            &[
+                (
+                    "main.ria",
+                    "import lib/m\nmain = (m = { x = 5 }; f = \\m -> m.x; add (f m) (add m.x lib))\nlib = m.x",
+                ),
+                ("lib/m.ria", "export (x)\nx = 1"),
+            ],
+        );
+        let mut loader = Loader::new();
+        let main = loader.load(dir.join("main.ria")).unwrap();
+
+        for evaluation in [Evaluation::Strict, Evaluation::Lazy] {
+            let program = compile_loaded(
+                &loader,
+                main,
+                evaluation,
+                &Natives::new(),
+                false,
+                Passes::default(),
+            )
+            .unwrap();
+            assert!(matches!(run(&program), Ok(Value::Int(11))));
+        }
+    }
+
+    #[test]
+    fn report_errors_in_their_module() {
+        let dir = TempDir::new(
+            "compile-errors",
+            &[
+                ("main.ria", "import other (b)\nmain = other.c\nx = other.e"),
+                ("other.ria", "export (a)\na = d\ne = 1"),
+            ],
+        );
+        let mut loader = Loader::new();
+        let main = loader.load(dir.join("main.ria")).unwrap();
+        let other = loader.module(main).imports[0];
+
+        let errors = compile_loaded(
+            &loader,
+            main,
+            Evaluation::Strict,
+            &Natives::new(),
+            false,
+            Passes::default(),
+        )
+        .unwrap_err();
+        let errors: Vec<_> = errors
+            .iter()
+            .map(|(id, err)| (*id, err.message.as_str()))
+            .collect();
+        assert_eq!(
+            errors,
+            [
+                (other, "cannot find `d` in this scope"),
+                (main, "`other` has no definition `b`"),
+                (main, "`other` has no definition `c`"),
+                (main, "`e` is private to `other`"),
+            ]
+        );
+
+        // a module with imports needs a loader to be compiled
+        let module = parse_module("import other\nmain = other.a").unwrap();
+        let errors = compile(&module, Evaluation::Strict).unwrap_err();
+        assert_eq!(errors[0].message, "imports can only be compiled from files");
+    }
+
+    #[test]
+    fn locate_runtime_errors_in_their_module() {
+        let dir = TempDir::new(
+            "compile-runtime-errors",
+            &[
+                (
+                    "main.ria",
+                    "import other
+main = other.half 0",
+                ),
+                (
+                    "other.ria",
+                    "export (half)
+half = \\n -> div 1 n",
+                ),
+            ],
+        );
+        let mut loader = Loader::new();
+        let main = loader.load(dir.join("main.ria")).unwrap();
+        let other = loader.module(main).imports[0];
+
+        for prelude in [false, true] {
+            let program = compile_loaded(
+                &loader,
+                main,
+                Evaluation::Strict,
+                &Natives::new(),
+                prelude,
+                Passes::default(),
+            )
+            .unwrap();
+            let err = run(&program).unwrap_err();
+            assert_eq!(err.module, Some(other));
+            assert_eq!(&loader.module(other).source[err.span], "div 1 n");
+        }
+    }
+}
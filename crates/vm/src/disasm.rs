@@ -0,0 +1,210 @@
+//! A human-readable listing of a [`Program`].
+
+use std::fmt::Write;
+
+use crate::bytecode::{CaptureSource, Op, Program};
+
+/// Lists the globals and tests of `program` and the instructions of each
+/// function.
+pub fn disassemble(program: &Program) -> String {
+    let mut out = String::new();
+    for (index, global) in program.globals.iter().enumerate() {
+        let _ = writeln!(out, "global {index} {} = fn {}", global.name, global.init);
+    }
+    for (index, constructor) in program.constructors.iter().enumerate() {
+        let _ = writeln!(
+            out,
+            "constructor {index} {}/{}",
+            constructor.name, constructor.arity
+        );
+    }
+    for (index, test) in program.tests.iter().enumerate() {
+        let _ = writeln!(out, "test {index} {:?} = fn {}", test.name, test.init);
+    }
+    let order: Vec<_> = program.init_order.iter().map(u32::to_string).collect();
+    let _ = writeln!(out, "init order: {}", order.join(", "));
+
+    for (id, function) in program.functions.iter().enumerate() {
+        let name = function.name.as_deref().unwrap_or("<lambda>");
+        let _ = write!(out, "\nfn {id} {name}");
+        if !function.has_param {
+            out.push_str(" (no param)");
+        }
+        let _ = writeln!(out, ", {} locals", function.locals);
+
+        for (index, capture) in function.captures.iter().enumerate() {
+            let source = match capture {
+                CaptureSource::Local(slot) => format!("local {slot}"),
+                CaptureSource::Capture(index) => format!("capture {index}"),
+            };
+            let _ = writeln!(out, "  capture {index} <- {source}");
+        }
+
+        for (offset, op) in function.code.iter().enumerate() {
+            let _ = write!(out, "  {offset:04} ");
+            let _ = match op {
+                Op::Int(int) => writeln!(out, "int {int}"),
+                Op::Local(slot) => writeln!(out, "local {slot}"),
+                Op::LocalCell(slot) => writeln!(out, "local_cell {slot}"),
+                Op::Capture(index) => writeln!(out, "capture {index}"),
+                Op::CaptureCell(index) => writeln!(out, "capture_cell {index}"),
+                Op::Global(index) => {
+                    writeln!(
+                        out,
+                        "global {index} ; {}",
+                        program.globals[*index as usize].name
+                    )
+                }
+                Op::Native(index) => {
+                    writeln!(
+                        out,
+                        "native {index} ; {}",
+                        program.natives[*index as usize].name
+                    )
+                }
+                Op::Construct(index) => {
+                    writeln!(
+                        out,
+                        "construct {index} ; {}",
+                        program.constructors[*index as usize].name
+                    )
+                }
+                Op::Closure(id) => writeln!(out, "closure fn {id}"),
+                Op::Thunk(id) => writeln!(out, "thunk fn {id}"),
+                Op::Force => writeln!(out, "force"),
+                Op::StoreLocal(slot) => writeln!(out, "store_local {slot}"),
+                Op::NewCell(slot) => writeln!(out, "new_cell {slot}"),
+                Op::StoreCell(slot) => writeln!(out, "store_cell {slot}"),
+                Op::Call => writeln!(out, "call"),
+                Op::TailCall => writeln!(out, "tail_call"),
+                Op::Return => writeln!(out, "return"),
+                Op::Jump(target) => writeln!(out, "jump {target:04}"),
+                Op::MatchConstructor {
+                    constructor,
+                    otherwise,
+                } => writeln!(
+                    out,
+                    "match_constructor {constructor} else {otherwise:04} ; {}",
+                    program.constructors[*constructor as usize].name
+                ),
+                Op::MatchInt { value, otherwise } => {
+                    writeln!(out, "match_int {value} else {otherwise:04}")
+                }
+                Op::Field(index) => writeln!(out, "field {index}"),
+                Op::Tuple(len) => writeln!(out, "tuple {len}"),
+                Op::Record(shape) => {
+                    let names: Vec<_> = program.shapes[*shape as usize]
+                        .iter()
+                        .map(|&field| program.fields[field as usize].as_str())
+                        .collect();
+                    writeln!(out, "record {shape} ; {}", names.join(", "))
+                }
+                Op::GetField(field) => {
+                    writeln!(
+                        out,
+                        "get_field {field} ; {}",
+                        program.fields[*field as usize]
+                    )
+                }
+                Op::GetItem(index) => writeln!(out, "get_item {index}"),
+                Op::NoMatch => writeln!(out, "no_match"),
+            };
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use ria_parser::parse_module;
+
+    use crate::{bytecode::Evaluation, compile::compile};
+
+    use super::disassemble;
+
+    #[test]
+    fn list_functions() {
+        let module = parse_module("id = \\x -> x\nmain = id id").unwrap();
+        let program = compile(&module, Evaluation::Strict).unwrap();
+        assert_eq!(
+            disassemble(&program),
+            "global 0 id = fn 0
+global 1 main = fn 2
+init order: 0, 1
+
+fn 0 id (no param), 0 locals
+  0000 closure fn 1
+  0001 return
+
+fn 1 id, 1 locals
+  0000 local 0
+  0001 return
+
+fn 2 main (no param), 0 locals
+  0000 global 0 ; id
+  0001 global 0 ; id
+  0002 tail_call
+  0003 return
+"
+        );
+    }
+
+    #[test]
+    fn list_constructors_and_matches() {
+        let source =
+            "type Option a = None | Some a\nmain = match Some 1 with\n  | Some 2 -> 0\n  | _ -> 1";
+        let module = parse_module(source).unwrap();
+        let program = compile(&module, Evaluation::Strict).unwrap();
+        assert_eq!(
+            disassemble(&program),
+            "global 0 main = fn 0
+constructor 0 None/0
+constructor 1 Some/1
+init order: 0
+
+fn 0 main (no param), 2 locals
+  0000 construct 1 ; Some
+  0001 int 1
+  0002 call
+  0003 store_local 0
+  0004 local 0
+  0005 match_constructor 1 else 0013 ; Some
+  0006 local 0
+  0007 field 0
+  0008 store_local 1
+  0009 local 1
+  0010 match_int 2 else 0013
+  0011 int 0
+  0012 jump 0016
+  0013 int 1
+  0014 jump 0016
+  0015 no_match
+  0016 return
+"
+        );
+    }
+
+    #[test]
+    fn list_tuples_and_records() {
+        let module = parse_module("main = (r = { a = 1, b = (2, 3) }\n  r.b.1)").unwrap();
+        let program = compile(&module, Evaluation::Strict).unwrap();
+        assert_eq!(
+            disassemble(&program),
+            "global 0 main = fn 0
+init order: 0
+
+fn 0 main (no param), 1 locals
+  0000 int 1
+  0001 int 2
+  0002 int 3
+  0003 tuple 2
+  0004 record 0 ; a, b
+  0005 store_local 0
+  0006 local 0
+  0007 get_field 1 ; b
+  0008 get_item 1
+  0009 return
+"
+        );
+    }
+}
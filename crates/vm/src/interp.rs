@@ -0,0 +1,235 @@
+//! Direct interpretation of the AST, as a baseline for the VM.
+//!
+//! The interpreter evaluates a [`Module`] with the same strict semantics as
+//! the compiled program, but looks variables up by name in a chain of
+//! environments instead of using slots. It doesn't know about natives,
+//! imports, data types, tuples or records, so evaluating an expression that
+//! uses them fails with [`ErrorKind::Unsupported`].
+
+use std::{cell::RefCell, ops::Range, rc::Rc};
+
+use ria_parser::{
+    def::DefList,
+    deps::DepGraph,
+    expr::{Expr, Lambda},
+    module::Module,
+};
+
+use crate::{
+    natives::type_error,
+    vm::{Error, ErrorKind},
+};
+
+/// A value computed by the interpreter.
+#[derive(Debug, Clone)]
+pub enum Value<'a, 'i> {
+    Int(i64),
+    Closure(Rc<Closure<'a, 'i>>),
+}
+
+impl Value<'_, '_> {
+    /// Returns whether `self` and `other` are the same closure, or equal
+    /// integers.
+    pub fn ptr_eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Value::Int(a), Value::Int(b)) => a == b,
+            (Value::Closure(a), Value::Closure(b)) => Rc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Closure<'a, 'i> {
+    lambda: &'a Lambda<'i>,
+    env: Env<'a, 'i>,
+}
+
+type Cell<'a, 'i> = Rc<RefCell<Option<Value<'a, 'i>>>>;
+
+/// The names bound at some point of the program, innermost first.
+#[derive(Debug, Clone, Default)]
+struct Env<'a, 'i>(Option<Rc<Scope<'a, 'i>>>);
+
+#[derive(Debug)]
+struct Scope<'a, 'i> {
+    vars: Vec<(&'i str, Cell<'a, 'i>)>,
+    parent: Env<'a, 'i>,
+}
+
+impl<'a, 'i> Env<'a, 'i> {
+    fn with(&self, vars: Vec<(&'i str, Cell<'a, 'i>)>) -> Self {
+        Env(Some(Rc::new(Scope {
+            vars,
+            parent: self.clone(),
+        })))
+    }
+
+    fn lookup(&self, name: &str) -> Option<&Cell<'a, 'i>> {
+        let mut env = self;
+        while let Some(scope) = &env.0 {
+            if let Some((_, cell)) = scope.vars.iter().find(|(n, _)| *n == name) {
+                return Some(cell);
+            }
+            env = &scope.parent;
+        }
+        None
+    }
+}
+
+/// The top-level defs of an evaluated module.
+pub struct Interpreter<'a, 'i> {
+    globals: Env<'a, 'i>,
+}
+
+impl<'a, 'i> Interpreter<'a, 'i> {
+    /// Evaluates every top-level def of `module`.
+    pub fn new(module: &'a Module<'i>) -> Result<Self, Error> {
+        let globals = define(&Env::default(), module.defs())?;
+        Ok(Self { globals })
+    }
+
+    /// Returns the value of the top-level def called `name`.
+    pub fn global(&self, name: &str) -> Option<Value<'a, 'i>> {
+        self.globals.lookup(name)?.borrow().clone()
+    }
+
+    /// Returns the value of `main`.
+    pub fn main(&self) -> Result<Value<'a, 'i>, Error> {
+        self.global("main").ok_or(Error::new(ErrorKind::NoMain))
+    }
+}
+
+/// Binds `defs` on top of `env`, evaluating them in dependency order.
+fn define<'a, 'i>(env: &Env<'a, 'i>, defs: &'a DefList<'i>) -> Result<Env<'a, 'i>, Error> {
+    let cells: Vec<Cell> = defs.defs.iter().map(|_| Rc::default()).collect();
+    let env = env.with(
+        defs.defs
+            .iter()
+            .zip(&cells)
+            .map(|(def, cell)| (def.ident.0, cell.clone()))
+            .collect(),
+    );
+    for component in DepGraph::new(defs).components() {
+        for index in component.defs {
+            let value = eval(&defs.defs[index].expr, &env)?;
+            *cells[index].borrow_mut() = Some(value);
+        }
+    }
+    Ok(env)
+}
+
+fn uninitialized(span: Range<usize>) -> Error {
+    Error {
+        kind: ErrorKind::Uninitialized,
+        span,
+        module: None,
+    }
+}
+
+fn unsupported(span: Range<usize>, what: &'static str) -> Error {
+    Error {
+        kind: ErrorKind::Unsupported(what),
+        span,
+        module: None,
+    }
+}
+
+/// Evaluates `expr` in `env`. Calls whose result is the result of `expr`
+/// reuse the loop instead of recursing, so tail calls run in constant space.
+fn eval<'a, 'i>(expr: &'a Expr<'i>, env: &Env<'a, 'i>) -> Result<Value<'a, 'i>, Error> {
+    let mut expr = expr;
+    let mut env = env.clone();
+    loop {
+        match expr {
+            Expr::Variable(ident) => {
+                // names that aren't bound by the module are natives
+                let cell = env
+                    .lookup(ident.0)
+                    .ok_or_else(|| unsupported(ident.1.clone(), "natives"))?;
+                let value = cell.borrow().clone();
+                return value.ok_or_else(|| uninitialized(ident.1.clone()));
+            }
+            Expr::Qualified(_) => return Err(unsupported(expr.span(), "imports")),
+            Expr::Match(_) => return Err(unsupported(expr.span(), "data types")),
+            Expr::Tuple(_) | Expr::Record(_) | Expr::Access(_) => {
+                return Err(unsupported(expr.span(), "tuples and records"));
+            }
+            Expr::Int(int) => return Ok(Value::Int(int.0)),
+            Expr::Lambda(lambda) => {
+                return Ok(Value::Closure(Rc::new(Closure {
+                    lambda,
+                    env: env.clone(),
+                })));
+            }
+            Expr::Block(block) => {
+                env = define(&env, &block.defs)?;
+                expr = block
+                    .expr
+                    .as_deref()
+                    .expect("compiled blocks end in an expression");
+            }
+            Expr::Call(call) => {
+                let Value::Closure(closure) = eval(&call.func, &env)? else {
+                    return Err(Error {
+                        span: expr.span(),
+                        ..type_error("a function", "an integer")
+                    });
+                };
+                let arg = eval(&call.arg, &env)?;
+                env = closure.env.with(vec![(
+                    closure.lambda.param.0,
+                    Rc::new(RefCell::new(Some(arg))),
+                )]);
+                expr = &closure.lambda.body;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use ria_parser::parse_module;
+
+    use crate::vm::ErrorKind;
+
+    use super::Interpreter;
+
+    const CHURCH: &str = include_str!("../benches/church.ria");
+
+    #[test]
+    fn interpret_church_arithmetic() {
+        let source = format!("{CHURCH}three = succ two\nmain = (p = pred three\n  eq p two)");
+        let module = parse_module(&source).unwrap();
+        let interpreter = Interpreter::new(&module).unwrap();
+        let main = interpreter.main().unwrap();
+        assert!(main.ptr_eq(&interpreter.global("true").unwrap()));
+    }
+
+    #[test]
+    fn report_uninitialized_defs() {
+        let source = "main = (x = y\n  y = x\n  x)";
+        let module = parse_module(source).unwrap();
+        let err = Interpreter::new(&module).err().unwrap();
+        assert_eq!(err.kind, ErrorKind::Uninitialized);
+        assert_eq!(&source[err.span], "y");
+    }
+
+    #[test]
+    fn report_unsupported_expressions() {
+        for (source, what, span) in [
+            ("main = add 1 2", "natives", "add"),
+            ("main = (1, 2)", "tuples and records", "(1, 2)"),
+            (
+                "type B = T | F\nmain = match T with\n  | T -> 1\n  | F -> 0",
+                "data types",
+                "match T with\n  | T -> 1\n  | F -> 0",
+            ),
+        ] {
+            let module = parse_module(source).unwrap();
+            let err = Interpreter::new(&module).err().unwrap();
+            assert_eq!(err.kind, ErrorKind::Unsupported(what));
+            assert_eq!(&source[err.span], span);
+        }
+    }
+}
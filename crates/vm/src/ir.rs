@@ -0,0 +1,790 @@
+//! Intermediate representations for backends, lowered from the AST.
+//!
+//! [`anf`] puts a module in A-normal form, where the function and argument of
+//! every call are variables or constants and every intermediate result is
+//! bound to a variable. [`cps`] lowers that to continuation-passing style,
+//! where calls don't return but pass their result on to a continuation, and
+//! [`lifted`] converts its closures to records and lifts its lambdas to
+//! top-level functions. Each comes with a printer and an interpreter, which
+//! tests use to check that lowering doesn't change what a program means.
+//!
+//! All of them are strict, like the default evaluation of the VM. They cover
+//! integers, functions, blocks, data types, matches, tuples, records and the
+//! arithmetic and comparison [`builtins`](crate::builtins), and a program of
+//! several modules lowers to one whose defs are all in the same scope. Building
+//! and taking apart data values, tuples and records are [`Op`]s, and a
+//! [`Match`] tests patterns against a value in order.
+
+pub mod anf;
+pub mod cps;
+pub mod lifted;
+
+use std::{cell::RefCell, fmt, rc::Rc};
+
+use crate::{
+    builtins::{Prim, PrimValue},
+    natives::type_error,
+    vm::{Error, ErrorKind},
+};
+
+/// A variable, which is bound once in a program.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Var(pub u32);
+
+/// A constructor of a data type, by its index in a program's [`Names`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ConstructorId(pub usize);
+
+/// The source names of the variables and constructors of a program, which
+/// the printers show them by.
+#[derive(Debug, Clone, Default)]
+pub struct Names {
+    vars: Vec<String>,
+    constructors: Vec<String>,
+}
+
+impl Names {
+    /// Returns a new variable named after `hint`.
+    pub fn fresh(&mut self, hint: &str) -> Var {
+        self.vars.push(hint.to_owned());
+        Var(self.vars.len() as u32 - 1)
+    }
+
+    /// Returns a new variable named after the same hint as `var`.
+    fn like(&mut self, var: Var) -> Var {
+        let hint = self.vars[var.0 as usize].clone();
+        self.fresh(&hint)
+    }
+
+    /// Returns the name `var` is printed with, which is its hint followed by
+    /// its number, so no other variable has it.
+    pub fn display(&self, var: Var) -> String {
+        format!("{}_{}", self.vars[var.0 as usize], var.0)
+    }
+
+    /// Returns a new constructor called `name`. Constructors of different
+    /// types may have the same name.
+    pub fn constructor(&mut self, name: &str) -> ConstructorId {
+        self.constructors.push(name.to_owned());
+        ConstructorId(self.constructors.len() - 1)
+    }
+
+    /// Returns the name of `constructor`.
+    pub fn constructor_name(&self, constructor: ConstructorId) -> &str {
+        &self.constructors[constructor.0]
+    }
+
+    fn atom(&self, atom: &Atom) -> String {
+        match atom {
+            Atom::Var(var) => self.display(*var),
+            Atom::Int(int) => int.to_string(),
+            Atom::Builtin(builtin) => builtin.name().to_owned(),
+        }
+    }
+
+    fn atoms<'a>(&self, atoms: impl IntoIterator<Item = &'a Atom>) -> String {
+        let atoms: Vec<_> = atoms.into_iter().map(|atom| self.atom(atom)).collect();
+        atoms.join(", ")
+    }
+
+    /// Returns how `op` is printed: a data value like `Cons(x_1, xs_2)`, a
+    /// tuple like `(a_1, b_2)`, a record like `{ x = a_1 }` and a field like
+    /// `r_1.x`.
+    fn op(&self, op: &Op) -> String {
+        match op {
+            Op::Data(constructor, fields) if fields.is_empty() => {
+                self.constructor_name(*constructor).to_owned()
+            }
+            Op::Data(constructor, fields) => {
+                format!(
+                    "{}({})",
+                    self.constructor_name(*constructor),
+                    self.atoms(fields)
+                )
+            }
+            Op::Tuple(items) => format!("({})", self.atoms(items)),
+            Op::Record(fields) if fields.is_empty() => "{}".to_owned(),
+            Op::Record(fields) => {
+                let fields: Vec<_> = fields
+                    .iter()
+                    .map(|(name, atom)| format!("{name} = {}", self.atom(atom)))
+                    .collect();
+                format!("{{ {} }}", fields.join(", "))
+            }
+            Op::Get(atom, field) => format!("{}.{field}", self.atom(atom)),
+        }
+    }
+
+    /// Returns how `pattern` is printed, with the patterns of fields in
+    /// parentheses if they have fields of their own.
+    fn pattern(&self, pattern: &Pattern) -> String {
+        match pattern {
+            Pattern::Wildcard => "_".to_owned(),
+            Pattern::Var(var) => self.display(*var),
+            Pattern::Int(int) => int.to_string(),
+            Pattern::Data(constructor, fields) => {
+                let mut out = self.constructor_name(*constructor).to_owned();
+                for field in fields.iter() {
+                    let printed = self.pattern(field);
+                    match field {
+                        Pattern::Data(_, fields) if !fields.is_empty() => {
+                            out.push_str(&format!(" ({printed})"))
+                        }
+                        _ => out.push_str(&format!(" {printed}")),
+                    }
+                }
+                out
+            }
+        }
+    }
+}
+
+/// An operand that takes no work to evaluate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Atom {
+    Var(Var),
+    Int(i64),
+    Builtin(Builtin),
+}
+
+/// A builtin the IRs know about, which takes two arguments.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Builtin {
+    True,
+    False,
+    Prim(Prim),
+}
+
+impl Builtin {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "true" => Some(Builtin::True),
+            "false" => Some(Builtin::False),
+            name => Prim::from_name(name).map(Builtin::Prim),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Builtin::True => "true",
+            Builtin::False => "false",
+            Builtin::Prim(prim) => prim.name(),
+        }
+    }
+}
+
+/// An operation that builds a data value, tuple or record out of atoms, or
+/// reads one of their fields.
+#[derive(Debug, Clone)]
+pub enum Op {
+    /// A value of a constructor, with a value for each of its fields.
+    Data(ConstructorId, Box<[Atom]>),
+    Tuple(Box<[Atom]>),
+    /// A record, with its fields in source order.
+    Record(Box<[(Rc<str>, Atom)]>),
+    /// A field of a record or an item of a tuple.
+    Get(Atom, Field),
+}
+
+impl Op {
+    /// Returns the atoms the operation reads.
+    pub fn atoms(&self) -> Vec<&Atom> {
+        match self {
+            Op::Data(_, atoms) | Op::Tuple(atoms) => atoms.iter().collect(),
+            Op::Record(fields) => fields.iter().map(|(_, atom)| atom).collect(),
+            Op::Get(atom, _) => vec![atom],
+        }
+    }
+
+    /// Returns the operation with each of its atoms replaced by `map`.
+    pub fn map(&self, map: impl Fn(&Atom) -> Atom) -> Op {
+        match self {
+            Op::Data(constructor, fields) => {
+                Op::Data(*constructor, fields.iter().map(map).collect())
+            }
+            Op::Tuple(items) => Op::Tuple(items.iter().map(map).collect()),
+            Op::Record(fields) => Op::Record(
+                fields
+                    .iter()
+                    .map(|(name, atom)| (name.clone(), map(atom)))
+                    .collect(),
+            ),
+            Op::Get(atom, field) => Op::Get(map(atom), field.clone()),
+        }
+    }
+}
+
+/// What [`Op::Get`] reads.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Field {
+    /// A field of a record.
+    Name(Rc<str>),
+    /// An item of a tuple, counting from 0.
+    Index(usize),
+}
+
+impl fmt::Display for Field {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Field::Name(name) => f.write_str(name),
+            Field::Index(index) => write!(f, "{index}"),
+        }
+    }
+}
+
+/// What a match arm tests its value against.
+#[derive(Debug, Clone)]
+pub enum Pattern {
+    Wildcard,
+    /// Matches anything, and binds the variable to it.
+    Var(Var),
+    Int(i64),
+    /// Matches a value of the constructor whose fields match the patterns.
+    Data(ConstructorId, Box<[Pattern]>),
+}
+
+impl Pattern {
+    /// Adds the variables the pattern binds to `vars`.
+    pub fn vars(&self, vars: &mut Vec<Var>) {
+        match self {
+            Pattern::Var(var) => vars.push(*var),
+            Pattern::Data(_, fields) => fields.iter().for_each(|field| field.vars(vars)),
+            Pattern::Wildcard | Pattern::Int(_) => {}
+        }
+    }
+}
+
+/// A match of the value of an atom against the patterns of its arms, in
+/// order, whose value is the value of the body of the first arm that
+/// matches. A value no arm matches is an error.
+#[derive(Debug, Clone)]
+pub struct Match<E> {
+    pub scrutinee: Atom,
+    pub arms: Box<[(Pattern, E)]>,
+}
+
+impl<E> Match<E> {
+    /// Returns a match of the same scrutinee whose arms' bodies are
+    /// converted by `convert`.
+    fn map<F>(&self, scrutinee: Atom, mut convert: impl FnMut(&E) -> F) -> Match<F> {
+        Match {
+            scrutinee,
+            arms: self
+                .arms
+                .iter()
+                .map(|(pattern, body)| (pattern.clone(), convert(body)))
+                .collect(),
+        }
+    }
+}
+
+/// Prints `matching` with each arm on a line of its own, indented by
+/// `indent`, and the body of each below it, printed with `body`.
+fn print_match<E>(
+    names: &Names,
+    matching: &Match<E>,
+    indent: usize,
+    out: &mut String,
+    mut body: impl FnMut(&E, usize, &mut String),
+) {
+    let pad = " ".repeat(indent);
+    out.push_str(&format!("match {}\n", names.atom(&matching.scrutinee)));
+    for (pattern, arm) in matching.arms.iter() {
+        out.push_str(&format!("{pad}  | {} ->\n", names.pattern(pattern)));
+        body(arm, indent + 4, out);
+    }
+}
+
+/// What a program evaluates to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value {
+    Int(i64),
+    Function,
+    /// A data value, tuple or record, written like the VM
+    /// [writes](crate::vm::Value::display) it, except that functions in it
+    /// are all written `<function>`.
+    Data(String),
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Int(int) => write!(f, "{int}"),
+            Value::Function => f.write_str("<function>"),
+            Value::Data(data) => f.write_str(data),
+        }
+    }
+}
+
+/// A value in an interpreter, whose closures are `C`.
+#[derive(Debug)]
+enum Val<C> {
+    Int(i64),
+    Closure(C),
+    /// A builtin and its first argument, if it has been given one.
+    Builtin(Builtin, Option<Rc<Val<C>>>),
+    Data(ConstructorId, Rc<[Val<C>]>),
+    Tuple(Rc<[Val<C>]>),
+    Record(Rc<[(Rc<str>, Val<C>)]>),
+}
+
+// a derived impl would need `C: Clone` only for the closures
+impl<C: Clone> Clone for Val<C> {
+    fn clone(&self) -> Self {
+        match self {
+            Val::Int(int) => Val::Int(*int),
+            Val::Closure(closure) => Val::Closure(closure.clone()),
+            Val::Builtin(builtin, first) => Val::Builtin(*builtin, first.clone()),
+            Val::Data(constructor, fields) => Val::Data(*constructor, fields.clone()),
+            Val::Tuple(items) => Val::Tuple(items.clone()),
+            Val::Record(fields) => Val::Record(fields.clone()),
+        }
+    }
+}
+
+/// The variables a pattern binds, each with its value.
+type Bound<C> = Vec<(Var, Val<C>)>;
+
+/// A part of a [`Value::Data`] that is yet to be written.
+enum Piece<'v, C> {
+    /// A value, and whether it is the field of a data value.
+    Val(&'v Val<C>, bool),
+    Text(&'v str),
+}
+
+impl<C: Clone> Val<C> {
+    /// Returns the value of `atom`, looking variables up with `lookup`.
+    fn atom(atom: &Atom, lookup: impl FnOnce(Var) -> Self) -> Self {
+        match atom {
+            Atom::Var(var) => lookup(*var),
+            Atom::Int(int) => Val::Int(*int),
+            Atom::Builtin(builtin) => Val::Builtin(*builtin, None),
+        }
+    }
+
+    /// Describes the type of the value, for error messages.
+    fn type_name(&self) -> &'static str {
+        match self {
+            Val::Int(_) => "an integer",
+            Val::Closure(_) | Val::Builtin(..) => "a function",
+            Val::Data(..) => "a data value",
+            Val::Tuple(_) => "a tuple",
+            Val::Record(_) => "a record",
+        }
+    }
+
+    /// Returns what the value is to a caller, naming constructors after
+    /// `names`.
+    fn value(&self, names: &Names) -> Value {
+        match self {
+            Val::Int(int) => return Value::Int(*int),
+            Val::Closure(_) | Val::Builtin(..) => return Value::Function,
+            Val::Data(..) | Val::Tuple(_) | Val::Record(_) => {}
+        }
+        // data values can be as deep as a long list, so the pieces are
+        // written from a stack rather than by recursion
+        let mut out = String::new();
+        let mut pieces = vec![Piece::Val(self, false)];
+        while let Some(piece) = pieces.pop() {
+            let (val, field) = match piece {
+                Piece::Val(val, field) => (val, field),
+                Piece::Text(text) => {
+                    out.push_str(text);
+                    continue;
+                }
+            };
+            match val {
+                Val::Int(int) => out.push_str(&int.to_string()),
+                Val::Closure(_) | Val::Builtin(..) => out.push_str("<function>"),
+                Val::Data(constructor, fields) => {
+                    let name = names.constructor_name(*constructor);
+                    if fields.is_empty() {
+                        out.push_str(name);
+                        continue;
+                    }
+                    if field {
+                        out.push('(');
+                        pieces.push(Piece::Text(")"));
+                    }
+                    out.push_str(name);
+                    for field in fields.iter().rev() {
+                        pieces.push(Piece::Val(field, true));
+                        pieces.push(Piece::Text(" "));
+                    }
+                }
+                Val::Tuple(items) => {
+                    out.push('(');
+                    pieces.push(Piece::Text(")"));
+                    for (index, item) in items.iter().enumerate().rev() {
+                        pieces.push(Piece::Val(item, false));
+                        if index > 0 {
+                            pieces.push(Piece::Text(", "));
+                        }
+                    }
+                }
+                Val::Record(fields) if fields.is_empty() => out.push_str("{}"),
+                Val::Record(fields) => {
+                    out.push_str("{ ");
+                    pieces.push(Piece::Text(" }"));
+                    for (index, (name, value)) in fields.iter().enumerate().rev() {
+                        pieces.push(Piece::Val(value, false));
+                        pieces.push(Piece::Text(" = "));
+                        pieces.push(Piece::Text(name));
+                        if index > 0 {
+                            pieces.push(Piece::Text(", "));
+                        }
+                    }
+                }
+            }
+        }
+        Value::Data(out)
+    }
+
+    /// Applies `builtin`, which already has `first` if it isn't `None`, to
+    /// `arg`.
+    fn apply_builtin(builtin: Builtin, first: Option<Rc<Self>>, arg: Self) -> Result<Self, Error> {
+        let Some(first) = first else {
+            return Ok(Val::Builtin(builtin, Some(Rc::new(arg))));
+        };
+        match builtin {
+            Builtin::True => Ok((*first).clone()),
+            Builtin::False => Ok(arg),
+            Builtin::Prim(prim) => {
+                let (a, b) = match (&*first, &arg) {
+                    (Val::Int(a), Val::Int(b)) => (*a, *b),
+                    (Val::Int(_), other) | (other, _) => {
+                        return Err(type_error("an integer", other.type_name()));
+                    }
+                };
+                let result = prim.apply(a, b).map_err(Error::new)?;
+                Ok(match result {
+                    PrimValue::Int(int) => Val::Int(int),
+                    PrimValue::Bool(true) => Val::Builtin(Builtin::True, None),
+                    PrimValue::Bool(false) => Val::Builtin(Builtin::False, None),
+                })
+            }
+        }
+    }
+
+    /// Returns the error of calling the value, which isn't a function.
+    fn not_a_function(&self) -> Error {
+        type_error("a function", self.type_name())
+    }
+
+    /// Carries out `op`, looking variables up with `lookup`.
+    fn op(op: &Op, lookup: impl Fn(Var) -> Self) -> Result<Self, Error> {
+        let atom = |atom: &Atom| Val::atom(atom, &lookup);
+        Ok(match op {
+            Op::Data(constructor, fields) => {
+                Val::Data(*constructor, fields.iter().map(atom).collect())
+            }
+            Op::Tuple(items) => Val::Tuple(items.iter().map(atom).collect()),
+            Op::Record(fields) => Val::Record(
+                fields
+                    .iter()
+                    .map(|(name, value)| (name.clone(), atom(value)))
+                    .collect(),
+            ),
+            Op::Get(value, Field::Name(name)) => match atom(value) {
+                Val::Record(fields) => match fields.iter().find(|(field, _)| field == name) {
+                    Some((_, value)) => value.clone(),
+                    None => return Err(Error::new(ErrorKind::NoField(name.to_string()))),
+                },
+                value => return Err(type_error("a record", value.type_name())),
+            },
+            Op::Get(value, Field::Index(index)) => match atom(value) {
+                Val::Tuple(items) => match items.get(*index) {
+                    Some(item) => item.clone(),
+                    None => return Err(Error::new(ErrorKind::NoField(index.to_string()))),
+                },
+                value => return Err(type_error("a tuple", value.type_name())),
+            },
+        })
+    }
+
+    /// Returns whether the value matches `pattern`, adding the variables it
+    /// binds to `bound` if it does. Like the VM, it's an error to test a
+    /// value against a pattern of another type.
+    fn matches(&self, pattern: &Pattern, bound: &mut Bound<C>) -> Result<bool, Error> {
+        match (pattern, self) {
+            (Pattern::Wildcard, _) => Ok(true),
+            (Pattern::Var(var), _) => {
+                bound.push((*var, self.clone()));
+                Ok(true)
+            }
+            (Pattern::Int(int), Val::Int(value)) => Ok(int == value),
+            (Pattern::Int(_), value) => Err(type_error("an integer", value.type_name())),
+            (Pattern::Data(constructor, patterns), Val::Data(of, fields)) => {
+                if constructor != of {
+                    return Ok(false);
+                }
+                for (pattern, field) in patterns.iter().zip(fields.iter()) {
+                    if !field.matches(pattern, bound)? {
+                        return Ok(false);
+                    }
+                }
+                Ok(true)
+            }
+            (Pattern::Data(..), value) => Err(type_error("a data value", value.type_name())),
+        }
+    }
+
+    /// Returns the body of the first arm of `matching` the value matches,
+    /// and the variables its pattern binds.
+    fn select<'m, E>(&self, matching: &'m Match<E>) -> Result<(&'m E, Bound<C>), Error> {
+        for (pattern, body) in matching.arms.iter() {
+            let mut bound = Vec::new();
+            if self.matches(pattern, &mut bound)? {
+                return Ok((body, bound));
+            }
+        }
+        Err(Error::new(ErrorKind::NoMatch))
+    }
+}
+
+/// How deeply the interpreters of [`anf`] and [`lifted`] may nest calls and
+/// matches that aren't in tail position. They recurse on the native stack for
+/// each, so going deeper is an [`ErrorKind::TooDeep`] error instead, like
+/// going over the VM's [`Limits::max_depth`](crate::vm::Limits::max_depth).
+/// It's low enough for the 2 MiB stack a spawned thread gets by default,
+/// even in a debug build.
+const MAX_DEPTH: usize = 500;
+
+/// Returns the depth of a call or match nested in evaluation at `depth`, or
+/// an error if that's deeper than [`MAX_DEPTH`].
+fn deeper(depth: usize) -> Result<usize, Error> {
+    if depth < MAX_DEPTH {
+        Ok(depth + 1)
+    } else {
+        Err(Error::new(ErrorKind::TooDeep))
+    }
+}
+
+/// The variables bound at some point of a program, innermost first.
+#[derive(Debug)]
+struct Env<T>(Option<Rc<Frame<T>>>);
+
+#[derive(Debug)]
+struct Frame<T> {
+    var: Var,
+    /// `None` until a `letrec` has defined it.
+    value: RefCell<Option<T>>,
+    parent: Env<T>,
+}
+
+impl<T> Clone for Env<T> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<T> Default for Env<T> {
+    fn default() -> Self {
+        Self(None)
+    }
+}
+
+impl<T> Drop for Env<T> {
+    /// Drops the frames no other environment shares in a loop, since a long
+    /// chain of `let`s binds as many and dropping them recursively could
+    /// overflow the stack.
+    fn drop(&mut self) {
+        let mut env = self.0.take();
+        while let Some(frame) = env {
+            env = match Rc::try_unwrap(frame) {
+                Ok(mut frame) => frame.parent.0.take(),
+                Err(_) => None,
+            };
+        }
+    }
+}
+
+impl<T: Clone> Env<T> {
+    fn bind(&self, var: Var, value: T) -> Self {
+        Self(Some(Rc::new(Frame {
+            var,
+            value: RefCell::new(Some(value)),
+            parent: self.clone(),
+        })))
+    }
+
+    /// Binds each variable of `bound` to its value, in order.
+    fn bind_all(&self, bound: Vec<(Var, T)>) -> Self {
+        bound
+            .into_iter()
+            .fold(self.clone(), |env, (var, value)| env.bind(var, value))
+    }
+
+    /// Binds `vars` to values made in the new environment by `make`, so that
+    /// they can refer to each other.
+    fn bind_recursive(&self, vars: &[Var], mut make: impl FnMut(usize, &Self) -> T) -> Self {
+        let mut env = self.clone();
+        for &var in vars {
+            env = Self(Some(Rc::new(Frame {
+                var,
+                value: RefCell::new(None),
+                parent: env,
+            })));
+        }
+        for (index, &var) in vars.iter().enumerate() {
+            let value = make(index, &env);
+            env.frame(var).value.replace(Some(value));
+        }
+        env
+    }
+
+    fn frame(&self, var: Var) -> &Frame<T> {
+        let mut env = self;
+        while let Some(frame) = &env.0 {
+            if frame.var == var {
+                return frame;
+            }
+            env = &frame.parent;
+        }
+        panic!("lowered variables are bound")
+    }
+
+    fn lookup(&self, var: Var) -> T {
+        let value = self.frame(var).value.borrow().clone();
+        value.expect("letrec binds functions, which don't use each other right away")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use ria_parser::parse_module;
+
+    use crate::{
+        bytecode::Evaluation,
+        compile::compile_with_prelude,
+        natives::Natives,
+        prelude::prelude,
+        vm::{run, ErrorKind},
+    };
+
+    use super::{anf, cps, lifted, Value};
+
+    /// Evaluates `main` of `source` with the prelude in every form, checking
+    /// that they agree.
+    fn evaluate(source: &str) -> Result<Value, ErrorKind> {
+        let module = parse_module(source).unwrap();
+        let anf = anf::lower_module(&module, Some(&prelude())).unwrap();
+        let cps = cps::lower(&anf);
+        let lifted = lifted::lift(&anf);
+        let result = anf::evaluate(&anf).map_err(|err| err.kind);
+        for other in [cps::evaluate(&cps), lifted::evaluate(&lifted)] {
+            assert_eq!(
+                other.map_err(|err| err.kind),
+                result,
+                "the forms of `{source}` disagree"
+            );
+        }
+        result
+    }
+
+    const LIST: &str = "type List a = Nil | Cons a (List a)
+range = \\n -> match n with
+  | 0 -> Nil
+  | _ -> Cons n (range (sub n 1))
+sum = \\list -> match list with
+  | Nil -> 0
+  | Cons x rest -> add x (sum rest)
+";
+
+    #[test]
+    fn preserve_meaning() {
+        let sources = [
+            "main = add 1 2".to_owned(),
+            "main = to_int (times (from_int 6) (from_int 7))".to_owned(),
+            "fact = \\n -> le n 1 (\\_ -> 1) (\\_ -> mul n (fact (sub n 1))) 0\nmain = fact 20".to_owned(),
+            "even = \\n -> eq n 0 (\\_ -> true) (\\_ -> odd (sub n 1)) 0\n\
+             odd = \\n -> eq n 0 (\\_ -> false) (\\_ -> even (sub n 1)) 0\n\
+             main = even 10 1 2"
+                .to_owned(),
+            "main = (sum = \\n -> \\acc -> eq n 0 (\\_ -> acc) (\\_ -> sum (sub n 1) (add acc n)) 0\n  \
+             sum 100000 0)"
+                .to_owned(),
+            "main = (x = (y = 2\n    mul y 3)\n  f = \\a -> (b = add a x\n    sub b 1)\n  f (f 1))".to_owned(),
+            "twice = \\f -> \\x -> f (f x)\nmain = twice (twice (mul 3)) 1".to_owned(),
+            "id = \\x -> mul x 2\nmain = id 21".to_owned(),
+            "add3 = \\a -> \\b -> \\c -> add a (add b c)\nmain = (f = add3 1 2\n  mul (f 3) (f 4))".to_owned(),
+            "main = (k = 7\n  \
+             even = \\n -> eq n 0 (\\_ -> k) (\\_ -> odd (sub n 1)) 0\n  \
+             odd = \\n -> eq n 0 (\\_ -> sub 0 k) (\\_ -> even (sub n 1)) 0\n  \
+             add (even 10) (odd 10))"
+                .to_owned(),
+            format!("{LIST}main = sum (range 10)"),
+            format!("{LIST}main = range 3"),
+            // a constructor that isn't applied to all its fields is a function
+            format!("{LIST}map = \\f -> \\list -> match list with\n  | Nil -> Nil\n  | Cons x rest -> Cons (f x) (map f rest)\nmain = (wrap = Cons 0\n  map (\\x -> wrap Nil) (range 2))"),
+            format!(
+                "{LIST}type Option a = None | Some a
+second = \\list -> match list with
+  | Cons _ (Cons x _) -> Some x
+  | _ -> None
+main = Cons (second (range 1)) (Cons (second (range 5)) Nil)"
+            ),
+            // a match that isn't in tail position continues after it
+            "main = (x = match 2 with\n    | 1 -> 10\n    | n -> mul n 100\n  add x 1)".to_owned(),
+            "main = (type Pair a b = Pair a b\n  swap = \\p -> match p with\n    | Pair a b -> Pair b a\n  swap (Pair 1 2))".to_owned(),
+            "main = (1, { a = 2, b = (3, 4) }, {})".to_owned(),
+            "type Option a = None | Some a
+swap = \\p -> (p.1, p.0)
+point = { x = 1, y = Some 2 }
+main = (p = swap (point.x, point)
+  p.0.y)"
+                .to_owned(),
+            // a local named like a field is read, captured or not
+            "main = (r = { x = 5 }\n  f = \\n -> add r.x n\n  f r.x)".to_owned(),
+        ];
+        for source in &sources {
+            let module = parse_module(source).unwrap();
+            let program =
+                compile_with_prelude(&module, Evaluation::Strict, &Natives::new()).unwrap();
+            let expected = match run(&program) {
+                Ok(value) => value.display(&program).to_string(),
+                Err(err) => panic!("`{source}` failed: {err:?}"),
+            };
+            let value = evaluate(source).map(|value| value.to_string());
+            assert_eq!(value, Ok(expected), "evaluating `{source}`");
+        }
+        assert_eq!(evaluate("main = add 1"), Ok(Value::Function));
+    }
+
+    #[test]
+    fn report_runtime_errors() {
+        assert_eq!(evaluate("main = div 1 0"), Err(ErrorKind::DivisionByZero));
+        assert_eq!(
+            evaluate("main = (f = 1\n  f 2)").unwrap_err().to_string(),
+            "expected a function, found an integer"
+        );
+        assert_eq!(
+            evaluate("main = add id 1").unwrap_err().to_string(),
+            "expected an integer, found a function"
+        );
+        assert_eq!(
+            evaluate("type A = A\ntype B = B\nf = \\x -> match x with\n  | A -> 1\nmain = f B"),
+            Err(ErrorKind::NoMatch)
+        );
+        assert_eq!(
+            evaluate("type A = A\nmain = match 1 with\n  | A -> 1")
+                .unwrap_err()
+                .to_string(),
+            "expected a data value, found an integer"
+        );
+        assert_eq!(
+            evaluate("get = \\r -> r.b\nmain = get { a = 1 }"),
+            Err(ErrorKind::NoField("b".to_owned()))
+        );
+        assert_eq!(
+            evaluate("second = \\t -> t.1\nmain = second { a = 1 }")
+                .unwrap_err()
+                .to_string(),
+            "expected a tuple, found a record"
+        );
+        assert_eq!(
+            evaluate("main = (1, 2) 3").unwrap_err().to_string(),
+            "expected a function, found a tuple"
+        );
+    }
+}
@@ -0,0 +1,917 @@
+//! A-normal form: every intermediate result has a name.
+//!
+//! The defs of a block become `let`s in dependency order, and each group of
+//! recursive defs a `letrec`, which may only bind functions, since a strict
+//! value can't use itself before it exists. The defs of the prelude that a
+//! program uses come first, then those of each module after the modules it
+//! imports, then `main`.
+//!
+//! A constructor applied to all its fields builds its value with an
+//! [`Op::Data`], and one used any other way becomes a curried function that
+//! does. A match in tail position ends its expression, and any other match
+//! is bound to a variable like a call.
+
+use std::{collections::HashMap, fmt::Write as _, mem, ops::Range, rc::Rc};
+
+use ria_lexer::Spanned;
+use ria_parser::{
+    def::DefList,
+    deps::{free_variables, DepGraph},
+    diagnostic::Diagnostic,
+    expr::{self, Lambda as AstLambda, Qualified},
+    loader::{Loader, ModuleId},
+    module::Module,
+    pattern,
+};
+
+use crate::{compile::dependency_order, vm::Error};
+
+use super::{
+    deeper, print_match, Atom, Builtin, ConstructorId, Env, Field, Names, Op, Pattern, Val, Value,
+    Var,
+};
+
+/// A program lowered to A-normal form.
+#[derive(Debug, Clone)]
+pub struct Program {
+    pub names: Names,
+    /// What evaluates to `main`.
+    pub body: Expr,
+}
+
+#[derive(Debug, Clone)]
+pub enum Expr {
+    /// `let x = bind` followed by the rest.
+    Let(Var, Bind, Box<Expr>),
+    /// Functions that may call each other, followed by the rest.
+    LetRec(Box<[(Var, Lambda)]>, Box<Expr>),
+    /// A call whose result is the result of the expression.
+    Call(Atom, Atom),
+    Atom(Atom),
+    /// A match whose arms end the expression.
+    Match(Match),
+}
+
+/// What a `let` binds.
+#[derive(Debug, Clone)]
+pub enum Bind {
+    Atom(Atom),
+    Call(Atom, Atom),
+    Lambda(Lambda),
+    Op(Op),
+    /// A match, whose value is the value of the arm that matched.
+    Match(Match),
+}
+
+impl Expr {
+    /// Takes the rest out of a `let` or `letrec`, leaving a placeholder.
+    fn take_rest(&mut self) -> Option<Expr> {
+        match self {
+            Expr::Let(_, _, rest) | Expr::LetRec(_, rest) => {
+                Some(mem::replace(rest, Expr::Atom(Atom::Int(0))))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl Drop for Expr {
+    /// Drops a chain of `let`s in a loop, since a long block lowers to one
+    /// and dropping it recursively could overflow the stack.
+    fn drop(&mut self) {
+        let mut rest = self.take_rest();
+        while let Some(mut expr) = rest {
+            rest = expr.take_rest();
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Lambda {
+    pub param: Var,
+    pub body: Box<Expr>,
+}
+
+pub type Match = super::Match<Expr>;
+
+/// Lowers `module` to A-normal form. The defs of `prelude`, if any, are in
+/// scope, shadowed by the module's own.
+pub fn lower_module<'i>(
+    module: &Module<'i>,
+    prelude: Option<&Module<'i>>,
+) -> Result<Program, Diagnostic> {
+    // the modules an import refers to are only known to a loader
+    if let Some(import) = module.imports().first() {
+        return Err(Diagnostic::new(
+            import.span.clone(),
+            "imports can only be lowered from files",
+        ));
+    }
+
+    let mut lowerer = Lowerer::default();
+    let mut binds = Vec::new();
+    if let Some(prelude) = prelude {
+        let used = used_prelude_defs(module.defs(), prelude.defs());
+        lowerer.defs(prelude.defs(), Some(&used), &mut binds)?;
+    }
+    lowerer.defs(module.defs(), None, &mut binds)?;
+    lowerer.finish(binds)
+}
+
+/// Lowers the module `main` of `loader` and the modules it imports to one
+/// program, with the defs of `prelude`, if any, in scope in each of them.
+///
+/// The defs of a module come after the defs of the modules it imports, and
+/// only see the names it defines or imports. Returns the first error, with
+/// the module it is in.
+pub fn lower_loaded(
+    loader: &Loader,
+    main: ModuleId,
+    prelude: Option<&Module<'static>>,
+) -> Result<Program, (ModuleId, Diagnostic)> {
+    let mut ids = Vec::new();
+    dependency_order(loader, main, &mut ids);
+
+    let mut lowerer = Lowerer::default();
+    let mut binds = Vec::new();
+    if let Some(prelude) = prelude {
+        let mut used = vec![false; prelude.defs().defs.len()];
+        for &id in &ids {
+            let module = loader.module(id).module.defs();
+            for (used, by_module) in used
+                .iter_mut()
+                .zip(used_prelude_defs(module, prelude.defs()))
+            {
+                *used |= by_module;
+            }
+        }
+        lowerer
+            .defs(prelude.defs(), Some(&used), &mut binds)
+            .map_err(|err| (main, err))?;
+    }
+
+    let depth = lowerer.scope.len();
+    let mut defined = HashMap::new();
+    for &id in &ids {
+        let loaded = loader.module(id);
+        lowerer.scope.truncate(depth);
+        lowerer.modules.clear();
+        for (import, imported) in loaded.module.imports().iter().zip(&loaded.imports) {
+            let names: &Rc<HashMap<_, _>> = &defined[imported];
+            for name in import.names.iter().flatten() {
+                let named = names.get(name.0).ok_or_else(|| {
+                    let message = format!("`{}` isn't defined in `{}`", name.0, import.alias());
+                    (id, Diagnostic::new(name.1.clone(), message))
+                })?;
+                lowerer.scope.push((name.0, *named));
+            }
+            lowerer.modules.insert(import.alias(), names.clone());
+        }
+        let own = lowerer.scope.len();
+        lowerer
+            .defs(loaded.module.defs(), None, &mut binds)
+            .map_err(|err| (id, err))?;
+        defined.insert(id, Rc::new(lowerer.scope[own..].iter().copied().collect()));
+    }
+    lowerer.finish(binds).map_err(|err| (main, err))
+}
+
+/// Returns which defs of `prelude` the defs of `module` use, directly or
+/// through other defs of the prelude.
+pub(crate) fn used_prelude_defs(module: &DefList, prelude: &DefList) -> Vec<bool> {
+    let own: Vec<_> = module.defs.iter().map(|def| def.ident.0).collect();
+    let graph = DepGraph::new(prelude);
+    let mut used = vec![false; prelude.defs.len()];
+    let mut pending: Vec<_> = module
+        .defs
+        .iter()
+        .flat_map(|def| free_variables(&def.expr))
+        .filter(|name| !own.contains(name))
+        .filter_map(|name| graph.names.iter().position(|def| *def == name))
+        .collect();
+    while let Some(index) = pending.pop() {
+        if !used[index] {
+            used[index] = true;
+            pending.extend(&graph.edges[index]);
+        }
+    }
+    used
+}
+
+fn unsupported(span: Range<usize>, what: &str) -> Diagnostic {
+    Diagnostic::new(span, format!("{what} can't be lowered yet"))
+}
+
+/// A binding waiting for the expression it scopes over.
+enum Binding {
+    Let(Var, Bind),
+    LetRec(Box<[(Var, Lambda)]>),
+}
+
+/// Returns `end` in the scope of `binds`, the first outermost.
+fn wrap(binds: Vec<Binding>, end: Expr) -> Expr {
+    binds
+        .into_iter()
+        .rev()
+        .fold(end, |rest, binding| match binding {
+            Binding::Let(var, bind) => Expr::Let(var, bind, Box::new(rest)),
+            Binding::LetRec(lambdas) => Expr::LetRec(lambdas, Box::new(rest)),
+        })
+}
+
+/// What a name in scope refers to.
+#[derive(Debug, Clone, Copy)]
+enum Named {
+    Var(Var),
+    /// A constructor, with its number of fields.
+    Constructor(ConstructorId, usize),
+}
+
+#[derive(Default)]
+struct Lowerer<'i> {
+    names: Names,
+    /// What each name in scope refers to, innermost last.
+    scope: Vec<(&'i str, Named)>,
+    /// The top-level names of each module the module being lowered imports,
+    /// by the name it is imported as.
+    modules: HashMap<&'i str, Rc<HashMap<&'i str, Named>>>,
+}
+
+impl<'i> Lowerer<'i> {
+    fn lookup(&self, name: &str) -> Option<Named> {
+        let mut bound = self.scope.iter().rev();
+        bound
+            .find(|(bound, _)| *bound == name)
+            .map(|&(_, named)| named)
+    }
+
+    /// Returns the program that evaluates to `main` after `binds`.
+    fn finish(self, binds: Vec<Binding>) -> Result<Program, Diagnostic> {
+        let Some(Named::Var(main)) = self.lookup("main") else {
+            return Err(Diagnostic::new(0..0, "no `main` definition to lower"));
+        };
+        Ok(Program {
+            names: self.names,
+            body: wrap(binds, Expr::Atom(Atom::Var(main))),
+        })
+    }
+
+    /// Binds the defs of `defs`, or only those that are `needed`, adding
+    /// their names and the constructors of their types to the scope.
+    fn defs(
+        &mut self,
+        defs: &DefList<'i>,
+        needed: Option<&[bool]>,
+        binds: &mut Vec<Binding>,
+    ) -> Result<(), Diagnostic> {
+        for type_def in defs.types.iter() {
+            for constructor in type_def.constructors.iter() {
+                let id = self.names.constructor(constructor.name.0);
+                let named = Named::Constructor(id, constructor.arity());
+                self.scope.push((constructor.name.0, named));
+            }
+        }
+        let vars: Vec<_> = defs
+            .defs
+            .iter()
+            .map(|def| self.names.fresh(def.ident.0))
+            .collect();
+        self.scope.extend(
+            defs.defs
+                .iter()
+                .zip(&vars)
+                .map(|(def, &var)| (def.ident.0, Named::Var(var))),
+        );
+
+        let graph = DepGraph::new(defs);
+        for component in graph.components() {
+            if needed.is_some_and(|needed| !needed[component.defs[0]]) {
+                continue;
+            }
+            if !graph.is_recursive(&component) {
+                let index = component.defs[0];
+                let bind = self.bind(&defs.defs[index].expr, binds)?;
+                binds.push(Binding::Let(vars[index], bind));
+                continue;
+            }
+            let lambdas = component
+                .defs
+                .iter()
+                .map(|&index| {
+                    let def = &defs.defs[index];
+                    match &def.expr {
+                        expr::Expr::Lambda(lambda) => Ok((vars[index], self.lambda(lambda)?)),
+                        _ => Err(Diagnostic::new(
+                            def.ident.1.clone(),
+                            format!("`{}` is recursive but isn't a function", def.ident.0),
+                        )),
+                    }
+                })
+                .collect::<Result<_, _>>()?;
+            binds.push(Binding::LetRec(lambdas));
+        }
+        Ok(())
+    }
+
+    fn lambda(&mut self, lambda: &AstLambda<'i>) -> Result<Lambda, Diagnostic> {
+        let param = self.names.fresh(lambda.param.0);
+        self.scope.push((lambda.param.0, Named::Var(param)));
+        let body = self.body(&lambda.body);
+        self.scope.pop();
+        Ok(Lambda {
+            param,
+            body: Box::new(body?),
+        })
+    }
+
+    /// Lowers `expr` to an expression of its own.
+    fn body(&mut self, expr: &expr::Expr<'i>) -> Result<Expr, Diagnostic> {
+        let mut binds = Vec::new();
+        let end = self.tail(expr, &mut binds)?;
+        Ok(wrap(binds, end))
+    }
+
+    /// Lowers `expr` to what ends an expression, after `binds`.
+    fn tail(
+        &mut self,
+        expr: &expr::Expr<'i>,
+        binds: &mut Vec<Binding>,
+    ) -> Result<Expr, Diagnostic> {
+        match expr {
+            expr::Expr::Call(call) if self.construction(call).is_none() => {
+                let func = self.atom(&call.func, binds)?;
+                Ok(Expr::Call(func, self.atom(&call.arg, binds)?))
+            }
+            expr::Expr::Block(block) => {
+                let depth = self.scope.len();
+                let end = self.block(block, binds, |lowerer, body, binds| {
+                    lowerer.tail(body, binds)
+                });
+                self.scope.truncate(depth);
+                end
+            }
+            expr::Expr::Match(matching) => Ok(Expr::Match(self.matching(matching, binds)?)),
+            _ => Ok(Expr::Atom(self.atom(expr, binds)?)),
+        }
+    }
+
+    /// Lowers `expr` to what a `let` binds, after `binds`.
+    fn bind(
+        &mut self,
+        expr: &expr::Expr<'i>,
+        binds: &mut Vec<Binding>,
+    ) -> Result<Bind, Diagnostic> {
+        if let Some(op) = self.op(expr, binds)? {
+            return Ok(Bind::Op(op));
+        }
+        match expr {
+            expr::Expr::Lambda(lambda) => Ok(Bind::Lambda(self.lambda(lambda)?)),
+            // an expression can't be moved out of, since it drops its rest
+            _ => match &mut self.tail(expr, binds)? {
+                Expr::Call(func, arg) => Ok(Bind::Call(*func, *arg)),
+                Expr::Atom(atom) => Ok(Bind::Atom(*atom)),
+                Expr::Match(matching) => Ok(Bind::Match(Match {
+                    scrutinee: matching.scrutinee,
+                    arms: mem::take(&mut matching.arms),
+                })),
+                Expr::Let(..) | Expr::LetRec(..) => unreachable!("tails don't bind"),
+            },
+        }
+    }
+
+    /// Lowers `expr` to an op if it builds or reads a data value, tuple or
+    /// record, after `binds`.
+    fn op(
+        &mut self,
+        expr: &expr::Expr<'i>,
+        binds: &mut Vec<Binding>,
+    ) -> Result<Option<Op>, Diagnostic> {
+        let op = match expr {
+            expr::Expr::Call(call) => match self.construction(call) {
+                Some((constructor, args)) => Op::Data(constructor, self.atoms(args, binds)?),
+                None => return Ok(None),
+            },
+            expr::Expr::Tuple(tuple) => Op::Tuple(self.atoms(tuple.items.iter(), binds)?),
+            expr::Expr::Record(record) => {
+                let fields = record
+                    .fields
+                    .iter()
+                    .map(|field| Ok((Rc::from(field.name.0), self.atom(&field.value, binds)?)))
+                    .collect::<Result<_, _>>()?;
+                Op::Record(fields)
+            }
+            expr::Expr::Access(access) => {
+                let field = match access.field.0 {
+                    expr::Field::Name(name) => Field::Name(Rc::from(name)),
+                    expr::Field::Index(index) => Field::Index(index),
+                };
+                Op::Get(self.atom(&access.expr, binds)?, field)
+            }
+            expr::Expr::Qualified(qualified) => match self.member(qualified)? {
+                Some(_) => return Ok(None),
+                None => Op::Get(
+                    self.name(&qualified.module, binds)?,
+                    Field::Name(Rc::from(qualified.name.0)),
+                ),
+            },
+            _ => return Ok(None),
+        };
+        Ok(Some(op))
+    }
+
+    /// Lowers `exprs` to atoms, in order.
+    fn atoms<'e>(
+        &mut self,
+        exprs: impl IntoIterator<Item = &'e expr::Expr<'i>>,
+        binds: &mut Vec<Binding>,
+    ) -> Result<Box<[Atom]>, Diagnostic>
+    where
+        'i: 'e,
+    {
+        exprs
+            .into_iter()
+            .map(|expr| self.atom(expr, binds))
+            .collect()
+    }
+
+    /// Lowers `expr` to an atom, binding what it takes to compute it to
+    /// variables in `binds`.
+    fn atom(
+        &mut self,
+        expr: &expr::Expr<'i>,
+        binds: &mut Vec<Binding>,
+    ) -> Result<Atom, Diagnostic> {
+        match expr {
+            expr::Expr::Variable(ident) => self.name(ident, binds),
+            expr::Expr::Qualified(qualified) => match self.member(qualified)? {
+                Some(named) => Ok(self.named(qualified.name.0, named, binds)),
+                None => self.intermediate(expr, binds),
+            },
+            expr::Expr::Int(int) => Ok(Atom::Int(int.0)),
+            expr::Expr::Block(block) => {
+                let depth = self.scope.len();
+                let atom = self.block(block, binds, |lowerer, body, binds| {
+                    lowerer.atom(body, binds)
+                });
+                self.scope.truncate(depth);
+                atom
+            }
+            _ => self.intermediate(expr, binds),
+        }
+    }
+
+    /// Binds the value of `expr` to a new variable in `binds`.
+    fn intermediate(
+        &mut self,
+        expr: &expr::Expr<'i>,
+        binds: &mut Vec<Binding>,
+    ) -> Result<Atom, Diagnostic> {
+        let var = self.names.fresh(match expr {
+            expr::Expr::Lambda(_) => "fn",
+            _ => "t",
+        });
+        let bind = self.bind(expr, binds)?;
+        binds.push(Binding::Let(var, bind));
+        Ok(Atom::Var(var))
+    }
+
+    /// Lowers the name `ident` to an atom.
+    fn name(
+        &mut self,
+        ident: &Spanned<&'i str>,
+        binds: &mut Vec<Binding>,
+    ) -> Result<Atom, Diagnostic> {
+        if let Some(named) = self.lookup(ident.0) {
+            return Ok(self.named(ident.0, named, binds));
+        }
+        Builtin::from_name(ident.0)
+            .map(Atom::Builtin)
+            .ok_or_else(|| match ident.0 {
+                "assert_eq" => unsupported(ident.1.clone(), "`assert_eq`"),
+                name => Diagnostic::new(
+                    ident.1.clone(),
+                    format!("`{name}` isn't a def or a builtin"),
+                ),
+            })
+    }
+
+    /// Lowers what `name` refers to to an atom.
+    fn named(&mut self, name: &str, named: Named, binds: &mut Vec<Binding>) -> Atom {
+        match named {
+            Named::Var(var) => Atom::Var(var),
+            Named::Constructor(constructor, arity) => {
+                let var = self.names.fresh(name);
+                let bind = self.constructor(name, constructor, arity);
+                binds.push(Binding::Let(var, bind));
+                Atom::Var(var)
+            }
+        }
+    }
+
+    /// Returns the value of `constructor`, called `name`, which is a curried
+    /// function of its `arity` fields unless it has none.
+    fn constructor(&mut self, name: &str, constructor: ConstructorId, arity: usize) -> Bind {
+        let params: Vec<_> = (0..arity).map(|_| self.names.fresh("x")).collect();
+        let fields = params.iter().copied().map(Atom::Var).collect();
+        let Some((&first, rest)) = params.split_first() else {
+            return Bind::Op(Op::Data(constructor, fields));
+        };
+        let data = self.names.fresh(name);
+        let body = Expr::Let(
+            data,
+            Bind::Op(Op::Data(constructor, fields)),
+            Box::new(Expr::Atom(Atom::Var(data))),
+        );
+        let body = rest.iter().rev().fold(body, |body, &param| {
+            let function = self.names.fresh("fn");
+            let lambda = Lambda {
+                param,
+                body: Box::new(body),
+            };
+            Expr::Let(
+                function,
+                Bind::Lambda(lambda),
+                Box::new(Expr::Atom(Atom::Var(function))),
+            )
+        });
+        Bind::Lambda(Lambda {
+            param: first,
+            body: Box::new(body),
+        })
+    }
+
+    /// Returns what `qualified` names in the module it's imported from, or
+    /// `None` if it reads a field of a variable instead, since a local
+    /// binding shadows the module or no module is imported as its name.
+    fn member(&self, qualified: &Qualified<'i>) -> Result<Option<Named>, Diagnostic> {
+        let (alias, name) = (qualified.module.0, qualified.name.0);
+        if self.lookup(alias).is_some() {
+            return Ok(None);
+        }
+        let Some(names) = self.modules.get(alias) else {
+            return Ok(None);
+        };
+        match names.get(name) {
+            Some(named) => Ok(Some(*named)),
+            None => Err(Diagnostic::new(
+                qualified.span(),
+                format!("`{name}` isn't defined in `{alias}`"),
+            )),
+        }
+    }
+
+    /// Returns the constructor `call` applies to all its fields and its
+    /// arguments in order, if it does.
+    fn construction<'e>(
+        &self,
+        call: &'e expr::Call<'i>,
+    ) -> Option<(ConstructorId, Vec<&'e expr::Expr<'i>>)> {
+        let mut args = vec![&*call.arg];
+        let mut func = &*call.func;
+        while let expr::Expr::Call(call) = func {
+            args.push(&call.arg);
+            func = &call.func;
+        }
+        let named = match func {
+            expr::Expr::Variable(ident) => self.lookup(ident.0),
+            expr::Expr::Qualified(qualified) => self.member(qualified).ok().flatten(),
+            _ => None,
+        };
+        match named {
+            Some(Named::Constructor(constructor, arity)) if arity == args.len() => {
+                args.reverse();
+                Some((constructor, args))
+            }
+            _ => None,
+        }
+    }
+
+    fn matching(
+        &mut self,
+        matching: &expr::Match<'i>,
+        binds: &mut Vec<Binding>,
+    ) -> Result<Match, Diagnostic> {
+        let scrutinee = self.atom(&matching.scrutinee, binds)?;
+        let arms = matching
+            .arms
+            .iter()
+            .map(|arm| {
+                let depth = self.scope.len();
+                let lowered = self
+                    .pattern(&arm.pattern)
+                    .and_then(|pattern| Ok((pattern, self.body(&arm.body)?)));
+                self.scope.truncate(depth);
+                lowered
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Match { scrutinee, arms })
+    }
+
+    /// Lowers `pattern`, adding the variables it binds to the scope.
+    fn pattern(&mut self, pattern: &pattern::Pattern<'i>) -> Result<Pattern, Diagnostic> {
+        match pattern {
+            pattern::Pattern::Wildcard(_) => Ok(Pattern::Wildcard),
+            pattern::Pattern::Variable(name) => {
+                let var = self.names.fresh(name.0);
+                self.scope.push((name.0, Named::Var(var)));
+                Ok(Pattern::Var(var))
+            }
+            pattern::Pattern::Int(int) => Ok(Pattern::Int(int.0)),
+            pattern::Pattern::Constructor(pattern) => {
+                let name = &pattern.name;
+                let Some(Named::Constructor(constructor, _)) = self.lookup(name.0) else {
+                    return Err(Diagnostic::new(
+                        name.1.clone(),
+                        format!("`{}` isn't a constructor", name.0),
+                    ));
+                };
+                let args = pattern
+                    .args
+                    .iter()
+                    .map(|arg| self.pattern(arg))
+                    .collect::<Result<_, _>>()?;
+                Ok(Pattern::Data(constructor, args))
+            }
+        }
+    }
+
+    /// Binds the defs of `block` and lowers its expression with `lower`,
+    /// leaving the defs in scope for the caller to drop.
+    fn block<T>(
+        &mut self,
+        block: &expr::Block<'i>,
+        binds: &mut Vec<Binding>,
+        lower: impl FnOnce(&mut Self, &expr::Expr<'i>, &mut Vec<Binding>) -> Result<T, Diagnostic>,
+    ) -> Result<T, Diagnostic> {
+        let body = block.expr.as_deref().ok_or_else(|| {
+            Diagnostic::new(block.span.clone(), "a block must end with an expression")
+        })?;
+        self.defs(&block.defs, None, binds)?;
+        lower(self, body, binds)
+    }
+}
+
+/// Prints `program` with a binding on each line, and the body of each
+/// function and match arm indented below it.
+pub fn print(program: &Program) -> String {
+    let mut out = String::new();
+    print_expr(&program.names, &program.body, 0, &mut out);
+    out
+}
+
+fn print_expr(names: &Names, mut expr: &Expr, indent: usize, out: &mut String) {
+    let pad = " ".repeat(indent);
+    let arm = |body: &Expr, indent, out: &mut String| print_expr(names, body, indent, out);
+    loop {
+        match expr {
+            Expr::Let(var, bind, rest) => {
+                write!(out, "{pad}let {} = ", names.display(*var)).unwrap();
+                match bind {
+                    Bind::Atom(atom) => writeln!(out, "{}", names.atom(atom)).unwrap(),
+                    Bind::Call(func, arg) => {
+                        writeln!(out, "{} {}", names.atom(func), names.atom(arg)).unwrap()
+                    }
+                    Bind::Lambda(lambda) => print_lambda(names, lambda, indent, out),
+                    Bind::Op(op) => writeln!(out, "{}", names.op(op)).unwrap(),
+                    Bind::Match(matching) => print_match(names, matching, indent, out, arm),
+                }
+                expr = rest;
+            }
+            Expr::LetRec(lambdas, rest) => {
+                writeln!(out, "{pad}letrec").unwrap();
+                for (var, lambda) in lambdas.iter() {
+                    write!(out, "{pad}  {} = ", names.display(*var)).unwrap();
+                    print_lambda(names, lambda, indent + 2, out);
+                }
+                expr = rest;
+            }
+            Expr::Call(func, arg) => {
+                writeln!(out, "{pad}{} {}", names.atom(func), names.atom(arg)).unwrap();
+                return;
+            }
+            Expr::Atom(atom) => {
+                writeln!(out, "{pad}{}", names.atom(atom)).unwrap();
+                return;
+            }
+            Expr::Match(matching) => {
+                out.push_str(&pad);
+                print_match(names, matching, indent, out, arm);
+                return;
+            }
+        }
+    }
+}
+
+fn print_lambda(names: &Names, lambda: &Lambda, indent: usize, out: &mut String) {
+    writeln!(out, "\\{} ->", names.display(lambda.param)).unwrap();
+    print_expr(names, &lambda.body, indent + 2, out);
+}
+
+#[derive(Debug, Clone)]
+struct Closure<'p>(Rc<(&'p Lambda, Env<V<'p>>)>);
+type V<'p> = Val<Closure<'p>>;
+
+/// Evaluates `program`. Calls and matches in tail position reuse the loop,
+/// but others recurse, up to [`MAX_DEPTH`](super::MAX_DEPTH) deep.
+pub fn evaluate(program: &Program) -> Result<Value, Error> {
+    eval(&program.body, Env::default(), 0).map(|value| value.value(&program.names))
+}
+
+fn eval_atom<'p>(atom: &Atom, env: &Env<V<'p>>) -> V<'p> {
+    Val::atom(atom, |var| env.lookup(var))
+}
+
+fn eval<'p>(mut expr: &'p Expr, mut env: Env<V<'p>>, depth: usize) -> Result<V<'p>, Error> {
+    loop {
+        match expr {
+            Expr::Let(var, bind, rest) => {
+                let value = match bind {
+                    Bind::Atom(atom) => eval_atom(atom, &env),
+                    Bind::Call(func, arg) => {
+                        apply(eval_atom(func, &env), eval_atom(arg, &env), depth)?
+                    }
+                    Bind::Lambda(lambda) => Val::Closure(Closure(Rc::new((lambda, env.clone())))),
+                    Bind::Op(op) => Val::op(op, |var| env.lookup(var))?,
+                    Bind::Match(matching) => {
+                        let (body, bound) =
+                            eval_atom(&matching.scrutinee, &env).select(matching)?;
+                        eval(body, env.bind_all(bound), deeper(depth)?)?
+                    }
+                };
+                env = env.bind(*var, value);
+                expr = rest;
+            }
+            Expr::LetRec(lambdas, rest) => {
+                let vars: Vec<_> = lambdas.iter().map(|(var, _)| *var).collect();
+                env = env.bind_recursive(&vars, |index, env| {
+                    Val::Closure(Closure(Rc::new((&lambdas[index].1, env.clone()))))
+                });
+                expr = rest;
+            }
+            Expr::Call(func, arg) => {
+                let arg = eval_atom(arg, &env);
+                match eval_atom(func, &env) {
+                    Val::Closure(closure) => {
+                        let (lambda, closure_env) = &*closure.0;
+                        env = closure_env.bind(lambda.param, arg);
+                        expr = &lambda.body;
+                    }
+                    func => return apply(func, arg, depth),
+                }
+            }
+            Expr::Atom(atom) => return Ok(eval_atom(atom, &env)),
+            Expr::Match(matching) => {
+                let (body, bound) = eval_atom(&matching.scrutinee, &env).select(matching)?;
+                env = env.bind_all(bound);
+                expr = body;
+            }
+        }
+    }
+}
+
+/// Applies `func` to `arg` in evaluation at `depth`.
+fn apply<'p>(func: V<'p>, arg: V<'p>, depth: usize) -> Result<V<'p>, Error> {
+    match func {
+        Val::Closure(closure) => {
+            let (lambda, env) = &*closure.0;
+            eval(&lambda.body, env.bind(lambda.param, arg), deeper(depth)?)
+        }
+        Val::Builtin(builtin, first) => Val::apply_builtin(builtin, first, arg),
+        func => Err(func.not_a_function()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use ria_parser::{loader::Loader, parse_module, temp_dir::TempDir};
+
+    use crate::vm::ErrorKind;
+
+    use super::{evaluate, lower_loaded, lower_module, print, Value};
+
+    #[test]
+    fn name_every_intermediate_result() {
+        let source = "fact = \\n -> le n 1 (\\_ -> 1) (\\_ -> mul n (fact (sub n 1))) 0
+main = (x = fact 5
+  add x 1)";
+        let module = parse_module(source).unwrap();
+        let program = lower_module(&module, None).unwrap();
+        assert_eq!(
+            print(&program),
+            "letrec
+  fact_0 = \\n_2 ->
+    let t_6 = le n_2
+    let t_5 = t_6 1
+    let fn_7 = \\__8 ->
+      1
+    let t_4 = t_5 fn_7
+    let fn_9 = \\__10 ->
+      let t_11 = mul n_2
+      let t_14 = sub n_2
+      let t_13 = t_14 1
+      let t_12 = fact_0 t_13
+      t_11 t_12
+    let t_3 = t_4 fn_9
+    t_3 0
+let x_15 = fact_0 5
+let t_16 = add x_15
+let main_1 = t_16 1
+main_1
+"
+        );
+    }
+
+    #[test]
+    fn reject_recursive_values() {
+        let source = "main = (xs = add 1 xs\n  xs)";
+        let module = parse_module(source).unwrap();
+        let err = lower_module(&module, None).unwrap_err();
+        assert_eq!(&source[err.span], "xs");
+        assert_eq!(err.message, "`xs` is recursive but isn't a function");
+    }
+
+    #[test]
+    fn build_data_and_match_it() {
+        let source = "type Option a = None | Some a
+f = \\o -> match o with
+  | Some (Some x) -> (x, { a = x }.a)
+  | _ -> (0, {})
+main = (p = f (Some None)
+  Some p.0)";
+        let module = parse_module(source).unwrap();
+        let program = lower_module(&module, None).unwrap();
+        assert_eq!(
+            print(&program),
+            "let f_0 = \\o_2 ->
+  match o_2
+    | Some (Some x_3) ->
+      let t_6 = { a = x_3 }
+      let t_5 = t_6.a
+      let t_4 = (x_3, t_5)
+      t_4
+    | _ ->
+      let t_8 = {}
+      let t_7 = (0, t_8)
+      t_7
+let None_11 = None
+let t_10 = Some(None_11)
+let p_9 = f_0 t_10
+let t_13 = p_9.0
+let t_12 = Some(t_13)
+let main_1 = t_12
+main_1
+"
+        );
+        assert_eq!(evaluate(&program), Ok(Value::Data("Some 0".to_owned())));
+    }
+
+    #[test]
+    fn lower_imported_modules() {
+        let dir = TempDir::new(
+            "anf-imports",
+            &[
+                (
+                    "main.ria",
+                    "import lib/m (Box)\nmain = match m.wrap (Box 2) with\n  | Box (Box n) -> add n m.x",
+                ),
+                (
+                    "lib/m.ria",
+                    "export (Box, wrap, x)\ntype Box a = Box a\nwrap = Box\nx = 40",
+                ),
+            ],
+        );
+        let mut loader = Loader::new();
+        let main = loader.load(dir.join("main.ria")).unwrap();
+
+        let program = lower_loaded(&loader, main, None).unwrap();
+        assert_eq!(evaluate(&program), Ok(Value::Int(42)));
+
+        let module = &loader.module(main).module;
+        let err = lower_module(module, None).unwrap_err();
+        assert_eq!(err.message, "imports can only be lowered from files");
+    }
+
+    #[test]
+    fn evaluate_long_chains_of_lets() {
+        let items: Vec<_> = (0..10_000).map(|i| format!("add {i} 1")).collect();
+        let source = format!("main = ({})", items.join(", "));
+        let program = lower_module(&parse_module(&source).unwrap(), None).unwrap();
+        assert!(print(&program).ends_with("main_0\n"));
+        let Ok(Value::Data(tuple)) = evaluate(&program) else {
+            panic!("`main` is a tuple");
+        };
+        assert!(tuple.ends_with(", 9999, 10000)"));
+    }
+
+    #[test]
+    fn limit_how_deeply_calls_nest() {
+        let sum = |n| {
+            let source = format!(
+                "sum = \\n -> eq n 0 (\\_ -> 0) (\\_ -> add n (sum (sub n 1))) 0\nmain = sum {n}"
+            );
+            evaluate(&lower_module(&parse_module(&source).unwrap(), None).unwrap())
+        };
+        assert_eq!(sum(100).unwrap(), Value::Int(5050));
+        assert_eq!(sum(5000).unwrap_err().kind, ErrorKind::TooDeep);
+    }
+}
@@ -0,0 +1,391 @@
+//! Continuation-passing style: no call returns.
+//!
+//! Every function takes a continuation besides its argument and passes its
+//! result to that instead of returning, so the rest of the computation after
+//! a call that isn't in tail position becomes a named continuation. The
+//! program as a whole ends by passing `main` to the `halt` continuation.
+//! Continuations are only bound by `letcont` and function parameters, never
+//! stored in values, so a backend can allocate them on a stack. Each arm of a
+//! match passes its value to the same continuation, so a match that isn't in
+//! tail position continues with a `letcont` for the rest.
+
+use std::{fmt::Write as _, mem, rc::Rc};
+
+use crate::vm::Error;
+
+use super::{anf, print_match, Atom, Env, Names, Op, Val, Value, Var};
+
+/// A module lowered to continuation-passing style.
+#[derive(Debug, Clone)]
+pub struct Program {
+    pub names: Names,
+    /// The continuation that ends the program with its value.
+    pub halt: Var,
+    pub body: Expr,
+}
+
+#[derive(Debug, Clone)]
+pub enum Expr {
+    /// `let x = atom` followed by the rest.
+    LetVal(Var, Atom, Box<Expr>),
+    /// `let x = op` followed by the rest.
+    LetOp(Var, Op, Box<Expr>),
+    /// `let f = fun` followed by the rest.
+    LetFun(Var, Fun, Box<Expr>),
+    /// Functions that may call each other, followed by the rest.
+    LetRec(Box<[(Var, Fun)]>, Box<Expr>),
+    /// `letcont k = cont` followed by the rest.
+    LetCont(Var, Cont, Box<Expr>),
+    /// Calls a function with an argument and a continuation.
+    App(Atom, Atom, Var),
+    /// Passes a value to a continuation.
+    Continue(Var, Atom),
+    /// A match whose arms continue the expression.
+    Match(Match),
+}
+
+impl Expr {
+    /// Moves the expressions a binding scopes over to `pending`, leaving
+    /// placeholders.
+    fn take_rest(&mut self, pending: &mut Vec<Expr>) {
+        let mut take = |rest: &mut Box<Expr>| {
+            pending.push(mem::replace(rest, Expr::Continue(Var(0), Atom::Int(0))));
+        };
+        match self {
+            Expr::LetVal(_, _, rest)
+            | Expr::LetOp(_, _, rest)
+            | Expr::LetFun(_, _, rest)
+            | Expr::LetRec(_, rest) => take(rest),
+            Expr::LetCont(_, cont, rest) => {
+                take(&mut cont.body);
+                take(rest);
+            }
+            Expr::App(..) | Expr::Continue(..) | Expr::Match(_) => {}
+        }
+    }
+}
+
+impl Drop for Expr {
+    /// Drops bindings in a loop, since the rest after each call that isn't
+    /// in tail position is the body of a continuation, and dropping a long
+    /// chain of them recursively could overflow the stack.
+    fn drop(&mut self) {
+        let mut pending = Vec::new();
+        self.take_rest(&mut pending);
+        while let Some(mut expr) = pending.pop() {
+            expr.take_rest(&mut pending);
+        }
+    }
+}
+
+pub type Match = super::Match<Expr>;
+
+/// A function, which takes its argument and a continuation.
+#[derive(Debug, Clone)]
+pub struct Fun {
+    pub param: Var,
+    pub cont: Var,
+    pub body: Box<Expr>,
+}
+
+/// A continuation, which takes the value passed to it.
+#[derive(Debug, Clone)]
+pub struct Cont {
+    pub param: Var,
+    pub body: Box<Expr>,
+}
+
+/// Lowers `program` to continuation-passing style.
+pub fn lower(program: &anf::Program) -> Program {
+    let mut names = program.names.clone();
+    let halt = names.fresh("halt");
+    let body = expr(&mut names, &program.body, halt);
+    Program { names, halt, body }
+}
+
+/// Lowers `expr` to pass its value to the continuation `cont`. A chain of
+/// `let`s and `letrec`s is lowered in a loop, wrapping the rest from the end.
+fn expr(names: &mut Names, mut expr: &anf::Expr, cont: Var) -> Expr {
+    let mut binds = Vec::new();
+    let end = loop {
+        match expr {
+            anf::Expr::Let(var, bind, rest) => {
+                binds.push(Binding::Let(*var, bind));
+                expr = rest;
+            }
+            anf::Expr::LetRec(lambdas, rest) => {
+                let funs = lambdas
+                    .iter()
+                    .map(|(var, lambda)| (*var, fun(names, lambda)))
+                    .collect();
+                binds.push(Binding::LetRec(funs));
+                expr = rest;
+            }
+            anf::Expr::Call(func, arg) => break Expr::App(*func, *arg, cont),
+            anf::Expr::Atom(atom) => break Expr::Continue(cont, *atom),
+            anf::Expr::Match(matching) => break self::matching(names, matching, cont),
+        }
+    };
+    binds.into_iter().rev().fold(end, |rest, binding| {
+        let (var, bind) = match binding {
+            Binding::Let(var, bind) => (var, bind),
+            Binding::LetRec(funs) => return Expr::LetRec(funs, Box::new(rest)),
+        };
+        let rest = Box::new(rest);
+        match bind {
+            anf::Bind::Atom(atom) => Expr::LetVal(var, *atom, rest),
+            anf::Bind::Lambda(lambda) => Expr::LetFun(var, fun(names, lambda), rest),
+            anf::Bind::Op(op) => Expr::LetOp(var, op.clone(), rest),
+            anf::Bind::Call(..) | anf::Bind::Match(_) => {
+                let join = names.fresh("k");
+                let after = Cont {
+                    param: var,
+                    body: rest,
+                };
+                let before = match bind {
+                    anf::Bind::Call(func, arg) => Expr::App(*func, *arg, join),
+                    anf::Bind::Match(matching) => self::matching(names, matching, join),
+                    _ => unreachable!("only calls and matches continue"),
+                };
+                Expr::LetCont(join, after, Box::new(before))
+            }
+        }
+    })
+}
+
+/// A binding of a chain being lowered, waiting for the rest of the chain.
+/// The functions of a `letrec` are lowered as the chain is walked, and a
+/// `let` once the rest of the chain is.
+enum Binding<'a> {
+    Let(Var, &'a anf::Bind),
+    LetRec(Box<[(Var, Fun)]>),
+}
+
+/// Lowers `matching` to pass the value of the arm that matches to `cont`.
+fn matching(names: &mut Names, matching: &anf::Match, cont: Var) -> Expr {
+    Expr::Match(matching.map(matching.scrutinee, |body| expr(names, body, cont)))
+}
+
+fn fun(names: &mut Names, lambda: &anf::Lambda) -> Fun {
+    let cont = names.fresh("k");
+    Fun {
+        param: lambda.param,
+        cont,
+        body: Box::new(expr(names, &lambda.body, cont)),
+    }
+}
+
+/// Prints `program` with a binding on each line, and the body of each
+/// function and continuation indented below it.
+pub fn print(program: &Program) -> String {
+    let mut out = String::new();
+    print_expr(&program.names, &program.body, 0, &mut out);
+    out
+}
+
+/// Prints `expr` with its bindings at `indent`. The body of a continuation
+/// is printed before the rest of the expression, which waits on a stack
+/// rather than in a recursive call, since the rest after each call that
+/// isn't in tail position is such a body.
+fn print_expr(names: &Names, expr: &Expr, indent: usize, out: &mut String) {
+    let mut pending = vec![(expr, indent)];
+    while let Some((mut expr, mut indent)) = pending.pop() {
+        let mut pad = " ".repeat(indent);
+        loop {
+            match expr {
+                Expr::LetVal(var, atom, rest) => {
+                    let (var, atom) = (names.display(*var), names.atom(atom));
+                    writeln!(out, "{pad}let {var} = {atom}").unwrap();
+                    expr = rest;
+                }
+                Expr::LetOp(var, op, rest) => {
+                    let (var, op) = (names.display(*var), names.op(op));
+                    writeln!(out, "{pad}let {var} = {op}").unwrap();
+                    expr = rest;
+                }
+                Expr::LetFun(var, fun, rest) => {
+                    write!(out, "{pad}let {} = ", names.display(*var)).unwrap();
+                    print_fun(names, fun, indent, out);
+                    expr = rest;
+                }
+                Expr::LetRec(funs, rest) => {
+                    writeln!(out, "{pad}letrec").unwrap();
+                    for (var, fun) in funs.iter() {
+                        write!(out, "{pad}  {} = ", names.display(*var)).unwrap();
+                        print_fun(names, fun, indent + 2, out);
+                    }
+                    expr = rest;
+                }
+                Expr::LetCont(var, cont, rest) => {
+                    let (var, param) = (names.display(*var), names.display(cont.param));
+                    writeln!(out, "{pad}letcont {var} {param} ->").unwrap();
+                    pending.push((rest, indent));
+                    expr = &cont.body;
+                    indent += 2;
+                    pad = " ".repeat(indent);
+                }
+                Expr::App(func, arg, cont) => {
+                    let (func, arg) = (names.atom(func), names.atom(arg));
+                    writeln!(out, "{pad}{func} {arg} {}", names.display(*cont)).unwrap();
+                    break;
+                }
+                Expr::Continue(cont, atom) => {
+                    let (cont, atom) = (names.display(*cont), names.atom(atom));
+                    writeln!(out, "{pad}continue {cont} {atom}").unwrap();
+                    break;
+                }
+                Expr::Match(matching) => {
+                    out.push_str(&pad);
+                    print_match(names, matching, indent, out, |body, indent, out| {
+                        print_expr(names, body, indent, out)
+                    });
+                    break;
+                }
+            }
+        }
+    }
+}
+
+fn print_fun(names: &Names, fun: &Fun, indent: usize, out: &mut String) {
+    let (param, cont) = (names.display(fun.param), names.display(fun.cont));
+    writeln!(out, "\\{param} {cont} ->").unwrap();
+    print_expr(names, &fun.body, indent + 2, out);
+}
+
+#[derive(Debug, Clone)]
+struct Closure<'p>(Rc<(&'p Fun, Env<V<'p>>)>);
+type V<'p> = Val<Closure<'p>>;
+
+#[derive(Debug)]
+enum ContClosure<'p> {
+    Halt,
+    /// A continuation with the values and continuations bound where it was
+    /// defined.
+    Cont(&'p Cont, Env<V<'p>>, Env<Rc<ContClosure<'p>>>),
+}
+
+fn eval_atom<'p>(atom: &Atom, env: &Env<V<'p>>) -> V<'p> {
+    Val::atom(atom, |var| env.lookup(var))
+}
+
+/// Evaluates `program`. Since no call returns, this loops without
+/// recursing.
+///
+/// Continuations are bound apart from values, so a closure doesn't keep the
+/// continuations around it alive: a loop would otherwise hold on to every
+/// iteration before it.
+pub fn evaluate(program: &Program) -> Result<Value, Error> {
+    let mut env = Env::default();
+    let mut conts = Env::default().bind(program.halt, Rc::new(ContClosure::Halt));
+    let mut expr = &program.body;
+    loop {
+        // the value to pass to a continuation, once the expression is one
+        let (next, value) = match expr {
+            Expr::LetVal(var, value, rest) => {
+                env = env.bind(*var, eval_atom(value, &env));
+                expr = rest;
+                continue;
+            }
+            Expr::LetOp(var, op, rest) => {
+                env = env.bind(*var, Val::op(op, |var| env.lookup(var))?);
+                expr = rest;
+                continue;
+            }
+            Expr::LetFun(var, fun, rest) => {
+                let closure = Val::Closure(Closure(Rc::new((fun, env.clone()))));
+                env = env.bind(*var, closure);
+                expr = rest;
+                continue;
+            }
+            Expr::LetRec(funs, rest) => {
+                let vars: Vec<_> = funs.iter().map(|(var, _)| *var).collect();
+                env = env.bind_recursive(&vars, |index, env| {
+                    Val::Closure(Closure(Rc::new((&funs[index].1, env.clone()))))
+                });
+                expr = rest;
+                continue;
+            }
+            Expr::LetCont(var, body, rest) => {
+                let closure = ContClosure::Cont(body, env.clone(), conts.clone());
+                conts = conts.bind(*var, Rc::new(closure));
+                expr = rest;
+                continue;
+            }
+            Expr::App(func, arg, next) => {
+                let arg = eval_atom(arg, &env);
+                let next = conts.lookup(*next);
+                match eval_atom(func, &env) {
+                    Val::Closure(closure) => {
+                        let (fun, closure_env) = &*closure.0;
+                        env = closure_env.bind(fun.param, arg);
+                        conts = Env::default().bind(fun.cont, next);
+                        expr = &fun.body;
+                        continue;
+                    }
+                    Val::Builtin(builtin, first) => {
+                        (next, Val::apply_builtin(builtin, first, arg)?)
+                    }
+                    func => return Err(func.not_a_function()),
+                }
+            }
+            Expr::Continue(next, value) => (conts.lookup(*next), eval_atom(value, &env)),
+            Expr::Match(matching) => {
+                let (body, bound) = eval_atom(&matching.scrutinee, &env).select(matching)?;
+                env = env.bind_all(bound);
+                expr = body;
+                continue;
+            }
+        };
+        match &*next {
+            ContClosure::Halt => return Ok(value.value(&program.names)),
+            ContClosure::Cont(next, cont_env, cont_conts) => {
+                env = cont_env.bind(next.param, value);
+                conts = cont_conts.clone();
+                expr = &next.body;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use ria_parser::parse_module;
+
+    use super::{super::anf, evaluate, lower, print, Value};
+
+    #[test]
+    fn name_every_continuation() {
+        let source = "twice = \\f -> \\x -> f (f x)\nmain = twice (add 1) 5";
+        let module = parse_module(source).unwrap();
+        let program = lower(&anf::lower_module(&module, None).unwrap());
+        assert_eq!(
+            print(&program),
+            "let twice_0 = \\f_2 k_12 ->
+  let fn_3 = \\x_4 k_13 ->
+    letcont k_14 t_5 ->
+      f_2 t_5 k_13
+    f_2 x_4 k_14
+  continue k_12 fn_3
+letcont k_11 t_7 ->
+  letcont k_10 t_6 ->
+    letcont k_9 main_1 ->
+      continue halt_8 main_1
+    t_6 5 k_9
+  twice_0 t_7 k_10
+add 1 k_11
+"
+        );
+    }
+
+    #[test]
+    fn lower_long_chains_of_lets() {
+        let items: Vec<_> = (0..10_000).map(|i| format!("add {i} 1")).collect();
+        let source = format!("main = ({})", items.join(", "));
+        let module = parse_module(&source).unwrap();
+        let program = lower(&anf::lower_module(&module, None).unwrap());
+        let Ok(Value::Data(tuple)) = evaluate(&program) else {
+            panic!("`main` is a tuple");
+        };
+        assert!(tuple.ends_with(", 9999, 10000)"));
+    }
+}
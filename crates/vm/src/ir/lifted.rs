@@ -0,0 +1,612 @@
+//! Closure conversion and lambda lifting: a first-order program.
+//!
+//! Every lambda of an [`anf`] program becomes a top-level function, and
+//! creating it becomes allocating a closure: a record of the function and
+//! the variables the lambda captures, which are the free variables of its
+//! body. A function takes the closure it was called through besides its
+//! argument, and starts by loading what it captured from the closure's
+//! fields. A function that refers to itself uses that closure instead of
+//! capturing it.
+//!
+//! Nothing refers to a variable bound outside its own function, so a backend
+//! can compile each function on its own and only needs to know how to lay out
+//! a closure.
+
+use std::{
+    cell::RefCell,
+    collections::{BTreeSet, HashMap},
+    fmt::Write as _,
+    mem,
+    rc::Rc,
+};
+
+use crate::vm::Error;
+
+use super::{anf, deeper, print_match, Atom, Env, Names, Op, Val, Value, Var};
+
+/// A module with its lambdas lifted to functions.
+#[derive(Debug, Clone)]
+pub struct Program {
+    pub names: Names,
+    /// The functions, each after the ones whose closures it allocates.
+    pub functions: Vec<Function>,
+    /// What evaluates to `main`.
+    pub body: Expr,
+}
+
+/// The index of a function of a [`Program`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FunId(pub usize);
+
+/// A lambda lifted to the top level.
+#[derive(Debug, Clone)]
+pub struct Function {
+    /// The variable the lambda was bound to, which names the function.
+    pub name: Var,
+    /// The closure the function was called through.
+    pub closure: Var,
+    pub param: Var,
+    pub body: Expr,
+}
+
+#[derive(Debug, Clone)]
+pub enum Expr {
+    /// `let x = bind` followed by the rest.
+    Let(Var, Bind, Box<Expr>),
+    /// Closures that may capture each other, followed by the rest. They are
+    /// all allocated before any of their fields are filled in.
+    LetRec(Box<[(Var, Closure)]>, Box<Expr>),
+    /// A call of a closure or builtin whose result is the result of the
+    /// expression.
+    Call(Atom, Atom),
+    Atom(Atom),
+    /// A match whose arms end the expression.
+    Match(Match),
+}
+
+impl Expr {
+    /// Takes the rest out of a `let` or `letrec`, leaving a placeholder.
+    fn take_rest(&mut self) -> Option<Expr> {
+        match self {
+            Expr::Let(_, _, rest) | Expr::LetRec(_, rest) => {
+                Some(mem::replace(rest, Expr::Atom(Atom::Int(0))))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl Drop for Expr {
+    /// Drops a chain of `let`s in a loop, like [`anf::Expr`].
+    fn drop(&mut self) {
+        let mut rest = self.take_rest();
+        while let Some(mut expr) = rest {
+            rest = expr.take_rest();
+        }
+    }
+}
+
+/// What a `let` binds.
+#[derive(Debug, Clone)]
+pub enum Bind {
+    Atom(Atom),
+    Call(Atom, Atom),
+    Closure(Closure),
+    /// A field of a closure, counting from 0.
+    Field(Var, usize),
+    Op(Op),
+    /// A match, whose value is the value of the arm that matched.
+    Match(Match),
+}
+
+pub type Match = super::Match<Expr>;
+
+/// A closure to allocate.
+#[derive(Debug, Clone)]
+pub struct Closure {
+    pub function: FunId,
+    /// The variables to store in its fields.
+    pub captures: Box<[Var]>,
+}
+
+/// Returns the variables `lambda` captures: those its body uses but doesn't
+/// bind.
+pub fn captures(lambda: &anf::Lambda) -> BTreeSet<Var> {
+    let mut free = free_vars(&lambda.body);
+    free.remove(&lambda.param);
+    free
+}
+
+/// Returns the variables `expr` uses but doesn't bind. Since a variable is
+/// bound once in a program, those bound anywhere in a chain of `let`s and
+/// `letrec`s are removed at the end, which walks a long chain in a loop.
+fn free_vars(mut expr: &anf::Expr) -> BTreeSet<Var> {
+    let atoms = |atoms: &[&Atom]| {
+        let vars = atoms.iter().filter_map(|atom| match atom {
+            Atom::Var(var) => Some(*var),
+            _ => None,
+        });
+        vars.collect::<BTreeSet<_>>()
+    };
+    let mut free = BTreeSet::new();
+    let mut bound = Vec::new();
+    loop {
+        match expr {
+            anf::Expr::Let(var, bind, rest) => {
+                bound.push(*var);
+                free.extend(match bind {
+                    anf::Bind::Atom(atom) => atoms(&[atom]),
+                    anf::Bind::Call(func, arg) => atoms(&[func, arg]),
+                    anf::Bind::Lambda(lambda) => captures(lambda),
+                    anf::Bind::Op(op) => atoms(&op.atoms()),
+                    anf::Bind::Match(matching) => match_free_vars(matching),
+                });
+                expr = rest;
+            }
+            anf::Expr::LetRec(lambdas, rest) => {
+                for (var, lambda) in lambdas.iter() {
+                    bound.push(*var);
+                    free.extend(captures(lambda));
+                }
+                expr = rest;
+            }
+            anf::Expr::Call(func, arg) => {
+                free.extend(atoms(&[func, arg]));
+                break;
+            }
+            anf::Expr::Atom(atom) => {
+                free.extend(atoms(&[atom]));
+                break;
+            }
+            anf::Expr::Match(matching) => {
+                free.extend(match_free_vars(matching));
+                break;
+            }
+        }
+    }
+    for var in bound {
+        free.remove(&var);
+    }
+    free
+}
+
+/// Returns the variables `matching` uses but doesn't bind in a pattern.
+fn match_free_vars(matching: &anf::Match) -> BTreeSet<Var> {
+    let mut free = BTreeSet::new();
+    if let Atom::Var(var) = matching.scrutinee {
+        free.insert(var);
+    }
+    for (pattern, body) in matching.arms.iter() {
+        let mut arm = free_vars(body);
+        let mut bound = Vec::new();
+        pattern.vars(&mut bound);
+        for var in bound {
+            arm.remove(&var);
+        }
+        free.extend(arm);
+    }
+    free
+}
+
+/// Converts the lambdas of `program` to closures and lifts them to
+/// functions.
+pub fn lift(program: &anf::Program) -> Program {
+    let mut lifter = Lifter {
+        names: program.names.clone(),
+        functions: Vec::new(),
+    };
+    let body = lifter.expr(&program.body, &HashMap::new());
+    Program {
+        names: lifter.names,
+        functions: lifter.functions,
+        body,
+    }
+}
+
+/// A binding waiting for the expression it scopes over.
+enum Binding {
+    Let(Var, Bind),
+    LetRec(Box<[(Var, Closure)]>),
+}
+
+struct Lifter {
+    names: Names,
+    functions: Vec<Function>,
+}
+
+impl Lifter {
+    /// Converts `expr`, in a function where the variables in `renamed` are
+    /// bound to others. A chain of `let`s and `letrec`s is converted in a
+    /// loop.
+    fn expr(&mut self, mut expr: &anf::Expr, renamed: &HashMap<Var, Var>) -> Expr {
+        let var = |var: &Var| *renamed.get(var).unwrap_or(var);
+        let atom = |atom: &Atom| match atom {
+            Atom::Var(v) => Atom::Var(var(v)),
+            atom => *atom,
+        };
+        let mut binds = Vec::new();
+        let end = loop {
+            match expr {
+                anf::Expr::Let(name, bind, rest) => {
+                    let bind = match bind {
+                        anf::Bind::Atom(value) => Bind::Atom(atom(value)),
+                        anf::Bind::Call(func, arg) => Bind::Call(atom(func), atom(arg)),
+                        anf::Bind::Lambda(lambda) => {
+                            Bind::Closure(self.closure(*name, lambda, false, renamed))
+                        }
+                        anf::Bind::Op(op) => Bind::Op(op.map(atom)),
+                        anf::Bind::Match(matching) => Bind::Match(self.matching(matching, renamed)),
+                    };
+                    binds.push(Binding::Let(*name, bind));
+                    expr = rest;
+                }
+                anf::Expr::LetRec(lambdas, rest) => {
+                    let closures = lambdas
+                        .iter()
+                        .map(|(name, lambda)| (*name, self.closure(*name, lambda, true, renamed)))
+                        .collect();
+                    binds.push(Binding::LetRec(closures));
+                    expr = rest;
+                }
+                anf::Expr::Call(func, arg) => break Expr::Call(atom(func), atom(arg)),
+                anf::Expr::Atom(value) => break Expr::Atom(atom(value)),
+                anf::Expr::Match(matching) => break Expr::Match(self.matching(matching, renamed)),
+            }
+        };
+        binds
+            .into_iter()
+            .rev()
+            .fold(end, |rest, binding| match binding {
+                Binding::Let(var, bind) => Expr::Let(var, bind, Box::new(rest)),
+                Binding::LetRec(closures) => Expr::LetRec(closures, Box::new(rest)),
+            })
+    }
+
+    fn matching(&mut self, matching: &anf::Match, renamed: &HashMap<Var, Var>) -> Match {
+        let scrutinee = match matching.scrutinee {
+            Atom::Var(var) => Atom::Var(*renamed.get(&var).unwrap_or(&var)),
+            atom => atom,
+        };
+        matching.map(scrutinee, |body| self.expr(body, renamed))
+    }
+
+    /// Lifts `lambda`, which is bound to `name` and can refer to itself if
+    /// it's `recursive`, and returns the closure that creates it.
+    fn closure(
+        &mut self,
+        name: Var,
+        lambda: &anf::Lambda,
+        recursive: bool,
+        renamed: &HashMap<Var, Var>,
+    ) -> Closure {
+        let mut captures = captures(lambda);
+        let closure = self.names.fresh("env");
+        let mut inner = HashMap::new();
+        if recursive && captures.remove(&name) {
+            inner.insert(name, closure);
+        }
+        let fields: Vec<_> = captures
+            .iter()
+            .map(|&captured| {
+                let field = self.names.like(captured);
+                inner.insert(captured, field);
+                field
+            })
+            .collect();
+        let body = fields
+            .into_iter()
+            .enumerate()
+            .rev()
+            .fold(self.expr(&lambda.body, &inner), |rest, (index, field)| {
+                Expr::Let(field, Bind::Field(closure, index), Box::new(rest))
+            });
+        self.functions.push(Function {
+            name,
+            closure,
+            param: lambda.param,
+            body,
+        });
+        Closure {
+            function: FunId(self.functions.len() - 1),
+            captures: captures
+                .into_iter()
+                .map(|var| *renamed.get(&var).unwrap_or(&var))
+                .collect(),
+        }
+    }
+}
+
+/// Prints the functions of `program`, each with its body indented below it,
+/// and then the body of the program.
+pub fn print(program: &Program) -> String {
+    let names = &program.names;
+    let mut out = String::new();
+    for function in &program.functions {
+        let name = names.display(function.name);
+        let (closure, param) = (
+            names.display(function.closure),
+            names.display(function.param),
+        );
+        writeln!(out, "fn {name} {closure} {param} ->").unwrap();
+        print_expr(program, &function.body, 2, &mut out);
+        out.push('\n');
+    }
+    print_expr(program, &program.body, 0, &mut out);
+    out
+}
+
+fn print_expr(program: &Program, mut expr: &Expr, indent: usize, out: &mut String) {
+    let names = &program.names;
+    let pad = " ".repeat(indent);
+    loop {
+        match expr {
+            Expr::Let(var, bind, rest) => {
+                write!(out, "{pad}let {} = ", names.display(*var)).unwrap();
+                match bind {
+                    Bind::Atom(atom) => writeln!(out, "{}", names.atom(atom)).unwrap(),
+                    Bind::Call(func, arg) => {
+                        writeln!(out, "{} {}", names.atom(func), names.atom(arg)).unwrap()
+                    }
+                    Bind::Closure(closure) => print_closure(program, closure, out),
+                    Bind::Field(closure, index) => {
+                        writeln!(out, "{}.{index}", names.display(*closure)).unwrap()
+                    }
+                    Bind::Op(op) => writeln!(out, "{}", names.op(op)).unwrap(),
+                    Bind::Match(matching) => {
+                        print_match(names, matching, indent, out, |body, indent, out| {
+                            print_expr(program, body, indent, out)
+                        })
+                    }
+                }
+                expr = rest;
+            }
+            Expr::LetRec(closures, rest) => {
+                writeln!(out, "{pad}letrec").unwrap();
+                for (var, closure) in closures.iter() {
+                    write!(out, "{pad}  {} = ", names.display(*var)).unwrap();
+                    print_closure(program, closure, out);
+                }
+                expr = rest;
+            }
+            Expr::Call(func, arg) => {
+                writeln!(out, "{pad}{} {}", names.atom(func), names.atom(arg)).unwrap();
+                return;
+            }
+            Expr::Atom(atom) => {
+                writeln!(out, "{pad}{}", names.atom(atom)).unwrap();
+                return;
+            }
+            Expr::Match(matching) => {
+                out.push_str(&pad);
+                print_match(names, matching, indent, out, |body, indent, out| {
+                    print_expr(program, body, indent, out)
+                });
+                return;
+            }
+        }
+    }
+}
+
+fn print_closure(program: &Program, closure: &Closure, out: &mut String) {
+    let names = &program.names;
+    let function = names.display(program.functions[closure.function.0].name);
+    let captures: Vec<_> = closure
+        .captures
+        .iter()
+        .map(|&var| names.display(var))
+        .collect();
+    writeln!(out, "closure {function} ({})", captures.join(", ")).unwrap();
+}
+
+/// A closure while the program runs. The fields of a `letrec` are filled in
+/// after it's allocated.
+#[derive(Debug)]
+struct Record {
+    function: FunId,
+    fields: RefCell<Vec<V>>,
+}
+
+type V = Val<Rc<Record>>;
+
+/// Evaluates `program`. Calls and matches in tail position reuse the loop,
+/// but others recurse, up to [`MAX_DEPTH`](super::MAX_DEPTH) deep.
+pub fn evaluate(program: &Program) -> Result<Value, Error> {
+    eval(program, &program.body, Env::default(), 0).map(|value| value.value(&program.names))
+}
+
+fn eval_atom(atom: &Atom, env: &Env<V>) -> V {
+    Val::atom(atom, |var| env.lookup(var))
+}
+
+fn record(closure: &Closure, env: &Env<V>) -> Rc<Record> {
+    Rc::new(Record {
+        function: closure.function,
+        fields: RefCell::new(
+            closure
+                .captures
+                .iter()
+                .map(|&var| env.lookup(var))
+                .collect(),
+        ),
+    })
+}
+
+/// Returns the environment a call of `record` with `arg` starts in.
+fn enter(program: &Program, record: Rc<Record>, arg: V) -> (&Expr, Env<V>) {
+    let function = &program.functions[record.function.0];
+    let env = Env::default()
+        .bind(function.closure, Val::Closure(record))
+        .bind(function.param, arg);
+    (&function.body, env)
+}
+
+fn eval<'p>(
+    program: &'p Program,
+    mut expr: &'p Expr,
+    mut env: Env<V>,
+    depth: usize,
+) -> Result<V, Error> {
+    loop {
+        match expr {
+            Expr::Let(var, bind, rest) => {
+                let value = match bind {
+                    Bind::Atom(atom) => eval_atom(atom, &env),
+                    Bind::Call(func, arg) => {
+                        apply(program, eval_atom(func, &env), eval_atom(arg, &env), depth)?
+                    }
+                    Bind::Closure(closure) => Val::Closure(record(closure, &env)),
+                    Bind::Field(closure, index) => match env.lookup(*closure) {
+                        Val::Closure(record) => record.fields.borrow()[*index].clone(),
+                        _ => unreachable!("fields are loaded from closures"),
+                    },
+                    Bind::Op(op) => Val::op(op, |var| env.lookup(var))?,
+                    Bind::Match(matching) => {
+                        let (body, bound) =
+                            eval_atom(&matching.scrutinee, &env).select(matching)?;
+                        eval(program, body, env.bind_all(bound), deeper(depth)?)?
+                    }
+                };
+                env = env.bind(*var, value);
+                expr = rest;
+            }
+            Expr::LetRec(closures, rest) => {
+                let records: Vec<_> = closures
+                    .iter()
+                    .map(|(_, closure)| {
+                        Rc::new(Record {
+                            function: closure.function,
+                            fields: RefCell::default(),
+                        })
+                    })
+                    .collect();
+                for ((var, _), record) in closures.iter().zip(&records) {
+                    env = env.bind(*var, Val::Closure(record.clone()));
+                }
+                for ((_, closure), record) in closures.iter().zip(&records) {
+                    let fields = closure.captures.iter().map(|&var| env.lookup(var));
+                    *record.fields.borrow_mut() = fields.collect();
+                }
+                expr = rest;
+            }
+            Expr::Call(func, arg) => {
+                let arg = eval_atom(arg, &env);
+                match eval_atom(func, &env) {
+                    Val::Closure(record) => (expr, env) = enter(program, record, arg),
+                    func => return apply(program, func, arg, depth),
+                }
+            }
+            Expr::Atom(atom) => return Ok(eval_atom(atom, &env)),
+            Expr::Match(matching) => {
+                let (body, bound) = eval_atom(&matching.scrutinee, &env).select(matching)?;
+                env = env.bind_all(bound);
+                expr = body;
+            }
+        }
+    }
+}
+
+/// Applies `func` to `arg` in evaluation at `depth`.
+fn apply(program: &Program, func: V, arg: V, depth: usize) -> Result<V, Error> {
+    match func {
+        Val::Closure(record) => {
+            let (body, env) = enter(program, record, arg);
+            eval(program, body, env, deeper(depth)?)
+        }
+        Val::Builtin(builtin, first) => Val::apply_builtin(builtin, first, arg),
+        func => Err(func.not_a_function()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use ria_parser::parse_module;
+
+    use crate::vm::ErrorKind;
+
+    use super::{super::anf, captures, evaluate, lift, print, Value};
+
+    #[test]
+    fn capture_free_variables() {
+        let source = "main = \\a -> \\b -> (c = add a b\n  \\d -> mul c d)";
+        let module = parse_module(source).unwrap();
+        let program = anf::lower_module(&module, None).unwrap();
+        let anf::Expr::Let(_, anf::Bind::Lambda(lambda), _) = &program.body else {
+            panic!("`main` is a lambda");
+        };
+        assert!(captures(lambda).is_empty());
+        assert_eq!(
+            print(&lift(&program)),
+            "fn fn_6 env_12 d_7 ->
+  let c_13 = env_12.0
+  let t_8 = mul c_13
+  t_8 d_7
+
+fn fn_2 env_10 b_3 ->
+  let a_11 = env_10.0
+  let t_5 = add a_11
+  let c_4 = t_5 b_3
+  let fn_6 = closure fn_6 (c_4)
+  fn_6
+
+fn main_0 env_9 a_1 ->
+  let fn_2 = closure fn_2 (a_1)
+  fn_2
+
+let main_0 = closure main_0 ()
+main_0
+"
+        );
+    }
+
+    #[test]
+    fn call_themselves_through_their_closure() {
+        let source = "main = (k = 2\n  f = \\n -> le n 0 k (f (sub n 1))\n  f 3)";
+        let module = parse_module(source).unwrap();
+        let program = anf::lower_module(&module, None).unwrap();
+        assert_eq!(
+            print(&lift(&program)),
+            "fn f_2 env_10 n_3 ->
+  let k_11 = env_10.0
+  let t_6 = le n_3
+  let t_5 = t_6 0
+  let t_4 = t_5 k_11
+  let t_9 = sub n_3
+  let t_8 = t_9 1
+  let t_7 = env_10 t_8
+  t_4 t_7
+
+let k_1 = 2
+letrec
+  f_2 = closure f_2 (k_1)
+let main_0 = f_2 3
+main_0
+"
+        );
+    }
+
+    #[test]
+    fn lift_long_chains_of_lets() {
+        let items: Vec<_> = (0..10_000).map(|i| format!("add {i} 1")).collect();
+        let source = format!("main = ({})", items.join(", "));
+        let module = parse_module(&source).unwrap();
+        let program = lift(&anf::lower_module(&module, None).unwrap());
+        assert!(print(&program).ends_with("main_0\n"));
+        let Ok(Value::Data(tuple)) = evaluate(&program) else {
+            panic!("`main` is a tuple");
+        };
+        assert!(tuple.ends_with(", 9999, 10000)"));
+    }
+
+    #[test]
+    fn limit_how_deeply_calls_nest() {
+        let sum = |n| {
+            let source = format!(
+                "sum = \\n -> eq n 0 (\\_ -> 0) (\\_ -> add n (sum (sub n 1))) 0\nmain = sum {n}"
+            );
+            let module = parse_module(&source).unwrap();
+            evaluate(&lift(&anf::lower_module(&module, None).unwrap()))
+        };
+        assert_eq!(sum(100).unwrap(), Value::Int(5050));
+        assert_eq!(sum(5000).unwrap_err().kind, ErrorKind::TooDeep);
+    }
+}
@@ -0,0 +1,960 @@
+//! A backend that compiles a module to a JavaScript ES module.
+//!
+//! Each def becomes a `const`, in dependency order, and each lambda an arrow
+//! function of one argument, so a def of several arguments is a curried
+//! function. A block or match becomes statements in the body of the function
+//! it ends, or in an immediately called arrow function anywhere else, and
+//! each arm of a match an `if` that tests the value against its pattern. The
+//! modules a module imports come before it, in the same scope as each other,
+//! with each def named apart from the rest. The exported defs and
+//! constructors of the module are exported under their own names, wrapped to
+//! run calls made in tail position, which return to their caller to be made
+//! without growing the stack.
+//!
+//! The positions of the code that came from the module are recorded, so a
+//! [`source_map`] can point from them back to the source.
+
+pub mod source_map;
+
+use std::{collections::HashMap, ops::Range, rc::Rc};
+
+use ria_lexer::Spanned;
+use ria_parser::{
+    def::DefList,
+    deps::DepGraph,
+    diagnostic::Diagnostic,
+    expr::{self, Expr, Qualified},
+    loader::{Loader, ModuleId},
+    module::Module,
+    pattern::Pattern,
+};
+
+use crate::{
+    compile::dependency_order,
+    ir::{anf::used_prelude_defs, Builtin},
+};
+
+use self::source_map::Mapping;
+
+/// The checked arithmetic, tail calls and builtins every module starts with.
+const RUNTIME: &str = include_str!("js/runtime.js");
+
+/// The words JavaScript doesn't allow as names in a module.
+const RESERVED: &[&str] = &[
+    "arguments",
+    "await",
+    "break",
+    "case",
+    "catch",
+    "class",
+    "const",
+    "continue",
+    "debugger",
+    "default",
+    "delete",
+    "do",
+    "else",
+    "enum",
+    "eval",
+    "export",
+    "extends",
+    "false",
+    "finally",
+    "for",
+    "function",
+    "if",
+    "implements",
+    "import",
+    "in",
+    "instanceof",
+    "interface",
+    "let",
+    "new",
+    "null",
+    "package",
+    "private",
+    "protected",
+    "public",
+    "return",
+    "static",
+    "super",
+    "switch",
+    "this",
+    "throw",
+    "true",
+    "try",
+    "typeof",
+    "var",
+    "void",
+    "while",
+    "with",
+    "yield",
+];
+
+/// A module compiled to JavaScript.
+#[derive(Debug, Clone)]
+pub struct Output {
+    pub code: String,
+    /// Where the code of the module, but not the prelude, runtime or modules
+    /// it imports, came from, in the order of the code.
+    pub mappings: Vec<Mapping>,
+}
+
+impl Output {
+    /// Returns the source map of the code into `source`, the text of the
+    /// module, which is called `name`.
+    pub fn source_map(&self, name: &str, source: &str) -> String {
+        source_map::source_map(&self.mappings, name, source)
+    }
+}
+
+/// Compiles `module` to JavaScript. The defs of `prelude`, if any, are in
+/// scope, shadowed by the module's own, but aren't exported.
+pub fn compile_module<'i>(
+    module: &Module<'i>,
+    prelude: Option<&Module<'i>>,
+) -> Result<Output, Diagnostic> {
+    // the modules an import refers to are only known to a loader
+    if let Some(import) = module.imports().first() {
+        return Err(Diagnostic::new(
+            import.span.clone(),
+            "imports can only be compiled from files",
+        ));
+    }
+
+    let mut emitter = Emitter::default();
+    emitter.write(RUNTIME);
+    if let Some(prelude) = prelude {
+        let used = used_prelude_defs(module.defs(), prelude.defs());
+        emitter.defs(prelude.defs(), Some(&used))?;
+    }
+    let own = emitter.scope.len();
+    emitter.mapped = true;
+    emitter.defs(module.defs(), None)?;
+    Ok(emitter.finish(module, own))
+}
+
+/// Compiles the module `main` of `loader` and the modules it imports to one
+/// JavaScript module, with the defs of `prelude`, if any, in scope in each
+/// of them, that exports what `main` exports. Returns the first error, with
+/// the module it is in.
+pub fn compile_loaded(
+    loader: &Loader,
+    main: ModuleId,
+    prelude: Option<&Module<'static>>,
+) -> Result<Output, (ModuleId, Diagnostic)> {
+    let mut ids = Vec::new();
+    dependency_order(loader, main, &mut ids);
+
+    let mut emitter = Emitter::default();
+    emitter.write(RUNTIME);
+    if let Some(prelude) = prelude {
+        let mut used = vec![false; prelude.defs().defs.len()];
+        for &id in &ids {
+            let module = loader.module(id).module.defs();
+            for (used, by_module) in used
+                .iter_mut()
+                .zip(used_prelude_defs(module, prelude.defs()))
+            {
+                *used |= by_module;
+            }
+        }
+        emitter
+            .defs(prelude.defs(), Some(&used))
+            .map_err(|err| (main, err))?;
+    }
+
+    let depth = emitter.scope.len();
+    let mut defined = HashMap::new();
+    let mut own = depth;
+    for &id in &ids {
+        let loaded = loader.module(id);
+        emitter.scope.truncate(depth);
+        emitter.modules.clear();
+        for (import, imported) in loaded.module.imports().iter().zip(&loaded.imports) {
+            let names: &Rc<HashMap<_, _>> = &defined[imported];
+            for name in import.names.iter().flatten() {
+                let named = names.get(name.0).ok_or_else(|| {
+                    let message = format!("`{}` isn't defined in `{}`", name.0, import.alias());
+                    (id, Diagnostic::new(name.1.clone(), message))
+                })?;
+                emitter.scope.push((name.0, Named::clone(named)));
+            }
+            emitter.modules.insert(import.alias(), names.clone());
+        }
+        own = emitter.scope.len();
+        emitter.mapped = id == main;
+        emitter
+            .defs(loaded.module.defs(), None)
+            .map_err(|err| (id, err))?;
+        defined.insert(id, Rc::new(emitter.scope[own..].iter().cloned().collect()));
+    }
+    Ok(emitter.finish(&loader.module(main).module, own))
+}
+
+fn unsupported(span: Range<usize>, what: &str) -> Diagnostic {
+    Diagnostic::new(span, format!("{what} can't be compiled to JavaScript yet"))
+}
+
+/// What a name in scope refers to, by its JavaScript name.
+#[derive(Debug, Clone)]
+enum Named {
+    Var(String),
+    /// A constructor, whose value has the id its data values are told apart
+    /// by.
+    Constructor(String),
+}
+
+impl Named {
+    fn name(&self) -> &str {
+        match self {
+            Named::Var(name) | Named::Constructor(name) => name,
+        }
+    }
+}
+
+#[derive(Default)]
+struct Emitter<'i> {
+    out: String,
+    /// The position the next code is written at, with the column in UTF-16
+    /// code units.
+    line: u32,
+    column: u32,
+    mappings: Vec<Mapping>,
+    /// Whether the code being written comes from the module, rather than the
+    /// prelude.
+    mapped: bool,
+    indent: usize,
+    /// What each ria name in scope refers to, innermost last.
+    scope: Vec<(&'i str, Named)>,
+    /// The top-level names of each module the module being compiled
+    /// imports, by the name it is imported as.
+    modules: HashMap<&'i str, Rc<HashMap<&'i str, Named>>>,
+    /// How many names have been made from each ria name, so each is unique
+    /// and no def can shadow another in the same function.
+    made: HashMap<&'i str, usize>,
+    /// How many constructors have been defined, which is the id of the next.
+    constructors: usize,
+}
+
+impl<'i> Emitter<'i> {
+    fn write(&mut self, code: &str) {
+        for line in code.split_inclusive('\n') {
+            if let Some(line) = line.strip_suffix('\n') {
+                self.out.push_str(line);
+                self.out.push('\n');
+                self.line += 1;
+                self.column = 0;
+            } else {
+                self.out.push_str(line);
+                self.column += line.encode_utf16().count() as u32;
+            }
+        }
+    }
+
+    fn newline(&mut self) {
+        self.write("\n");
+        self.write(&"  ".repeat(self.indent));
+    }
+
+    /// Records that the code written next comes from `span`.
+    fn mark(&mut self, span: &Range<usize>) {
+        if self.mapped {
+            self.mappings.push(Mapping {
+                line: self.line,
+                column: self.column,
+                source: span.start,
+            });
+        }
+    }
+
+    /// Returns a new JavaScript name for `name`.
+    fn fresh(&mut self, name: &'i str) -> String {
+        let count = self.made.entry(name).or_default();
+        *count += 1;
+        let base = if RESERVED.contains(&name) {
+            format!("{name}$")
+        } else {
+            name.to_owned()
+        };
+        match *count {
+            1 => base,
+            count => format!("{base}${}", count - 1),
+        }
+    }
+
+    /// Exports the exported defs and constructors of `module`, whose
+    /// top-level names are in scope from `own` on, and returns the code.
+    fn finish(mut self, module: &Module<'i>, own: usize) -> Output {
+        // the constructors and then the defs are in scope in order
+        let defs = module.defs();
+        let constructors = defs
+            .types
+            .iter()
+            .flat_map(|type_def| &type_def.constructors);
+        let idents = constructors
+            .map(|constructor| &constructor.name)
+            .chain(defs.defs.iter().map(|def| &def.ident));
+        let mut exports = Vec::new();
+        for (ident, (_, named)) in idents.zip(self.scope[own..].to_vec()) {
+            if !module.is_exported(ident.0) {
+                continue;
+            }
+            let name = named.name();
+            self.newline();
+            self.mark(&ident.1);
+            self.write(&format!("const {name}$export = $export({name});"));
+            exports.push(format!("{name}$export as {}", ident.0));
+        }
+        if !exports.is_empty() {
+            self.newline();
+            self.write(&format!("export {{ {} }};", exports.join(", ")));
+        }
+        self.write("\n");
+        Output {
+            code: self.out,
+            mappings: self.mappings,
+        }
+    }
+
+    fn lookup(&self, name: &str) -> Option<&Named> {
+        let mut bound = self.scope.iter().rev();
+        bound
+            .find(|(bound, _)| *bound == name)
+            .map(|(_, named)| named)
+    }
+
+    /// Writes a `const` for each constructor of the types of `defs`, and
+    /// each def, or only those that are `needed`, in dependency order,
+    /// adding their names to the scope.
+    fn defs(&mut self, defs: &DefList<'i>, needed: Option<&[bool]>) -> Result<(), Diagnostic> {
+        for type_def in defs.types.iter() {
+            for constructor in type_def.constructors.iter() {
+                let name = self.fresh(constructor.name.0);
+                let (id, arity) = (self.constructors, constructor.arity());
+                self.constructors += 1;
+                self.newline();
+                self.mark(&constructor.name.1);
+                self.write(&format!(
+                    "const {name} = $constructor({id}, \"{}\", {arity});",
+                    constructor.name.0
+                ));
+                self.scope
+                    .push((constructor.name.0, Named::Constructor(name)));
+            }
+        }
+        let names: Vec<_> = defs
+            .defs
+            .iter()
+            .map(|def| self.fresh(def.ident.0))
+            .collect();
+        self.scope.extend(
+            defs.defs
+                .iter()
+                .zip(&names)
+                .map(|(def, name)| (def.ident.0, Named::Var(name.clone()))),
+        );
+
+        let graph = DepGraph::new(defs);
+        for component in graph.components() {
+            if needed.is_some_and(|needed| !needed[component.defs[0]]) {
+                continue;
+            }
+            let recursive = graph.is_recursive(&component);
+            for &index in &component.defs {
+                let def = &defs.defs[index];
+                // a strict value can't use itself before it exists
+                if recursive && !matches!(def.expr, Expr::Lambda(_)) {
+                    return Err(Diagnostic::new(
+                        def.ident.1.clone(),
+                        format!("`{}` is recursive but isn't a function", def.ident.0),
+                    ));
+                }
+                self.newline();
+                self.mark(&def.ident.1);
+                self.write(&format!("const {} = ", names[index]));
+                self.expr(&def.expr, false)?;
+                self.write(";");
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes `expr`. A call in `tail` position of a function returns to be
+    /// made by the caller.
+    fn expr(&mut self, expr: &Expr<'i>, tail: bool) -> Result<(), Diagnostic> {
+        self.mark(&expr.span());
+        match expr {
+            Expr::Variable(ident) => self.name(ident)?,
+            Expr::Qualified(qualified) => match self.member(qualified)? {
+                Some(named) => self.write(&named),
+                None => {
+                    // a field of a variable
+                    self.write("$field(");
+                    self.name(&qualified.module)?;
+                    self.write(&format!(", \"{}\")", qualified.name.0));
+                }
+            },
+            Expr::Int(int) => self.write(&format!("{}n", int.0)),
+            Expr::Lambda(lambda) => {
+                let param = self.fresh(lambda.param.0);
+                self.write(&format!("({param}) => "));
+                self.scope.push((lambda.param.0, Named::Var(param)));
+                let body = match &*lambda.body {
+                    body @ (Expr::Block(_) | Expr::Match(_)) => {
+                        self.write("{");
+                        self.indent += 1;
+                        let body = self.returns(body, true);
+                        self.indent -= 1;
+                        self.newline();
+                        self.write("}");
+                        body
+                    }
+                    body => self.expr(body, true),
+                };
+                self.scope.pop();
+                body?;
+            }
+            Expr::Call(call) => {
+                self.write(if tail { "new $Tail(" } else { "$call(" });
+                self.expr(&call.func, false)?;
+                self.write(", ");
+                self.expr(&call.arg, false)?;
+                self.write(")");
+            }
+            Expr::Block(_) | Expr::Match(_) => {
+                self.write("(() => {");
+                self.indent += 1;
+                self.returns(expr, false)?;
+                self.indent -= 1;
+                self.newline();
+                self.write("})()");
+            }
+            Expr::Tuple(tuple) => {
+                self.write("new $Tuple([");
+                for (index, item) in tuple.items.iter().enumerate() {
+                    if index > 0 {
+                        self.write(", ");
+                    }
+                    self.expr(item, false)?;
+                }
+                self.write("])");
+            }
+            Expr::Record(record) => {
+                self.write("new $Record([");
+                for (index, field) in record.fields.iter().enumerate() {
+                    if index > 0 {
+                        self.write(", ");
+                    }
+                    self.write(&format!("[\"{}\", ", field.name.0));
+                    self.expr(&field.value, false)?;
+                    self.write("]");
+                }
+                self.write("])");
+            }
+            Expr::Access(access) => {
+                let get = match access.field.0 {
+                    expr::Field::Name(_) => "$field(",
+                    expr::Field::Index(_) => "$item(",
+                };
+                self.write(get);
+                self.expr(&access.expr, false)?;
+                match access.field.0 {
+                    expr::Field::Name(name) => self.write(&format!(", \"{name}\")")),
+                    expr::Field::Index(index) => self.write(&format!(", {index})")),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the name `ident`.
+    fn name(&mut self, ident: &Spanned<&'i str>) -> Result<(), Diagnostic> {
+        if let Some(named) = self.lookup(ident.0) {
+            let name = named.name().to_owned();
+            self.write(&name);
+            return Ok(());
+        }
+        let builtin = Builtin::from_name(ident.0).ok_or_else(|| match ident.0 {
+            "assert_eq" => unsupported(ident.1.clone(), "`assert_eq`"),
+            name => Diagnostic::new(
+                ident.1.clone(),
+                format!("`{name}` isn't a def or a builtin"),
+            ),
+        })?;
+        self.write(&format!("${}", builtin.name()));
+        Ok(())
+    }
+
+    /// Returns the JavaScript name of what `qualified` names in the module
+    /// it's imported from, or `None` if it reads a field of a variable
+    /// instead, since a local binding shadows the module or no module is
+    /// imported as its name.
+    fn member(&self, qualified: &Qualified<'i>) -> Result<Option<String>, Diagnostic> {
+        let (alias, name) = (qualified.module.0, qualified.name.0);
+        if self.lookup(alias).is_some() {
+            return Ok(None);
+        }
+        let Some(names) = self.modules.get(alias) else {
+            return Ok(None);
+        };
+        match names.get(name) {
+            Some(named) => Ok(Some(named.name().to_owned())),
+            None => Err(Diagnostic::new(
+                qualified.span(),
+                format!("`{name}` isn't defined in `{alias}`"),
+            )),
+        }
+    }
+
+    /// Writes statements that return the value of `expr`.
+    fn returns(&mut self, expr: &Expr<'i>, tail: bool) -> Result<(), Diagnostic> {
+        match expr {
+            Expr::Block(block) => self.block(block, tail),
+            Expr::Match(matching) => self.matching(matching, tail),
+            _ => {
+                self.newline();
+                self.write("return ");
+                self.expr(expr, tail)?;
+                self.write(";");
+                Ok(())
+            }
+        }
+    }
+
+    /// Writes the defs of `block` as `const`s and returns its expression.
+    fn block(&mut self, block: &expr::Block<'i>, tail: bool) -> Result<(), Diagnostic> {
+        let body = block.expr.as_deref().ok_or_else(|| {
+            Diagnostic::new(block.span.clone(), "a block must end with an expression")
+        })?;
+        let depth = self.scope.len();
+        let result = self
+            .defs(&block.defs, None)
+            .and_then(|_| self.returns(body, tail));
+        self.scope.truncate(depth);
+        result
+    }
+
+    /// Writes `matching` as an `if` for each arm, up to the first that
+    /// matches anything, that returns its body, followed by throwing that no
+    /// arm matches if none does.
+    fn matching(&mut self, matching: &expr::Match<'i>, tail: bool) -> Result<(), Diagnostic> {
+        let value = self.fresh("$value");
+        self.newline();
+        self.mark(&matching.span);
+        self.write(&format!("const {value} = "));
+        self.expr(&matching.scrutinee, false)?;
+        self.write(";");
+        for arm in matching.arms.iter() {
+            let depth = self.scope.len();
+            let (mut tests, mut bound) = (Vec::new(), Vec::new());
+            let result = self
+                .pattern(&arm.pattern, &value, &mut tests, &mut bound)
+                .and_then(|_| {
+                    self.newline();
+                    self.mark(&arm.pattern.span());
+                    if tests.is_empty() {
+                        self.write("{");
+                    } else {
+                        self.write(&format!("if ({}) {{", tests.join(" && ")));
+                    }
+                    self.indent += 1;
+                    for (name, part) in &bound {
+                        self.newline();
+                        self.write(&format!("const {name} = {part};"));
+                    }
+                    let body = self.returns(&arm.body, tail);
+                    self.indent -= 1;
+                    self.newline();
+                    self.write("}");
+                    body
+                });
+            self.scope.truncate(depth);
+            result?;
+            if tests.is_empty() {
+                return Ok(());
+            }
+        }
+        self.newline();
+        self.write("return $noMatch();");
+        Ok(())
+    }
+
+    /// Adds the tests of whether `value` matches `pattern` to `tests`, in
+    /// the order they are made, and the parts of `value` the variables of
+    /// the pattern bind to `bound`, adding the variables to the scope. Each
+    /// field of a data value is only read once the tests before it have
+    /// passed.
+    fn pattern(
+        &mut self,
+        pattern: &Pattern<'i>,
+        value: &str,
+        tests: &mut Vec<String>,
+        bound: &mut Vec<(String, String)>,
+    ) -> Result<(), Diagnostic> {
+        match pattern {
+            Pattern::Wildcard(_) => {}
+            Pattern::Variable(name) => {
+                let var = self.fresh(name.0);
+                bound.push((var.clone(), value.to_owned()));
+                self.scope.push((name.0, Named::Var(var)));
+            }
+            Pattern::Int(int) => tests.push(format!("$isInt({value}, {}n)", int.0)),
+            Pattern::Constructor(pattern) => {
+                let name = &pattern.name;
+                let Some(Named::Constructor(constructor)) = self.lookup(name.0) else {
+                    return Err(Diagnostic::new(
+                        name.1.clone(),
+                        format!("`{}` isn't a constructor", name.0),
+                    ));
+                };
+                tests.push(format!("$is({value}, {constructor})"));
+                for (index, arg) in pattern.args.iter().enumerate() {
+                    self.pattern(arg, &format!("{value}.fields[{index}]"), tests, bound)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::process::Command;
+
+    use ria_parser::{loader::Loader, parse_module, temp_dir::TempDir};
+
+    use crate::{
+        bytecode::Evaluation, compile::compile_with_prelude, natives::Natives, prelude::prelude,
+        vm::run,
+    };
+
+    use super::{compile_loaded, compile_module, RUNTIME};
+
+    /// Compiles `source` with the prelude to `module.mjs`, and runs `script`
+    /// next to it with `node`, returning what it prints. Returns `None` if
+    /// there's no `node` to run it with.
+    fn run_node(test: &str, source: &str, script: &str) -> Option<String> {
+        let module = parse_module(source).unwrap();
+        let output = compile_module(&module, Some(&prelude())).unwrap();
+        run_code(test, source, &output.code, script)
+    }
+
+    fn run_code(test: &str, source: &str, code: &str, script: &str) -> Option<String> {
+        let dir = TempDir::new(
+            &format!("js-run-{test}"),
+            &[("module.mjs", code), ("script.mjs", script)],
+        );
+        let ran = Command::new("node").arg(dir.join("script.mjs")).output();
+        let ran = match ran {
+            Ok(ran) => ran,
+            Err(err) => {
+                eprintln!("skipping `{test}`: can't run node: {err}");
+                return None;
+            }
+        };
+        assert!(
+            ran.status.success(),
+            "node failed on `{source}`:\n{}",
+            String::from_utf8_lossy(&ran.stderr)
+        );
+        Some(String::from_utf8(ran.stdout).unwrap())
+    }
+
+    const LIST: &str = "type List a = Nil | Cons a (List a)
+range = \\n -> match n with
+  | 0 -> Nil
+  | _ -> Cons n (range (sub n 1))
+";
+
+    #[test]
+    fn match_the_vm() {
+        for (test, source) in [
+            ("arith", "main = sub (mul 6 7) (div 9 (mod 7 4))"),
+            ("church", "main = to_int (times (from_int 6) (from_int 7))"),
+            (
+                "fact",
+                "fact = \\n -> le n 1 (\\_ -> 1) (\\_ -> mul n (fact (sub n 1))) 0\nmain = fact 20",
+            ),
+            (
+                "sum",
+                "main = (sum = \\n -> \\acc -> eq n 0 (\\_ -> acc) (\\_ -> sum (sub n 1) (add acc n)) 0\n  \
+                 sum 1000000 0)",
+            ),
+            (
+                "shadow",
+                "id = \\x -> mul x 2\nnew = (x = 1\n  f = \\x -> (y = add x 1\n    id y)\n  f x)\nmain = new",
+            ),
+            ("twice", "twice = \\f -> \\x -> f (f x)\nmain = twice (twice (mul 3)) 1"),
+            ("list", &format!("{LIST}main = range 3")),
+            (
+                "map",
+                &format!(
+                    "{LIST}map = \\f -> \\list -> match list with\n  \
+                     | Nil -> Nil\n  \
+                     | Cons x rest -> Cons (f x) (map f rest)\n\
+                     main = (wrap = Cons 0\n  map (\\x -> wrap Nil) (range 2))"
+                ),
+            ),
+            (
+                "nested",
+                &format!(
+                    "{LIST}type Option a = None | Some a\n\
+                     second = \\list -> match list with\n  \
+                     | Cons _ (Cons x _) -> Some x\n  \
+                     | _ -> None\n\
+                     main = Cons (second (range 1)) (Cons (second (range 5)) Nil)"
+                ),
+            ),
+            (
+                "long",
+                &format!(
+                    "{LIST}sum = \\list -> \\acc -> match list with\n  \
+                     | Nil -> acc\n  \
+                     | Cons x rest -> sum rest (add acc x)\n\
+                     count = \\n -> \\list -> match n with\n  \
+                     | 0 -> list\n  \
+                     | _ -> count (sub n 1) (Cons n list)\n\
+                     main = sum (count 100000 Nil) 0"
+                ),
+            ),
+            (
+                "bound_match",
+                "main = (x = match 2 with\n    | 1 -> 10\n    | n -> mul n 100\n  add x 1)",
+            ),
+            (
+                "local_type",
+                // values built by one call match the patterns of another
+                "make = \\n -> (type Pair a b = Pair a b\n  \
+                 swap = \\p -> match p with\n    | Pair a b -> Pair b a\n  \
+                 match n with\n    | 0 -> Pair 1 2\n    | _ -> swap)\n\
+                 main = make 1 (make 0)",
+            ),
+            ("records", "main = (1, { a = 2, b = (3, 4) }, {})"),
+            (
+                "access",
+                "type Option a = None | Some a\n\
+                 swap = \\p -> (p.1, p.0)\n\
+                 point = { x = 1, y = Some 2 }\n\
+                 main = (p = swap (point.x, point)\n  p.0.y)",
+            ),
+        ] {
+            let module = parse_module(source).unwrap();
+            let program =
+                compile_with_prelude(&module, Evaluation::Strict, &Natives::new()).unwrap();
+            let expected = match run(&program) {
+                Ok(value) => format!("{}\n", value.display(&program)),
+                Err(err) => panic!("`{source}` failed: {err:?}"),
+            };
+            let script = "import { main } from \"./module.mjs\";\nconsole.log(String(main));\n";
+            let Some(printed) = run_node(test, source, script) else {
+                return;
+            };
+            assert_eq!(printed, expected, "running `{source}`");
+        }
+    }
+
+    #[test]
+    fn export_curried_functions() {
+        let source = "export (add3, answer, default, Pair)\n\
+                      type Pair a b = Pair a b | Hidden\n\
+                      add3 = \\a -> \\b -> \\c -> add a (add b c)\n\
+                      answer = 42\n\
+                      default = add3 1\n\
+                      hidden = 1";
+        let script = "import * as m from \"./module.mjs\";\n\
+                      console.log(m.add3(1n)(2n)(3n), m.add3(1)(2)(3), m.answer);\n\
+                      console.log(m.default(2)(3), String(m.Pair(1)(m.answer)));\n\
+                      console.log(Object.keys(m).join());\n";
+        let Some(printed) = run_node("exports", source, script) else {
+            return;
+        };
+        assert_eq!(
+            printed,
+            "6n 6n 42n\n6n Pair 1 42\nPair,add3,answer,default\n"
+        );
+    }
+
+    #[test]
+    fn throw_runtime_errors() {
+        for (test, source, message) in [
+            ("div", "main = div 1 0", "division by zero"),
+            (
+                "overflow",
+                "main = mul 4611686018427387904 2",
+                "integer overflow",
+            ),
+            (
+                "call",
+                "main = (f = 1\n  f 2)",
+                "expected a function, found an integer",
+            ),
+            (
+                "add",
+                "export (f)\nf = add id",
+                "expected an integer, found a function",
+            ),
+            (
+                "no_match",
+                "type A = A\ntype B = B\nf = \\x -> match x with\n  | A -> 1\nmain = f B",
+                "no pattern matches the value",
+            ),
+            (
+                "pattern",
+                "type A = A\nmain = match 1 with\n  | A -> 1",
+                "expected a data value, found an integer",
+            ),
+            (
+                "int_pattern",
+                "type A = A\nmain = match A with\n  | 1 -> 1\n  | _ -> 2",
+                "expected an integer, found a data value",
+            ),
+            (
+                "field",
+                "get = \\r -> r.b\nmain = get { a = 1 }",
+                "no field `b`",
+            ),
+            (
+                "item",
+                "third = \\t -> t.2\nmain = third (1, 2)",
+                "no field `2`",
+            ),
+            (
+                "tuple",
+                "second = \\t -> t.1\nmain = second { a = 1 }",
+                "expected a tuple, found a record",
+            ),
+            (
+                "call_tuple",
+                "main = (1, 2) 3",
+                "expected a function, found a tuple",
+            ),
+        ] {
+            let script = "try {\n  const m = await import(\"./module.mjs\");\n  m.f?.(1);\n} \
+                          catch (err) {\n  console.log(err.message);\n}\n";
+            let Some(printed) = run_node(test, source, script) else {
+                return;
+            };
+            assert_eq!(printed, format!("{message}\n"), "running `{source}`");
+        }
+    }
+
+    #[test]
+    fn compile_imported_modules() {
+        let dir = TempDir::new(
+            "js-imports",
+            &[
+                (
+                    "main.ria",
+                    "export (main)\nimport lib/m (Box)\n\
+                     main = match m.wrap (Box 2) with\n  | Box (Box n) -> (add n m.x, Box n)",
+                ),
+                (
+                    "lib/m.ria",
+                    "export (Box, wrap, x)\ntype Box a = Box a\nwrap = Box\nx = 40",
+                ),
+            ],
+        );
+        let mut loader = Loader::new();
+        let main = loader.load(dir.join("main.ria")).unwrap();
+
+        let output = compile_loaded(&loader, main, Some(&prelude())).unwrap();
+        let script = "import * as m from \"./module.mjs\";\n\
+                      console.log(String(m.main), Object.keys(m).join());\n";
+        let Some(printed) = run_code("imports", "main.ria", &output.code, script) else {
+            return;
+        };
+        assert_eq!(printed, "(42, Box 2) main\n");
+
+        let module = &loader.module(main).module;
+        let err = compile_module(module, None).unwrap_err();
+        assert_eq!(err.message, "imports can only be compiled from files");
+    }
+
+    #[test]
+    fn emit_matches_as_ifs() {
+        let source = "type Option a = None | Some a\n\
+                      get = \\o -> match o with\n  | Some 0 -> 1\n  | Some n -> n\n  | None -> 0";
+        let module = parse_module(source).unwrap();
+        let output = compile_module(&module, None).unwrap();
+        assert_eq!(
+            output.code.strip_prefix(RUNTIME).unwrap(),
+            "
+const None = $constructor(0, \"None\", 0);
+const Some = $constructor(1, \"Some\", 1);
+const get = (o) => {
+  const $value = o;
+  if ($is($value, Some) && $isInt($value.fields[0], 0n)) {
+    return 1n;
+  }
+  if ($is($value, Some)) {
+    const n = $value.fields[0];
+    return n;
+  }
+  if ($is($value, None)) {
+    return 0n;
+  }
+  return $noMatch();
+};
+const None$export = $export(None);
+const Some$export = $export(Some);
+const get$export = $export(get);
+export { None$export as None, Some$export as Some, get$export as get };
+"
+        );
+    }
+
+    #[test]
+    fn emit_consts_and_tail_calls() {
+        let source = "export (main)\n\
+                      f = \\n -> (m = mul n 2\n  add m 1)\n\
+                      main = (x = f 3\n  f x)";
+        let module = parse_module(source).unwrap();
+        let output = compile_module(&module, None).unwrap();
+        assert_eq!(
+            output.code.strip_prefix(RUNTIME).unwrap(),
+            "
+const f = (n) => {
+  const m = $call($call($mul, n), 2n);
+  return new $Tail($call($add, m), 1n);
+};
+const main = (() => {
+  const x = $call(f, 3n);
+  return $call(f, x);
+})();
+const main$export = $export(main);
+export { main$export as main };
+"
+        );
+    }
+
+    #[test]
+    fn map_code_to_its_source() {
+        let source = "main = add 1 2";
+        let module = parse_module(source).unwrap();
+        let output = compile_module(&module, None).unwrap();
+        // the module starts after a blank line
+        let line = RUNTIME.lines().count() as u32 + 1;
+        let marked: Vec<_> = output
+            .mappings
+            .iter()
+            .map(|mapping| (mapping.line - line, mapping.column, mapping.source))
+            .collect();
+        // `main`, the calls, `add`, 1, 2 and then the export of `main`
+        assert_eq!(
+            marked,
+            [
+                (0, 0, 0),
+                (0, 13, 7),
+                (0, 19, 7),
+                (0, 25, 7),
+                (0, 31, 11),
+                (0, 36, 13),
+                (1, 0, 0)
+            ]
+        );
+        assert!(output
+            .source_map("main.ria", source)
+            .contains(r#""sources":["main.ria"],"sourcesContent":["main = add 1 2"]"#));
+    }
+}
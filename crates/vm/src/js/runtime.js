@@ -0,0 +1,241 @@
+// The runtime of a ria module compiled to JavaScript.
+//
+// Integers are BigInts that must fit in 64 bits, and every function takes
+// one argument. A call in tail position returns a `$Tail` instead of making
+// the call, and the `$call` that called the function makes it, so loops don't
+// grow the stack. Exported defs are wrapped to make their calls to the end,
+// and to take numbers as well as BigInts from JavaScript.
+//
+// Data values, tuples and records are objects of their own classes, which
+// turn into strings the way the VM displays them. A data value holds the id
+// of its constructor, which tells it apart from any other constructor,
+// however many times the code defining it runs.
+
+const $MIN = -(2n ** 63n);
+const $MAX = 2n ** 63n - 1n;
+
+class $Tail {
+  constructor(func, arg) {
+    this.func = func;
+    this.arg = arg;
+  }
+}
+
+class $Data {
+  constructor(id, name, fields) {
+    this.id = id;
+    this.name = name;
+    this.fields = fields;
+  }
+
+  toString() {
+    return $display(this);
+  }
+}
+
+class $Tuple {
+  constructor(items) {
+    this.items = items;
+  }
+
+  toString() {
+    return $display(this);
+  }
+}
+
+class $Record {
+  // the fields are `[name, value]` pairs, in the order they're written
+  constructor(fields) {
+    this.fields = fields;
+  }
+
+  toString() {
+    return $display(this);
+  }
+}
+
+const $typeName = (value) => {
+  if (typeof value === "bigint") {
+    return "an integer";
+  } else if (value instanceof $Data) {
+    return "a data value";
+  } else if (value instanceof $Tuple) {
+    return "a tuple";
+  } else if (value instanceof $Record) {
+    return "a record";
+  }
+  return "a function";
+};
+
+const $typeError = (expected, value) =>
+  new Error(`expected ${expected}, found ${$typeName(value)}`);
+
+// Returns the value of a constructor: a curried function of its fields that
+// builds a data value, or the data value itself if it has none. Either has
+// the constructor's id.
+const $constructor = (id, name, arity) => {
+  const build = (fields) =>
+    fields.length === arity
+      ? new $Data(id, name, fields)
+      : Object.assign((arg) => build([...fields, arg]), { id });
+  return build([]);
+};
+
+const $is = (value, constructor) => {
+  if (!(value instanceof $Data)) {
+    throw $typeError("a data value", value);
+  }
+  return value.id === constructor.id;
+};
+
+const $isInt = (value, int) => {
+  if (typeof value !== "bigint") {
+    throw $typeError("an integer", value);
+  }
+  return value === int;
+};
+
+const $noMatch = () => {
+  throw new Error("no pattern matches the value");
+};
+
+const $field = (value, name) => {
+  if (!(value instanceof $Record)) {
+    throw $typeError("a record", value);
+  }
+  const field = value.fields.find(([field]) => field === name);
+  if (!field) {
+    throw new Error(`no field \`${name}\``);
+  }
+  return field[1];
+};
+
+const $item = (value, index) => {
+  if (!(value instanceof $Tuple)) {
+    throw $typeError("a tuple", value);
+  }
+  if (index >= value.items.length) {
+    throw new Error(`no field \`${index}\``);
+  }
+  return value.items[index];
+};
+
+// Data values can be as deep as a long list, so the pieces of one are
+// written from a stack rather than by recursion. A piece is text, or a value
+// and whether it's a field of a data value, which is in parentheses if it
+// has fields of its own.
+const $display = (value) => {
+  let out = "";
+  const pieces = [[value, false]];
+  while (pieces.length > 0) {
+    const piece = pieces.pop();
+    if (typeof piece === "string") {
+      out += piece;
+      continue;
+    }
+    const [value, field] = piece;
+    if (typeof value === "bigint") {
+      out += String(value);
+    } else if (value instanceof $Data) {
+      if (value.fields.length > 0 && field) {
+        out += "(";
+        pieces.push(")");
+      }
+      out += value.name;
+      for (let i = value.fields.length - 1; i >= 0; i--) {
+        pieces.push([value.fields[i], true], " ");
+      }
+    } else if (value instanceof $Tuple) {
+      out += "(";
+      pieces.push(")");
+      for (let i = value.items.length - 1; i >= 0; i--) {
+        pieces.push([value.items[i], false]);
+        if (i > 0) {
+          pieces.push(", ");
+        }
+      }
+    } else if (value instanceof $Record) {
+      if (value.fields.length === 0) {
+        out += "{}";
+        continue;
+      }
+      out += "{ ";
+      pieces.push(" }");
+      for (let i = value.fields.length - 1; i >= 0; i--) {
+        const [name, field] = value.fields[i];
+        pieces.push([field, false], " = ", name);
+        if (i > 0) {
+          pieces.push(", ");
+        }
+      }
+    } else {
+      out += "<function>";
+    }
+  }
+  return out;
+};
+
+const $apply = (func, arg) => {
+  if (typeof func !== "function") {
+    throw $typeError("a function", func);
+  }
+  return func(arg);
+};
+
+const $call = (func, arg) => {
+  let result = $apply(func, arg);
+  while (result instanceof $Tail) {
+    result = $apply(result.func, result.arg);
+  }
+  return result;
+};
+
+const $export = (value) =>
+  typeof value === "function"
+    ? (arg) => $export($call(value, typeof arg === "number" ? BigInt(arg) : arg))
+    : value;
+
+const $int = (value) => {
+  if (typeof value !== "bigint") {
+    throw $typeError("an integer", value);
+  }
+  return value;
+};
+
+const $checked = (int) => {
+  if (int < $MIN || int > $MAX) {
+    throw new Error("integer overflow");
+  }
+  return int;
+};
+
+const $divisor = (a, b) => {
+  if (b === 0n) {
+    throw new Error("division by zero");
+  }
+  if (a === $MIN && b === -1n) {
+    throw new Error("integer overflow");
+  }
+};
+
+const $true = (a) => (_) => a;
+const $false = (_) => (b) => b;
+const $bool = (value) => (value ? $true : $false);
+
+const $add = (a) => (b) => $checked($int(a) + $int(b));
+const $sub = (a) => (b) => $checked($int(a) - $int(b));
+const $mul = (a) => (b) => $checked($int(a) * $int(b));
+const $div = (a) => (b) => {
+  $divisor($int(a), $int(b));
+  return a / b;
+};
+const $mod = (a) => (b) => {
+  $divisor($int(a), $int(b));
+  return a % b;
+};
+const $eq = (a) => (b) => $bool($int(a) === $int(b));
+const $ne = (a) => (b) => $bool($int(a) !== $int(b));
+const $lt = (a) => (b) => $bool($int(a) < $int(b));
+const $le = (a) => (b) => $bool($int(a) <= $int(b));
+const $gt = (a) => (b) => $bool($int(a) > $int(b));
+const $ge = (a) => (b) => $bool($int(a) >= $int(b));
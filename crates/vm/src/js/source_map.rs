@@ -0,0 +1,203 @@
+//! Source maps, which tie positions in generated code to positions in the
+//! source it came from.
+//!
+//! A map is JSON in the [version 3 format], whose `mappings` encode each
+//! position relative to the one before it as base64 variable-length
+//! quantities. Columns count UTF-16 code units, like JavaScript strings.
+//!
+//! [version 3 format]: https://tc39.es/ecma426/
+
+use std::fmt::Write as _;
+
+/// A position in generated code that came from a position in the source.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Mapping {
+    /// The line in the generated code, from 0.
+    pub line: u32,
+    /// The column in the generated code, from 0.
+    pub column: u32,
+    /// The byte offset in the source.
+    pub source: usize,
+}
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Returns the source map of `mappings`, which must be in the order of the
+/// generated code, into `source`, which is called `name`.
+pub fn source_map(mappings: &[Mapping], name: &str, source: &str) -> String {
+    let line_starts: Vec<usize> = std::iter::once(0)
+        .chain(source.match_indices('\n').map(|(index, _)| index + 1))
+        .collect();
+    // how many more bytes than UTF-16 code units the source has up to the
+    // end of each character that isn't ASCII, so that finding a column
+    // doesn't count the units of a long line over again for each mapping
+    let mut wide = Vec::new();
+    let mut excess = 0;
+    for (index, c) in source.char_indices().filter(|(_, c)| !c.is_ascii()) {
+        excess += c.len_utf8() - c.len_utf16();
+        wide.push((index + c.len_utf8(), excess));
+    }
+    let utf16 = |offset: usize| match wide.partition_point(|&(end, _)| end <= offset) {
+        0 => offset,
+        after => offset - wide[after - 1].1,
+    };
+    let position = |offset: usize| {
+        let line = line_starts.partition_point(|&start| start <= offset) - 1;
+        let column = utf16(offset) - utf16(line_starts[line]);
+        (line as i64, column as i64)
+    };
+
+    let mut encoded = String::new();
+    let (mut line, mut column) = (0, 0);
+    let (mut source_line, mut source_column) = (0, 0);
+    for mapping in mappings {
+        if mapping.line > line {
+            for _ in line..mapping.line {
+                encoded.push(';');
+            }
+            line = mapping.line;
+            column = 0;
+        } else if !encoded.is_empty() && !encoded.ends_with(';') {
+            encoded.push(',');
+        }
+        let (next_line, next_column) = position(mapping.source);
+        // the generated column, the index of the source, and its line and
+        // column, each relative to the last
+        for field in [
+            i64::from(mapping.column) - column,
+            0,
+            next_line - source_line,
+            next_column - source_column,
+        ] {
+            vlq(field, &mut encoded);
+        }
+        column = i64::from(mapping.column);
+        (source_line, source_column) = (next_line, next_column);
+    }
+
+    format!(
+        r#"{{"version":3,"sources":[{}],"sourcesContent":[{}],"names":[],"mappings":"{encoded}"}}"#,
+        json_string(name),
+        json_string(source)
+    )
+}
+
+/// Appends `value` as a base64 variable-length quantity: five bits to a
+/// digit, least significant first, with the sign in the lowest bit.
+fn vlq(value: i64, out: &mut String) {
+    let mut bits = (value.unsigned_abs() << 1) | u64::from(value < 0);
+    loop {
+        let digit = (bits & 0b11111) as usize;
+        bits >>= 5;
+        let more = if bits == 0 { 0 } else { 0b100000 };
+        out.push(BASE64[digit | more] as char);
+        if bits == 0 {
+            return;
+        }
+    }
+}
+
+/// Returns `bytes` in base64, padded with `=`.
+pub fn base64(bytes: &[u8]) -> String {
+    let mut out = String::new();
+    for chunk in bytes.chunks(3) {
+        let bits = chunk.iter().enumerate().fold(0u32, |bits, (index, &byte)| {
+            bits | (u32::from(byte) << (16 - 8 * index))
+        });
+        for index in 0..4 {
+            if index <= chunk.len() {
+                out.push(BASE64[((bits >> (18 - 6 * index)) & 0b111111) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+fn json_string(text: &str) -> String {
+    let mut out = String::from('"');
+    for c in text.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c < ' ' => write!(out, "\\u{:04x}", c as u32).unwrap(),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::{base64, source_map, vlq, Mapping};
+
+    #[test]
+    fn encode_quantities() {
+        for (value, expected) in [
+            (0, "A"),
+            (1, "C"),
+            (-1, "D"),
+            (15, "e"),
+            (16, "gB"),
+            (-1000, "x+B"),
+        ] {
+            let mut out = String::new();
+            vlq(value, &mut out);
+            assert_eq!(out, expected, "encoding {value}");
+        }
+        assert_eq!(base64(b"ria"), "cmlh");
+        assert_eq!(base64(b"ria!"), "cmlhIQ==");
+        assert_eq!(base64(b"ria!?"), "cmlhIT8=");
+    }
+
+    #[test]
+    fn map_positions_relative_to_the_last() {
+        let source = "a = 1\nb = \"é\" c\n";
+        let mappings = [
+            Mapping {
+                line: 0,
+                column: 6,
+                source: 0,
+            },
+            Mapping {
+                line: 0,
+                column: 16,
+                source: 4,
+            },
+            Mapping {
+                line: 2,
+                column: 6,
+                source: 6,
+            },
+            Mapping {
+                line: 2,
+                column: 8,
+                source: 15,
+            },
+        ];
+        assert_eq!(
+            source_map(&mappings, "a.ria", source),
+            r#"{"version":3,"sources":["a.ria"],"sourcesContent":["a = 1\nb = \"é\" c\n"],"names":[],"mappings":"MAAA,UAAI;;MACJ,EAAQ"}"#
+        );
+    }
+
+    #[test]
+    fn map_long_lines() {
+        let source = "é".repeat(100_000);
+        let mappings: Vec<_> = (0..100_000)
+            .map(|index| Mapping {
+                line: 0,
+                column: index,
+                source: 2 * index as usize,
+            })
+            .collect();
+        let map = source_map(&mappings, "a.ria", &source);
+        let expected = format!("\"mappings\":\"AAAA{}\"}}", ",CAAC".repeat(99_999));
+        assert!(map.ends_with(&expected));
+    }
+}
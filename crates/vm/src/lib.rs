@@ -0,0 +1,70 @@
+//! Compiling ria modules to bytecode and running them.
+//!
+//! [`compile`] turns a resolved [`Module`](ria_parser::module::Module) into a
+//! [`Program`]: one function per lambda, with the variables a lambda uses
+//! from enclosing functions copied into captured slots when its closure is
+//! created, and the defs of each block in local slots of the enclosing
+//! function. A [`Vm`] runs the program on an operand stack, replacing the
+//! current frame for calls in tail position.
+//!
+//! Every program can use the [`builtins`]: integer arithmetic and
+//! comparisons, and the Church booleans the comparisons return. Programs
+//! compiled with [`compile_with_prelude`] can also use the defs of the
+//! [`prelude`]. [`compile_loaded`] compiles a file together with the files it
+//! imports, loaded with a [`Loader`](ria_parser::loader::Loader), and can
+//! [`simplify`] them first. The [`ski`] backend compiles a module to
+//! combinators instead, for a machine that reduces them as a graph, and
+//! [`ir`] lowers it to the A-normal form, continuation-passing style and
+//! lifted functions that other backends start from, like [`c`], which
+//! compiles it to a C program. [`js`] compiles a module to a JavaScript
+//! module with a source map.
+//!
+//! Hosts can make Rust functions callable from ria by registering them as
+//! [`Natives`], and call ria defs from Rust:
+//!
+//! ```
+//! use std::rc::Rc;
+//!
+//! use ria_parser::parse_module;
+//! use ria_vm::{compile_with_natives, Error, Evaluation, FromValue, IntoValue, Natives, Vm};
+//!
+//! let mut natives = Natives::new();
+//! natives.register("greet", 1, |args| {
+//!     let name = Rc::<String>::from_value(args[0].clone())?;
+//!     Ok(Rc::new(format!("hello, {name}")).into_value())
+//! });
+//!
+//! let module = parse_module("welcome = \\name -> greet name").unwrap();
+//! let program = compile_with_natives(&module, Evaluation::Strict, &natives).unwrap();
+//! let mut vm = Vm::new(&program);
+//! vm.init()?;
+//!
+//! let name = Rc::new(String::from("ria")).into_value();
+//! let greeting: Rc<String> = vm.call_global("welcome", [name])?;
+//! assert_eq!(*greeting, "hello, ria");
+//! # Ok::<(), Error>(())
+//! ```
+
+pub mod builtins;
+pub mod bytecode;
+pub mod c;
+pub mod compile;
+pub mod disasm;
+pub mod interp;
+pub mod ir;
+pub mod js;
+pub mod natives;
+pub mod prelude;
+pub mod simplify;
+pub mod ski;
+pub mod vm;
+
+pub use self::{
+    builtins::builtins,
+    bytecode::{Evaluation, Program},
+    compile::{compile, compile_loaded, compile_with_natives, compile_with_prelude},
+    disasm::disassemble,
+    natives::{FromValue, IntoValue, Natives},
+    simplify::{simplify_module, Passes},
+    vm::{run, run_with_limits, Error, ErrorKind, Limits, Value, Vm},
+};
@@ -0,0 +1,333 @@
+//! Native functions and conversions between Rust values and ria values.
+
+use std::{any::Any, fmt, rc::Rc};
+
+use crate::vm::{Error, ErrorKind, Value};
+
+type DynNativeFn = dyn Fn(&[Value]) -> Result<Value, Error>;
+
+/// The Rust function behind a [`Native`].
+///
+/// The function gets exactly as many arguments as the native's arity, each
+/// already evaluated unless the native is lazy. The span of an error it
+/// returns is replaced with the span of the call that reached it.
+#[derive(Clone)]
+pub struct NativeFn(Repr);
+
+#[derive(Clone)]
+enum Repr {
+    Rust(Rc<DynNativeFn>),
+    Intrinsic(Intrinsic),
+}
+
+/// A builtin that the VM applies itself, since it needs more of the program
+/// than its arguments.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Intrinsic {
+    /// Compares two values of any type, see [`Vm::equal`](crate::vm::Vm::equal).
+    AssertEq,
+}
+
+impl NativeFn {
+    /// Calls the function. An intrinsic fails, since only the VM can apply
+    /// it.
+    pub fn call(&self, args: &[Value]) -> Result<Value, Error> {
+        match &self.0 {
+            Repr::Rust(func) => func(args),
+            Repr::Intrinsic(_) => Err(Error::native("only the VM can apply this native")),
+        }
+    }
+
+    pub(crate) fn intrinsic(&self) -> Option<Intrinsic> {
+        match self.0 {
+            Repr::Intrinsic(intrinsic) => Some(intrinsic),
+            Repr::Rust(_) => None,
+        }
+    }
+}
+
+impl fmt::Debug for NativeFn {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Repr::Rust(_) => f.write_str("NativeFn"),
+            Repr::Intrinsic(intrinsic) => write!(f, "NativeFn({intrinsic:?})"),
+        }
+    }
+}
+
+impl PartialEq for NativeFn {
+    fn eq(&self, other: &Self) -> bool {
+        match (&self.0, &other.0) {
+            (Repr::Rust(a), Repr::Rust(b)) => Rc::ptr_eq(a, b),
+            (Repr::Intrinsic(a), Repr::Intrinsic(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+impl Eq for NativeFn {}
+
+/// A Rust function that ria code can call by name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Native {
+    pub name: String,
+    /// The number of arguments the function takes. Calls with fewer
+    /// arguments return a partial application.
+    pub arity: usize,
+    /// Whether the arguments are evaluated before the function gets them.
+    /// A lazy native may get thunks, which it can only pass on.
+    pub strict: bool,
+    pub func: NativeFn,
+}
+
+/// The natives a program is compiled against.
+#[derive(Debug, Clone, Default)]
+pub struct Natives {
+    natives: Vec<Native>,
+}
+
+impl Natives {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers `func` under `name`, replacing any native with that name.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `arity` is 0, since every ria function takes an argument.
+    pub fn register<F>(&mut self, name: &str, arity: usize, func: F) -> &mut Self
+    where
+        F: Fn(&[Value]) -> Result<Value, Error> + 'static,
+    {
+        self.register_native(name, arity, true, func)
+    }
+
+    /// Like [`register`](Self::register), but the arguments are passed to
+    /// `func` without evaluating them, so a lazy program only evaluates the
+    /// ones the value `func` returns needs.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `arity` is 0, since every ria function takes an argument.
+    pub fn register_lazy<F>(&mut self, name: &str, arity: usize, func: F) -> &mut Self
+    where
+        F: Fn(&[Value]) -> Result<Value, Error> + 'static,
+    {
+        self.register_native(name, arity, false, func)
+    }
+
+    fn register_native<F>(&mut self, name: &str, arity: usize, strict: bool, func: F) -> &mut Self
+    where
+        F: Fn(&[Value]) -> Result<Value, Error> + 'static,
+    {
+        assert!(arity > 0, "natives take at least one argument");
+        self.insert(Native {
+            name: name.to_owned(),
+            arity,
+            strict,
+            func: NativeFn(Repr::Rust(Rc::new(func))),
+        });
+        self
+    }
+
+    /// Registers an intrinsic under `name`, with strict arguments.
+    pub(crate) fn register_intrinsic(
+        &mut self,
+        name: &str,
+        arity: usize,
+        intrinsic: Intrinsic,
+    ) -> &mut Self {
+        self.insert(Native {
+            name: name.to_owned(),
+            arity,
+            strict: true,
+            func: NativeFn(Repr::Intrinsic(intrinsic)),
+        });
+        self
+    }
+
+    /// Adds `native`, replacing any native with the same name in place, so
+    /// the indices of the other natives don't change.
+    pub(crate) fn insert(&mut self, native: Native) {
+        match self.natives.iter_mut().find(|n| n.name == native.name) {
+            Some(existing) => *existing = native,
+            None => self.natives.push(native),
+        }
+    }
+
+    /// Returns the registered names, in registration order.
+    pub fn names(&self) -> Vec<&str> {
+        self.natives
+            .iter()
+            .map(|native| native.name.as_str())
+            .collect()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Native> {
+        self.natives.iter().find(|native| native.name == name)
+    }
+
+    /// Returns the index of the native called `name`.
+    pub(crate) fn index(&self, name: &str) -> Option<u32> {
+        self.natives
+            .iter()
+            .position(|native| native.name == name)
+            .map(|index| index as u32)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Native> {
+        self.natives.iter()
+    }
+}
+
+/// Converts a Rust value into a ria value.
+pub trait IntoValue {
+    fn into_value(self) -> Value;
+}
+
+/// Converts a ria value into a Rust value, failing with
+/// [`ErrorKind::Type`] if the value has the wrong type.
+pub trait FromValue: Sized {
+    fn from_value(value: Value) -> Result<Self, Error>;
+}
+
+impl IntoValue for Value {
+    fn into_value(self) -> Value {
+        self
+    }
+}
+
+impl FromValue for Value {
+    fn from_value(value: Value) -> Result<Self, Error> {
+        Ok(value)
+    }
+}
+
+impl IntoValue for i64 {
+    fn into_value(self) -> Value {
+        Value::Int(self)
+    }
+}
+
+impl FromValue for i64 {
+    fn from_value(value: Value) -> Result<Self, Error> {
+        match value {
+            Value::Int(int) => Ok(int),
+            value => Err(type_error("an integer", value.type_name())),
+        }
+    }
+}
+
+/// Any Rust value can be passed through ria code as an opaque host value.
+impl<T: Any> IntoValue for Rc<T> {
+    fn into_value(self) -> Value {
+        Value::Host(self)
+    }
+}
+
+impl<T: Any> FromValue for Rc<T> {
+    fn from_value(value: Value) -> Result<Self, Error> {
+        let found = value.type_name();
+        match value {
+            Value::Host(host) => host
+                .downcast()
+                .map_err(|_| type_error(std::any::type_name::<T>(), found)),
+            _ => Err(type_error("a host value", found)),
+        }
+    }
+}
+
+/// Returns an error for a value of type `found` where `expected` was needed.
+pub fn type_error(expected: &'static str, found: &'static str) -> Error {
+    Error::new(ErrorKind::Type { expected, found })
+}
+
+#[cfg(test)]
+mod test {
+    use std::rc::Rc;
+
+    use ria_parser::parse_module;
+
+    use crate::{
+        bytecode::Evaluation,
+        compile::{compile, compile_with_natives},
+        vm::Vm,
+        ErrorKind,
+    };
+
+    use super::{FromValue, IntoValue, Natives};
+
+    /// A host type passed through ria code.
+    #[derive(Debug, PartialEq)]
+    struct Config {
+        name: &'static str,
+        port: u16,
+    }
+
+    fn natives() -> Natives {
+        let mut natives = Natives::new();
+        natives
+            .register("port", 1, |args| {
+                let config = Rc::<Config>::from_value(args[0].clone())?;
+                Ok(Rc::new(config.port).into_value())
+            })
+            .register("pick", 2, |args| {
+                let a = Rc::<Config>::from_value(args[0].clone())?;
+                let b = Rc::<Config>::from_value(args[1].clone())?;
+                Ok(if a.port > b.port { a } else { b }.into_value())
+            });
+        natives
+    }
+
+    #[test]
+    fn call_natives_and_defs_from_the_host() {
+        let natives = natives();
+        let module = parse_module("choose = \\a -> \\b -> pick a b\nport_of = port").unwrap();
+
+        for evaluation in [Evaluation::Strict, Evaluation::Lazy] {
+            let program = compile_with_natives(&module, evaluation, &natives).unwrap();
+            let mut vm = Vm::new(&program);
+            vm.init().unwrap();
+
+            let a = Rc::new(Config {
+                name: "a",
+                port: 80,
+            });
+            let b = Rc::new(Config {
+                name: "b",
+                port: 8080,
+            });
+            let chosen: Rc<Config> = vm
+                .call_global("choose", [a.into_value(), b.clone().into_value()])
+                .unwrap();
+            assert_eq!(chosen.name, "b");
+
+            let port: Rc<u16> = vm.call_global("port_of", [b.into_value()]).unwrap();
+            assert_eq!(*port, 8080);
+        }
+    }
+
+    #[test]
+    fn report_type_errors_at_the_call() {
+        let source = "id = \\x -> x\nmain = port id";
+        let module = parse_module(source).unwrap();
+        let program = compile_with_natives(&module, Evaluation::Strict, &natives()).unwrap();
+        let err = crate::run(&program).unwrap_err();
+        assert_eq!(
+            err.kind,
+            ErrorKind::Type {
+                expected: "a host value",
+                found: "a function"
+            }
+        );
+        assert_eq!(&source[err.span], "port id");
+    }
+
+    #[test]
+    fn report_unknown_natives() {
+        let module = parse_module("main = port").unwrap();
+        let errors = compile(&module, Evaluation::Strict).unwrap_err();
+        assert_eq!(errors[0].message, "cannot find `port` in this scope");
+    }
+}
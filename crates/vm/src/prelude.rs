@@ -0,0 +1,196 @@
+//! The standard prelude: common combinators, Church booleans, pairs and
+//! numerals, written in ria.
+//!
+//! Programs compiled with [`compile_with_prelude`](crate::compile_with_prelude)
+//! can use the prelude's defs without defining them, and defs of the same
+//! name shadow them. The booleans are the [`builtins`](crate::builtins)
+//! `true` and `false` rather than defs, since the comparison builtins return
+//! them, with or without the prelude.
+
+use ria_parser::{module::Module, parse_module};
+
+/// The source of the prelude.
+pub const SOURCE: &str = include_str!("prelude.ria");
+
+/// Returns the parsed prelude.
+pub fn prelude() -> Module<'static> {
+    parse_module(SOURCE).expect("the prelude parses")
+}
+
+/// Returns the names the prelude defines.
+pub fn names() -> Vec<&'static str> {
+    prelude()
+        .defs()
+        .defs
+        .iter()
+        .map(|def| def.ident.0)
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use ria_parser::{parse_module, resolve::resolve_with_builtins};
+
+    use crate::{
+        builtins::builtins,
+        bytecode::Evaluation,
+        compile::{compile, compile_with_prelude},
+        natives::Natives,
+        vm::{run, ErrorKind, Value},
+    };
+
+    use super::{names, prelude};
+
+    /// Runs `main` with the prelude in both evaluation modes, checking that
+    /// they agree.
+    fn run_main(source: &str) -> i64 {
+        let module = parse_module(source).unwrap();
+        let results: Vec<_> = [Evaluation::Strict, Evaluation::Lazy]
+            .into_iter()
+            .map(|evaluation| {
+                let program = compile_with_prelude(&module, evaluation, &Natives::new()).unwrap();
+                match run(&program) {
+                    Ok(Value::Int(int)) => int,
+                    result => panic!("`{source}` evaluated to {result:?}"),
+                }
+            })
+            .collect();
+        assert_eq!(results[0], results[1], "strict and lazy results differ");
+        results[0]
+    }
+
+    #[test]
+    fn resolve_prelude() {
+        let builtins = builtins();
+        let resolution = resolve_with_builtins(&prelude(), &builtins.names());
+        assert_eq!(resolution.errors, []);
+    }
+
+    #[test]
+    fn evaluate_every_def() {
+        // each def of the prelude, and a program using it
+        let cases = [
+            ("id", "main = id 1", 1),
+            ("const", "main = const 1 2", 1),
+            ("flip", "main = flip sub 1 10", 9),
+            (
+                "compose",
+                "main = (f = mul 2\n  g = add 3\n  compose f g 1)",
+                8,
+            ),
+            (
+                "fix",
+                "fact = \\rec -> \\n -> (base = \\_ -> 1
+  step = \\_ -> (m = sub n 1
+    r = rec m
+    mul n r)
+  branch = eq n 0 base step
+  branch n)
+main = fix fact 5",
+                120,
+            ),
+            ("not", "main = (b = lt 1 2\n  not b 1 0)", 0),
+            ("and", "main = (a = lt 1 2\n  b = lt 2 1\n  and a b 1 0)", 0),
+            ("or", "main = (a = lt 1 2\n  b = lt 2 1\n  or a b 1 0)", 1),
+            ("pair", "main = (p = pair 1 2\n  p sub)", -1),
+            ("fst", "main = (p = pair 1 2\n  fst p)", 1),
+            ("snd", "main = (p = pair 1 2\n  snd p)", 2),
+            ("zero", "main = to_int zero", 0),
+            ("succ", "main = (one = succ zero\n  to_int one)", 1),
+            ("is_zero", "main = (b = is_zero zero\n  b 1 0)", 1),
+            (
+                "plus",
+                "main = (a = from_int 2\n  b = from_int 3\n  n = plus a b\n  to_int n)",
+                5,
+            ),
+            (
+                "times",
+                "main = (a = from_int 2\n  b = from_int 3\n  n = times a b\n  to_int n)",
+                6,
+            ),
+            (
+                "pred",
+                "main = (a = from_int 7\n  n = pred a\n  to_int n)",
+                6,
+            ),
+            (
+                "minus",
+                "main = (a = from_int 7\n  b = from_int 3\n  n = minus a b\n  to_int n)",
+                4,
+            ),
+            ("to_int", "main = (n = from_int 4\n  to_int n)", 4),
+            ("from_int", "main = (n = from_int 0\n  to_int n)", 0),
+        ];
+
+        let tested: Vec<_> = cases.iter().map(|(name, ..)| *name).collect();
+        assert_eq!(tested, names(), "every prelude def is tested, in order");
+
+        for (name, source, expected) in cases {
+            assert_eq!(run_main(source), expected, "testing `{name}`");
+        }
+    }
+
+    #[test]
+    fn evaluate_builtin_booleans() {
+        // the prelude's boolean defs take the booleans comparisons return
+        for (source, expected) in [
+            ("main = true 1 0", 1),
+            ("main = false 1 0", 0),
+            ("main = (b = not true\n  b 1 0)", 0),
+            ("main = (b = eq 1 1\n  c = and b true\n  c 1 0)", 1),
+        ] {
+            assert_eq!(run_main(source), expected, "running `{source}`");
+        }
+
+        // and they're there without it
+        let module = parse_module("main = (b = lt 1 2\n  t = b true false\n  t 1 0)").unwrap();
+        let program = compile(&module, Evaluation::Strict).unwrap();
+        assert!(matches!(run(&program), Ok(Value::Int(1))));
+    }
+
+    #[test]
+    fn shadow_prelude_defs() {
+        // the prelude's `plus` still uses its own `succ`
+        let source = "succ = \\n -> n
+main = (a = from_int 2
+  b = plus a a
+  x = to_int b
+  y = succ 7
+  add x y)";
+        assert_eq!(run_main(source), 11);
+    }
+
+    #[test]
+    fn compile_only_used_defs() {
+        let module = parse_module("main = snd").unwrap();
+        let program = compile_with_prelude(&module, Evaluation::Strict, &Natives::new()).unwrap();
+        let globals: Vec<_> = program.globals.iter().map(|g| g.name.as_str()).collect();
+        assert_eq!(globals, ["main", "snd"]);
+        // `snd` is initialized first
+        assert_eq!(&*program.init_order, [1, 0]);
+    }
+
+    #[test]
+    fn report_prelude_errors_at_the_call() {
+        // the call isn't in tail position, so its frame is still there
+        let source = "main = (n = from_int 3\n  m = to_int n\n  r = compose m id 0\n  r)";
+        let module = parse_module(source).unwrap();
+        let program = compile_with_prelude(&module, Evaluation::Strict, &Natives::new()).unwrap();
+        let err = run(&program).unwrap_err();
+        assert_eq!(
+            err.kind,
+            ErrorKind::Type {
+                expected: "a function",
+                found: "an integer"
+            }
+        );
+        assert_eq!(&source[err.span], "compose m id 0");
+    }
+
+    #[test]
+    fn opt_out_of_prelude() {
+        let module = parse_module("main = id").unwrap();
+        let errors = compile(&module, Evaluation::Strict).unwrap_err();
+        assert_eq!(errors[0].message, "cannot find `id` in this scope");
+    }
+}
@@ -0,0 +1,714 @@
+//! Simplifying a module before it is compiled.
+//!
+//! [`simplify_module`] rewrites expressions into smaller ones that evaluate
+//! to the same value, with the passes turned on in [`Passes`]:
+//!
+//! - eta-reduction turns `\x -> f x` into `f`, when `x` isn't free in `f`,
+//! - beta-reduction turns a lambda that is applied right away,
+//!   `(\x -> body) arg`, into the block `(x = arg; body)`,
+//! - inlining replaces a block def that is used once with its expression,
+//! - dead def elimination removes the block defs that nothing uses.
+//!
+//! The passes make each other's work possible, so they run until none of
+//! them changes anything.
+//!
+//! A strict program evaluates a def as soon as its block is reached, so
+//! there a def is only moved or removed if it is a value, which can't fail
+//! or loop: an integer, a lambda, a variable that is known to be
+//! initialized, or a tuple or record of values. A lazy program only
+//! evaluates a def when it is needed, so any def can be moved or removed,
+//! but one that isn't a value isn't moved into a lambda, where it could be
+//! evaluated many times instead of once.
+
+use ria_lexer::Spanned;
+use ria_parser::{
+    def::{Def, DefList},
+    deps::free_variables,
+    expr::{Access, Arm, Block, Call, Expr, Field, Lambda, Match, Qualified},
+    fold::{walk_block, walk_call, walk_lambda, walk_module, Fold},
+    module::Module,
+    visit::{walk_arm, walk_block as visit_block, walk_lambda as visit_lambda, Visitor},
+};
+
+use crate::bytecode::Evaluation;
+
+/// The simplifications [`simplify_module`] makes. The default makes none.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Passes {
+    /// Turn `\x -> f x` into `f`.
+    pub eta_reduce: bool,
+    /// Turn `(\x -> body) arg` into `(x = arg; body)`.
+    pub beta_reduce: bool,
+    /// Replace block defs that are used once with their expression.
+    pub inline: bool,
+    /// Remove block defs that nothing uses.
+    pub dead_defs: bool,
+}
+
+impl Passes {
+    /// Returns the passes with every one turned on.
+    pub fn all() -> Self {
+        Self {
+            eta_reduce: true,
+            beta_reduce: true,
+            inline: true,
+            dead_defs: true,
+        }
+    }
+
+    fn any(&self) -> bool {
+        self.eta_reduce || self.beta_reduce || self.inline || self.dead_defs
+    }
+}
+
+/// Simplifies the defs and tests of `module` with `passes`, keeping what
+/// they evaluate to with `evaluation`.
+///
+/// The module should be resolved first, since a pass may remove an
+/// expression that resolution would have rejected.
+pub fn simplify_module(mut module: Module, evaluation: Evaluation, passes: Passes) -> Module {
+    if !passes.any() {
+        return module;
+    }
+    let mut simplifier = Simplifier {
+        evaluation,
+        passes,
+        scopes: Vec::new(),
+        changed: true,
+    };
+    while simplifier.changed {
+        simplifier.changed = false;
+        module = simplifier.fold_module(module);
+    }
+    module
+}
+
+/// What binds a name at some point of a module.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Binding {
+    Param,
+    /// A variable of a match pattern.
+    Pattern,
+    /// A def, where it may not have been evaluated yet.
+    Def,
+    /// A def of a block, in the block's expression, which runs once every
+    /// def is evaluated.
+    Evaluated,
+    Constructor,
+}
+
+struct Simplifier<'i> {
+    evaluation: Evaluation,
+    passes: Passes,
+    /// The names bound at the current point, innermost last.
+    scopes: Vec<(&'i str, Binding)>,
+    /// Whether the current round changed anything.
+    changed: bool,
+}
+
+impl<'i> Simplifier<'i> {
+    /// Binds the constructors and defs of `defs`.
+    fn bind_defs(&mut self, defs: &DefList<'i>) {
+        let constructors = defs.types.iter().flat_map(|ty| ty.constructors.iter());
+        self.scopes
+            .extend(constructors.map(|constructor| (constructor.name.0, Binding::Constructor)));
+        self.scopes
+            .extend(defs.defs.iter().map(|def| (def.ident.0, Binding::Def)));
+    }
+
+    /// Returns whether reading `name` can't fail. Names bound outside the
+    /// module are natives, constructors or the defs of modules that are
+    /// initialized before it.
+    fn initialized(&self, name: &str) -> bool {
+        let binding = self
+            .scopes
+            .iter()
+            .rev()
+            .find(|(bound, _)| *bound == name)
+            .map(|&(_, binding)| binding);
+        match binding {
+            None | Some(Binding::Constructor) => true,
+            // in a lazy program, these may be thunks that fail when forced
+            Some(Binding::Param | Binding::Pattern | Binding::Evaluated) => {
+                self.evaluation == Evaluation::Strict
+            }
+            // a def may be read while its own value is being computed
+            Some(Binding::Def) => false,
+        }
+    }
+
+    /// Returns whether evaluating `expr` can't fail or take more than a
+    /// few steps.
+    fn is_value(&self, expr: &Expr<'i>) -> bool {
+        match expr {
+            Expr::Int(_) | Expr::Lambda(_) => true,
+            Expr::Variable(name) => self.evaluation == Evaluation::Lazy || self.initialized(name.0),
+            Expr::Tuple(tuple) => tuple.items.iter().all(|item| self.is_value(item)),
+            Expr::Record(record) => record
+                .fields
+                .iter()
+                .all(|field| self.is_value(&field.value)),
+            _ => false,
+        }
+    }
+
+    /// Turns `\x -> f x` into `f`, if `x` isn't free in `f` and `f` is
+    /// a lambda or an initialized variable.
+    fn eta_reduce(&mut self, lambda: Lambda<'i>) -> Expr<'i> {
+        let reducible = match &*lambda.body {
+            Expr::Call(Call { func, arg, .. }) => {
+                matches!(&**arg, Expr::Variable(arg) if arg.0 == lambda.param.0)
+                    && match &**func {
+                        Expr::Lambda(_) => true,
+                        Expr::Variable(func) => {
+                            func.0 != lambda.param.0 && self.initialized(func.0)
+                        }
+                        _ => false,
+                    }
+                    && !free_variables(func).contains(&lambda.param.0)
+            }
+            _ => false,
+        };
+        match *lambda.body {
+            Expr::Call(call) if reducible => {
+                self.changed = true;
+                *call.func
+            }
+            body => Expr::Lambda(Lambda {
+                param: lambda.param,
+                body: body.into(),
+            }),
+        }
+    }
+
+    /// Inlines the defs of `block` and removes the dead ones, as the passes
+    /// allow, and replaces a block left without defs with its expression.
+    fn simplify_block(&mut self, mut block: Block<'i>) -> Expr<'i> {
+        let depth = self.scopes.len();
+        self.bind_defs(&block.defs);
+        if self.passes.inline {
+            while let Some(index) = self.inlinable(&block) {
+                self.inline(&mut block, index);
+            }
+        }
+        if self.passes.dead_defs {
+            self.remove_dead_defs(&mut block);
+        }
+        self.scopes.truncate(depth);
+
+        match block {
+            Block {
+                defs,
+                expr: Some(expr),
+                ..
+            } if defs.types.is_empty() && defs.defs.is_empty() => {
+                self.changed = true;
+                *expr
+            }
+            block => Expr::Block(block),
+        }
+    }
+
+    /// Returns the index of a def of `block` that can be inlined: one that
+    /// is used once, somewhere its free variables mean the same.
+    fn inlinable(&self, block: &Block<'i>) -> Option<usize> {
+        let defs = &block.defs.defs;
+        defs.iter().position(|def| {
+            let free = free_variables(&def.expr);
+            let mut uses = Uses::new(def.ident.0, &free);
+            for other in defs.iter() {
+                uses.visit_expr(&other.expr);
+            }
+            if let Some(expr) = &block.expr {
+                uses.visit_expr(expr);
+            }
+            let value = self.is_value(&def.expr);
+            uses.count == 1
+                && !free.contains(&def.ident.0)
+                && !uses.captured
+                && match self.evaluation {
+                    Evaluation::Strict => value,
+                    Evaluation::Lazy => value || !uses.under_lambda,
+                }
+        })
+    }
+
+    /// Replaces the use of the def at `index` in `block` with its
+    /// expression, and removes the def.
+    fn inline(&mut self, block: &mut Block<'i>, index: usize) {
+        let mut defs = std::mem::take(&mut block.defs.defs).into_vec();
+        let def = defs.remove(index);
+        let mut substitute = Substitute {
+            name: def.ident.0,
+            replacement: Some(def.expr),
+            bound: Vec::new(),
+        };
+        block.defs.defs = defs
+            .into_iter()
+            .map(|def| substitute.fold_def(def))
+            .collect();
+        block.expr = block
+            .expr
+            .take()
+            .map(|expr| substitute.fold_expr(*expr).into());
+        self.changed = true;
+    }
+
+    /// Removes the defs of `block` that neither its expression nor the defs
+    /// that are kept use, directly or not. In a strict program, defs that
+    /// aren't values are kept.
+    fn remove_dead_defs(&mut self, block: &mut Block<'i>) {
+        let defs = &block.defs.defs;
+        let mut live: Vec<bool> = defs.iter().map(|def| !self.is_value(&def.expr)).collect();
+        if self.evaluation == Evaluation::Lazy {
+            live.fill(false);
+        }
+        let mut pending: Vec<_> = block
+            .expr
+            .iter()
+            .flat_map(|expr| free_variables(expr))
+            .collect();
+        for (def, _) in defs.iter().zip(&live).filter(|(_, &live)| live) {
+            pending.extend(free_variables(&def.expr));
+        }
+        while let Some(name) = pending.pop() {
+            if let Some(index) = defs.iter().position(|def| def.ident.0 == name) {
+                if !live[index] {
+                    live[index] = true;
+                    pending.extend(free_variables(&defs[index].expr));
+                }
+            }
+        }
+
+        if live.iter().all(|&live| live) {
+            return;
+        }
+        let defs = std::mem::take(&mut block.defs.defs).into_vec();
+        block.defs.defs = defs
+            .into_iter()
+            .zip(live)
+            .filter_map(|(def, live)| live.then_some(def))
+            .collect();
+        self.changed = true;
+    }
+}
+
+impl<'i> Fold<'i> for Simplifier<'i> {
+    fn fold_module(&mut self, module: Module<'i>) -> Module<'i> {
+        let depth = self.scopes.len();
+        self.bind_defs(module.defs());
+        let module = walk_module(self, module);
+        self.scopes.truncate(depth);
+        module
+    }
+
+    fn fold_lambda(&mut self, lambda: Lambda<'i>) -> Expr<'i> {
+        self.scopes.push((lambda.param.0, Binding::Param));
+        let lambda = walk_lambda(self, lambda);
+        self.scopes.pop();
+        match lambda {
+            Expr::Lambda(lambda) if self.passes.eta_reduce => self.eta_reduce(lambda),
+            expr => expr,
+        }
+    }
+
+    fn fold_block(&mut self, block: Block<'i>) -> Expr<'i> {
+        let depth = self.scopes.len();
+        self.bind_defs(&block.defs);
+        let defs = self.fold_def_list(block.defs);
+        for (_, binding) in &mut self.scopes[depth..] {
+            if *binding == Binding::Def {
+                *binding = Binding::Evaluated;
+            }
+        }
+        let expr = block.expr.map(|expr| self.fold_expr(*expr).into());
+        self.scopes.truncate(depth);
+        self.simplify_block(Block {
+            defs,
+            expr,
+            span: block.span,
+        })
+    }
+
+    fn fold_call(&mut self, call: Call<'i>) -> Expr<'i> {
+        let span = call.span.clone();
+        let call = match walk_call(self, call) {
+            Expr::Call(call) => call,
+            expr => return expr,
+        };
+        match *call.func {
+            // the def would be in scope in its own expression
+            Expr::Lambda(lambda)
+                if self.passes.beta_reduce
+                    && !free_variables(&call.arg).contains(&lambda.param.0) =>
+            {
+                self.changed = true;
+                let def = Def {
+                    ident: lambda.param,
+                    expr: *call.arg,
+                };
+                self.simplify_block(Block {
+                    defs: DefList {
+                        types: Box::new([]),
+                        defs: Box::new([def]),
+                    },
+                    expr: Some(lambda.body),
+                    span,
+                })
+            }
+            func => Expr::Call(Call {
+                func: func.into(),
+                arg: call.arg,
+                span: call.span,
+            }),
+        }
+    }
+
+    fn fold_match(&mut self, matching: Match<'i>) -> Expr<'i> {
+        let scrutinee = self.fold_expr(*matching.scrutinee);
+        let arms = matching
+            .arms
+            .into_vec()
+            .into_iter()
+            .map(|arm| {
+                let depth = self.scopes.len();
+                arm.pattern
+                    .for_each_variable(&mut |name| self.scopes.push((name.0, Binding::Pattern)));
+                let body = self.fold_expr(arm.body);
+                self.scopes.truncate(depth);
+                Arm {
+                    pattern: arm.pattern,
+                    body,
+                }
+            })
+            .collect();
+        Expr::Match(Match {
+            scrutinee: scrutinee.into(),
+            arms,
+            span: matching.span,
+        })
+    }
+}
+
+/// Counts the free uses of a name, and notes whether any of them is inside
+/// a lambda or where one of `free` is bound by something else.
+struct Uses<'a, 'i> {
+    name: &'i str,
+    free: &'a [&'i str],
+    /// The names bound at the current point, innermost last.
+    bound: Vec<&'i str>,
+    lambdas: usize,
+    count: usize,
+    under_lambda: bool,
+    captured: bool,
+}
+
+impl<'a, 'i> Uses<'a, 'i> {
+    fn new(name: &'i str, free: &'a [&'i str]) -> Self {
+        Self {
+            name,
+            free,
+            bound: Vec::new(),
+            lambdas: 0,
+            count: 0,
+            under_lambda: false,
+            captured: false,
+        }
+    }
+}
+
+impl<'i> Visitor<'i> for Uses<'_, 'i> {
+    fn visit_variable(&mut self, Spanned(name, _): &Spanned<&'i str>) {
+        if *name == self.name && !self.bound.contains(name) {
+            self.count += 1;
+            self.under_lambda |= self.lambdas > 0;
+            self.captured |= self.free.iter().any(|free| self.bound.contains(free));
+        }
+    }
+
+    fn visit_qualified(&mut self, qualified: &Qualified<'i>) {
+        self.visit_variable(&qualified.module);
+    }
+
+    fn visit_lambda(&mut self, lambda: &Lambda<'i>) {
+        self.bound.push(lambda.param.0);
+        self.lambdas += 1;
+        visit_lambda(self, lambda);
+        self.lambdas -= 1;
+        self.bound.pop();
+    }
+
+    fn visit_block(&mut self, block: &Block<'i>) {
+        let depth = self.bound.len();
+        let constructors = block
+            .defs
+            .types
+            .iter()
+            .flat_map(|ty| ty.constructors.iter());
+        self.bound
+            .extend(constructors.map(|constructor| constructor.name.0));
+        self.bound
+            .extend(block.defs.defs.iter().map(|def| def.ident.0));
+        visit_block(self, block);
+        self.bound.truncate(depth);
+    }
+
+    fn visit_arm(&mut self, arm: &Arm<'i>) {
+        let depth = self.bound.len();
+        arm.pattern
+            .for_each_variable(&mut |name| self.bound.push(name.0));
+        walk_arm(self, arm);
+        self.bound.truncate(depth);
+    }
+}
+
+/// Replaces the free use of `name` with `replacement`.
+struct Substitute<'i> {
+    name: &'i str,
+    replacement: Option<Expr<'i>>,
+    /// The names bound at the current point, innermost last.
+    bound: Vec<&'i str>,
+}
+
+impl<'i> Substitute<'i> {
+    fn take(&mut self, name: &str) -> Option<Expr<'i>> {
+        if name == self.name && !self.bound.contains(&name) {
+            self.replacement.take()
+        } else {
+            None
+        }
+    }
+}
+
+impl<'i> Fold<'i> for Substitute<'i> {
+    fn fold_variable(&mut self, ident: Spanned<&'i str>) -> Expr<'i> {
+        self.take(ident.0).unwrap_or(Expr::Variable(ident))
+    }
+
+    fn fold_qualified(&mut self, qualified: Qualified<'i>) -> Expr<'i> {
+        // a qualified name whose module is a variable reads a record field
+        match self.take(qualified.module.0) {
+            Some(record) => Expr::Access(Access {
+                expr: record.into(),
+                span: qualified.span(),
+                field: qualified.name.map(Field::Name),
+            }),
+            None => Expr::Qualified(qualified),
+        }
+    }
+
+    fn fold_lambda(&mut self, lambda: Lambda<'i>) -> Expr<'i> {
+        self.bound.push(lambda.param.0);
+        let lambda = walk_lambda(self, lambda);
+        self.bound.pop();
+        lambda
+    }
+
+    fn fold_block(&mut self, block: Block<'i>) -> Expr<'i> {
+        let depth = self.bound.len();
+        let constructors = block
+            .defs
+            .types
+            .iter()
+            .flat_map(|ty| ty.constructors.iter());
+        self.bound
+            .extend(constructors.map(|constructor| constructor.name.0));
+        self.bound
+            .extend(block.defs.defs.iter().map(|def| def.ident.0));
+        let block = walk_block(self, block);
+        self.bound.truncate(depth);
+        block
+    }
+
+    fn fold_match(&mut self, matching: Match<'i>) -> Expr<'i> {
+        let scrutinee = self.fold_expr(*matching.scrutinee);
+        let arms = matching
+            .arms
+            .into_vec()
+            .into_iter()
+            .map(|arm| {
+                let depth = self.bound.len();
+                arm.pattern
+                    .for_each_variable(&mut |name| self.bound.push(name.0));
+                let body = self.fold_expr(arm.body);
+                self.bound.truncate(depth);
+                Arm {
+                    pattern: arm.pattern,
+                    body,
+                }
+            })
+            .collect();
+        Expr::Match(Match {
+            scrutinee: scrutinee.into(),
+            arms,
+            span: matching.span,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use ria_parser::{parse_module, print::print_module};
+
+    use crate::{
+        bytecode::Evaluation, compile::compile_with_prelude, natives::Natives, vm::run, ErrorKind,
+    };
+
+    use super::{simplify_module, Passes};
+
+    fn simplify(source: &str, evaluation: Evaluation, passes: Passes) -> String {
+        let module = parse_module(source).unwrap();
+        print_module(&simplify_module(module, evaluation, passes))
+    }
+
+    /// Runs `main` in `source`, simplified with `passes`, and returns how its
+    /// value is displayed.
+    fn evaluate(source: &str, evaluation: Evaluation, passes: Passes) -> Result<String, ErrorKind> {
+        let module = simplify_module(parse_module(source).unwrap(), evaluation, passes);
+        let program = compile_with_prelude(&module, evaluation, &Natives::new()).unwrap();
+        run(&program)
+            .map(|value| value.display(&program).to_string())
+            .map_err(|err| err.kind)
+    }
+
+    #[test]
+    fn eta_reduce() {
+        let passes = Passes {
+            eta_reduce: true,
+            ..Passes::default()
+        };
+        let source =
+            "apply = \\f -> \\x -> f x\nloop = \\x -> loop x\nnested = \\x -> (\\y -> y) x\n";
+        assert_eq!(
+            simplify(source, Evaluation::Strict, passes),
+            "apply = \\f -> f\nloop = \\x -> loop x\nnested = \\y -> y\n"
+        );
+        // a lazy parameter may be a thunk that fails when it is forced
+        assert_eq!(
+            simplify(source, Evaluation::Lazy, passes),
+            "apply = \\f -> \\x -> f x\nloop = \\x -> loop x\nnested = \\y -> y\n"
+        );
+        assert_eq!(
+            simplify("f = \\x -> add x x\n", Evaluation::Strict, passes),
+            "f = \\x -> add x x\n"
+        );
+    }
+
+    #[test]
+    fn beta_reduce_and_inline() {
+        let beta = Passes {
+            beta_reduce: true,
+            ..Passes::default()
+        };
+        let source = "main = (\\x -> add x 1) 2\n";
+        assert_eq!(
+            simplify(source, Evaluation::Strict, beta),
+            "main = (x = 2; add x 1)\n"
+        );
+        let inline = Passes {
+            inline: true,
+            ..beta
+        };
+        assert_eq!(
+            simplify(source, Evaluation::Strict, inline),
+            "main = add 2 1\n"
+        );
+
+        // a block's defs are evaluated by the time its expression runs
+        let source = "main = (q = (1, 2); add ((\\r -> r.0) q) q.1)\n";
+        assert_eq!(
+            simplify(source, Evaluation::Strict, inline),
+            "main = (q = (1, 2); add q.0 q.1)\n"
+        );
+
+        // the argument can't be a def that it uses itself
+        let source = "f = \\x -> (\\x -> x) (add x 1)\n";
+        assert_eq!(simplify(source, Evaluation::Strict, inline), source);
+
+        // a def that is used in a lambda where one of its variables is
+        // bound to something else stays
+        let source = "f = \\y -> (x = y; \\y -> x)\n";
+        assert_eq!(simplify(source, Evaluation::Strict, inline), source);
+        // as does a def that isn't a value in a lambda of a lazy program
+        let source = "f = \\y -> (x = add y 1; \\z -> x)\n";
+        assert_eq!(simplify(source, Evaluation::Lazy, inline), source);
+        assert_eq!(
+            simplify("f = \\y -> (x = add y 1; x)\n", Evaluation::Lazy, inline),
+            "f = \\y -> add y 1\n"
+        );
+    }
+
+    #[test]
+    fn remove_dead_defs() {
+        let passes = Passes {
+            dead_defs: true,
+            ..Passes::default()
+        };
+        let source = "main = (a = 1; b = a; c = div 1 0; d = \\x -> x; b)\n";
+        // evaluating `c` fails in a strict program, and uses nothing
+        assert_eq!(
+            simplify(source, Evaluation::Strict, passes),
+            "main = (a = 1; b = a; c = div 1 0; b)\n"
+        );
+        assert_eq!(
+            simplify(source, Evaluation::Lazy, passes),
+            "main = (a = 1; b = a; b)\n"
+        );
+        assert_eq!(
+            simplify("main = (a = 1; 2)\n", Evaluation::Lazy, passes),
+            "main = 2\n"
+        );
+    }
+
+    #[test]
+    fn simplified_programs_evaluate_the_same() {
+        let programs = [
+            "main = to_int (times (from_int 3) (from_int 4))",
+            "main = (fact = fix \\f -> \\n -> le n 1 (\\_ -> 1) (\\_ -> mul n (f (sub n 1))) n
+  fact 5)",
+            "type List a = Nil | Cons a (List a)
+map = \\f -> \\list -> match list with
+  | Nil -> Nil
+  | Cons x rest -> Cons (f x) (map f rest)
+range = \\n -> match n with
+  | 0 -> Nil
+  | _ -> (m = sub n 1
+    Cons n (range m))
+main = (inc = \\x -> add x 1
+  unused = range 3
+  twice = \\f -> \\x -> f (f x)
+  map (\\x -> twice inc x) (range 3))",
+            "main = (p = (1, { a = 2 }); q = p.1; (\\r -> add r.a p.0) q)",
+            "main = (y = 1; f = \\y -> (x = y; g = \\y -> x; g 5); f 2)",
+            "main = (x = div 1 0; 1)",
+            "main = (\\x -> 2) (div 1 0)",
+            "main = (\\f -> (\\x -> f x) 1) (\\x -> div x 0)",
+        ];
+        let mut configs = vec![Passes::all()];
+        for disabled in 0..4 {
+            let mut passes = Passes::all();
+            let pass = [
+                &mut passes.eta_reduce,
+                &mut passes.beta_reduce,
+                &mut passes.inline,
+                &mut passes.dead_defs,
+            ];
+            *pass.into_iter().nth(disabled).unwrap() = false;
+            configs.push(passes);
+        }
+
+        for source in programs {
+            for evaluation in [Evaluation::Strict, Evaluation::Lazy] {
+                let expected = evaluate(source, evaluation, Passes::default());
+                for &passes in &configs {
+                    assert_eq!(
+                        evaluate(source, evaluation, passes),
+                        expected,
+                        "{source}\nwith {passes:?} and {evaluation:?} is simplified to\n{}",
+                        simplify(source, evaluation, passes)
+                    );
+                }
+            }
+        }
+    }
+}
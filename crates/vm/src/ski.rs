@@ -0,0 +1,491 @@
+//! Compiling modules to SKI combinators, and reducing them as a graph.
+//!
+//! [`compile_module`] removes every variable from a module by bracket
+//! abstraction, with Turner's `B` and `C` combinators for applications where
+//! only one side uses the variable, and eta reduction where the variable is
+//! just the argument. A block becomes lambdas applied to its defs, from the
+//! innermost component of its dependency graph out: recursive defs take their
+//! value from `Y`, and mutually recursive ones share a Church tuple of their
+//! values. Integers, the arithmetic and comparison [`builtins`](crate::builtins)
+//! and the Church booleans stay in the term as constants.
+//!
+//! The [`machine`] reduces the term in normal order, sharing each argument
+//! between its uses, so it evaluates the way a lazy program does. Data types,
+//! tuples, records and imports aren't supported.
+
+pub mod machine;
+
+use std::fmt;
+
+use ria_parser::{
+    def::DefList, deps::DepGraph, diagnostic::Diagnostic, expr::Expr, module::Module,
+};
+
+use crate::builtins::Prim;
+
+pub use self::machine::{evaluate, Value};
+
+/// A combinator, which rewrites its first [`arity`](Comb::arity) arguments.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comb {
+    /// `S f g x = f x (g x)`
+    S,
+    /// `K x y = x`
+    K,
+    /// `I x = x`
+    I,
+    /// `B f g x = f (g x)`
+    B,
+    /// `C f g x = f x g`
+    C,
+    /// `Y f = f (Y f)`
+    Y,
+}
+
+impl Comb {
+    pub fn arity(self) -> usize {
+        match self {
+            Comb::I | Comb::Y => 1,
+            Comb::K => 2,
+            Comb::S | Comb::B | Comb::C => 3,
+        }
+    }
+}
+
+impl fmt::Display for Comb {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+/// A term without variables.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Term {
+    Comb(Comb),
+    Int(i64),
+    Prim(Prim),
+    App(Box<Term>, Box<Term>),
+}
+
+impl Term {
+    pub fn app(func: Term, arg: Term) -> Self {
+        Term::App(Box::new(func), Box::new(arg))
+    }
+}
+
+/// Writes applications left-nested, like `S (K I) K`.
+impl fmt::Display for Term {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Term::Comb(comb) => write!(f, "{comb}"),
+            Term::Int(int) => write!(f, "{int}"),
+            Term::Prim(prim) => f.write_str(prim.name()),
+            Term::App(func, arg) => {
+                write!(f, "{func} ")?;
+                match **arg {
+                    Term::App(..) => write!(f, "({arg})"),
+                    _ => write!(f, "{arg}"),
+                }
+            }
+        }
+    }
+}
+
+/// A term that may still have variables, during bracket abstraction.
+#[derive(Debug, Clone)]
+enum Open {
+    Var(String),
+    Term(Term),
+    App(Box<Open>, Box<Open>),
+}
+
+/// Names that [`Translator`] makes up. No identifier starts with `#`, so they
+/// can't capture a variable of the program.
+const TUPLE: &str = "#tuple";
+const GROUP: &str = "#group";
+const SELF: &str = "#self";
+
+fn app(func: Open, arg: Open) -> Open {
+    Open::App(Box::new(func), Box::new(arg))
+}
+
+fn comb(comb: Comb) -> Open {
+    Open::Term(Term::Comb(comb))
+}
+
+impl Open {
+    fn occurs(&self, name: &str) -> bool {
+        match self {
+            Open::Var(var) => var == name,
+            Open::Term(_) => false,
+            Open::App(func, arg) => func.occurs(name) || arg.occurs(name),
+        }
+    }
+
+    /// Returns the term with the free variables of `self` but `name` that
+    /// applied to a value `v` is equal to `self` with `name` bound to `v`.
+    fn abstract_var(self, name: &str) -> Open {
+        if !self.occurs(name) {
+            return app(comb(Comb::K), self);
+        }
+        match self {
+            Open::Var(_) => comb(Comb::I),
+            Open::Term(_) => unreachable!("closed terms have no variables"),
+            Open::App(func, arg) => match (func.occurs(name), arg.occurs(name)) {
+                (false, true) if matches!(&*arg, Open::Var(var) if var == name) => *func,
+                (true, true) => app(
+                    app(comb(Comb::S), func.abstract_var(name)),
+                    arg.abstract_var(name),
+                ),
+                (true, false) => app(app(comb(Comb::C), func.abstract_var(name)), *arg),
+                (false, _) => app(app(comb(Comb::B), *func), arg.abstract_var(name)),
+            },
+        }
+    }
+
+    /// Abstracts `names` from `self`, the last one innermost.
+    fn abstract_vars<'a>(self, names: impl DoubleEndedIterator<Item = &'a str>) -> Open {
+        names.rev().fold(self, |body, name| body.abstract_var(name))
+    }
+
+    fn close(self) -> Term {
+        match self {
+            Open::Var(var) => unreachable!("`{var}` is bound"),
+            Open::Term(term) => term,
+            Open::App(func, arg) => Term::app(func.close(), arg.close()),
+        }
+    }
+}
+
+/// Returns the term that selects the value at `index` from a Church tuple of
+/// `len` values.
+fn select(index: usize, len: usize) -> Open {
+    let names: Vec<_> = (0..len).map(|i| format!("#{i}")).collect();
+    Open::Var(names[index].clone()).abstract_vars(names.iter().map(String::as_str))
+}
+
+/// Returns the terms that select each of the `len` values of the Church
+/// tuple `tuple`.
+fn selections(tuple: &str, len: usize) -> impl Iterator<Item = Open> + '_ {
+    (0..len).map(move |index| app(Open::Var(tuple.into()), select(index, len)))
+}
+
+/// Compiles `module` to a term that evaluates to its `main` def. The defs of
+/// `prelude`, if any, are in scope, shadowed by the module's own.
+pub fn compile_module<'i>(
+    module: &Module<'i>,
+    prelude: Option<&Module<'i>>,
+) -> Result<Term, Diagnostic> {
+    if let Some(import) = module.imports().first() {
+        return Err(unsupported(import.span.clone(), "imports"));
+    }
+    let main = module.defs().defs.iter().any(|def| def.ident.0 == "main");
+    if !main {
+        return Err(Diagnostic::new(0..0, "no `main` definition to compile"));
+    }
+
+    let mut translator = Translator::default();
+    let body = |translator: &mut Translator<'i>| {
+        translator.letrec(module.defs(), |_| Ok(Open::Var("main".into())))
+    };
+    let term = match prelude {
+        Some(prelude) => translator.letrec(prelude.defs(), body)?,
+        None => body(&mut translator)?,
+    };
+    Ok(term.close())
+}
+
+fn unsupported(span: std::ops::Range<usize>, what: &str) -> Diagnostic {
+    Diagnostic::new(span, format!("{what} can't be compiled to combinators"))
+}
+
+/// Translates expressions to open terms, knowing which names are bound.
+#[derive(Default)]
+struct Translator<'i> {
+    bound: Vec<&'i str>,
+}
+
+impl<'i> Translator<'i> {
+    fn expr(&mut self, expr: &Expr<'i>) -> Result<Open, Diagnostic> {
+        match expr {
+            Expr::Variable(ident) => {
+                if self.bound.contains(&ident.0) {
+                    return Ok(Open::Var(ident.0.to_owned()));
+                }
+                let term = match ident.0 {
+                    "true" => Term::Comb(Comb::K),
+                    "false" => Term::app(Term::Comb(Comb::K), Term::Comb(Comb::I)),
+                    name => match Prim::from_name(name) {
+                        Some(prim) => Term::Prim(prim),
+                        None => {
+                            return Err(Diagnostic::new(
+                                ident.1.clone(),
+                                format!("`{name}` isn't a def or a builtin"),
+                            ))
+                        }
+                    },
+                };
+                Ok(Open::Term(term))
+            }
+            Expr::Int(int) => Ok(Open::Term(Term::Int(int.0))),
+            Expr::Lambda(lambda) => {
+                self.bound.push(lambda.param.0);
+                let body = self.expr(&lambda.body);
+                self.bound.pop();
+                Ok(body?.abstract_var(lambda.param.0))
+            }
+            Expr::Call(call) => Ok(app(self.expr(&call.func)?, self.expr(&call.arg)?)),
+            Expr::Block(block) => {
+                let body = block.expr.as_deref().ok_or_else(|| {
+                    Diagnostic::new(block.span.clone(), "a block must end with an expression")
+                })?;
+                self.letrec(&block.defs, |translator| translator.expr(body))
+            }
+            Expr::Qualified(_) => Err(unsupported(expr.span(), "imported names")),
+            Expr::Match(_) => Err(unsupported(expr.span(), "matches")),
+            Expr::Tuple(_) => Err(unsupported(expr.span(), "tuples")),
+            Expr::Record(_) => Err(unsupported(expr.span(), "records")),
+            Expr::Access(_) => Err(unsupported(expr.span(), "field accesses")),
+        }
+    }
+
+    /// Translates `body` with `defs` bound around it. Defs that `body`
+    /// doesn't need are left out.
+    fn letrec(
+        &mut self,
+        defs: &DefList<'i>,
+        body: impl FnOnce(&mut Self) -> Result<Open, Diagnostic>,
+    ) -> Result<Open, Diagnostic> {
+        if let Some(type_def) = defs.types.first() {
+            return Err(unsupported(type_def.span.clone(), "data types"));
+        }
+
+        let depth = self.bound.len();
+        self.bound.extend(defs.defs.iter().map(|def| def.ident.0));
+        let translated = self.needed_defs(defs, body);
+        self.bound.truncate(depth);
+        let (mut term, mut values) = translated?;
+
+        let graph = DepGraph::new(defs);
+        for component in graph.components().into_iter().rev() {
+            // the defs of a component need each other, so either all of them
+            // are needed or none
+            if values[component.defs[0]].is_none() {
+                continue;
+            }
+            let names: Vec<_> = component.defs.iter().map(|&i| graph.names[i]).collect();
+            let mut exprs: Vec<_> = component
+                .defs
+                .iter()
+                .map(|&i| values[i].take().expect("needed defs are translated"))
+                .collect();
+            term = if component.is_mutually_recursive() {
+                // the group is the fixed point of a tuple of the defs, which
+                // selects their values from the tuple itself
+                let len = names.len();
+                let tuple = exprs
+                    .into_iter()
+                    .fold(Open::Var(TUPLE.into()), app)
+                    .abstract_var(TUPLE)
+                    .abstract_vars(names.iter().copied());
+                let group = selections(SELF, len).fold(tuple, app).abstract_var(SELF);
+                let scope = selections(GROUP, len)
+                    .fold(term.abstract_vars(names.iter().copied()), app)
+                    .abstract_var(GROUP);
+                app(scope, app(comb(Comb::Y), group))
+            } else {
+                let name = names[0];
+                let mut value = exprs.pop().expect("components aren't empty");
+                if graph.references_itself(component.defs[0]) {
+                    value = app(comb(Comb::Y), value.abstract_var(name));
+                }
+                match term {
+                    Open::Var(var) if var == name => value,
+                    term => app(term.abstract_var(name), value),
+                }
+            };
+        }
+        Ok(term)
+    }
+
+    /// Translates `body` and the defs it needs, directly or through other
+    /// defs. The defs that aren't needed are left as `None`.
+    fn needed_defs(
+        &mut self,
+        defs: &DefList<'i>,
+        body: impl FnOnce(&mut Self) -> Result<Open, Diagnostic>,
+    ) -> Result<(Open, Vec<Option<Open>>), Diagnostic> {
+        let body = body(self)?;
+        let mut values: Vec<Option<Open>> = defs.defs.iter().map(|_| None).collect();
+        let mut pending: Vec<_> = (0..defs.defs.len())
+            .filter(|&i| body.occurs(defs.defs[i].ident.0))
+            .collect();
+        while let Some(index) = pending.pop() {
+            if values[index].is_some() {
+                continue;
+            }
+            let value = self.expr(&defs.defs[index].expr)?;
+            pending.extend((0..defs.defs.len()).filter(|&i| value.occurs(defs.defs[i].ident.0)));
+            values[index] = Some(value);
+        }
+        Ok((body, values))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use ria_parser::parse_module;
+
+    use crate::{
+        bytecode::Evaluation,
+        compile::compile_with_prelude,
+        interp::{self, Interpreter},
+        natives::Natives,
+        prelude::prelude,
+        vm::{self, run, ErrorKind, Limits},
+    };
+
+    use super::{compile_module, evaluate, Value};
+
+    const CHURCH: &str = include_str!("../benches/church.ria");
+
+    /// Compiles and evaluates `main` of `source`, with the prelude.
+    fn ski_main(source: &str) -> Result<Value, vm::Error> {
+        let module = parse_module(source).unwrap();
+        let term = compile_module(&module, Some(&prelude())).unwrap();
+        evaluate(&term, Limits::default())
+    }
+
+    #[test]
+    fn abstract_with_optimized_combinators() {
+        for (source, expected) in [
+            ("main = \\x -> x", "I"),
+            ("main = \\x -> \\y -> x", "K"),
+            ("main = \\f -> \\x -> f x", "I"),
+            ("main = \\x -> add x 1", "C add 1"),
+            ("main = \\f -> \\g -> \\x -> f (g x)", "B"),
+            ("main = \\f -> \\x -> f x x", "C S I"),
+            ("main = (one = 1\n  add one one)", "S add I 1"),
+            ("main = (f = \\n -> f n\n  f)", "Y I"),
+        ] {
+            let module = parse_module(source).unwrap();
+            let term = compile_module(&module, None).unwrap();
+            assert_eq!(term.to_string(), expected, "compiling `{source}`");
+        }
+    }
+
+    #[test]
+    fn match_the_interpreter() {
+        for (expr, expected) in [
+            ("pick (is_zero zero) 1 2", 1),
+            ("pick (is_zero (pred three)) 1 2", 2),
+            ("pick (eq (pred three) two) 3 4", 3),
+            ("(k = \\x -> \\_ -> x\n  k 5 6)", 5),
+        ] {
+            let source = format!(
+                "{CHURCH}three = succ two\npick = \\b -> \\x -> \\y -> b x y\nmain = {expr}"
+            );
+            let module = parse_module(&source).unwrap();
+            let interpreted = match Interpreter::new(&module).unwrap().main().unwrap() {
+                interp::Value::Int(int) => int,
+                interp::Value::Closure(_) => panic!("`{expr}` should be an integer"),
+            };
+            assert_eq!(interpreted, expected, "interpreting `{expr}`");
+            let term = compile_module(&module, None).unwrap();
+            let reduced = evaluate(&term, Limits::default()).unwrap();
+            assert_eq!(reduced, Value::Int(expected), "reducing `{expr}`");
+        }
+    }
+
+    #[test]
+    fn match_the_vm() {
+        for source in [
+            "main = to_int (times (from_int 6) (from_int 7))",
+            "fact = \\n -> le n 1 (\\_ -> 1) (\\_ -> mul n (fact (sub n 1))) 0\nmain = fact 10",
+            "even = \\n -> eq n 0 (\\_ -> true) (\\_ -> odd (sub n 1)) 0\n\
+             odd = \\n -> eq n 0 (\\_ -> false) (\\_ -> even (sub n 1)) 0\n\
+             main = even 10 1 2",
+            "main = (sum = \\n -> \\acc -> eq n 0 (\\_ -> acc) (\\_ -> sum (sub n 1) (add acc n)) 0\n  \
+             sum 1000 0)",
+            "main = (a = \\n -> eq n 0 (\\_ -> 1) (\\_ -> b (sub n 1)) 0\n  \
+             b = \\n -> eq n 0 (\\_ -> 2) (\\_ -> c (sub n 1)) 0\n  \
+             c = \\n -> eq n 0 (\\_ -> 3) (\\_ -> a (sub n 1)) 0\n  a 7)",
+            "id = \\x -> mul x 2\nmain = id 21",
+        ] {
+            let module = parse_module(source).unwrap();
+            let program =
+                compile_with_prelude(&module, Evaluation::Strict, &Natives::new()).unwrap();
+            let expected = match run(&program) {
+                Ok(vm::Value::Int(int)) => int,
+                result => panic!("`{source}` evaluated to {result:?}"),
+            };
+            assert_eq!(
+                ski_main(source),
+                Ok(Value::Int(expected)),
+                "reducing `{source}`"
+            );
+        }
+    }
+
+    #[test]
+    fn report_runtime_errors() {
+        for (source, kind) in [
+            ("main = div 1 0", ErrorKind::DivisionByZero),
+            ("main = add 9223372036854775807 1", ErrorKind::Overflow),
+            (
+                "main = 1 2",
+                ErrorKind::Type {
+                    expected: "a function",
+                    found: "an integer",
+                },
+            ),
+            (
+                "main = add id 2",
+                ErrorKind::Type {
+                    expected: "an integer",
+                    found: "a function",
+                },
+            ),
+        ] {
+            assert_eq!(
+                ski_main(source).unwrap_err().kind,
+                kind,
+                "reducing `{source}`"
+            );
+        }
+        assert_eq!(ski_main("main = add 1"), Ok(Value::Function));
+
+        let module = parse_module("loop = \\x -> loop x\nmain = loop 1").unwrap();
+        let term = compile_module(&module, None).unwrap();
+        assert_eq!(
+            evaluate(&term, Limits::default()).unwrap_err().kind,
+            ErrorKind::Loop
+        );
+
+        let module = parse_module("count = \\n -> count (add n 1)\nmain = count 0").unwrap();
+        let term = compile_module(&module, None).unwrap();
+        let limits = Limits {
+            fuel: Some(1000),
+            ..Limits::default()
+        };
+        assert_eq!(
+            evaluate(&term, limits).unwrap_err().kind,
+            ErrorKind::OutOfFuel
+        );
+    }
+
+    #[test]
+    fn reject_unsupported_exprs() {
+        for (source, culprit) in [
+            ("type T = A\nmain = 1", "type T = A"),
+            ("main = (1, 2)", "(1, 2)"),
+            ("main = match 1 with | _ -> 2", "match 1 with | _ -> 2"),
+            ("main = assert_eq 1 1", "assert_eq"),
+            ("import a\nmain = 1", "import a"),
+        ] {
+            let module = parse_module(source).unwrap();
+            let err = compile_module(&module, None).unwrap_err();
+            assert_eq!(&source[err.span], culprit, "compiling `{source}`");
+        }
+    }
+}
@@ -0,0 +1,241 @@
+//! A graph-reduction machine for combinator terms.
+//!
+//! The term is loaded as a graph of application nodes. The machine walks
+//! down the left spine of the graph to the combinator at its head and, once
+//! the head has all its arguments, overwrites the root of the redex with the
+//! result, so every node that shares the redex sees the result too. `Y f`
+//! becomes a node that applies `f` to itself, a cycle instead of a copy.
+//!
+//! A builtin needs its arguments as integers, so the machine saves the spine
+//! on a dump, reduces each argument in turn and comes back to the builtin.
+//! Nothing recurses on the native stack.
+
+use std::{fmt, time::Instant};
+
+use crate::{
+    builtins::{Prim, PrimValue},
+    natives::type_error,
+    vm::{Error, ErrorKind, Limits, DEADLINE_INTERVAL},
+};
+
+use super::{Comb, Term};
+
+/// What a term reduces to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Value {
+    Int(i64),
+    /// A combinator or builtin applied to fewer arguments than it takes.
+    Function,
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Int(int) => write!(f, "{int}"),
+            Value::Function => f.write_str("<function>"),
+        }
+    }
+}
+
+type NodeId = usize;
+
+#[derive(Debug, Clone, Copy)]
+enum Node {
+    App(NodeId, NodeId),
+    /// A redex that reduced to another node.
+    Ind(NodeId),
+    Comb(Comb),
+    Int(i64),
+    Prim(Prim),
+}
+
+/// Reduces `term` to weak head normal form, within `limits`. Fuel counts
+/// reduction steps, depth the builtins waiting for an argument, and
+/// allocations the nodes of the graph.
+pub fn evaluate(term: &Term, limits: Limits) -> Result<Value, Error> {
+    let mut machine = Machine {
+        nodes: Vec::new(),
+        limits,
+        steps: 0,
+    };
+    let root = machine.load(term)?;
+    let value = machine.whnf(root)?;
+    Ok(match machine.nodes[value] {
+        Node::Int(int) => Value::Int(int),
+        _ => Value::Function,
+    })
+}
+
+fn error(kind: ErrorKind) -> Error {
+    Error::new(kind)
+}
+
+struct Machine {
+    nodes: Vec<Node>,
+    limits: Limits,
+    steps: u64,
+}
+
+impl Machine {
+    fn alloc(&mut self, node: Node) -> Result<NodeId, Error> {
+        if self
+            .limits
+            .max_allocations
+            .is_some_and(|max| self.nodes.len() as u64 >= max)
+        {
+            return Err(error(ErrorKind::TooManyAllocations));
+        }
+        self.nodes.push(node);
+        Ok(self.nodes.len() - 1)
+    }
+
+    fn load(&mut self, term: &Term) -> Result<NodeId, Error> {
+        let node = match term {
+            Term::Comb(comb) => Node::Comb(*comb),
+            Term::Int(int) => Node::Int(*int),
+            Term::Prim(prim) => Node::Prim(*prim),
+            Term::App(func, arg) => Node::App(self.load(func)?, self.load(arg)?),
+        };
+        self.alloc(node)
+    }
+
+    /// Follows indirections from `node`.
+    fn resolve(&self, mut node: NodeId) -> NodeId {
+        while let Node::Ind(target) = self.nodes[node] {
+            node = target;
+        }
+        node
+    }
+
+    /// Takes a reduction step out of the fuel, and checks the deadline.
+    fn step(&mut self) -> Result<(), Error> {
+        self.steps += 1;
+        if self.limits.fuel.is_some_and(|fuel| self.steps > fuel) {
+            return Err(error(ErrorKind::OutOfFuel));
+        }
+        if self.steps % DEADLINE_INTERVAL == 0
+            && self
+                .limits
+                .deadline
+                .is_some_and(|deadline| Instant::now() >= deadline)
+        {
+            return Err(error(ErrorKind::DeadlineExceeded));
+        }
+        Ok(())
+    }
+
+    /// Reduces the graph at `root` until its head can't be reduced, and
+    /// returns the node it ends up at.
+    fn whnf(&mut self, root: NodeId) -> Result<NodeId, Error> {
+        // the nodes from the root down to the head, each the function of the
+        // one before
+        let mut spine = vec![root];
+        // the spines of the builtins waiting for an argument
+        let mut dump: Vec<Vec<NodeId>> = Vec::new();
+        loop {
+            let top = *spine.last().expect("the spine has a root");
+            let arity = match self.nodes[top] {
+                Node::App(func, _) => {
+                    spine.push(func);
+                    continue;
+                }
+                Node::Ind(target) => {
+                    // the function of the parent can skip the indirection
+                    if let Some(&parent) = spine.iter().rev().nth(1) {
+                        if let Node::App(_, arg) = self.nodes[parent] {
+                            self.nodes[parent] = Node::App(target, arg);
+                        }
+                    }
+                    *spine.last_mut().expect("the spine has a root") = target;
+                    continue;
+                }
+                Node::Comb(comb) => comb.arity(),
+                Node::Prim(_) => 2,
+                Node::Int(_) if spine.len() > 1 => {
+                    return Err(type_error("a function", "an integer"))
+                }
+                Node::Int(_) => 0,
+            };
+
+            if spine.len() - 1 < arity || arity == 0 {
+                let value = spine[0];
+                let Some(saved) = dump.pop() else {
+                    return Ok(value);
+                };
+                if !matches!(self.nodes[value], Node::Int(_)) {
+                    return Err(type_error("an integer", "a function"));
+                }
+                spine = saved;
+                continue;
+            }
+
+            self.step()?;
+            let len = spine.len();
+            let arg = |machine: &Self, index: usize| match machine.nodes[spine[len - 2 - index]] {
+                Node::App(_, arg) => arg,
+                _ => unreachable!("the spine is made of applications"),
+            };
+            let redex = spine[len - 1 - arity];
+            match self.nodes[top] {
+                Node::Comb(comb) => {
+                    self.nodes[redex] = match comb {
+                        Comb::I | Comb::K => {
+                            // a redex that reduces to itself, like `Y I`, can't
+                            // make progress
+                            let result = self.resolve(arg(self, 0));
+                            if result == redex {
+                                return Err(error(ErrorKind::Loop));
+                            }
+                            Node::Ind(result)
+                        }
+                        Comb::S => {
+                            let (f, g, x) = (arg(self, 0), arg(self, 1), arg(self, 2));
+                            Node::App(self.alloc(Node::App(f, x))?, self.alloc(Node::App(g, x))?)
+                        }
+                        Comb::B => {
+                            let (f, g, x) = (arg(self, 0), arg(self, 1), arg(self, 2));
+                            Node::App(f, self.alloc(Node::App(g, x))?)
+                        }
+                        Comb::C => {
+                            let (f, g, x) = (arg(self, 0), arg(self, 1), arg(self, 2));
+                            Node::App(self.alloc(Node::App(f, x))?, g)
+                        }
+                        Comb::Y => Node::App(arg(self, 0), redex),
+                    };
+                }
+                Node::Prim(prim) => {
+                    let operands = [arg(self, 0), arg(self, 1)].map(|node| self.resolve(node));
+                    let unreduced = operands
+                        .into_iter()
+                        .find(|&node| !matches!(self.nodes[node], Node::Int(_)));
+                    if let Some(operand) = unreduced {
+                        if self.limits.max_depth.is_some_and(|max| dump.len() >= max) {
+                            return Err(error(ErrorKind::TooDeep));
+                        }
+                        dump.push(std::mem::replace(&mut spine, vec![operand]));
+                        continue;
+                    }
+                    let [Node::Int(a), Node::Int(b)] = operands.map(|node| self.nodes[node]) else {
+                        unreachable!("the operands are reduced");
+                    };
+                    self.nodes[redex] = self.apply(prim, a, b)?;
+                }
+                _ => unreachable!("only combinators and builtins take arguments"),
+            }
+            spine.truncate(len - arity);
+        }
+    }
+
+    /// Returns the node for `prim` applied to `a` and `b`.
+    fn apply(&mut self, prim: Prim, a: i64, b: i64) -> Result<Node, Error> {
+        Ok(match prim.apply(a, b).map_err(error)? {
+            PrimValue::Int(int) => Node::Int(int),
+            // the Church booleans: `true` is `K` and `false` is `K I`
+            PrimValue::Bool(true) => Node::Comb(Comb::K),
+            PrimValue::Bool(false) => {
+                let k = self.alloc(Node::Comb(Comb::K))?;
+                Node::App(k, self.alloc(Node::Comb(Comb::I))?)
+            }
+        })
+    }
+}
@@ -0,0 +1,1904 @@
+//! The stack machine that runs a [`Program`].
+
+use std::{
+    any::Any, cell::RefCell, collections::HashSet, fmt, mem, ops::Range, rc::Rc, time::Instant,
+};
+
+use ria_parser::loader::ModuleId;
+
+use crate::{
+    builtins::boolean,
+    bytecode::{CaptureSource, Evaluation, Function, FunctionId, Op, Origin, Program},
+    natives::{type_error, FromValue, Intrinsic},
+};
+
+/// A runtime value.
+#[derive(Debug, Clone)]
+pub enum Value {
+    Int(i64),
+    Closure(Rc<Closure>),
+    /// A value that hasn't been computed yet, in lazy programs.
+    Thunk(Rc<Thunk>),
+    /// A native function, possibly applied to some of its arguments.
+    Native(Rc<Partial>),
+    /// A Rust value that ria code can pass around but not look into.
+    Host(Rc<dyn Any>),
+    /// A value built with a constructor of a data type.
+    Data(Rc<Data>),
+    /// A constructor applied to fewer values than it has fields.
+    Constructor(Rc<Data>),
+    Tuple(Rc<Tuple>),
+    Record(Rc<Record>),
+}
+
+impl Value {
+    /// Returns whether `self` and `other` are the same closure, thunk,
+    /// native, host, data value, tuple or record, or equal integers.
+    pub fn ptr_eq(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::Int(a), Value::Int(b)) => a == b,
+            (Value::Closure(a), Value::Closure(b)) => Rc::ptr_eq(a, b),
+            (Value::Thunk(a), Value::Thunk(b)) => Rc::ptr_eq(a, b),
+            (Value::Native(a), Value::Native(b)) => Rc::ptr_eq(a, b),
+            (Value::Host(a), Value::Host(b)) => Rc::ptr_eq(a, b),
+            (Value::Data(a), Value::Data(b)) => Rc::ptr_eq(a, b),
+            (Value::Constructor(a), Value::Constructor(b)) => Rc::ptr_eq(a, b),
+            (Value::Tuple(a), Value::Tuple(b)) => Rc::ptr_eq(a, b),
+            (Value::Record(a), Value::Record(b)) => Rc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
+
+    /// Describes the type of the value, for error messages.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Int(_) => "an integer",
+            Value::Closure(_) | Value::Native(_) | Value::Constructor(_) => "a function",
+            Value::Thunk(_) => "a thunk",
+            Value::Host(_) => "a host value",
+            Value::Data(_) => "a data value",
+            Value::Tuple(_) => "a tuple",
+            Value::Record(_) => "a record",
+        }
+    }
+
+    /// Returns a [`fmt::Display`] for the value, which names functions after
+    /// the def they were bound to in `program`, and writes data values like
+    /// `Cons 1 (Cons 2 Nil)`, tuples like `(1, 2)` and records like
+    /// `{ x = 1, y = 2 }`. Thunks that were forced show their value.
+    pub fn display<'a>(&'a self, program: &'a Program) -> impl fmt::Display + 'a {
+        DisplayValue {
+            value: self,
+            program,
+        }
+    }
+}
+
+struct DisplayValue<'a> {
+    value: &'a Value,
+    program: &'a Program,
+}
+
+/// A part of a [`DisplayValue`] that is yet to be written.
+enum Piece<'a> {
+    /// A value, and whether it is the field of a data value.
+    Value(Value, bool),
+    Text(&'a str),
+}
+
+impl fmt::Display for DisplayValue<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // data values can be as deep as a long list, so the pieces are
+        // written from a stack rather than by recursion
+        let mut pieces = vec![Piece::Value(self.value.clone(), false)];
+        while let Some(piece) = pieces.pop() {
+            let (value, field) = match piece {
+                Piece::Value(value, field) => (value, field),
+                Piece::Text(text) => {
+                    f.write_str(text)?;
+                    continue;
+                }
+            };
+            match value {
+                Value::Int(int) => write!(f, "{int}")?,
+                Value::Closure(closure) => {
+                    match &self.program.functions[closure.function as usize].name {
+                        Some(name) => write!(f, "<function {name}>")?,
+                        None => write!(f, "<function>")?,
+                    }
+                }
+                Value::Thunk(thunk) => match &*thunk.state.borrow() {
+                    ThunkState::Done(value) => pieces.push(Piece::Value(value.clone(), field)),
+                    _ => write!(f, "<thunk>")?,
+                },
+                Value::Native(partial) => {
+                    let name = &self.program.natives[partial.native as usize].name;
+                    write!(f, "<native {name}>")?;
+                }
+                Value::Host(_) => write!(f, "<host value>")?,
+                Value::Data(data) => {
+                    let name = &self.program.constructors[data.constructor as usize].name;
+                    if data.fields.is_empty() {
+                        f.write_str(name)?;
+                        continue;
+                    }
+                    if field {
+                        f.write_str("(")?;
+                        pieces.push(Piece::Text(")"));
+                    }
+                    f.write_str(name)?;
+                    for value in data.fields.iter().rev() {
+                        pieces.push(Piece::Value(value.clone(), true));
+                        pieces.push(Piece::Text(" "));
+                    }
+                }
+                Value::Constructor(partial) => {
+                    let name = &self.program.constructors[partial.constructor as usize].name;
+                    write!(f, "<constructor {name}>")?;
+                }
+                Value::Tuple(tuple) => {
+                    f.write_str("(")?;
+                    pieces.push(Piece::Text(")"));
+                    for (index, item) in tuple.items.iter().enumerate().rev() {
+                        pieces.push(Piece::Value(item.clone(), false));
+                        if index > 0 {
+                            pieces.push(Piece::Text(", "));
+                        }
+                    }
+                }
+                Value::Record(record) => {
+                    if record.fields.is_empty() {
+                        f.write_str("{}")?;
+                        continue;
+                    }
+                    f.write_str("{ ")?;
+                    pieces.push(Piece::Text(" }"));
+                    let shape = &self.program.shapes[record.shape as usize];
+                    for (index, (value, &name)) in
+                        record.fields.iter().zip(shape.iter()).enumerate().rev()
+                    {
+                        pieces.push(Piece::Value(value.clone(), false));
+                        pieces.push(Piece::Text(" = "));
+                        pieces.push(Piece::Text(&self.program.fields[name as usize]));
+                        if index > 0 {
+                            pieces.push(Piece::Text(", "));
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A function together with the values it captured.
+#[derive(Debug)]
+pub struct Closure {
+    pub function: FunctionId,
+    captures: Box<[Slot]>,
+}
+
+/// A native function and the arguments it has been applied to so far.
+#[derive(Debug)]
+pub struct Partial {
+    pub(crate) native: u32,
+    pub(crate) args: Box<[Value]>,
+}
+
+/// A constructor and the values of its fields.
+#[derive(Debug)]
+pub struct Data {
+    /// The index of the constructor in [`Program::constructors`].
+    pub constructor: u32,
+    pub fields: Box<[Value]>,
+}
+
+/// The items of a tuple.
+#[derive(Debug)]
+pub struct Tuple {
+    pub items: Box<[Value]>,
+}
+
+/// The values of the fields of a record.
+#[derive(Debug)]
+pub struct Record {
+    /// The index of the record's shape in [`Program::shapes`], which names
+    /// its fields.
+    pub shape: u32,
+    pub fields: Box<[Value]>,
+}
+
+/// A value that holds other values.
+///
+/// Dropping a long list, or a long chain of closures or thunks, value by
+/// value would recurse as deep as the chain is long. So when a holder is
+/// dropped, the values it holds are moved out of it, and each of those that
+/// is about to be dropped too has its own values moved out, from a stack.
+trait Holder {
+    /// Moves the values that `self` holds into `values`.
+    fn take_values(&mut self, values: &mut Vec<Value>);
+
+    fn drop_values(&mut self) {
+        let mut values = Vec::new();
+        self.take_values(&mut values);
+        while let Some(mut value) = values.pop() {
+            match &mut value {
+                Value::Int(_) | Value::Host(_) => {}
+                Value::Closure(closure) => take_values(closure, &mut values),
+                Value::Thunk(thunk) => take_values(thunk, &mut values),
+                Value::Native(partial) => take_values(partial, &mut values),
+                Value::Data(data) | Value::Constructor(data) => take_values(data, &mut values),
+                Value::Tuple(tuple) => take_values(tuple, &mut values),
+                Value::Record(record) => take_values(record, &mut values),
+            }
+        }
+    }
+}
+
+/// Moves the values that `holder` holds into `values`, if nothing else
+/// shares it, so dropping it drops nothing else.
+fn take_values(holder: &mut Rc<impl Holder>, values: &mut Vec<Value>) {
+    if let Some(holder) = Rc::get_mut(holder) {
+        holder.take_values(values);
+    }
+}
+
+macro_rules! drop_holder {
+    ($($holder:ty),*) => {
+        $(impl Drop for $holder {
+            fn drop(&mut self) {
+                self.drop_values();
+            }
+        })*
+    };
+}
+
+drop_holder!(Closure, Thunk, Partial, Data, Tuple, Record);
+
+impl Holder for Closure {
+    fn take_values(&mut self, values: &mut Vec<Value>) {
+        for slot in mem::take(&mut self.captures).into_vec() {
+            match slot {
+                Slot::Empty => {}
+                Slot::Value(value) => values.push(value),
+                Slot::Cell(cell) => {
+                    if let Some(cell) = Rc::into_inner(cell) {
+                        values.extend(cell.into_inner());
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Holder for Thunk {
+    fn take_values(&mut self, values: &mut Vec<Value>) {
+        values.push(
+            match mem::replace(self.state.get_mut(), ThunkState::Done(Value::Int(0))) {
+                ThunkState::Pending(closure) | ThunkState::Forcing(closure) => {
+                    Value::Closure(closure)
+                }
+                ThunkState::Done(value) => value,
+            },
+        );
+    }
+}
+
+impl Holder for Partial {
+    fn take_values(&mut self, values: &mut Vec<Value>) {
+        values.append(&mut mem::take(&mut self.args).into_vec());
+    }
+}
+
+impl Holder for Data {
+    fn take_values(&mut self, values: &mut Vec<Value>) {
+        values.append(&mut mem::take(&mut self.fields).into_vec());
+    }
+}
+
+impl Holder for Tuple {
+    fn take_values(&mut self, values: &mut Vec<Value>) {
+        values.append(&mut mem::take(&mut self.items).into_vec());
+    }
+}
+
+impl Holder for Record {
+    fn take_values(&mut self, values: &mut Vec<Value>) {
+        values.append(&mut mem::take(&mut self.fields).into_vec());
+    }
+}
+
+/// A delayed computation, which is run at most once.
+#[derive(Debug)]
+pub struct Thunk {
+    state: RefCell<ThunkState>,
+}
+
+#[derive(Debug)]
+enum ThunkState {
+    /// Not forced yet. The closure is of a function without a parameter.
+    Pending(Rc<Closure>),
+    /// Being forced, so forcing it again would never finish.
+    Forcing(Rc<Closure>),
+    /// Forced, to a value that is never itself a thunk.
+    Done(Value),
+}
+
+impl Thunk {
+    fn new(closure: Rc<Closure>) -> Self {
+        Self {
+            state: RefCell::new(ThunkState::Pending(closure)),
+        }
+    }
+}
+
+/// The contents of a local slot or a captured variable.
+#[derive(Debug, Clone)]
+enum Slot {
+    Empty,
+    Value(Value),
+    /// A def that may be captured before it is initialized.
+    Cell(Rc<RefCell<Option<Value>>>),
+}
+
+impl Slot {
+    fn value(&self) -> &Value {
+        match self {
+            Slot::Value(value) => value,
+            _ => unreachable!("slot is read as a value"),
+        }
+    }
+
+    fn cell(&self) -> &RefCell<Option<Value>> {
+        match self {
+            Slot::Cell(cell) => cell,
+            _ => unreachable!("slot is read as a cell"),
+        }
+    }
+
+    /// Returns the value in the slot, if it has been initialized.
+    fn get(&self) -> Option<Value> {
+        match self {
+            Slot::Empty => None,
+            Slot::Value(value) => Some(value.clone()),
+            Slot::Cell(cell) => cell.borrow().clone(),
+        }
+    }
+}
+
+/// A runtime error, with the span of the expression that caused it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Error {
+    pub kind: ErrorKind,
+    pub span: Range<usize>,
+    /// The imported module the span is in, or `None` if it's in the main
+    /// module.
+    pub module: Option<ModuleId>,
+}
+
+impl Error {
+    /// Returns an error of `kind`, at the start of the main module.
+    pub fn new(kind: ErrorKind) -> Self {
+        Self {
+            kind,
+            span: 0..0,
+            module: None,
+        }
+    }
+
+    /// Returns an error for a native to fail with. The VM fills in the span.
+    pub fn native(message: impl Into<String>) -> Self {
+        Self::new(ErrorKind::Native(message.into()))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// A def was used while its own value was being computed.
+    Uninitialized,
+    /// A thunk demanded its own value while it was being forced.
+    Loop,
+    /// The program has no `main` def to run.
+    NoMain,
+    /// The host asked for a def that the program doesn't have.
+    NoDef(String),
+    /// A value had the wrong type, like a call of something that isn't a
+    /// function or a native getting the wrong kind of argument.
+    Type {
+        expected: &'static str,
+        found: &'static str,
+    },
+    /// A native failed.
+    Native(String),
+    /// No arm of a `match` matched the value.
+    NoMatch,
+    /// A record or tuple has no field with the name or index that was read.
+    NoField(String),
+    /// Integer arithmetic overflowed.
+    Overflow,
+    /// An integer was divided by zero.
+    DivisionByZero,
+    /// The VM ran more instructions than [`Limits::fuel`] allows.
+    OutOfFuel,
+    /// Calls and thunks were nested deeper than [`Limits::max_depth`].
+    TooDeep,
+    /// The VM allocated more than [`Limits::max_allocations`].
+    TooManyAllocations,
+    /// Evaluation was still running at [`Limits::deadline`].
+    DeadlineExceeded,
+    /// The arguments of `assert_eq` weren't equal. Each is written as
+    /// [`Value::display`] writes it.
+    AssertionFailed { left: String, right: String },
+    /// An evaluator that runs only part of the language, like the
+    /// [`interp`](crate::interp) baseline, reached an expression outside of
+    /// it, of the kind named.
+    Unsupported(&'static str),
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ErrorKind::Uninitialized => write!(f, "value is used before it is initialized"),
+            ErrorKind::Loop => write!(f, "<<loop>>"),
+            ErrorKind::NoMain => write!(f, "no `main` definition to run"),
+            ErrorKind::NoDef(name) => write!(f, "no definition named `{name}`"),
+            ErrorKind::Type { expected, found } => write!(f, "expected {expected}, found {found}"),
+            ErrorKind::Native(message) => write!(f, "{message}"),
+            ErrorKind::NoMatch => write!(f, "no pattern matches the value"),
+            ErrorKind::NoField(field) => write!(f, "no field `{field}`"),
+            ErrorKind::Overflow => write!(f, "integer overflow"),
+            ErrorKind::DivisionByZero => write!(f, "division by zero"),
+            ErrorKind::OutOfFuel => write!(f, "ran out of fuel"),
+            ErrorKind::TooDeep => write!(f, "calls are nested too deeply"),
+            ErrorKind::TooManyAllocations => write!(f, "too many allocations"),
+            ErrorKind::DeadlineExceeded => write!(f, "evaluation ran past its deadline"),
+            ErrorKind::AssertionFailed { left, right } => {
+                write!(f, "assertion failed: `{left}` isn't equal to `{right}`")
+            }
+            ErrorKind::Unsupported(what) => write!(f, "{what} can't be evaluated here"),
+        }
+    }
+}
+
+/// How many instructions run between checks of [`Limits::deadline`].
+pub const DEADLINE_INTERVAL: u64 = 1024;
+
+/// Caps on the resources a [`Vm`] may use, over its whole lifetime. Each limit
+/// is off when it is `None`, which is the default.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Limits {
+    /// The number of instructions the VM may run.
+    pub fuel: Option<u64>,
+    /// The number of calls and thunk forces that may be in progress at once.
+    pub max_depth: Option<usize>,
+    /// The number of closures, thunks, cells, data values, tuples and
+    /// records the VM may create.
+    pub max_allocations: Option<u64>,
+    /// When evaluation must stop. It is checked every [`DEADLINE_INTERVAL`]
+    /// instructions, so it may be overrun by that many instructions.
+    pub deadline: Option<Instant>,
+}
+
+/// Where in the source of a program an error is reported.
+#[derive(Debug, Clone, Default)]
+struct Location {
+    span: Range<usize>,
+    /// The imported module the span is in, or `None` for the main module.
+    module: Option<ModuleId>,
+}
+
+impl Location {
+    fn error(self, kind: ErrorKind) -> Error {
+        Error {
+            kind,
+            span: self.span,
+            module: self.module,
+        }
+    }
+}
+
+/// A call in progress.
+struct Frame {
+    closure: Rc<Closure>,
+    ip: usize,
+    /// The index of the frame's first local slot.
+    base: usize,
+    /// The thunk that the frame is forcing, which is updated with the result.
+    update: Option<Rc<Thunk>>,
+    /// Whether the result is only for the thunk, rather than also for the
+    /// frame below.
+    discard: bool,
+}
+
+/// Runs the functions of a [`Program`].
+///
+/// Calls don't recurse on the native stack, so deep recursion in a program
+/// only grows the VM's own stacks. [`Limits`] bound how far those stacks and
+/// the rest of the VM's work may grow.
+pub struct Vm<'p> {
+    program: &'p Program,
+    limits: Limits,
+    /// The number of instructions run so far.
+    steps: u64,
+    /// The number of closures, thunks, cells, data values, tuples and records
+    /// created so far.
+    allocations: u64,
+    globals: Vec<Option<Value>>,
+    /// A value for each native of the program, not yet applied to anything.
+    natives: Box<[Value]>,
+    /// A value for each constructor of the program, not yet applied to
+    /// anything.
+    constructors: Box<[Value]>,
+    frames: Vec<Frame>,
+    locals: Vec<Slot>,
+    stack: Vec<Value>,
+}
+
+/// Initializes the globals of `program` and returns the value of `main`,
+/// with the fields of data values forced all the way down.
+pub fn run(program: &Program) -> Result<Value, Error> {
+    run_with_limits(program, Limits::default())
+}
+
+/// Like [`run`], but stops with an error when evaluation goes over `limits`.
+pub fn run_with_limits(program: &Program, limits: Limits) -> Result<Value, Error> {
+    let mut vm = Vm::with_limits(program, limits);
+    vm.init()?;
+    let main = vm
+        .global("main")
+        .cloned()
+        .ok_or(Error::new(ErrorKind::NoMain))?;
+    vm.force_deep(main)
+}
+
+impl<'p> Vm<'p> {
+    pub fn new(program: &'p Program) -> Self {
+        Self::with_limits(program, Limits::default())
+    }
+
+    pub fn with_limits(program: &'p Program, limits: Limits) -> Self {
+        Self {
+            program,
+            limits,
+            steps: 0,
+            allocations: 0,
+            globals: vec![None; program.globals.len()],
+            natives: (0..program.natives.len() as u32)
+                .map(|native| {
+                    Value::Native(Rc::new(Partial {
+                        native,
+                        args: Box::new([]),
+                    }))
+                })
+                .collect(),
+            constructors: program
+                .constructors
+                .iter()
+                .zip(0..)
+                .map(|(constructor, index)| {
+                    let data = Rc::new(Data {
+                        constructor: index,
+                        fields: Box::new([]),
+                    });
+                    match constructor.arity {
+                        0 => Value::Data(data),
+                        _ => Value::Constructor(data),
+                    }
+                })
+                .collect(),
+            frames: Vec::new(),
+            locals: Vec::new(),
+            stack: Vec::new(),
+        }
+    }
+
+    /// Returns the number of instructions the VM has run.
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    /// Returns the number of closures, thunks, cells and data values the VM
+    /// has created.
+    pub fn allocations(&self) -> u64 {
+        self.allocations
+    }
+
+    /// Computes the value of every global, in dependency order. In a lazy
+    /// program, each global is instead set to a thunk that computes it.
+    pub fn init(&mut self) -> Result<(), Error> {
+        for &index in self.program.init_order.iter() {
+            let closure = Rc::new(Closure {
+                function: self.program.globals[index as usize].init,
+                captures: Box::new([]),
+            });
+            let value = match self.program.evaluation {
+                Evaluation::Strict => self.enter(closure, None, None)?,
+                Evaluation::Lazy => Value::Thunk(Rc::new(Thunk::new(closure))),
+            };
+            self.globals[index as usize] = Some(value);
+        }
+        Ok(())
+    }
+
+    /// Returns the value of the global called `name`, if it is initialized.
+    /// In a lazy program, the value may be a thunk that is yet to be forced.
+    pub fn global(&self, name: &str) -> Option<&Value> {
+        let index = self.program.global(name)?;
+        self.globals[index as usize].as_ref()
+    }
+
+    /// Runs the test at `index` in [`Program::tests`], after [`Vm::init`],
+    /// and returns its value with the fields of data values forced all the
+    /// way down.
+    pub fn run_test(&mut self, index: usize) -> Result<Value, Error> {
+        let closure = Rc::new(Closure {
+            function: self.program.tests[index].init,
+            captures: Box::new([]),
+        });
+        let value = self.enter(closure, None, None)?;
+        self.force_deep(value)
+    }
+
+    /// Calls `func` with `arg` and returns the result, which may be a thunk
+    /// in a lazy program.
+    pub fn call(&mut self, func: &Value, arg: Value) -> Result<Value, Error> {
+        match self.force(func.clone())? {
+            Value::Closure(closure) => self.enter(closure, Some(arg), None),
+            Value::Native(partial) => self.apply_native(&partial, arg, Location::default()),
+            Value::Constructor(partial) => {
+                self.apply_constructor(&partial, arg, Location::default())
+            }
+            Value::Thunk(_) => unreachable!("forced values aren't thunks"),
+            value => Err(type_error("a function", value.type_name())),
+        }
+    }
+
+    /// Calls `func` with each of `args` in turn, and returns the forced
+    /// result.
+    pub fn apply(
+        &mut self,
+        func: &Value,
+        args: impl IntoIterator<Item = Value>,
+    ) -> Result<Value, Error> {
+        let mut result = func.clone();
+        for arg in args {
+            result = self.call(&result, arg)?;
+        }
+        self.force(result)
+    }
+
+    /// Calls the global called `name` with each of `args` in turn, and
+    /// converts the result to a Rust value.
+    pub fn call_global<R: FromValue>(
+        &mut self,
+        name: &str,
+        args: impl IntoIterator<Item = Value>,
+    ) -> Result<R, Error> {
+        let func = self
+            .global(name)
+            .cloned()
+            .ok_or_else(|| Error::new(ErrorKind::NoDef(name.to_owned())))?;
+        R::from_value(self.apply(&func, args)?)
+    }
+
+    /// Applies a native to one more argument, calling it if that was its
+    /// last argument. Errors from the native are reported at `location`.
+    fn apply_native(
+        &mut self,
+        partial: &Partial,
+        arg: Value,
+        location: Location,
+    ) -> Result<Value, Error> {
+        let native = &self.program.natives[partial.native as usize];
+        let mut args = partial.args.to_vec();
+        args.push(arg);
+        if args.len() < native.arity {
+            return Ok(Value::Native(Rc::new(Partial {
+                native: partial.native,
+                args: args.into(),
+            })));
+        }
+
+        if native.strict {
+            for arg in &mut args {
+                *arg = self.force(arg.clone())?;
+            }
+        }
+        let result = match native.func.intrinsic() {
+            Some(Intrinsic::AssertEq) => self.assert_eq(&args[0], &args[1]),
+            None => native.func.call(&args),
+        };
+        result.map_err(|err| location.error(err.kind))
+    }
+
+    /// Returns the first argument that a strict native needs forced before
+    /// it can be called with `arg` as well as the arguments of `partial`,
+    /// with its closure, if it is a thunk that hasn't been forced yet.
+    fn unforced_arg(&self, partial: &Partial, arg: &Value) -> Option<(Rc<Thunk>, Rc<Closure>)> {
+        let native = &self.program.natives[partial.native as usize];
+        if !native.strict || partial.args.len() + 1 < native.arity {
+            return None;
+        }
+        partial.args.iter().chain([arg]).find_map(|arg| match arg {
+            Value::Thunk(thunk) => match &*thunk.state.borrow() {
+                ThunkState::Pending(closure) => Some((thunk.clone(), closure.clone())),
+                _ => None,
+            },
+            _ => None,
+        })
+    }
+
+    fn assert_eq(&mut self, left: &Value, right: &Value) -> Result<Value, Error> {
+        if self.equal(left, right)? {
+            return Ok(boolean(true));
+        }
+        Err(Error::new(ErrorKind::AssertionFailed {
+            left: left.display(self.program).to_string(),
+            right: right.display(self.program).to_string(),
+        }))
+    }
+
+    /// Returns whether `a` and `b` are equal, forcing as much of them as it
+    /// takes to tell.
+    ///
+    /// Integers are equal if they have the same value, and host values if
+    /// they are the same value. Data values, tuples and records are equal if
+    /// they have the same constructor or field names and equal fields, and
+    /// partially applied natives and constructors if they are the same and
+    /// were applied to equal arguments. Closures are equal if their
+    /// functions are alpha-equivalent, running the same instructions on
+    /// variables that may have different names, and their captured values
+    /// are equal. Values that refer back to themselves are equal if no
+    /// difference turns up.
+    ///
+    /// Like [`Vm::force_deep`], this doesn't finish for equal infinite data.
+    pub fn equal(&mut self, a: &Value, b: &Value) -> Result<bool, Error> {
+        let program = self.program;
+        let mut pending = vec![(a.clone(), b.clone())];
+        // closures being compared, which are taken to be equal if they are
+        // reached again
+        let mut assumed = HashSet::new();
+        let mut equivalent = HashSet::new();
+        while let Some((a, b)) = pending.pop() {
+            let (a, b) = (self.force(a)?, self.force(b)?);
+            if a.ptr_eq(&b) {
+                continue;
+            }
+            let equal = match (&a, &b) {
+                (Value::Data(a), Value::Data(b))
+                | (Value::Constructor(a), Value::Constructor(b)) => {
+                    let equal = a.constructor == b.constructor && a.fields.len() == b.fields.len();
+                    pending.extend(a.fields.iter().cloned().zip(b.fields.iter().cloned()));
+                    equal
+                }
+                (Value::Tuple(a), Value::Tuple(b)) => {
+                    pending.extend(a.items.iter().cloned().zip(b.items.iter().cloned()));
+                    a.items.len() == b.items.len()
+                }
+                (Value::Record(a), Value::Record(b)) => {
+                    let fields = |record: &Record| {
+                        let shape = &program.shapes[record.shape as usize];
+                        let mut fields: Vec<_> =
+                            shape.iter().zip(record.fields.iter().cloned()).collect();
+                        fields.sort_by_key(|&(&name, _)| name);
+                        fields
+                    };
+                    let (a, b) = (fields(a), fields(b));
+                    let names = |fields: &[(&u32, Value)]| -> Vec<u32> {
+                        fields.iter().map(|&(&name, _)| name).collect()
+                    };
+                    let equal = names(&a) == names(&b);
+                    pending.extend(a.into_iter().zip(b).map(|((_, a), (_, b))| (a, b)));
+                    equal
+                }
+                (Value::Native(a), Value::Native(b)) => {
+                    pending.extend(a.args.iter().cloned().zip(b.args.iter().cloned()));
+                    a.native == b.native && a.args.len() == b.args.len()
+                }
+                (Value::Closure(a), Value::Closure(b)) => {
+                    if !assumed.insert((Rc::as_ptr(a), Rc::as_ptr(b))) {
+                        continue;
+                    }
+                    if !self.alpha_equivalent(a.function, b.function, &mut equivalent) {
+                        return Ok(false);
+                    }
+                    for (a, b) in a.captures.iter().zip(b.captures.iter()) {
+                        match (a.get(), b.get()) {
+                            (Some(a), Some(b)) => pending.push((a, b)),
+                            (None, None) => {}
+                            _ => return Ok(false),
+                        }
+                    }
+                    true
+                }
+                _ => false,
+            };
+            if !equal {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Returns whether functions `f` and `g` take the same parameter and
+    /// number of captures and run the same instructions, apart from spans,
+    /// where the functions they create closures and thunks of capture the
+    /// same slots and are alpha-equivalent too. Pairs in `equivalent` are
+    /// taken to be alpha-equivalent, and the pairs compared are added to it.
+    fn alpha_equivalent(
+        &self,
+        f: FunctionId,
+        g: FunctionId,
+        equivalent: &mut HashSet<(FunctionId, FunctionId)>,
+    ) -> bool {
+        let mut pending = vec![(f, g)];
+        while let Some((f, g)) = pending.pop() {
+            if f == g || !equivalent.insert((f, g)) {
+                continue;
+            }
+            let f = &self.program.functions[f as usize];
+            let g = &self.program.functions[g as usize];
+            if f.has_param != g.has_param
+                || f.locals != g.locals
+                || f.captures.len() != g.captures.len()
+                || f.code.len() != g.code.len()
+            {
+                return false;
+            }
+            for (a, b) in f.code.iter().zip(g.code.iter()) {
+                match (*a, *b) {
+                    (Op::Closure(a), Op::Closure(b)) | (Op::Thunk(a), Op::Thunk(b)) => {
+                        let functions = &self.program.functions;
+                        if functions[a as usize].captures != functions[b as usize].captures {
+                            return false;
+                        }
+                        pending.push((a, b));
+                    }
+                    (a, b) if a == b => {}
+                    _ => return false,
+                }
+            }
+        }
+        true
+    }
+
+    /// Applies a constructor to the value of its next field, building a data
+    /// value if that was its last field. Going over
+    /// [`Limits::max_allocations`] is reported at `location`.
+    fn apply_constructor(
+        &mut self,
+        partial: &Data,
+        arg: Value,
+        location: Location,
+    ) -> Result<Value, Error> {
+        self.allocations += 1;
+        if self
+            .limits
+            .max_allocations
+            .is_some_and(|limit| self.allocations > limit)
+        {
+            return Err(location.error(ErrorKind::TooManyAllocations));
+        }
+
+        let constructor = &self.program.constructors[partial.constructor as usize];
+        let mut fields = partial.fields.to_vec();
+        fields.push(arg);
+        let data = Rc::new(Data {
+            constructor: partial.constructor,
+            fields: fields.into(),
+        });
+        if data.fields.len() < constructor.arity as usize {
+            Ok(Value::Constructor(data))
+        } else {
+            Ok(Value::Data(data))
+        }
+    }
+
+    /// Returns the value of `value`, forcing it if it is a thunk.
+    pub fn force(&mut self, value: Value) -> Result<Value, Error> {
+        let Value::Thunk(thunk) = value else {
+            return Ok(value);
+        };
+        let state = thunk.state.borrow();
+        match &*state {
+            ThunkState::Done(value) => Ok(value.clone()),
+            ThunkState::Forcing(closure) => {
+                let function = &self.program.functions[closure.function as usize];
+                Err(self
+                    .location(function, function.spans.len() - 1)
+                    .error(ErrorKind::Loop))
+            }
+            ThunkState::Pending(closure) => {
+                let closure = closure.clone();
+                drop(state);
+                *thunk.state.borrow_mut() = ThunkState::Forcing(closure.clone());
+                self.enter(closure, None, Some(thunk))
+            }
+        }
+    }
+
+    /// Like [`Vm::force`], but if the value is a data value, tuple or
+    /// record, also forces its fields, their fields and so on.
+    ///
+    /// This doesn't finish for infinite data, like a stream that is never
+    /// cut off.
+    pub fn force_deep(&mut self, value: Value) -> Result<Value, Error> {
+        let value = self.force(value)?;
+        let mut pending = vec![value.clone()];
+        while let Some(value) = pending.pop() {
+            match self.force(value)? {
+                Value::Data(data) => pending.extend(data.fields.iter().cloned()),
+                Value::Tuple(tuple) => pending.extend(tuple.items.iter().cloned()),
+                Value::Record(record) => pending.extend(record.fields.iter().cloned()),
+                _ => {}
+            }
+        }
+        Ok(value)
+    }
+
+    /// Runs `closure` to completion, leaving the VM as it was if it fails.
+    fn enter(
+        &mut self,
+        closure: Rc<Closure>,
+        arg: Option<Value>,
+        update: Option<Rc<Thunk>>,
+    ) -> Result<Value, Error> {
+        // natives that compare values enter the VM again on the native stack
+        if let Some(frame) = self.frames.last() {
+            let function = &self.program.functions[frame.closure.function as usize];
+            if let Err(err) = self.check_depth(function, frame.ip - 1) {
+                if let Some(thunk) = update {
+                    *thunk.state.borrow_mut() = ThunkState::Pending(closure);
+                }
+                return Err(err);
+            }
+        }
+        let depth = self.frames.len();
+        let (locals, stack) = (self.locals.len(), self.stack.len());
+        self.push_frame(closure, arg, update);
+        let result = self.execute(depth);
+        if result.is_err() {
+            // thunks that were being forced can be forced again later
+            for frame in self.frames.drain(depth..) {
+                if let Some(thunk) = frame.update {
+                    let mut state = thunk.state.borrow_mut();
+                    if let ThunkState::Forcing(closure) = &*state {
+                        *state = ThunkState::Pending(closure.clone());
+                    }
+                }
+            }
+            self.locals.truncate(locals);
+            self.stack.truncate(stack);
+        }
+        result
+    }
+
+    fn push_frame(&mut self, closure: Rc<Closure>, arg: Option<Value>, update: Option<Rc<Thunk>>) {
+        let function = &self.program.functions[closure.function as usize];
+        let base = self.locals.len();
+        if let Some(arg) = arg {
+            self.locals.push(Slot::Value(arg));
+        }
+        self.locals
+            .resize(base + function.locals as usize, Slot::Empty);
+        self.frames.push(Frame {
+            closure,
+            ip: 0,
+            base,
+            update,
+            discard: false,
+        });
+    }
+
+    /// Pops the frame that is returning `value`, and returns the value if it
+    /// was the frame at `depth` that the VM was entered with.
+    fn return_value(&mut self, value: Value, depth: usize) -> Option<Value> {
+        let frame = self.frames.pop().expect("a frame is running");
+        if let Some(thunk) = frame.update {
+            *thunk.state.borrow_mut() = ThunkState::Done(value.clone());
+        }
+        self.locals.truncate(frame.base);
+        if self.frames.len() == depth {
+            return Some(value);
+        }
+        if !frame.discard {
+            self.stack.push(value);
+        }
+        None
+    }
+
+    fn pop(&mut self) -> Value {
+        self.stack.pop().expect("operand stack is not empty")
+    }
+
+    /// Returns where to report an error at the instruction at `ip` of
+    /// `function`, the running function.
+    ///
+    /// Spans of the prelude are in its own source, which isn't shown, so an
+    /// error there is reported at the innermost call from a module instead.
+    fn location(&self, function: &Function, ip: usize) -> Location {
+        let located = |function: &Function, ip: usize| {
+            let module = match function.origin {
+                Origin::Main => None,
+                Origin::Import(module) => Some(module),
+                Origin::Prelude => return None,
+            };
+            Some(Location {
+                span: function.spans[ip].clone(),
+                module,
+            })
+        };
+        located(function, ip)
+            .or_else(|| {
+                self.frames.iter().rev().find_map(|frame| {
+                    let function = &self.program.functions[frame.closure.function as usize];
+                    // the frame is past the call it is waiting on
+                    located(function, frame.ip - 1)
+                })
+            })
+            .unwrap_or_default()
+    }
+
+    /// Returns an error of `kind` at the instruction at `ip` if `count` is
+    /// over `limit`.
+    fn check_limit(
+        &self,
+        count: u64,
+        limit: Option<u64>,
+        kind: ErrorKind,
+        function: &Function,
+        ip: usize,
+    ) -> Result<(), Error> {
+        match limit {
+            Some(limit) if count > limit => Err(self.location(function, ip).error(kind)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Returns an error if pushing another frame would go over
+    /// [`Limits::max_depth`].
+    fn check_depth(&self, function: &Function, ip: usize) -> Result<(), Error> {
+        self.check_limit(
+            self.frames.len() as u64 + 1,
+            self.limits.max_depth.map(|depth| depth as u64),
+            ErrorKind::TooDeep,
+            function,
+            ip,
+        )
+    }
+
+    /// Runs instructions until the frame at `depth` returns.
+    fn execute(&mut self, depth: usize) -> Result<Value, Error> {
+        let program = self.program;
+        loop {
+            let frame = self.frames.last_mut().expect("a frame is running");
+            let ip = frame.ip;
+            frame.ip += 1;
+            let frame = &self.frames[self.frames.len() - 1];
+            let function = &program.functions[frame.closure.function as usize];
+            let base = frame.base;
+
+            self.steps += 1;
+            self.check_limit(
+                self.steps,
+                self.limits.fuel,
+                ErrorKind::OutOfFuel,
+                function,
+                ip,
+            )?;
+            if self.steps % DEADLINE_INTERVAL == 0
+                && self
+                    .limits
+                    .deadline
+                    .is_some_and(|deadline| Instant::now() >= deadline)
+            {
+                return Err(self
+                    .location(function, ip)
+                    .error(ErrorKind::DeadlineExceeded));
+            }
+
+            match function.code[ip] {
+                Op::Int(int) => self.stack.push(Value::Int(int)),
+                Op::Local(slot) => {
+                    let value = self.locals[base + slot as usize].value().clone();
+                    self.stack.push(value);
+                }
+                Op::LocalCell(slot) => {
+                    let value = self.locals[base + slot as usize].cell().borrow().clone();
+                    let value = value.ok_or_else(|| {
+                        self.location(function, ip).error(ErrorKind::Uninitialized)
+                    })?;
+                    self.stack.push(value);
+                }
+                Op::Capture(index) => {
+                    let value = frame.closure.captures[index as usize].value().clone();
+                    self.stack.push(value);
+                }
+                Op::CaptureCell(index) => {
+                    let value = frame.closure.captures[index as usize]
+                        .cell()
+                        .borrow()
+                        .clone();
+                    let value = value.ok_or_else(|| {
+                        self.location(function, ip).error(ErrorKind::Uninitialized)
+                    })?;
+                    self.stack.push(value);
+                }
+                Op::Native(index) => {
+                    self.stack.push(self.natives[index as usize].clone());
+                }
+                Op::Construct(index) => {
+                    self.stack.push(self.constructors[index as usize].clone());
+                }
+                Op::Global(index) => {
+                    let value = self.globals[index as usize].clone().ok_or_else(|| {
+                        self.location(function, ip).error(ErrorKind::Uninitialized)
+                    })?;
+                    self.stack.push(value);
+                }
+                op @ (Op::Closure(id) | Op::Thunk(id)) => {
+                    self.allocations += 1;
+                    self.check_limit(
+                        self.allocations,
+                        self.limits.max_allocations,
+                        ErrorKind::TooManyAllocations,
+                        function,
+                        ip,
+                    )?;
+                    let captures = program.functions[id as usize]
+                        .captures
+                        .iter()
+                        .map(|source| match *source {
+                            CaptureSource::Local(slot) => self.locals[base + slot as usize].clone(),
+                            CaptureSource::Capture(index) => {
+                                frame.closure.captures[index as usize].clone()
+                            }
+                        })
+                        .collect();
+                    let closure = Rc::new(Closure {
+                        function: id,
+                        captures,
+                    });
+                    let value = match op {
+                        Op::Thunk(_) => Value::Thunk(Rc::new(Thunk::new(closure))),
+                        _ => Value::Closure(closure),
+                    };
+                    self.stack.push(value);
+                }
+                Op::StoreLocal(slot) => {
+                    let value = self.pop();
+                    self.locals[base + slot as usize] = Slot::Value(value);
+                }
+                Op::NewCell(slot) => {
+                    self.allocations += 1;
+                    self.check_limit(
+                        self.allocations,
+                        self.limits.max_allocations,
+                        ErrorKind::TooManyAllocations,
+                        function,
+                        ip,
+                    )?;
+                    self.locals[base + slot as usize] = Slot::Cell(Rc::default());
+                }
+                Op::StoreCell(slot) => {
+                    let value = self.pop();
+                    *self.locals[base + slot as usize].cell().borrow_mut() = Some(value);
+                }
+                Op::Force => {
+                    let thunk = match self.pop() {
+                        Value::Thunk(thunk) => thunk,
+                        value => {
+                            self.stack.push(value);
+                            continue;
+                        }
+                    };
+                    let state = thunk.state.borrow();
+                    match &*state {
+                        ThunkState::Done(value) => self.stack.push(value.clone()),
+                        ThunkState::Forcing(_) => {
+                            return Err(self.location(function, ip).error(ErrorKind::Loop));
+                        }
+                        ThunkState::Pending(closure) => {
+                            let closure = closure.clone();
+                            drop(state);
+                            self.check_depth(function, ip)?;
+                            *thunk.state.borrow_mut() = ThunkState::Forcing(closure.clone());
+                            self.push_frame(closure, None, Some(thunk));
+                        }
+                    }
+                }
+                op @ (Op::Call | Op::TailCall) => {
+                    let arg = self.pop();
+                    match self.pop() {
+                        Value::Closure(closure) if op == Op::TailCall => {
+                            let frame = self.frames.pop().expect("a frame is running");
+                            debug_assert!(frame.update.is_none(), "thunks don't make tail calls");
+                            self.locals.truncate(base);
+                            self.push_frame(closure, Some(arg), None);
+                        }
+                        Value::Closure(closure) => {
+                            self.check_depth(function, ip)?;
+                            self.push_frame(closure, Some(arg), None);
+                        }
+                        func @ (Value::Native(_) | Value::Constructor(_)) => {
+                            let unforced = match &func {
+                                Value::Native(partial) => self.unforced_arg(partial, &arg),
+                                _ => None,
+                            };
+                            if let Some((thunk, closure)) = unforced {
+                                // forcing the argument here rather than in
+                                // the native keeps it off the native stack,
+                                // and the call is made again once it's done
+                                self.check_depth(function, ip)?;
+                                self.stack.push(func);
+                                self.stack.push(arg);
+                                self.frames.last_mut().expect("a frame is running").ip = ip;
+                                *thunk.state.borrow_mut() = ThunkState::Forcing(closure.clone());
+                                self.push_frame(closure, None, Some(thunk));
+                                self.frames.last_mut().expect("a frame is running").discard = true;
+                                continue;
+                            }
+                            let location = self.location(function, ip);
+                            let value = match func {
+                                Value::Native(partial) => {
+                                    self.apply_native(&partial, arg, location)?
+                                }
+                                Value::Constructor(partial) => {
+                                    self.apply_constructor(&partial, arg, location)?
+                                }
+                                _ => unreachable!("the function is a native or a constructor"),
+                            };
+                            if op == Op::Call {
+                                self.stack.push(value);
+                            } else if let Some(value) = self.return_value(value, depth) {
+                                return Ok(value);
+                            }
+                        }
+                        Value::Thunk(_) => {
+                            unreachable!("functions are forced before they are called")
+                        }
+                        value => {
+                            return Err(self
+                                .location(function, ip)
+                                .error(type_error("a function", value.type_name()).kind));
+                        }
+                    }
+                }
+                Op::Return => {
+                    let value = self.pop();
+                    if let Some(value) = self.return_value(value, depth) {
+                        return Ok(value);
+                    }
+                }
+                Op::Jump(target) => {
+                    self.frames.last_mut().expect("a frame is running").ip = target as usize;
+                }
+                Op::MatchConstructor {
+                    constructor,
+                    otherwise,
+                } => {
+                    let matched = match self.pop() {
+                        Value::Data(data) => data.constructor == constructor,
+                        value => {
+                            return Err(self
+                                .location(function, ip)
+                                .error(type_error("a data value", value.type_name()).kind));
+                        }
+                    };
+                    if !matched {
+                        self.frames.last_mut().expect("a frame is running").ip = otherwise as usize;
+                    }
+                }
+                Op::MatchInt { value, otherwise } => {
+                    let matched = match self.pop() {
+                        Value::Int(int) => int == value,
+                        value => {
+                            return Err(self
+                                .location(function, ip)
+                                .error(type_error("an integer", value.type_name()).kind));
+                        }
+                    };
+                    if !matched {
+                        self.frames.last_mut().expect("a frame is running").ip = otherwise as usize;
+                    }
+                }
+                Op::Field(index) => {
+                    let Value::Data(data) = self.pop() else {
+                        unreachable!("fields are read from matched data values");
+                    };
+                    self.stack.push(data.fields[index as usize].clone());
+                }
+                op @ (Op::Tuple(_) | Op::Record(_)) => {
+                    self.allocations += 1;
+                    self.check_limit(
+                        self.allocations,
+                        self.limits.max_allocations,
+                        ErrorKind::TooManyAllocations,
+                        function,
+                        ip,
+                    )?;
+                    let value = match op {
+                        Op::Tuple(len) => {
+                            let items = self.stack.split_off(self.stack.len() - len as usize);
+                            Value::Tuple(Rc::new(Tuple {
+                                items: items.into(),
+                            }))
+                        }
+                        Op::Record(shape) => {
+                            let len = program.shapes[shape as usize].len();
+                            let fields = self.stack.split_off(self.stack.len() - len);
+                            Value::Record(Rc::new(Record {
+                                shape,
+                                fields: fields.into(),
+                            }))
+                        }
+                        _ => unreachable!("the op builds a tuple or a record"),
+                    };
+                    self.stack.push(value);
+                }
+                Op::GetField(name) => {
+                    let record = match self.pop() {
+                        Value::Record(record) => record,
+                        value => {
+                            return Err(self
+                                .location(function, ip)
+                                .error(type_error("a record", value.type_name()).kind));
+                        }
+                    };
+                    let shape = &program.shapes[record.shape as usize];
+                    let Some(index) = shape.iter().position(|&field| field == name) else {
+                        return Err(self
+                            .location(function, ip)
+                            .error(ErrorKind::NoField(program.fields[name as usize].clone())));
+                    };
+                    self.stack.push(record.fields[index].clone());
+                }
+                Op::GetItem(index) => {
+                    let tuple = match self.pop() {
+                        Value::Tuple(tuple) => tuple,
+                        value => {
+                            return Err(self
+                                .location(function, ip)
+                                .error(type_error("a tuple", value.type_name()).kind));
+                        }
+                    };
+                    let Some(item) = tuple.items.get(index as usize) else {
+                        return Err(self
+                            .location(function, ip)
+                            .error(ErrorKind::NoField(index.to_string())));
+                    };
+                    self.stack.push(item.clone());
+                }
+                Op::NoMatch => {
+                    return Err(self.location(function, ip).error(ErrorKind::NoMatch));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use ria_parser::parse_module;
+
+    use crate::{bytecode::Evaluation, compile::compile};
+
+    use std::time::{Duration, Instant};
+
+    use super::{run, run_with_limits, ErrorKind, Limits, Value, Vm};
+
+    const CHURCH: &str = include_str!("../benches/church.ria");
+
+    /// Runs `main` after the Church numeral defs, and returns whether it is
+    /// `true` and whether it is `false`.
+    fn run_main_with(main: &str, evaluation: Evaluation) -> (bool, bool) {
+        let source = format!("{CHURCH}{main}");
+        let module = parse_module(&source).unwrap();
+        let program = compile(&module, evaluation).unwrap();
+        let mut vm = Vm::new(&program);
+        vm.init().unwrap();
+        let mut get = |name| {
+            let value = vm.global(name).unwrap().clone();
+            vm.force(value).unwrap()
+        };
+        let (main, t, f) = (get("main"), get("true"), get("false"));
+        (main.ptr_eq(&t), main.ptr_eq(&f))
+    }
+
+    fn run_main(main: &str) -> (bool, bool) {
+        let strict = run_main_with(main, Evaluation::Strict);
+        assert_eq!(run_main_with(main, Evaluation::Lazy), strict);
+        strict
+    }
+
+    #[test]
+    fn run_church_arithmetic() {
+        let main = "three = succ two\nfour = succ three\nmain = (p = pred four\n  eq p three)";
+        assert_eq!(run_main(main), (true, false));
+
+        assert_eq!(run_main("main = eq one zero"), (false, true));
+    }
+
+    #[test]
+    fn run_recursive_block_defs() {
+        let main = "main = (
+  even = \\n -> (stop = \\u -> true
+    go = \\u -> (p = pred n
+      odd p)
+    is_zero n stop go zero)
+  odd = \\n -> (stop = \\u -> false
+    go = \\u -> (p = pred n
+      even p)
+    is_zero n stop go zero)
+  four = double two
+  even four)";
+        assert_eq!(run_main(main), (true, false));
+    }
+
+    #[test]
+    fn deep_tail_recursion() {
+        // `count` calls itself in tail position once per step of `n`, so the
+        // frame stack stays flat however large `n` is.
+        let main = "count = \\n -> (stop = \\u -> true
+  go = \\u -> (p = pred n
+    count p)
+  is_zero n stop go zero)
+four = double two
+eight = double four
+big = double eight
+main = count big";
+        assert_eq!(run_main(main), (true, false));
+    }
+
+    #[test]
+    fn report_uninitialized_defs() {
+        let source = "main = (x = y\n  y = x\n  x)";
+        let module = parse_module(source).unwrap();
+        let program = compile(&module, Evaluation::Strict).unwrap();
+        let err = run(&program).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::Uninitialized);
+        assert_eq!(&source[err.span], "y");
+    }
+
+    #[test]
+    fn report_missing_main() {
+        let module = parse_module("id = \\x -> x").unwrap();
+        let program = compile(&module, Evaluation::Strict).unwrap();
+        assert_eq!(run(&program).unwrap_err().kind, ErrorKind::NoMain);
+    }
+
+    #[test]
+    fn lazy_arguments_are_only_evaluated_when_needed() {
+        let main = "loop = loop\nconst = \\x -> \\y -> x\nmain = const true loop";
+        assert_eq!(run_main_with(main, Evaluation::Lazy), (true, false));
+    }
+
+    #[test]
+    fn lazy_infinite_streams() {
+        let main = "cons = \\h -> \\t -> \\s -> s h t
+head = \\p -> p true
+tail = \\p -> p false
+nats = (from = \\n -> (m = succ n
+    rest = from m
+    cons n rest)
+  from zero)
+main = (t = tail nats
+  t2 = tail t
+  h = head t2
+  eq h two)";
+        // `from` has no base case, so only lazy evaluation can build the
+        // stream.
+        assert_eq!(run_main_with(main, Evaluation::Lazy), (true, false));
+    }
+
+    #[test]
+    fn lazy_fixed_point() {
+        let main = "fix = \\f -> (x = f x
+  x)
+even = fix \\self -> \\n -> (p = pred n
+  o = self p
+  odd = not o
+  is_zero n true odd)
+not = \\b -> b false true
+four = double two
+main = even four";
+        assert_eq!(run_main_with(main, Evaluation::Lazy), (true, false));
+    }
+
+    #[test]
+    fn thunks_are_memoized() {
+        let module = parse_module("id = \\x -> x\nf = id id").unwrap();
+        let program = compile(&module, Evaluation::Lazy).unwrap();
+        let mut vm = Vm::new(&program);
+        vm.init().unwrap();
+        let f = vm.global("f").unwrap().clone();
+        let first = vm.force(f.clone()).unwrap();
+        let second = vm.force(f).unwrap();
+        assert!(first.ptr_eq(&second));
+    }
+
+    #[test]
+    fn report_loops() {
+        let source = "main = (x = y\n  y = x\n  x)";
+        let module = parse_module(source).unwrap();
+        let program = compile(&module, Evaluation::Lazy).unwrap();
+        let err = run(&program).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::Loop);
+        assert_eq!(err.kind.to_string(), "<<loop>>");
+        assert_eq!(&source[err.span], "x");
+
+        let module = parse_module("main = main").unwrap();
+        let program = compile(&module, Evaluation::Lazy).unwrap();
+        assert_eq!(run(&program).unwrap_err().kind, ErrorKind::Loop);
+    }
+
+    /// Runs `main` in `source` with both evaluation strategies, and returns
+    /// how its value is displayed.
+    fn display_main(source: &str) -> String {
+        let module = parse_module(source).unwrap();
+        let results: Vec<_> = [Evaluation::Strict, Evaluation::Lazy]
+            .into_iter()
+            .map(|evaluation| {
+                let program = compile(&module, evaluation).unwrap();
+                let value = run(&program).unwrap();
+                let display = value.display(&program).to_string();
+                display
+            })
+            .collect();
+        assert_eq!(results[0], results[1], "strict and lazy results differ");
+        results[0].clone()
+    }
+
+    const LIST: &str = "type List a = Nil | Cons a (List a)
+range = \\n -> match n with
+  | 0 -> Nil
+  | _ -> (rest = range (m = sub n 1
+      m)
+    Cons n rest)
+sum = \\list -> match list with
+  | Nil -> 0
+  | Cons x rest -> (s = sum rest
+    add x s)
+";
+
+    #[test]
+    fn match_data_values() {
+        assert_eq!(
+            display_main(&format!("{LIST}main = (list = range 4\n  sum list)")),
+            "10"
+        );
+        assert_eq!(
+            display_main(&format!("{LIST}main = range 3")),
+            "Cons 3 (Cons 2 (Cons 1 Nil))"
+        );
+
+        let nested = "type Option a = None | Some a
+second = \\list -> match list with
+  | Cons _ (Cons x _) -> Some x
+  | _ -> None
+main = (short = range 1
+  long = range 5
+  a = second short
+  b = second long
+  tail = Cons b Nil
+  Cons a tail)";
+        assert_eq!(
+            display_main(&format!("{LIST}{nested}")),
+            "Cons None (Cons (Some 4) Nil)"
+        );
+
+        // types can be declared in blocks too
+        let block = "main = (type Pair a b = Pair a b
+  swap = \\p -> match p with
+    | Pair a b -> Pair b a
+  p = Pair 1 2
+  swap p)";
+        assert_eq!(display_main(block), "Pair 2 1");
+    }
+
+    #[test]
+    fn run_grouped_expressions() {
+        assert_eq!(display_main("main = (\\x -> x) (add 1 (mul 2 3))"), "7");
+        assert_eq!(
+            display_main(&format!("{LIST}main = sum (range (sub 5 1))")),
+            "10"
+        );
+    }
+
+    #[test]
+    fn partially_apply_constructors() {
+        let source = "type Pair a b = Pair a b\npartial = Pair 1\nmain = partial 2";
+        assert_eq!(display_main(source), "Pair 1 2");
+        let source = "type Pair a b = Pair a b\nmain = Pair 1";
+        assert_eq!(display_main(source), "<constructor Pair>");
+    }
+
+    #[test]
+    fn lazy_fields_are_only_evaluated_when_needed() {
+        let source = format!(
+            "{LIST}loop = \\x -> loop x
+head = \\list -> match list with
+  | Cons x _ -> x
+  | Nil -> 0
+main = (list = Cons 1 (x = loop 0
+  x)
+  head list)"
+        );
+        let module = parse_module(&source).unwrap();
+        let program = compile(&module, Evaluation::Lazy).unwrap();
+        assert!(matches!(run(&program), Ok(Value::Int(1))));
+    }
+
+    #[test]
+    fn long_lists() {
+        // displaying and dropping a list doesn't recurse on the native stack
+        let source = format!("{LIST}main = range 100000");
+        let module = parse_module(&source).unwrap();
+        let program = compile(&module, Evaluation::Strict).unwrap();
+        let list = run(&program).unwrap().display(&program).to_string();
+        assert!(list.starts_with("Cons 100000 (Cons 99999 ("));
+        assert!(list.ends_with(&format!("(Cons 1 Nil){}", ")".repeat(99_998))));
+    }
+
+    #[test]
+    fn drop_long_chains() {
+        // each link holds the last, so dropping the first would recurse as
+        // deep as the chain is long
+        let build = "build = \\n -> \\f -> match n with\n  | 0 -> f\n  | _ -> build (sub n 1) ";
+        for (evaluation, link) in [
+            (Evaluation::Strict, "(\\x -> f x)"),
+            (Evaluation::Lazy, "(\\x -> f x)"),
+            (Evaluation::Strict, "(f, n)"),
+            (Evaluation::Strict, "{ next = f }"),
+        ] {
+            let source = format!("{build}{link}\nmain = (f = build 200000 0\n  1)");
+            let module = parse_module(&source).unwrap();
+            let program = compile(&module, evaluation).unwrap();
+            assert!(
+                matches!(run(&program), Ok(Value::Int(1))),
+                "building {link}"
+            );
+        }
+    }
+
+    #[test]
+    fn report_unmatched_values() {
+        let source = "type A = A\ntype B = B\nf = \\x -> match x with\n  | A -> 1\nmain = f B";
+        let module = parse_module(source).unwrap();
+        let program = compile(&module, Evaluation::Strict).unwrap();
+        let err = run(&program).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::NoMatch);
+        assert_eq!(&source[err.span], "match x");
+
+        let source = "type A = A\nmain = match 1 with\n  | A -> 1";
+        let module = parse_module(source).unwrap();
+        let program = compile(&module, Evaluation::Strict).unwrap();
+        let err = run(&program).unwrap_err();
+        assert_eq!(
+            err.kind.to_string(),
+            "expected a data value, found an integer"
+        );
+        assert_eq!(&source[err.span], "A");
+    }
+
+    #[test]
+    fn build_and_read_tuples_and_records() {
+        assert_eq!(
+            display_main("main = (1, { a = 2, b = (3, 4) }, {})"),
+            "(1, { a = 2, b = (3, 4) }, {})"
+        );
+
+        let source = "type Option a = None | Some a
+swap = \\p -> (p.1, p.0)
+point = { x = 1, y = Some 2 }
+main = (p = swap (point.x, point)
+  p.0.y)";
+        assert_eq!(display_main(source), "Some 2");
+
+        // a lambda in a field is named after it
+        let source = "r = { f = \\x -> x }\nmain = r.f";
+        assert_eq!(display_main(source), "<function f>");
+    }
+
+    #[test]
+    fn lazy_record_fields_are_only_evaluated_when_needed() {
+        let source = "loop = \\x -> loop x\nr = { a = loop 0, b = 1 }\nmain = (r.b, (r, 2).1)";
+        let module = parse_module(source).unwrap();
+        let program = compile(&module, Evaluation::Lazy).unwrap();
+        let mut vm = Vm::new(&program);
+        vm.init().unwrap();
+        let main = vm.global("main").unwrap().clone();
+        let main = vm.force(main).unwrap();
+        let Value::Tuple(tuple) = main else {
+            panic!("expected a tuple");
+        };
+        assert!(matches!(
+            vm.force(tuple.items[0].clone()),
+            Ok(Value::Int(1))
+        ));
+        assert!(matches!(
+            vm.force(tuple.items[1].clone()),
+            Ok(Value::Int(2))
+        ));
+    }
+
+    #[test]
+    fn report_missing_fields() {
+        let source = "get = \\r -> r.b\nmain = get { a = 1 }";
+        let module = parse_module(source).unwrap();
+        let program = compile(&module, Evaluation::Strict).unwrap();
+        let err = run(&program).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::NoField("b".to_owned()));
+        assert_eq!(err.kind.to_string(), "no field `b`");
+        assert_eq!(&source[err.span], "r.b");
+
+        let source = "second = \\t -> t.1\nmain = second { a = 1 }";
+        let module = parse_module(source).unwrap();
+        let program = compile(&module, Evaluation::Lazy).unwrap();
+        let err = run(&program).unwrap_err();
+        assert_eq!(err.kind.to_string(), "expected a tuple, found a record");
+        assert_eq!(&source[err.span], "t.1");
+    }
+
+    /// Runs `source` strictly with `limits`, returning the error kind and the
+    /// source of the span it points at.
+    fn run_limited(source: &str, limits: Limits) -> (ErrorKind, &str) {
+        let module = parse_module(source).unwrap();
+        let program = compile(&module, Evaluation::Strict).unwrap();
+        let err = run_with_limits(&program, limits).unwrap_err();
+        (err.kind, &source[err.span])
+    }
+
+    #[test]
+    fn limit_fuel() {
+        let source = "w = \\x -> x x\nmain = w w";
+        let limits = Limits {
+            fuel: Some(1000),
+            ..Limits::default()
+        };
+        assert_eq!(run_limited(source, limits).0, ErrorKind::OutOfFuel);
+    }
+
+    #[test]
+    fn limit_depth() {
+        let source = "f = \\x -> (y = f x\n  y)\nmain = f f";
+        let limits = Limits {
+            max_depth: Some(100),
+            ..Limits::default()
+        };
+        assert_eq!(run_limited(source, limits), (ErrorKind::TooDeep, "f x"));
+    }
+
+    #[test]
+    fn lazy_natives_force_arguments_in_the_vm() {
+        // the sum is a chain of thunks, each adding to the last
+        let source = "count = \\n -> \\acc -> match n with\n  | 0 -> acc\n  | _ -> count (sub n 1) (add acc 1)\n\
+                      main = count 100000 0";
+        let module = parse_module(source).unwrap();
+        let program = compile(&module, Evaluation::Lazy).unwrap();
+        assert!(matches!(run(&program), Ok(Value::Int(100_000))));
+
+        let limits = Limits {
+            max_depth: Some(50),
+            ..Limits::default()
+        };
+        let err = run_with_limits(&program, limits).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::TooDeep);
+    }
+
+    #[test]
+    fn limit_allocations() {
+        let source = "f = \\x -> (g = \\y -> y\n  f g)\nmain = f f";
+        let limits = Limits {
+            max_allocations: Some(100),
+            ..Limits::default()
+        };
+        assert_eq!(
+            run_limited(source, limits),
+            (ErrorKind::TooManyAllocations, "y -> y")
+        );
+    }
+
+    #[test]
+    fn limit_deadline() {
+        let source = "w = \\x -> x x\nmain = w w";
+        let limits = Limits {
+            deadline: Some(Instant::now() + Duration::from_millis(10)),
+            ..Limits::default()
+        };
+        assert_eq!(run_limited(source, limits).0, ErrorKind::DeadlineExceeded);
+    }
+
+    #[test]
+    fn count_steps_within_limits() {
+        let module = parse_module("id = \\x -> x\nmain = id id").unwrap();
+        let program = compile(&module, Evaluation::Strict).unwrap();
+        let limits = Limits {
+            fuel: Some(100),
+            max_depth: Some(10),
+            max_allocations: Some(10),
+            deadline: None,
+        };
+        let mut vm = Vm::with_limits(&program, limits);
+        vm.init().unwrap();
+        assert_eq!(vm.allocations(), 1);
+        assert!(vm.steps() > 0);
+    }
+
+    /// Runs each test in `source` with both evaluation strategies, and
+    /// returns the errors of the tests that failed, by name.
+    fn run_tests(source: &str) -> Vec<(String, ErrorKind, &str)> {
+        let module = parse_module(source).unwrap();
+        let results: Vec<_> = [Evaluation::Strict, Evaluation::Lazy]
+            .into_iter()
+            .map(|evaluation| {
+                let program = compile(&module, evaluation).unwrap();
+                let mut failures = Vec::new();
+                for (index, test) in program.tests.iter().enumerate() {
+                    let mut vm = Vm::new(&program);
+                    vm.init().unwrap();
+                    if let Err(err) = vm.run_test(index) {
+                        failures.push((test.name.clone(), err.kind, &source[err.span]));
+                    }
+                }
+                failures
+            })
+            .collect();
+        assert_eq!(results[0], results[1], "strict and lazy results differ");
+        results[0].clone()
+    }
+
+    #[test]
+    fn compare_values_in_tests() {
+        let source = "type List a = Nil | Cons a (List a)
+id = \\x -> x
+test \"ints\" = assert_eq (add 1 2) 3
+test \"data\" = assert_eq (Cons 1 Nil) (Cons 1 Nil)
+test \"tuples and records\" = assert_eq ({ a = 1, b = (2, 3) }) ({ b = (2, 3), a = 1 })
+test \"alpha-equivalent\" = assert_eq (\\y -> y) id
+test \"partial\" = assert_eq (add 1) (add 1)
+test \"constructors\" = assert_eq (Cons (add 1)) (Cons (add 1))
+test \"recursive\" = (f = \\x -> f x; g = \\y -> g y; assert_eq f g)
+";
+        assert_eq!(run_tests(source), []);
+    }
+
+    #[test]
+    fn report_failed_assertions() {
+        let source = "type List a = Nil | Cons a (List a)
+test \"data\" = assert_eq (Cons 1 Nil) (Cons 2 Nil)
+test \"fields\" = assert_eq { a = 1 } { b = 1 }
+test \"captures\" = (a = 1; b = 2; assert_eq (\\x -> a) (\\x -> b))
+test \"code\" = assert_eq (\\x -> x) (\\x -> 1)
+";
+        let failed = |left: &str, right: &str| ErrorKind::AssertionFailed {
+            left: left.to_owned(),
+            right: right.to_owned(),
+        };
+        assert_eq!(
+            run_tests(source),
+            [
+                (
+                    "data".to_owned(),
+                    failed("Cons 1 Nil", "Cons 2 Nil"),
+                    "assert_eq (Cons 1 Nil) (Cons 2 Nil)"
+                ),
+                (
+                    "fields".to_owned(),
+                    failed("{ a = 1 }", "{ b = 1 }"),
+                    "assert_eq { a = 1 } { b = 1 }"
+                ),
+                (
+                    "captures".to_owned(),
+                    failed("<function>", "<function>"),
+                    "assert_eq (\\x -> a) (\\x -> b)"
+                ),
+                (
+                    "code".to_owned(),
+                    failed("<function>", "<function>"),
+                    "assert_eq (\\x -> x) (\\x -> 1)"
+                ),
+            ]
+        );
+    }
+}